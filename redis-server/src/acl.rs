@@ -0,0 +1,328 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use crate::commands::arg_bytes;
+use crate::commands::table::{self, CommandInfo, CATEGORIES};
+use crate::digest::{sha256, to_hex};
+use crate::glob::glob_match;
+use crate::parser::Value;
+
+pub const DEFAULT_USER: &str = "default";
+
+/// An ACL user, what it may do is set by the rules of `ACL SETUSER` e.g.
+/// `on >secret ~cache:* +@read -keys`.
+#[derive(Debug, Clone)]
+pub struct User {
+    pub name: String,
+    pub enabled: bool,
+    /// any password is accepted
+    pub nopass: bool,
+    /// SHA-256 of the passwords, hex encoded
+    pub passwords: BTreeSet<String>,
+    /// names of the commands the user may run, as in the command table
+    allowed: HashSet<&'static str>,
+    /// `+`/`-` command rules in the order they were given, starting from the
+    /// last `+@all` or `-@all`
+    command_rules: Vec<String>,
+    /// glob style patterns of the keys the user may access
+    key_patterns: Vec<String>,
+}
+
+/// Commands of a category, `all` is every command.
+fn category_commands(category: &str) -> Option<Vec<&'static str>> {
+    if category != "all" && !CATEGORIES.contains(&category) {
+        return None;
+    }
+    Some(
+        table::commands()
+            .filter(|(group, command)| {
+                category == "all" || table::categories(group, command).contains(&category)
+            })
+            .map(|(_, command)| command.name)
+            .collect(),
+    )
+}
+
+impl User {
+    /// A new user can't do anything until rules are given.
+    pub fn new(name: &str) -> User {
+        User {
+            name: name.to_string(),
+            enabled: false,
+            nopass: false,
+            passwords: BTreeSet::new(),
+            allowed: HashSet::new(),
+            command_rules: vec!["-@all".to_string()],
+            key_patterns: Vec::new(),
+        }
+    }
+
+    /// Apply a single `ACL SETUSER` rule.
+    pub fn apply(&mut self, rule: &str) -> Result<(), String> {
+        let lower = rule.to_lowercase();
+        match lower.as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => self.key_patterns = vec!["*".to_string()],
+            "resetkeys" => self.key_patterns.clear(),
+            "allcommands" => return self.apply("+@all"),
+            "nocommands" => return self.apply("-@all"),
+            "reset" => {
+                *self = User::new(&self.name);
+            }
+            _ => return self.apply_prefixed(rule, &lower),
+        }
+        Ok(())
+    }
+
+    fn apply_prefixed(&mut self, rule: &str, lower: &str) -> Result<(), String> {
+        let (prefix, rest) = rule.split_at(rule.chars().next().map_or(0, char::len_utf8));
+        match prefix {
+            ">" => {
+                self.passwords.insert(to_hex(&sha256(rest.as_bytes())));
+                self.nopass = false;
+            }
+            "<" => {
+                if !self.passwords.remove(&to_hex(&sha256(rest.as_bytes()))) {
+                    return Err(
+                        "The password you are trying to remove from the user does not exist"
+                            .to_string(),
+                    );
+                }
+            }
+            "#" => {
+                let hash = rest.to_lowercase();
+                if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err("The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters".to_string());
+                }
+                self.passwords.insert(hash);
+                self.nopass = false;
+            }
+            "!" => {
+                if !self.passwords.remove(&rest.to_lowercase()) {
+                    return Err(
+                        "The password you are trying to remove from the user does not exist"
+                            .to_string(),
+                    );
+                }
+            }
+            "~" => self.key_patterns.push(rest.to_string()),
+            "+" | "-" => {
+                let names = match rest.strip_prefix('@') {
+                    Some(category) => category_commands(&category.to_lowercase()),
+                    None => table::lookup(rest).map(|command| vec![command.name]),
+                }
+                .ok_or("Unknown command or category name in ACL")?;
+                for name in names {
+                    if prefix == "+" {
+                        self.allowed.insert(name);
+                    } else {
+                        self.allowed.remove(name);
+                    }
+                }
+                if lower == "+@all" || lower == "-@all" {
+                    self.command_rules.clear();
+                }
+                self.command_rules.push(lower.to_string());
+            }
+            _ => return Err("Syntax error".to_string()),
+        }
+        Ok(())
+    }
+
+    pub fn accepts(&self, password: &str) -> bool {
+        self.enabled
+            && (self.nopass
+                || self
+                    .passwords
+                    .contains(&to_hex(&sha256(password.as_bytes()))))
+    }
+
+    /// Command rules as `ACL LIST` shows them.
+    pub fn commands(&self) -> String {
+        self.command_rules.join(" ")
+    }
+
+    /// Key patterns as `ACL LIST` shows them.
+    pub fn keys(&self) -> String {
+        self.key_patterns
+            .iter()
+            .map(|pattern| format!("~{}", pattern))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// The user as a line of `ACL LIST`, the rules recreate it.
+    pub fn describe(&self) -> String {
+        let mut rules = vec![
+            format!("user {}", self.name),
+            if self.enabled { "on" } else { "off" }.to_string(),
+        ];
+        if self.nopass {
+            rules.push("nopass".to_string());
+        }
+        rules.extend(self.passwords.iter().map(|hash| format!("#{}", hash)));
+        if !self.key_patterns.is_empty() {
+            rules.push(self.keys());
+        }
+        rules.push(self.commands());
+        rules.join(" ")
+    }
+
+    /// Whether the user may run `command`, `args` are the arguments after
+    /// the command name. Replies the error to send back when it may not.
+    pub fn check(&self, command: &CommandInfo, args: &[Value]) -> Result<(), Value> {
+        if !self.allowed.contains(command.name) {
+            return Err(Value::error(&format!(
+                "NOPERM User {} has no permissions to run the '{}' command",
+                self.name,
+                command.name.to_lowercase()
+            )));
+        }
+        for position in command.key_args(args) {
+            let key = arg_bytes(args, position - 1);
+            if !self
+                .key_patterns
+                .iter()
+                .any(|pattern| glob_match(pattern.as_bytes(), &key))
+            {
+                return Err(Value::error("NOPERM No permissions to access a key"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Users known to the server. The `default` user always exists, connections
+/// start out authenticated as it when it needs no password.
+pub struct Acl {
+    pub users: BTreeMap<String, User>,
+}
+
+impl Acl {
+    /// Only the `default` user which may do everything, with `requirepass`
+    /// as its password.
+    pub fn new(requirepass: &str) -> Acl {
+        let mut user = User::new(DEFAULT_USER);
+        for rule in ["on", "allkeys", "+@all"] {
+            let _ = user.apply(rule);
+        }
+        let mut acl = Acl {
+            users: BTreeMap::from([(DEFAULT_USER.to_string(), user)]),
+        };
+        acl.set_requirepass(requirepass);
+        acl
+    }
+
+    /// `requirepass` is the password of the `default` user, an empty one
+    /// means no password is needed.
+    pub fn set_requirepass(&mut self, password: &str) {
+        if let Some(user) = self.users.get_mut(DEFAULT_USER) {
+            let rule = format!(">{}", password);
+            let _ = user.apply("resetpass");
+            let _ = user.apply(if password.is_empty() { "nopass" } else { &rule });
+        }
+    }
+
+    /// Whether connections are logged in as `default` without `AUTH`.
+    pub fn default_login(&self) -> bool {
+        self.users
+            .get(DEFAULT_USER)
+            .is_some_and(|user| user.enabled && user.nopass)
+    }
+
+    pub fn authenticate(&self, name: &str, password: &str) -> bool {
+        self.users
+            .get(name)
+            .is_some_and(|user| user.accepts(password))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::command;
+
+    fn user(rules: &[&str]) -> User {
+        let mut user = User::new("alice");
+        for rule in rules {
+            user.apply(rule).unwrap();
+        }
+        user
+    }
+
+    fn check(user: &User, args: &[&str]) -> Result<(), String> {
+        let value = command(args);
+        let command = table::lookup(args[0]).unwrap();
+        user.check(command, &value.array[1..])
+            .map_err(|e| String::from_utf8(e.value.unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_command_rules() {
+        let alice = user(&["on", "allkeys", "+@read", "-keys", "+set"]);
+        assert!(check(&alice, &["GET", "k"]).is_ok());
+        assert!(check(&alice, &["SET", "k", "v"]).is_ok());
+        assert_eq!(
+            check(&alice, &["KEYS", "*"]).unwrap_err(),
+            "NOPERM User alice has no permissions to run the 'keys' command"
+        );
+        assert!(check(&alice, &["DEL", "k"]).is_err());
+        assert_eq!(alice.commands(), "-@all +@read -keys +set");
+
+        let alice = user(&["+@read", "-@all", "+ping"]);
+        assert!(check(&alice, &["GET", "k"]).is_err());
+        assert!(check(&alice, &["PING"]).is_ok());
+        assert_eq!(alice.commands(), "-@all +ping");
+
+        assert!(User::new("bob").apply("+nosuchcommand").is_err());
+        assert!(User::new("bob").apply("+@nosuchcategory").is_err());
+        assert!(User::new("bob").apply("bogus").is_err());
+    }
+
+    #[test]
+    fn test_key_patterns() {
+        let alice = user(&["on", "~cache:*", "~user:?", "+@all"]);
+        assert!(check(&alice, &["GET", "cache:1"]).is_ok());
+        assert!(check(&alice, &["MSET", "cache:1", "a", "user:1", "b"]).is_ok());
+        assert_eq!(
+            check(&alice, &["MSET", "cache:1", "a", "user:10", "b"]).unwrap_err(),
+            "NOPERM No permissions to access a key"
+        );
+        // values aren't keys
+        assert!(check(&alice, &["SET", "cache:1", "user:10"]).is_ok());
+        assert!(check(&alice, &["PING"]).is_ok());
+    }
+
+    #[test]
+    fn test_passwords() {
+        let mut alice = user(&["on", ">first", ">second"]);
+        assert!(alice.accepts("first"));
+        assert!(alice.accepts("second"));
+        assert!(!alice.accepts("third"));
+        alice.apply("<first").unwrap();
+        assert!(!alice.accepts("first"));
+        assert!(alice.apply("<first").is_err());
+        alice.apply("off").unwrap();
+        assert!(!alice.accepts("second"));
+
+        let mut acl = Acl::new("");
+        assert!(acl.default_login());
+        acl.set_requirepass("secret");
+        assert!(!acl.default_login());
+        assert!(acl.authenticate(DEFAULT_USER, "secret"));
+        assert!(!acl.authenticate(DEFAULT_USER, "wrong"));
+        assert!(!acl.authenticate("nobody", "secret"));
+        assert_eq!(
+            acl.users[DEFAULT_USER].describe(),
+            format!("user default on #{} ~* +@all", to_hex(&sha256(b"secret")))
+        );
+    }
+}
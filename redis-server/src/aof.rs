@@ -0,0 +1,650 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::client::Client;
+use crate::commands::execute_command;
+use crate::config::FsyncPolicy;
+use crate::dictionary_server::{now_ms, DictionaryServer, RedisValue};
+use crate::parser::{self, ParseError, Parser, Value};
+use crate::server::Server;
+use crate::sorted_set::format_score;
+use crate::stream::{Stream, StreamId};
+
+/// Append only file: every write command is logged in RESP so replaying the
+/// file rebuilds the dataset.
+pub struct Aof {
+    file: Option<File>,
+    /// commands executed while a rewrite runs, they are appended to the new
+    /// file before it replaces the old one
+    rewrite_buffer: Option<Vec<u8>>,
+    /// data was written but not fsynced yet
+    unsynced: bool,
+    /// unix time (ms) of the last fsync
+    pub last_fsync: u64,
+    /// database the logged commands go to, `None` until the log has a
+    /// `SELECT`
+    selected_db: Option<usize>,
+}
+
+impl Aof {
+    pub fn new() -> Aof {
+        Aof {
+            file: None,
+            rewrite_buffer: None,
+            unsynced: false,
+            last_fsync: now_ms(),
+            selected_db: None,
+        }
+    }
+
+    /// Start appending to the file at `path`, it gets created if missing.
+    pub fn open(&mut self, path: &Path) -> io::Result<()> {
+        self.file = Some(OpenOptions::new().create(true).append(true).open(path)?);
+        self.selected_db = None;
+        Ok(())
+    }
+
+    /// Stop logging, what was written so far is synced first.
+    pub fn close(&mut self) -> io::Result<()> {
+        self.fsync()?;
+        self.file = None;
+        Ok(())
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.file.is_some()
+    }
+
+    pub fn is_rewriting(&self) -> bool {
+        self.rewrite_buffer.is_some()
+    }
+
+    /// Log a command of database `db`, preceded by a `SELECT` when the
+    /// previous one went to another database. With `appendfsync always` it is
+    /// on the disk once this returns.
+    pub fn append(&mut self, db: usize, entry: &[u8], policy: FsyncPolicy) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        if self.selected_db != Some(db) {
+            bytes = encode_command(&["SELECT", &db.to_string()]);
+            self.selected_db = Some(db);
+        }
+        bytes.extend_from_slice(entry);
+        if let Some(buffer) = self.rewrite_buffer.as_mut() {
+            buffer.extend_from_slice(&bytes);
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(&bytes)?;
+            self.unsynced = true;
+            if policy == FsyncPolicy::Always {
+                self.fsync()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn fsync(&mut self) -> io::Result<()> {
+        self.last_fsync = now_ms();
+        if let (Some(file), true) = (self.file.as_mut(), self.unsynced) {
+            file.sync_data()?;
+            self.unsynced = false;
+        }
+        Ok(())
+    }
+
+    pub fn start_rewrite(&mut self) {
+        self.rewrite_buffer = Some(Vec::new());
+        // the buffer follows a rewrite which may end in any database
+        self.selected_db = None;
+    }
+
+    /// Stops collecting commands for the rewrite and returns them.
+    pub fn finish_rewrite(&mut self) -> Vec<u8> {
+        self.rewrite_buffer.take().unwrap_or_default()
+    }
+}
+
+/// Arguments of a command sent by the client.
+pub fn command_args(value: &Value) -> Vec<Vec<u8>> {
+    value
+        .array
+        .iter()
+        .map(|v| v.value.clone().unwrap_or_default())
+        .collect()
+}
+
+/// RESP representation of a command i.e. an array of bulk strings.
+pub fn encode_command<T: AsRef<[u8]>>(args: &[T]) -> Vec<u8> {
+    let command = Value::array(args.iter().map(Value::bulk_string).collect());
+    parser::stringify(&command)
+}
+
+/// Relative timeouts would restart from zero when the log gets replayed, so
+/// after the command ran they are translated into absolute deadlines: the
+/// `EXPIRE` family becomes `PEXPIREAT`, or `DEL` when it deleted the key, and
+/// `SET ... EX` becomes `SET ... PXAT`.
+/// `INCRBYFLOAT` is logged as a `SET` of its result so floating point
+/// rounding can't make the replayed value drift. `XADD` gets the id the entry
+/// was given in place of `*`, and `XREADGROUP` loses its `BLOCK` option.
+/// Blocking pops which got an element right away are logged as the plain
+/// `LPOP`, `RPOP` or `LMOVE`, `reply` tells which key `BLPOP` popped from.
+pub fn log_entry(args: Vec<Vec<u8>>, reply: &Value, map: &mut DictionaryServer) -> Vec<Vec<u8>> {
+    let name = args[0].to_ascii_uppercase();
+    match name.as_slice() {
+        b"SET" => {
+            let mut rewritten = Vec::with_capacity(args.len());
+            let mut has_expiry = false;
+            let mut i = 0;
+            while i < args.len() {
+                match args[i].to_ascii_uppercase().as_slice() {
+                    b"EX" | b"PX" | b"EXAT" | b"PXAT" if i >= 3 => {
+                        has_expiry = true;
+                        i += 2;
+                    }
+                    _ => {
+                        rewritten.push(args[i].clone());
+                        i += 1;
+                    }
+                }
+            }
+            match map.expiry(&args[1]) {
+                Some(Some(when)) if has_expiry => {
+                    rewritten.push(b"PXAT".to_vec());
+                    rewritten.push(when.to_string().into_bytes());
+                    rewritten
+                }
+                _ => args,
+            }
+        }
+        b"EXPIRE" | b"PEXPIRE" | b"EXPIREAT" => match map.expiry(&args[1]) {
+            Some(Some(when)) => vec![
+                b"PEXPIREAT".to_vec(),
+                args[1].clone(),
+                when.to_string().into_bytes(),
+            ],
+            // a deadline in the past deleted the key
+            _ => vec![b"DEL".to_vec(), args[1].clone()],
+        },
+        b"INCRBYFLOAT" => match map.get(&args[1]) {
+            Ok(Some(value)) => vec![b"SET".to_vec(), args[1].clone(), value, b"KEEPTTL".to_vec()],
+            _ => args,
+        },
+        b"BLPOP" | b"BRPOP" => match reply.array.first().and_then(|key| key.value.clone()) {
+            Some(key) => vec![name[1..].to_vec(), key],
+            None => args,
+        },
+        b"BLMOVE" if reply.value.is_some() => {
+            let mut rewritten = vec![b"LMOVE".to_vec()];
+            rewritten.extend_from_slice(&args[1..5]);
+            rewritten
+        }
+        b"XADD" => {
+            // skip the options, the id follows them
+            let mut i = 2;
+            while i < args.len() {
+                match args[i].to_ascii_uppercase().as_slice() {
+                    b"NOMKSTREAM" => i += 1,
+                    b"MAXLEN" | b"MINID" => {
+                        i += 1;
+                        if matches!(args.get(i).map(Vec::as_slice), Some(b"~" | b"=")) {
+                            i += 1;
+                        }
+                        i += 1;
+                    }
+                    b"LIMIT" => i += 2,
+                    _ => break,
+                }
+            }
+            let last_id = map
+                .stream_mut(&args[1], false)
+                .ok()
+                .flatten()
+                .map(|stream| stream.last_id.to_string());
+            match last_id {
+                Some(last_id) if i < args.len() => {
+                    let mut args = args;
+                    args[i] = last_id.into_bytes();
+                    args
+                }
+                _ => args,
+            }
+        }
+        b"XREADGROUP" => {
+            let mut rewritten = Vec::with_capacity(args.len());
+            let mut i = 0;
+            while i < args.len() {
+                match args[i].to_ascii_uppercase().as_slice() {
+                    b"STREAMS" => {
+                        rewritten.extend_from_slice(&args[i..]);
+                        break;
+                    }
+                    b"BLOCK" => i += 2,
+                    b"GROUP" => {
+                        rewritten.extend_from_slice(&args[i..(i + 3).min(args.len())]);
+                        i += 3;
+                    }
+                    _ => {
+                        rewritten.push(args[i].clone());
+                        i += 1;
+                    }
+                }
+            }
+            rewritten
+        }
+        _ => args,
+    }
+}
+
+/// Collections are rewritten with commands of at most this many elements so a
+/// huge list doesn't turn into a single gigantic command.
+const REWRITE_ITEMS_PER_COMMAND: usize = 64;
+
+/// Commands which recreate a single key.
+fn rewrite_value(key: &[u8], value: &RedisValue, content: &mut Vec<u8>) {
+    let command = |name: &str| vec![name.as_bytes().to_vec(), key.to_vec()];
+    match value {
+        RedisValue::String(string) => {
+            let mut args = command("SET");
+            args.push(string.clone());
+            content.extend(encode_command(&args));
+        }
+        RedisValue::List(list) => {
+            let elements: Vec<&Vec<u8>> = list.iter().collect();
+            for chunk in elements.chunks(REWRITE_ITEMS_PER_COMMAND) {
+                let mut args = command("RPUSH");
+                args.extend(chunk.iter().map(|element| element.to_vec()));
+                content.extend(encode_command(&args));
+            }
+        }
+        RedisValue::Hash(hash) => {
+            let pairs: Vec<(&Vec<u8>, &Vec<u8>)> = hash.iter().collect();
+            for chunk in pairs.chunks(REWRITE_ITEMS_PER_COMMAND) {
+                let mut args = command("HSET");
+                for (field, value) in chunk {
+                    args.push(field.to_vec());
+                    args.push(value.to_vec());
+                }
+                content.extend(encode_command(&args));
+            }
+        }
+        RedisValue::Set(set) => {
+            let members: Vec<&Vec<u8>> = set.iter().collect();
+            for chunk in members.chunks(REWRITE_ITEMS_PER_COMMAND) {
+                let mut args = command("SADD");
+                args.extend(chunk.iter().map(|member| member.to_vec()));
+                content.extend(encode_command(&args));
+            }
+        }
+        RedisValue::SortedSet(zset) => {
+            for chunk in zset.iter().chunks(REWRITE_ITEMS_PER_COMMAND) {
+                let mut args = command("ZADD");
+                for (member, score) in chunk {
+                    args.push(format_score(*score).into_bytes());
+                    args.push(member.clone());
+                }
+                content.extend(encode_command(&args));
+            }
+        }
+        RedisValue::Stream(stream) => rewrite_stream(key, stream, content),
+    }
+}
+
+/// Like redis a stream is recreated entry by entry, then its last id is set
+/// and its groups are created. The pending entries are claimed by their
+/// consumer again with their delivery time and count, an empty stream is
+/// created by adding an entry and trimming it away right after.
+fn rewrite_stream(key: &[u8], stream: &Stream, content: &mut Vec<u8>) {
+    let last_id = stream.last_id.to_string();
+    if stream.is_empty() {
+        // XADD refuses 0-0, XSETID below puts the last id back
+        let id = stream.last_id.max(StreamId::new(0, 1)).to_string();
+        content.extend(encode_command::<&[u8]>(&[
+            b"XADD",
+            key,
+            b"MAXLEN",
+            b"0",
+            id.as_bytes(),
+            b"x",
+            b"y",
+        ]));
+    }
+    for (id, fields) in stream.range(StreamId::MIN, StreamId::MAX, None, false) {
+        let mut args = vec![b"XADD".to_vec(), key.to_vec()];
+        args.push(id.to_string().into_bytes());
+        for (field, value) in fields {
+            args.push(field.clone());
+            args.push(value.clone());
+        }
+        content.extend(encode_command(&args));
+    }
+    content.extend(encode_command::<&[u8]>(&[
+        b"XSETID",
+        key,
+        last_id.as_bytes(),
+    ]));
+
+    for (name, group) in &stream.groups {
+        let last_delivered = group.last_delivered.to_string();
+        content.extend(encode_command::<&[u8]>(&[
+            b"XGROUP",
+            b"CREATE",
+            key,
+            name.as_bytes(),
+            last_delivered.as_bytes(),
+        ]));
+        for (id, entry) in &group.pending {
+            content.extend(encode_command::<&[u8]>(&[
+                b"XCLAIM",
+                key,
+                name.as_bytes(),
+                entry.consumer.as_bytes(),
+                b"0",
+                id.to_string().as_bytes(),
+                b"TIME",
+                entry.delivered_at.to_string().as_bytes(),
+                b"RETRYCOUNT",
+                entry.delivery_count.to_string().as_bytes(),
+                b"JUSTID",
+                b"FORCE",
+            ]));
+        }
+        let pending = group.pending_per_consumer();
+        for consumer in group.consumers.keys() {
+            if !pending.contains_key(consumer.as_str()) {
+                content.extend(encode_command::<&[u8]>(&[
+                    b"XGROUP",
+                    b"CREATECONSUMER",
+                    key,
+                    name.as_bytes(),
+                    consumer.as_bytes(),
+                ]));
+            }
+        }
+    }
+}
+
+/// Commands which recreate the dataset, used by `BGREWRITEAOF` to compact the
+/// log. The keys of every database follow a `SELECT` of it.
+pub fn rewrite(map: &DictionaryServer) -> Vec<u8> {
+    let now = now_ms();
+    let mut content = Vec::new();
+    for db in 0..map.databases() {
+        let mut selected = false;
+        for (key, entry) in map.entries(db).iter() {
+            if entry.expires_at.is_some_and(|when| when <= now) {
+                continue;
+            }
+            if !selected {
+                content.extend(encode_command(&["SELECT", &db.to_string()]));
+                selected = true;
+            }
+            rewrite_value(key, &entry.value, &mut content);
+            if let Some(when) = entry.expires_at {
+                content.extend(encode_command::<&[u8]>(&[
+                    b"PEXPIREAT",
+                    key,
+                    when.to_string().as_bytes(),
+                ]));
+            }
+        }
+    }
+    content
+}
+
+/// Replay the append only file at `path` into the server's dictionary.
+/// Returns `false` if there is no such file. A command cut in half at the end
+/// of the file (e.g. the server crashed while writing it) is discarded and the
+/// file truncated, just like redis does with `aof-load-truncated yes`.
+pub fn load(path: &Path, server: &Arc<Server>) -> io::Result<bool> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+
+    // the log is replayed through a client which isn't connected to anything
+    let mut client = Client::new(0);
+    let mut map = server.lock();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let mut parser = Parser::new(&bytes[pos..]);
+        match parser.parse() {
+            Ok(value) => {
+                execute_command(&value, server, &mut client, &mut map);
+                pos += parser.position();
+            }
+            Err(ParseError::Incomplete) => {
+                eprintln!(
+                    "AOF {} is truncated, discarding the last {} bytes",
+                    path.display(),
+                    bytes.len() - pos
+                );
+                OpenOptions::new()
+                    .write(true)
+                    .open(path)?
+                    .set_len(pos as u64)?;
+                break;
+            }
+            Err(ParseError::Protocol(msg)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("bad AOF format at byte {pos}: {msg}"),
+                ));
+            }
+        }
+    }
+    // keys found expired while replaying expire again on the next replay
+    map.expired.clear();
+    map.dirty = 0;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::run;
+
+    fn args(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_log_entry_uses_absolute_expiry() {
+        let mut map = DictionaryServer::new();
+        run(&mut map, &["SET", "k", "v", "EX", "100", "NX"]);
+        let when = map.expiry(b"k").unwrap().unwrap();
+
+        let entry = log_entry(
+            args(&["SET", "k", "v", "EX", "100", "NX"]),
+            &Value::ok(),
+            &mut map,
+        );
+        assert_eq!(
+            entry,
+            args(&["SET", "k", "v", "NX", "PXAT", &when.to_string()])
+        );
+
+        run(&mut map, &["EXPIRE", "k", "50"]);
+        let when = map.expiry(b"k").unwrap().unwrap();
+        let entry = log_entry(args(&["EXPIRE", "k", "50"]), &Value::ok(), &mut map);
+        assert_eq!(entry, args(&["PEXPIREAT", "k", &when.to_string()]));
+
+        let entry = log_entry(args(&["SET", "k", "ex"]), &Value::ok(), &mut map);
+        assert_eq!(entry, args(&["SET", "k", "ex"]));
+
+        run(&mut map, &["INCRBYFLOAT", "n", "0.1"]);
+        let entry = log_entry(args(&["INCRBYFLOAT", "n", "0.1"]), &Value::ok(), &mut map);
+        assert_eq!(entry, args(&["SET", "n", "0.1", "KEEPTTL"]));
+
+        // the id the stream picked is the one replayed
+        run(&mut map, &["XADD", "s", "MAXLEN", "~", "10", "*", "f", "v"]);
+        let id = map.stream_mut(b"s", false).unwrap().unwrap().last_id;
+        let entry = log_entry(
+            args(&["XADD", "s", "MAXLEN", "~", "10", "*", "f", "v"]),
+            &Value::ok(),
+            &mut map,
+        );
+        assert_eq!(
+            entry,
+            args(&["XADD", "s", "MAXLEN", "~", "10", &id.to_string(), "f", "v"])
+        );
+        let read = [
+            "XREADGROUP",
+            "GROUP",
+            "g",
+            "c",
+            "BLOCK",
+            "0",
+            "STREAMS",
+            "s",
+            ">",
+        ];
+        let entry = log_entry(args(&read), &Value::ok(), &mut map);
+        assert_eq!(
+            entry,
+            args(&["XREADGROUP", "GROUP", "g", "c", "STREAMS", "s", ">"])
+        );
+
+        // the key BLPOP popped from is in its reply
+        let popped = Value::array(vec![Value::bulk_string("b"), Value::bulk_string("x")]);
+        let entry = log_entry(args(&["BLPOP", "a", "b", "0"]), &popped, &mut map);
+        assert_eq!(entry, args(&["LPOP", "b"]));
+        let blmove = args(&["BLMOVE", "a", "b", "LEFT", "RIGHT", "1.5"]);
+        let entry = log_entry(blmove, &Value::bulk_string("x"), &mut map);
+        assert_eq!(entry, args(&["LMOVE", "a", "b", "LEFT", "RIGHT"]));
+    }
+
+    #[test]
+    fn test_rewrite_selects_databases() {
+        let mut map = DictionaryServer::new();
+        map.set(b"a", b"1", None);
+        map.select(2);
+        map.set(b"b", b"2", None);
+        map.select(1);
+        let expected = [
+            encode_command(&["SELECT", "0"]),
+            encode_command(&["SET", "a", "1"]),
+            encode_command(&["SELECT", "2"]),
+            encode_command(&["SET", "b", "2"]),
+        ]
+        .concat();
+        assert_eq!(rewrite(&map), expected);
+
+        // appended commands tell which database they go to when it changes
+        let mut log = Aof::new();
+        log.start_rewrite();
+        log.append(2, b"x", FsyncPolicy::No).unwrap();
+        log.append(2, b"y", FsyncPolicy::No).unwrap();
+        log.append(0, b"z", FsyncPolicy::No).unwrap();
+        let expected = [
+            encode_command(&["SELECT", "2"]),
+            b"xy".to_vec(),
+            encode_command(&["SELECT", "0"]),
+            b"z".to_vec(),
+        ]
+        .concat();
+        assert_eq!(log.finish_rewrite(), expected);
+    }
+
+    #[test]
+    fn test_load_binary_keys() {
+        let mut map = DictionaryServer::new();
+        let key = b"k\xff\x00";
+        map.set(key, b"v", None);
+        map.list_mut(b"l\xff", true)
+            .unwrap()
+            .unwrap()
+            .push_back(b"a".to_vec());
+        let path = std::env::temp_dir().join(format!("redis-aof-test-{}.aof", std::process::id()));
+        fs::write(&path, rewrite(&map)).unwrap();
+
+        let server = crate::commands::test::server(crate::config::Config::default());
+        assert!(load(&path, &server).unwrap());
+        let mut replayed = server.lock();
+        assert_eq!(replayed.get(key), Ok(Some(b"v".to_vec())));
+        assert_eq!(
+            replayed.server[b"l\xff".as_slice()].value,
+            map.server[b"l\xff".as_slice()].value
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_rewrite_recreates_dataset() {
+        let mut map = DictionaryServer::new();
+        run(&mut map, &["SET", "a", "1"]);
+        run(&mut map, &["SET", "b", "2", "EX", "100"]);
+        let elements: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+        let mut push = vec!["RPUSH", "list"];
+        push.extend(elements.iter().map(|e| e.as_str()));
+        run(&mut map, &push);
+        run(&mut map, &["HSET", "hash", "a", "1", "b", "2"]);
+        run(
+            &mut map,
+            &["XGROUP", "CREATE", "stream", "g", "$", "MKSTREAM"],
+        );
+        run(
+            &mut map,
+            &["XGROUP", "CREATE", "empty", "g", "$", "MKSTREAM"],
+        );
+        for id in ["1-1", "2-1", "3-1"] {
+            run(&mut map, &["XADD", "stream", id, "f", id]);
+        }
+        run(&mut map, &["XTRIM", "stream", "MAXLEN", "2"]);
+        run(
+            &mut map,
+            &[
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                "a",
+                "COUNT",
+                "1",
+                "STREAMS",
+                "stream",
+                ">",
+            ],
+        );
+        run(&mut map, &["XGROUP", "CREATECONSUMER", "stream", "g", "b"]);
+        run(&mut map, &["XSETID", "stream", "9-9"]);
+
+        let content = rewrite(&map);
+        let mut replayed = DictionaryServer::new();
+        let mut pos = 0;
+        while pos < content.len() {
+            let mut parser = Parser::new(&content[pos..]);
+            let value = parser.parse().unwrap();
+            let args: Vec<String> = command_args(&value)
+                .iter()
+                .map(|arg| String::from_utf8(arg.clone()).unwrap())
+                .collect();
+            let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+            run(&mut replayed, &args);
+            pos += parser.position();
+        }
+
+        assert_eq!(replayed.get(b"a"), Ok(Some(b"1".to_vec())));
+        assert_eq!(
+            replayed.server[b"list".as_slice()].value,
+            map.server[b"list".as_slice()].value
+        );
+        assert_eq!(
+            replayed.server[b"hash".as_slice()].value,
+            map.server[b"hash".as_slice()].value
+        );
+        for key in ["stream", "empty"] {
+            let stream = map.stream_mut(key.as_bytes(), false).unwrap().unwrap();
+            let (len, last_id, group) = (stream.len(), stream.last_id, stream.groups["g"].clone());
+            let replayed = replayed.stream_mut(key.as_bytes(), false).unwrap().unwrap();
+            assert_eq!((replayed.len(), replayed.last_id), (len, last_id));
+            let replayed = &replayed.groups["g"];
+            assert_eq!(replayed.last_delivered, group.last_delivered);
+            assert_eq!(replayed.pending, group.pending);
+            assert!(replayed.consumers.keys().eq(group.consumers.keys()));
+        }
+        assert_eq!(replayed.expiry(b"b"), map.expiry(b"b"));
+    }
+}
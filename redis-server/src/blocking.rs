@@ -0,0 +1,260 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::Instant;
+
+use crate::parser::{serialize, Protocol, Value};
+use crate::stream::StreamId;
+
+/// What a blocked client waits for, it is tried again every time one of its
+/// keys changes.
+#[derive(Debug, Clone)]
+pub enum BlockedOn {
+    /// `XREAD`: entries after the given ids
+    Streams {
+        streams: Vec<(Vec<u8>, StreamId)>,
+        count: Option<usize>,
+    },
+    /// `XREADGROUP ... >`: entries not yet delivered to the group
+    Group {
+        group: String,
+        consumer: String,
+        keys: Vec<Vec<u8>>,
+        count: Option<usize>,
+        noack: bool,
+    },
+    /// `BLPOP` and `BRPOP`: an element of the first list which gets one
+    Pop { keys: Vec<Vec<u8>>, left: bool },
+    /// `BLMOVE`: an element of `source`, pushed to `destination`
+    Move {
+        source: Vec<u8>,
+        destination: Vec<u8>,
+        from_left: bool,
+        to_left: bool,
+    },
+}
+
+impl BlockedOn {
+    pub fn keys(&self) -> Vec<Vec<u8>> {
+        match self {
+            BlockedOn::Streams { streams, .. } => {
+                streams.iter().map(|(key, _)| key.clone()).collect()
+            }
+            BlockedOn::Group { keys, .. } | BlockedOn::Pop { keys, .. } => keys.clone(),
+            BlockedOn::Move { source, .. } => vec![source.clone()],
+        }
+    }
+
+    /// Reply once the timeout expired without anything to serve.
+    pub fn timeout_reply(&self) -> Value {
+        match self {
+            BlockedOn::Move { .. } => Value::null(),
+            _ => Value::null_array(),
+        }
+    }
+}
+
+/// A client waiting on keys. It is served by whichever client changes one
+/// of them, so the reply goes straight to its outbox.
+#[derive(Debug)]
+pub struct Waiter {
+    pub op: BlockedOn,
+    /// database the keys of `op` belong to
+    pub db: usize,
+    pub protocol: Protocol,
+    outbox: Sender<Vec<u8>>,
+    /// tells the connection thread it was served
+    served: Sender<()>,
+}
+
+impl Waiter {
+    /// Send the reply and let the connection go on with its next command.
+    pub fn serve(&self, reply: &Value) {
+        let _ = self.outbox.send(serialize(reply, self.protocol));
+        let _ = self.served.send(());
+    }
+}
+
+/// What the connection thread of a blocked client waits on, outside of the
+/// dictionary lock.
+#[derive(Debug)]
+pub struct Blocking {
+    pub served: Receiver<()>,
+    /// `None` blocks forever
+    pub deadline: Option<Instant>,
+}
+
+/// Clients blocked on keys of the dictionary, like redis they are served in
+/// the order they blocked.
+#[derive(Debug, Default)]
+pub struct Blocked {
+    /// ids of the clients waiting on each key of each database, oldest first
+    keys: HashMap<(usize, Vec<u8>), VecDeque<u64>>,
+    waiters: HashMap<u64, Waiter>,
+    /// keys with waiters which changed since they were last served
+    ready: Vec<(usize, Vec<u8>)>,
+}
+
+/// Waiters belong to connections, not to the data: a copy of the dictionary
+/// (e.g. a snapshot) has nobody waiting on it.
+impl Clone for Blocked {
+    fn clone(&self) -> Blocked {
+        Blocked::new()
+    }
+}
+
+impl Blocked {
+    pub fn new() -> Blocked {
+        Blocked::default()
+    }
+
+    /// Park a client until `op` can be served by database `db`.
+    pub fn block(
+        &mut self,
+        client: u64,
+        db: usize,
+        op: BlockedOn,
+        outbox: Sender<Vec<u8>>,
+        protocol: Protocol,
+        served: Sender<()>,
+    ) {
+        for key in op.keys() {
+            let waiting = self.keys.entry((db, key)).or_default();
+            if !waiting.contains(&client) {
+                waiting.push_back(client);
+            }
+        }
+        let waiter = Waiter {
+            op,
+            db,
+            outbox,
+            protocol,
+            served,
+        };
+        self.waiters.insert(client, waiter);
+    }
+
+    /// Forget a blocked client, returns `None` when it wasn't blocked (any
+    /// more).
+    pub fn unblock(&mut self, client: u64) -> Option<Waiter> {
+        let waiter = self.waiters.remove(&client)?;
+        for key in waiter.op.keys() {
+            let key = (waiter.db, key);
+            if let Some(waiting) = self.keys.get_mut(&key) {
+                waiting.retain(|id| *id != client);
+                if waiting.is_empty() {
+                    self.keys.remove(&key);
+                }
+            }
+        }
+        Some(waiter)
+    }
+
+    /// `key` of database `db` changed, the clients waiting on it get another
+    /// chance.
+    pub fn signal(&mut self, db: usize, key: &[u8]) {
+        // nobody blocks most of the time, don't build the lookup key then
+        if self.keys.is_empty() {
+            return;
+        }
+        let key = (db, key.to_vec());
+        if self.keys.contains_key(&key) && !self.ready.contains(&key) {
+            self.ready.push(key);
+        }
+    }
+
+    /// Every key of database `db` may have changed, e.g. with `SWAPDB`.
+    pub fn signal_database(&mut self, db: usize) {
+        for key in self.keys.keys().filter(|(index, _)| *index == db) {
+            if !self.ready.contains(key) {
+                self.ready.push(key.clone());
+            }
+        }
+    }
+
+    /// Next key which changed along with its database, its waiters are taken
+    /// care of by the caller.
+    pub fn next_ready(&mut self) -> Option<(usize, Vec<u8>)> {
+        self.ready.pop()
+    }
+
+    /// Clients waiting on `key` of database `db`, oldest first.
+    pub fn waiting(&self, db: usize, key: &[u8]) -> Vec<u64> {
+        self.keys
+            .get(&(db, key.to_vec()))
+            .map(|waiting| waiting.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn waiter(&self, client: u64) -> Option<&Waiter> {
+        self.waiters.get(&client)
+    }
+
+    /// Number of blocked clients.
+    pub fn len(&self) -> usize {
+        self.waiters.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    fn op(keys: &[&str]) -> BlockedOn {
+        BlockedOn::Streams {
+            streams: keys
+                .iter()
+                .map(|key| (key.as_bytes().to_vec(), StreamId::MIN))
+                .collect(),
+            count: None,
+        }
+    }
+
+    #[test]
+    fn test_waiters_in_order() {
+        let mut blocked = Blocked::new();
+        let (outbox, inbox) = mpsc::channel();
+        let (served, notified) = mpsc::channel();
+        for client in [3, 1, 2] {
+            let keys = if client == 2 {
+                vec!["b"]
+            } else {
+                vec!["a", "b"]
+            };
+            blocked.block(
+                client,
+                0,
+                op(&keys),
+                outbox.clone(),
+                Protocol::Resp2,
+                served.clone(),
+            );
+        }
+        assert_eq!(blocked.waiting(0, b"a"), [3, 1]);
+        assert_eq!(blocked.waiting(0, b"b"), [3, 1, 2]);
+
+        blocked.signal(0, b"a");
+        blocked.signal(0, b"a");
+        blocked.signal(0, b"nobody-waits");
+        // the same key of another database is another key
+        blocked.signal(1, b"b");
+        assert_eq!(blocked.next_ready(), Some((0, b"a".to_vec())));
+        assert_eq!(blocked.next_ready(), None);
+        blocked.signal_database(0);
+        assert_eq!(blocked.next_ready().map(|(db, _)| db), Some(0));
+        assert_eq!(blocked.next_ready().map(|(db, _)| db), Some(0));
+        assert_eq!(blocked.next_ready(), None);
+
+        let waiter = blocked.unblock(3).unwrap();
+        waiter.serve(&Value::integer(1));
+        assert_eq!(inbox.try_recv().unwrap(), b":1\r\n");
+        assert!(notified.try_recv().is_ok());
+        assert!(blocked.unblock(3).is_none());
+        assert_eq!(blocked.waiting(0, b"b"), [1, 2]);
+        blocked.unblock(1);
+        assert!(blocked.waiting(0, b"a").is_empty());
+        // copies of the dictionary don't carry the waiters
+        assert!(blocked.clone().waiting(0, b"b").is_empty());
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::{BTreeMap, HashSet};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::mpsc::Sender;
+
+use crate::blocking::Blocking;
+use crate::dictionary_server::now_ms;
+use crate::parser::{serialize, Protocol, Value};
+use crate::pubsub::Subscriber;
+
+/// Whether the writes of `EXEC` or of a script go to the replicas and the AOF
+/// wrapped in `MULTI`/`EXEC`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Wrapping {
+    /// every write goes out on its own
+    Off,
+    /// `MULTI` goes out before the first write, nothing if none comes
+    Pending,
+    /// `MULTI` went out, `EXEC` has to follow
+    Started,
+}
+
+/// State of a single client connection, handed to every command it runs.
+#[derive(Debug)]
+pub struct Client {
+    /// unique and increasing for the lifetime of the server
+    pub id: u64,
+    pub name: Option<String>,
+    /// address of the peer, `None` for internal clients
+    pub addr: Option<SocketAddr>,
+    /// ACL user the client runs commands as, `None` for internal clients like
+    /// the one replaying the AOF which may do everything
+    pub user: Option<String>,
+    /// `AUTH` succeeded, or the user needs no password
+    pub authenticated: bool,
+    /// encoding of the replies, switched with `HELLO`
+    pub protocol: Protocol,
+    /// commands queued since `MULTI`, `None` outside of a transaction
+    pub multi: Option<Vec<Value>>,
+    /// a command was rejected while queueing, `EXEC` discards the transaction
+    pub multi_failed: bool,
+    /// database the commands run against, changed with `SELECT`
+    pub db: usize,
+    /// keys passed to `WATCH` since the last `EXEC`, `DISCARD` or `UNWATCH`,
+    /// along with the database they were watched in
+    pub watched: Vec<(usize, Vec<u8>)>,
+    /// encoded replies and pushes to write to the socket, `None` for clients
+    /// which aren't connected to anything like the one replaying the AOF
+    pub outbox: Option<Sender<Vec<u8>>>,
+    /// channels and patterns the client is subscribed to
+    pub channels: HashSet<String>,
+    pub patterns: HashSet<String>,
+    /// port a replica announced with `REPLCONF listening-port`
+    pub replica_port: Option<u16>,
+    /// the link to our master, what it sends is propagated as it was
+    /// received instead of command by command
+    pub is_master: bool,
+    /// set by a blocking command like `XREAD BLOCK` which has to wait, the
+    /// connection waits for it before reading the next command
+    pub blocked: Option<Blocking>,
+    /// blocking commands don't wait, e.g. while `EXEC` runs a transaction
+    pub deny_blocking: bool,
+    pub wrapping: Wrapping,
+}
+
+impl Client {
+    pub fn new(id: u64) -> Client {
+        Client {
+            id,
+            name: None,
+            addr: None,
+            user: None,
+            authenticated: true,
+            protocol: Protocol::Resp2,
+            multi: None,
+            multi_failed: false,
+            db: 0,
+            watched: Vec::new(),
+            outbox: None,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            replica_port: None,
+            is_master: false,
+            blocked: None,
+            deny_blocking: false,
+            wrapping: Wrapping::Off,
+        }
+    }
+
+    /// Queue `value` to be written to the client.
+    pub fn send(&self, value: &Value) {
+        if let Some(outbox) = &self.outbox {
+            // the connection is closing when its writer is gone
+            let _ = outbox.send(serialize(value, self.protocol));
+        }
+    }
+
+    /// Number of channels and patterns the client is subscribed to. A RESP2
+    /// client with subscriptions can only run the pub/sub commands.
+    pub fn subscriptions(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    /// Handle to deliver published messages to this client.
+    pub fn subscriber(&self) -> Option<Subscriber> {
+        self.outbox.clone().map(|outbox| Subscriber {
+            outbox,
+            protocol: self.protocol,
+        })
+    }
+}
+
+/// What other connections see of a client with `CLIENT LIST`, refreshed
+/// after each of its commands.
+#[derive(Debug)]
+pub struct ClientInfo {
+    pub id: u64,
+    pub addr: Option<SocketAddr>,
+    /// address of the socket on our side
+    pub laddr: Option<SocketAddr>,
+    pub name: Option<String>,
+    pub user: Option<String>,
+    pub protocol: Protocol,
+    pub db: usize,
+    /// unix time (ms) the connection was accepted
+    pub created_at: u64,
+    /// unix time (ms) of the last command
+    pub last_interaction: u64,
+    /// lowercase name of the last command, empty before the first one
+    pub last_command: String,
+    pub channels: usize,
+    pub patterns: usize,
+    /// commands queued since `MULTI`
+    pub multi: Option<usize>,
+    pub watched: usize,
+    /// `CLIENT KILL` shuts it down
+    connection: Option<TcpStream>,
+}
+
+impl ClientInfo {
+    /// Stop reading from the connection, the client is disconnected once
+    /// it's done with the command it runs and its replies are written.
+    pub fn kill(&self) {
+        if let Some(connection) = &self.connection {
+            let _ = connection.shutdown(Shutdown::Read);
+        }
+    }
+}
+
+/// Every connected client by id, so they are listed in the order they
+/// connected.
+#[derive(Debug, Default)]
+pub struct Clients {
+    clients: BTreeMap<u64, ClientInfo>,
+}
+
+impl Clients {
+    pub fn new() -> Clients {
+        Clients::default()
+    }
+
+    /// A new connection, `connection` is what `CLIENT KILL` closes.
+    pub fn register(&mut self, client: &Client, connection: Option<TcpStream>) {
+        let now = now_ms();
+        let info = ClientInfo {
+            id: client.id,
+            addr: client.addr,
+            laddr: connection.as_ref().and_then(|c| c.local_addr().ok()),
+            name: None,
+            user: None,
+            protocol: client.protocol,
+            db: client.db,
+            created_at: now,
+            last_interaction: now,
+            last_command: String::new(),
+            channels: 0,
+            patterns: 0,
+            multi: None,
+            watched: 0,
+            connection,
+        };
+        self.clients.insert(client.id, info);
+        self.update(client, None);
+    }
+
+    /// Refresh what is shown of `client` after it ran `command`. Clients
+    /// which aren't registered, like the one replaying the AOF, are ignored.
+    pub fn update(&mut self, client: &Client, command: Option<&str>) {
+        let info = match self.clients.get_mut(&client.id) {
+            Some(info) => info,
+            None => return,
+        };
+        info.name = client.name.clone();
+        info.user = client.user.clone();
+        info.protocol = client.protocol;
+        info.db = client.db;
+        info.channels = client.channels.len();
+        info.patterns = client.patterns.len();
+        info.multi = client.multi.as_ref().map(Vec::len);
+        info.watched = client.watched.len();
+        if let Some(command) = command {
+            info.last_command = command.to_lowercase();
+            info.last_interaction = now_ms();
+        }
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        self.clients.remove(&id);
+    }
+
+    pub fn get(&self, id: u64) -> Option<&ClientInfo> {
+        self.clients.get(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ClientInfo> {
+        self.clients.values()
+    }
+}
@@ -0,0 +1,279 @@
+use std::sync::Arc;
+
+use crate::acl::{User, DEFAULT_USER};
+use crate::client::Client;
+use crate::commands::table::{self, CATEGORIES};
+use crate::commands::{arg, wrong_arity, SYNTAX_ERROR};
+use crate::parser::Value;
+use crate::server::{lock, Server};
+
+pub const WRONGPASS: &str = "WRONGPASS invalid username-password pair or user is disabled.";
+
+/// Log the client in as `name`, used by `AUTH` and `HELLO ... AUTH`.
+pub fn login(server: &Arc<Server>, client: &mut Client, name: &str, password: &str) -> bool {
+    if !lock(&server.acl).authenticate(name, password) {
+        return false;
+    }
+    client.user = Some(name.to_string());
+    client.authenticated = true;
+    true
+}
+
+/// `AUTH [username] password`, without a username the `default` user is
+/// meant like with `requirepass`.
+pub fn auth_command(values: &[Value], server: &Arc<Server>, client: &mut Client) -> Value {
+    let (name, password) = match values.len() {
+        1 => (DEFAULT_USER.to_string(), arg(values, 0)),
+        2 => (arg(values, 0), arg(values, 1)),
+        _ => return Value::error(SYNTAX_ERROR),
+    };
+    if values.len() == 1 && lock(&server.acl).default_login() {
+        return Value::error("ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?");
+    }
+    match login(server, client, &name, &password) {
+        true => Value::ok(),
+        false => Value::error(WRONGPASS),
+    }
+}
+
+/// `ACL SETUSER | GETUSER | DELUSER | LIST | USERS | WHOAMI | CAT`
+pub fn acl_command(values: &[Value], server: &Arc<Server>, client: &Client) -> Value {
+    let subcommand = arg(values, 0).to_uppercase();
+    match subcommand.as_str() {
+        "SETUSER" if values.len() >= 2 => setuser(values, server),
+        "GETUSER" if values.len() == 2 => getuser(&arg(values, 1), server),
+        "DELUSER" if values.len() >= 2 => deluser(values, server),
+        "LIST" if values.len() == 1 => Value::array(
+            lock(&server.acl)
+                .users
+                .values()
+                .map(|user| Value::bulk_string(user.describe()))
+                .collect(),
+        ),
+        "USERS" if values.len() == 1 => Value::array(
+            lock(&server.acl)
+                .users
+                .keys()
+                .map(Value::bulk_string)
+                .collect(),
+        ),
+        "WHOAMI" if values.len() == 1 => {
+            Value::bulk_string(client.user.as_deref().unwrap_or(DEFAULT_USER))
+        }
+        "CAT" if values.len() <= 2 => cat(values),
+        "SETUSER" | "GETUSER" | "DELUSER" | "LIST" | "USERS" | "WHOAMI" | "CAT" => {
+            wrong_arity(&format!("acl|{}", subcommand.to_lowercase()))
+        }
+        _ => Value::error(&format!(
+            "ERR unknown subcommand '{}'. Try ACL HELP.",
+            arg(values, 0)
+        )),
+    }
+}
+
+/// `ACL SETUSER username [rule ...]` creates the user or applies the rules
+/// to it. When a rule is invalid the user is left as it was.
+fn setuser(values: &[Value], server: &Arc<Server>) -> Value {
+    let name = arg(values, 1);
+    if name.contains(|c: char| c.is_whitespace()) {
+        return Value::error("ERR Usernames can't contain spaces or null characters");
+    }
+    let mut acl = lock(&server.acl);
+    let mut user = acl
+        .users
+        .get(&name)
+        .cloned()
+        .unwrap_or_else(|| User::new(&name));
+    for i in 2..values.len() {
+        let rule = arg(values, i);
+        if let Err(e) = user.apply(&rule) {
+            return Value::error(&format!(
+                "ERR Error in ACL SETUSER modifier '{}': {}",
+                rule, e
+            ));
+        }
+    }
+    acl.users.insert(name, user);
+    Value::ok()
+}
+
+/// `ACL GETUSER username` describes the user, a null reply when it doesn't
+/// exist.
+fn getuser(name: &str, server: &Arc<Server>) -> Value {
+    let acl = lock(&server.acl);
+    let user = match acl.users.get(name) {
+        Some(user) => user,
+        None => return Value::null(),
+    };
+    let mut flags = vec![Value::bulk_string(if user.enabled { "on" } else { "off" })];
+    if user.nopass {
+        flags.push(Value::bulk_string("nopass"));
+    }
+    Value::map(vec![
+        (Value::bulk_string("flags"), Value::array(flags)),
+        (
+            Value::bulk_string("passwords"),
+            Value::array(user.passwords.iter().map(Value::bulk_string).collect()),
+        ),
+        (
+            Value::bulk_string("commands"),
+            Value::bulk_string(user.commands()),
+        ),
+        (Value::bulk_string("keys"), Value::bulk_string(user.keys())),
+    ])
+}
+
+/// `ACL DELUSER username [username ...]` replies how many users were deleted.
+fn deluser(values: &[Value], server: &Arc<Server>) -> Value {
+    let names: Vec<String> = (1..values.len()).map(|i| arg(values, i)).collect();
+    if names.iter().any(|name| name == DEFAULT_USER) {
+        return Value::error("ERR The 'default' user cannot be removed");
+    }
+    let mut acl = lock(&server.acl);
+    let deleted = names
+        .iter()
+        .filter(|name| acl.users.remove(*name).is_some())
+        .count();
+    Value::integer(deleted as i64)
+}
+
+/// `ACL CAT [category]` lists the categories, or the commands of one.
+fn cat(values: &[Value]) -> Value {
+    if values.len() == 1 {
+        return Value::array(CATEGORIES.iter().map(Value::bulk_string).collect());
+    }
+    let category = arg(values, 1).to_lowercase();
+    if !CATEGORIES.contains(&category.as_str()) {
+        return Value::error(&format!("ERR Unknown category '{}'", category));
+    }
+    Value::array(
+        table::commands()
+            .filter(|(group, command)| {
+                table::categories(group, command).contains(&category.as_str())
+            })
+            .map(|(_, command)| Value::bulk_string(command.name.to_lowercase()))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::Session;
+    use crate::config::Config;
+
+    /// (Re)connect `client`, logged in as `default` unless it has a password.
+    fn connect(session: &mut Session, client: usize) {
+        let id = session.clients[client].id;
+        session.clients[client] = Client::new(id);
+        session.clients[client].user = Some(DEFAULT_USER.to_string());
+        session.clients[client].authenticated = lock(&session.server.acl).default_login();
+    }
+
+    #[test]
+    fn test_requirepass() {
+        let config = Config {
+            requirepass: "secret".to_string(),
+            ..Config::default()
+        };
+        let mut session = Session::with_config(config, 1);
+        connect(&mut session, 0);
+
+        assert_eq!(
+            session.run(0, &["GET", "k"]),
+            "-NOAUTH Authentication required.\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["AUTH", "wrong"]),
+            format!("-{}\r\n", WRONGPASS)
+        );
+        assert_eq!(session.run(0, &["AUTH", "secret"]), "+OK\r\n");
+        assert_eq!(session.run(0, &["GET", "k"]), "$-1\r\n");
+        assert_eq!(session.run(0, &["ACL", "WHOAMI"]), "$7\r\ndefault\r\n");
+
+        // HELLO can log in while switching protocols
+        connect(&mut session, 0);
+        assert!(session.run(0, &["HELLO", "3"]).starts_with("-NOAUTH"));
+        assert!(session
+            .run(0, &["HELLO", "3", "AUTH", "default", "secret"])
+            .starts_with("%7"));
+        assert_eq!(session.run(0, &["PING"]), "+PONG\r\n");
+    }
+
+    #[test]
+    fn test_acl_users() {
+        let mut session = Session::new(2);
+        connect(&mut session, 0);
+        assert_eq!(
+            session.run(0, &["AUTH", "secret"]),
+            "-ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?\r\n"
+        );
+
+        assert_eq!(
+            session.run(
+                0,
+                &["ACL", "SETUSER", "alice", "on", ">pw", "~cache:*", "+get"]
+            ),
+            "+OK\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["ACL", "SETUSER", "alice", "+nope"]),
+            "-ERR Error in ACL SETUSER modifier '+nope': Unknown command or category name in ACL\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["ACL", "USERS"]),
+            "*2\r\n$5\r\nalice\r\n$7\r\ndefault\r\n"
+        );
+        assert!(session.run(0, &["ACL", "LIST"])
+            .contains("user alice on #30c952fab122c3f9759f02a6d95c3758b246b4fee239957b2d4fee46e26170c4 ~cache:* -@all +get"));
+        assert_eq!(
+            session.run(0, &["ACL", "GETUSER", "alice"]),
+            "*8\r\n$5\r\nflags\r\n*1\r\n$2\r\non\r\n\
+             $9\r\npasswords\r\n*1\r\n$64\r\n30c952fab122c3f9759f02a6d95c3758b246b4fee239957b2d4fee46e26170c4\r\n\
+             $8\r\ncommands\r\n$10\r\n-@all +get\r\n$4\r\nkeys\r\n$8\r\n~cache:*\r\n"
+        );
+        assert_eq!(session.run(0, &["ACL", "GETUSER", "bob"]), "$-1\r\n");
+
+        connect(&mut session, 1);
+        assert_eq!(
+            session.run(1, &["AUTH", "alice", "nope"]),
+            format!("-{}\r\n", WRONGPASS)
+        );
+        assert_eq!(session.run(1, &["AUTH", "alice", "pw"]), "+OK\r\n");
+        assert_eq!(session.run(1, &["GET", "cache:1"]), "$-1\r\n");
+        assert_eq!(
+            session.run(1, &["GET", "secret"]),
+            "-NOPERM No permissions to access a key\r\n"
+        );
+        assert_eq!(
+            session.run(1, &["SET", "cache:1", "v"]),
+            "-NOPERM User alice has no permissions to run the 'set' command\r\n"
+        );
+
+        // rejected commands abort a transaction like any other error
+        session.run(0, &["ACL", "SETUSER", "alice", "+multi", "+exec"]);
+        session.run(1, &["MULTI"]);
+        session.run(1, &["SET", "cache:1", "v"]);
+        assert!(session.run(1, &["EXEC"]).starts_with("-EXECABORT"));
+
+        assert_eq!(
+            session.run(0, &["ACL", "DELUSER", "default"]),
+            "-ERR The 'default' user cannot be removed\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["ACL", "DELUSER", "alice", "bob"]),
+            ":1\r\n"
+        );
+        assert_eq!(
+            session.run(1, &["GET", "cache:1"]),
+            "-NOAUTH Authentication required.\r\n"
+        );
+
+        assert!(session.run(0, &["ACL", "CAT"]).contains("sortedset"));
+        assert!(session.run(0, &["ACL", "CAT", "hash"]).contains("hgetall"));
+        assert_eq!(
+            session.run(0, &["ACL", "CAT", "nope"]),
+            "-ERR Unknown category 'nope'\r\n"
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use std::sync::Arc;
+
+use crate::commands::{arg, wrong_arity};
+use crate::config::PARAMETERS;
+use crate::dictionary_server::DictionaryServer;
+use crate::glob::glob_match;
+use crate::parser::Value;
+use crate::server::{lock, Server};
+
+/// `CONFIG GET parameter [parameter ...] | SET parameter value [parameter
+/// value ...] | REWRITE`
+pub fn config_command(values: &[Value], server: &Arc<Server>, map: &mut DictionaryServer) -> Value {
+    match arg(values, 0).to_uppercase().as_str() {
+        "GET" if values.len() >= 2 => config_get(&values[1..], server),
+        "GET" => wrong_arity("config|get"),
+        "SET" if values.len() >= 3 && !values.len().is_multiple_of(2) => {
+            config_set(&values[1..], server, map)
+        }
+        "SET" => wrong_arity("config|set"),
+        "REWRITE" if values.len() == 1 => config_rewrite(server),
+        "REWRITE" => wrong_arity("config|rewrite"),
+        _ => Value::error(&format!(
+            "ERR unknown subcommand '{}'. Try CONFIG HELP.",
+            arg(values, 0)
+        )),
+    }
+}
+
+/// Name and value of every parameter matching one of the glob style
+/// patterns.
+fn config_get(patterns: &[Value], server: &Arc<Server>) -> Value {
+    let config = server.config();
+    let patterns: Vec<String> = (0..patterns.len())
+        .map(|i| arg(patterns, i).to_lowercase())
+        .collect();
+    let pairs = PARAMETERS
+        .iter()
+        .filter(|name| {
+            patterns
+                .iter()
+                .any(|pattern| glob_match(pattern.as_bytes(), name.as_bytes()))
+        })
+        .map(|name| {
+            let value = config.get(name).unwrap_or_default();
+            (Value::bulk_string(name), Value::bulk_string(value))
+        })
+        .collect();
+    Value::map(pairs)
+}
+
+/// Either every parameter is changed or, when one of them is invalid, none
+/// is. Turning `appendonly` on writes the current dataset to a new append only
+/// file first, `requirepass` becomes the password of the `default` user.
+fn config_set(pairs: &[Value], server: &Arc<Server>, map: &mut DictionaryServer) -> Value {
+    let mut config = server.config().clone();
+    for i in (0..pairs.len()).step_by(2) {
+        let name = arg(pairs, i).to_lowercase();
+        if let Err(e) = config.update(&name, &arg(pairs, i + 1)) {
+            return set_failed(&name, &e);
+        }
+    }
+
+    let appendonly = config.appendonly;
+    let requirepass = config.requirepass.clone();
+    let backlog_size = config.repl_backlog_size as usize;
+    let previous = std::mem::replace(&mut *server.config(), config);
+    if appendonly != previous.appendonly {
+        let switched = if appendonly {
+            server.start_aof(map)
+        } else {
+            lock(&server.aof).close()
+        };
+        if let Err(e) = switched {
+            *server.config() = previous;
+            return set_failed("appendonly", &e.to_string());
+        }
+    }
+    if requirepass != previous.requirepass {
+        lock(&server.acl).set_requirepass(&requirepass);
+    }
+    lock(&server.replication).set_backlog_size(backlog_size);
+    Value::ok()
+}
+
+fn set_failed(name: &str, reason: &str) -> Value {
+    Value::error(&format!(
+        "ERR CONFIG SET failed (possibly related to argument '{}') - {}",
+        name, reason
+    ))
+}
+
+/// Write the current settings back to the configuration file.
+fn config_rewrite(server: &Arc<Server>) -> Value {
+    let config = server.config().clone();
+    if config.file.is_none() {
+        return Value::error("ERR The server is running without a config file");
+    }
+    match config.rewrite() {
+        Ok(()) => Value::ok(),
+        Err(e) => Value::error(&format!("ERR Rewriting config file: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use super::*;
+    use crate::commands::test::Session;
+    use crate::config::Config;
+
+    #[test]
+    fn test_config_get_and_set() {
+        let dir = env::temp_dir().join(format!("redis-config-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = Config {
+            dir: dir.to_string_lossy().to_string(),
+            ..Config::default()
+        };
+        let mut session = Session::with_config(config, 1);
+
+        assert_eq!(
+            session.run(0, &["CONFIG", "GET", "port"]),
+            "*2\r\n$4\r\nport\r\n$4\r\n6379\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["CONFIG", "GET", "maxmemory*"]),
+            "*6\r\n$9\r\nmaxmemory\r\n$1\r\n0\r\n\
+             $16\r\nmaxmemory-policy\r\n$10\r\nnoeviction\r\n\
+             $17\r\nmaxmemory-samples\r\n$1\r\n5\r\n"
+        );
+
+        assert_eq!(
+            session.run(0, &["CONFIG", "SET", "maxmemory", "1mb", "save", "60 100"]),
+            "+OK\r\n"
+        );
+        assert_eq!(session.server.config().maxmemory, 1024 * 1024);
+        assert_eq!(
+            session.run(0, &["CONFIG", "GET", "save"]),
+            "*2\r\n$4\r\nsave\r\n$6\r\n60 100\r\n"
+        );
+
+        // nothing changes when one of the parameters is wrong
+        assert_eq!(
+            session.run(0, &["CONFIG", "SET", "maxclients", "10", "maxmemory", "lots"]
+            ),
+            "-ERR CONFIG SET failed (possibly related to argument 'maxmemory') - argument must be a memory value for 'maxmemory'\r\n"
+        );
+        assert_eq!(session.server.config().maxclients, 10000);
+        assert!(session
+            .run(0, &["CONFIG", "SET", "port", "7000"])
+            .contains("can't set immutable config"));
+        assert!(session
+            .run(0, &["CONFIG", "SET", "databases", "4"])
+            .contains("can't set immutable config"));
+        assert!(session
+            .run(0, &["CONFIG", "SET", "nope", "1"])
+            .contains("Unknown option"));
+        assert_eq!(
+            session.run(0, &["CONFIG", "SET", "port"]),
+            "-ERR wrong number of arguments for 'config|set' command\r\n"
+        );
+
+        // switching the append only file on writes the dataset into it
+        session.run(0, &["SET", "k", "v"]);
+        session.run(0, &["CONFIG", "SET", "appendonly", "yes"]);
+        assert!(lock(&session.server.aof).is_open());
+        let log = fs::read(dir.join("appendonly.aof")).unwrap();
+        assert!(String::from_utf8(log)
+            .unwrap()
+            .contains("$1\r\nk\r\n$1\r\nv\r\n"));
+        session.run(0, &["CONFIG", "SET", "appendonly", "no"]);
+        assert!(!lock(&session.server.aof).is_open());
+
+        assert_eq!(
+            session.run(0, &["CONFIG", "REWRITE"]),
+            "-ERR The server is running without a config file\r\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,452 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use crate::client::{Client, ClientInfo};
+use crate::commands::{acl, arg, arg_bytes, wrong_arity, SYNTAX_ERROR};
+use crate::dictionary_server::{now_ms, DictionaryServer};
+use crate::parser::{Protocol, Value};
+use crate::server::{lock, Server};
+
+/// Below method replies the `PING` command sent by redis client, `PING message`
+/// replies the message instead of `PONG`. RESP2 clients in subscriber mode
+/// get the reply as a push so it can't be mistaken for a published message.
+pub fn ping_command(values: &[Value], client: &Client) -> Value {
+    if values.len() > 1 {
+        return wrong_arity("ping");
+    }
+    if client.protocol == Protocol::Resp2 && client.subscriptions() > 0 {
+        return Value::push(vec![
+            Value::bulk_string("pong"),
+            Value::bulk_string(arg_bytes(values, 0)),
+        ]);
+    }
+    match values.len() {
+        0 => Value::simple_string("PONG"),
+        _ => Value::bulk_string(arg_bytes(values, 0)),
+    }
+}
+
+/// `ECHO message` replies the message as it was sent.
+pub fn echo_command(values: &[Value]) -> Value {
+    Value::bulk_string(arg_bytes(values, 0))
+}
+
+/// `HELLO [protover [AUTH username password] [SETNAME clientname]]` switches
+/// the connection to the requested protocol version and replies a map
+/// describing the server, already encoded with the new protocol. Clients
+/// which didn't authenticate yet have to pass `AUTH`.
+pub fn hello_command(values: &[Value], server: &Arc<Server>, client: &mut Client) -> Value {
+    let mut protocol = client.protocol;
+    if !values.is_empty() {
+        protocol = match arg(values, 0).parse::<i64>() {
+            Ok(2) => Protocol::Resp2,
+            Ok(3) => Protocol::Resp3,
+            Ok(_) => return Value::error("NOPROTO unsupported protocol version"),
+            Err(_) => {
+                return Value::error("ERR Protocol version is not an integer or out of range")
+            }
+        };
+    }
+
+    let mut name = None;
+    let mut credentials = None;
+    let mut i = 1;
+    while i < values.len() {
+        match arg(values, i).to_uppercase().as_str() {
+            "AUTH" if i + 2 < values.len() => {
+                credentials = Some((arg(values, i + 1), arg(values, i + 2)));
+                i += 3;
+            }
+            "SETNAME" if i + 1 < values.len() => {
+                let new_name = arg(values, i + 1);
+                if let Err(error) = check_client_name(&new_name) {
+                    return error;
+                }
+                name = Some(new_name);
+                i += 2;
+            }
+            _ => return Value::error(SYNTAX_ERROR),
+        }
+    }
+
+    match credentials {
+        Some((user, password)) if !acl::login(server, client, &user, &password) => {
+            return Value::error(acl::WRONGPASS)
+        }
+        None if !client.authenticated => return Value::error("NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time"),
+        _ => {}
+    }
+
+    client.protocol = protocol;
+    if name.is_some() {
+        client.name = name;
+    }
+    Value::map(vec![
+        (Value::bulk_string("server"), Value::bulk_string("redis")),
+        (
+            Value::bulk_string("version"),
+            Value::bulk_string(env!("CARGO_PKG_VERSION")),
+        ),
+        (
+            Value::bulk_string("proto"),
+            Value::integer(protocol.version()),
+        ),
+        (Value::bulk_string("id"), Value::integer(client.id as i64)),
+        (Value::bulk_string("mode"), Value::bulk_string("standalone")),
+        (Value::bulk_string("role"), Value::bulk_string("master")),
+        (Value::bulk_string("modules"), Value::array(Vec::new())),
+    ])
+}
+
+/// Client names are shown by `CLIENT LIST`, which separates its fields
+/// with spaces.
+fn check_client_name(name: &str) -> Result<(), Value> {
+    match name.chars().any(|c| !c.is_ascii_graphic()) {
+        true => Err(Value::error(
+            "ERR Client names cannot contain spaces, newlines or special characters.",
+        )),
+        false => Ok(()),
+    }
+}
+
+/// Kind of connection as `CLIENT LIST TYPE` and `CLIENT KILL TYPE` filter
+/// them, the link to our master isn't registered so it never matches.
+fn client_type(info: &ClientInfo, replicas: &BTreeSet<u64>) -> &'static str {
+    if replicas.contains(&info.id) {
+        "replica"
+    } else if info.channels + info.patterns > 0 {
+        "pubsub"
+    } else {
+        "normal"
+    }
+}
+
+fn parse_client_type(name: &str) -> Result<&'static str, Value> {
+    match name.to_lowercase().as_str() {
+        "normal" => Ok("normal"),
+        "replica" | "slave" => Ok("replica"),
+        "pubsub" => Ok("pubsub"),
+        "master" => Ok("master"),
+        _ => Err(Value::error(&format!("ERR Unknown client type '{}'", name))),
+    }
+}
+
+/// A line of `CLIENT LIST`, e.g. `id=3 addr=127.0.0.1:50422 ... cmd=get`.
+fn describe_client(
+    info: &ClientInfo,
+    replicas: &BTreeSet<u64>,
+    map: &DictionaryServer,
+    now: u64,
+) -> String {
+    let mut flags = String::new();
+    if replicas.contains(&info.id) {
+        flags.push('S');
+    }
+    if info.channels + info.patterns > 0 {
+        flags.push('P');
+    }
+    if info.multi.is_some() {
+        flags.push('x');
+    }
+    if map.blocked.waiter(info.id).is_some() {
+        flags.push('b');
+    }
+    if flags.is_empty() {
+        flags.push('N');
+    }
+    let addr = |addr: Option<std::net::SocketAddr>| addr.map(|a| a.to_string()).unwrap_or_default();
+    format!(
+        "id={} addr={} laddr={} name={} age={} idle={} flags={} db={} sub={} psub={} multi={} watch={} cmd={} user={} resp={}\n",
+        info.id,
+        addr(info.addr),
+        addr(info.laddr),
+        info.name.as_deref().unwrap_or_default(),
+        now.saturating_sub(info.created_at) / 1000,
+        now.saturating_sub(info.last_interaction) / 1000,
+        flags,
+        info.db,
+        info.channels,
+        info.patterns,
+        info.multi.map_or(-1, |queued| queued as i64),
+        info.watched,
+        match info.last_command.as_str() {
+            "" => "NULL",
+            command => command,
+        },
+        info.user.as_deref().unwrap_or("default"),
+        info.protocol.version(),
+    )
+}
+
+fn replica_ids(server: &Arc<Server>) -> BTreeSet<u64> {
+    lock(&server.replication).replicas.keys().copied().collect()
+}
+
+/// `CLIENT LIST [TYPE type] [ID id [id ...]]`
+fn client_list(values: &[Value], server: &Arc<Server>, map: &DictionaryServer) -> Value {
+    let mut kind = None;
+    let mut ids = None;
+    let filter = values.first().map(|_| arg(values, 0).to_uppercase());
+    match filter.as_deref() {
+        None => {}
+        Some("TYPE") if values.len() == 2 => {
+            kind = Some(try_reply!(parse_client_type(&arg(values, 1))));
+        }
+        Some("ID") if values.len() > 1 => {
+            let mut wanted = BTreeSet::new();
+            for i in 1..values.len() {
+                match arg(values, i).parse::<u64>() {
+                    Ok(id) if id > 0 => wanted.insert(id),
+                    _ => return Value::error("ERR Invalid client ID"),
+                };
+            }
+            ids = Some(wanted);
+        }
+        Some(_) => return Value::error(SYNTAX_ERROR),
+    }
+
+    let replicas = replica_ids(server);
+    let now = now_ms();
+    let list: String = lock(&server.clients)
+        .iter()
+        .filter(|info| kind.is_none_or(|kind| client_type(info, &replicas) == kind))
+        .filter(|info| ids.as_ref().is_none_or(|ids| ids.contains(&info.id)))
+        .map(|info| describe_client(info, &replicas, map, now))
+        .collect();
+    Value::bulk_string(list)
+}
+
+/// `CLIENT KILL addr` closes the client connected from `addr`, `CLIENT KILL
+/// filter value ...` every client matching all of the filters and replies
+/// how many there were. The client itself is spared unless `SKIPME no` is
+/// given.
+fn client_kill(values: &[Value], server: &Arc<Server>, client: &Client) -> Value {
+    let old_form = values.len() == 1;
+    let mut id = None;
+    let mut addr = None;
+    let mut laddr = None;
+    let mut user = None;
+    let mut kind = None;
+    let mut skip_me = true;
+    if old_form {
+        addr = Some(arg(values, 0));
+    } else {
+        if !values.len().is_multiple_of(2) {
+            return Value::error(SYNTAX_ERROR);
+        }
+        for pair in values.chunks(2) {
+            let value = arg(pair, 1);
+            match arg(pair, 0).to_uppercase().as_str() {
+                "ID" => match value.parse::<u64>() {
+                    Ok(parsed) if parsed > 0 => id = Some(parsed),
+                    _ => return Value::error("ERR client-id should be greater than 0"),
+                },
+                "ADDR" => addr = Some(value),
+                "LADDR" => laddr = Some(value),
+                "USER" => user = Some(value),
+                "TYPE" => kind = Some(try_reply!(parse_client_type(&value))),
+                "SKIPME" => match value.to_lowercase().as_str() {
+                    "yes" => skip_me = true,
+                    "no" => skip_me = false,
+                    _ => return Value::error(SYNTAX_ERROR),
+                },
+                _ => return Value::error(SYNTAX_ERROR),
+            }
+        }
+    }
+
+    let replicas = replica_ids(server);
+    let clients = lock(&server.clients);
+    let matches = |info: &&ClientInfo| {
+        let address = |addr: Option<std::net::SocketAddr>| addr.map(|a| a.to_string());
+        id.is_none_or(|id| info.id == id)
+            && addr
+                .as_ref()
+                .is_none_or(|addr| address(info.addr).as_ref() == Some(addr))
+            && laddr
+                .as_ref()
+                .is_none_or(|laddr| address(info.laddr).as_ref() == Some(laddr))
+            && user
+                .as_deref()
+                .is_none_or(|user| info.user.as_deref().unwrap_or("default") == user)
+            && kind.is_none_or(|kind| client_type(info, &replicas) == kind)
+            && !(skip_me && !old_form && info.id == client.id)
+    };
+    let mut killed = 0;
+    for info in clients.iter().filter(matches) {
+        info.kill();
+        killed += 1;
+    }
+    match old_form {
+        true if killed == 0 => Value::error("ERR No such client"),
+        true => Value::ok(),
+        false => Value::integer(killed),
+    }
+}
+
+/// `CLIENT ID | INFO | LIST | GETNAME | SETNAME name | KILL ...` inspects
+/// and manages the connected clients.
+pub fn client_command(
+    values: &[Value],
+    server: &Arc<Server>,
+    client: &mut Client,
+    map: &DictionaryServer,
+) -> Value {
+    let subcommand = arg(values, 0).to_uppercase();
+    match subcommand.as_str() {
+        "ID" if values.len() == 1 => Value::integer(client.id as i64),
+        "GETNAME" if values.len() == 1 => match &client.name {
+            Some(name) => Value::bulk_string(name.as_str()),
+            None => Value::null(),
+        },
+        "SETNAME" if values.len() == 2 => {
+            let name = arg(values, 1);
+            try_reply!(check_client_name(&name));
+            client.name = Some(name).filter(|name| !name.is_empty());
+            Value::ok()
+        }
+        "INFO" if values.len() == 1 => {
+            let replicas = replica_ids(server);
+            let mut clients = lock(&server.clients);
+            clients.update(client, Some("client"));
+            match clients.get(client.id) {
+                Some(info) => Value::bulk_string(describe_client(info, &replicas, map, now_ms())),
+                None => Value::null(),
+            }
+        }
+        "LIST" => {
+            lock(&server.clients).update(client, Some("client"));
+            client_list(&values[1..], server, map)
+        }
+        "KILL" if values.len() > 1 => client_kill(&values[1..], server, client),
+        "ID" | "GETNAME" | "SETNAME" | "INFO" | "KILL" => {
+            wrong_arity(&format!("client|{}", subcommand.to_lowercase()))
+        }
+        _ => Value::error(&format!(
+            "ERR unknown subcommand '{}'. Try CLIENT HELP.",
+            arg(values, 0)
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::client::Client;
+    use crate::commands::test::Session;
+    use crate::parser::Protocol;
+    use crate::server::lock;
+
+    #[test]
+    fn test_hello_switches_protocol() {
+        let mut session = Session::new(1);
+        let reply = session.run(0, &["HELLO"]);
+        assert!(reply.starts_with("*14\r\n$6\r\nserver\r\n$5\r\nredis\r\n"));
+        assert!(reply.contains("$5\r\nproto\r\n:2\r\n$2\r\nid\r\n:1\r\n"));
+
+        let reply = session.run(0, &["HELLO", "3", "SETNAME", "worker"]);
+        assert!(reply.starts_with("%7\r\n"));
+        assert!(reply.contains("$5\r\nproto\r\n:3\r\n"));
+        assert_eq!(session.clients[0].protocol, Protocol::Resp3);
+        assert_eq!(session.clients[0].name, Some("worker".to_string()));
+
+        assert_eq!(
+            session.run(0, &["HELLO", "4"]),
+            "-NOPROTO unsupported protocol version\r\n"
+        );
+        assert_eq!(session.clients[0].protocol, Protocol::Resp3);
+        assert!(session.run(0, &["HELLO", "2"]).starts_with("*14\r\n"));
+        assert_eq!(session.clients[0].protocol, Protocol::Resp2);
+    }
+
+    #[test]
+    fn test_hello_options() {
+        let mut session = Session::new(1);
+        assert!(session
+            .run(0, &["HELLO", "3", "AUTH", "default", "pw"])
+            .starts_with('%'));
+        assert_eq!(
+            session.run(0, &["HELLO", "2", "AUTH", "alice", "pw"]),
+            "-WRONGPASS invalid username-password pair or user is disabled.\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["HELLO", "two"]),
+            "-ERR Protocol version is not an integer or out of range\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["HELLO", "3", "SETNAME"]),
+            "-ERR syntax error\r\n"
+        );
+        assert_eq!(session.clients[0].protocol, Protocol::Resp3);
+    }
+
+    #[test]
+    fn test_client_command() {
+        let mut session = Session::new(0);
+        session.clients = vec![Client::new(4), Client::new(9)];
+        session.clients[1].addr = Some("127.0.0.1:5000".parse().unwrap());
+        // subscribing replies through the outbox
+        let _inbox = session.connect(1);
+        for client in &session.clients {
+            lock(&session.server.clients).register(client, None);
+        }
+
+        assert_eq!(session.run(0, &["CLIENT", "ID"]), ":4\r\n");
+        assert_eq!(session.run(0, &["CLIENT", "GETNAME"]), "$-1\r\n");
+        assert_eq!(
+            session.run(0, &["CLIENT", "SETNAME", "my name"]),
+            "-ERR Client names cannot contain spaces, newlines or special characters.\r\n"
+        );
+        assert_eq!(session.run(0, &["CLIENT", "SETNAME", "app"]), "+OK\r\n");
+        session.run(0, &["SELECT", "5"]);
+        assert_eq!(session.run(0, &["CLIENT", "GETNAME"]), "$3\r\napp\r\n");
+        session.run(1, &["SUBSCRIBE", "news"]);
+
+        let list = session.run(0, &["CLIENT", "LIST"]);
+        let lines: Vec<&str> = list
+            .split('\n')
+            .filter(|line| line.contains("id="))
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("id=4 addr= laddr= name=app "));
+        assert!(lines[0].contains(" flags=N db=5 sub=0 psub=0 multi=-1 watch=0 cmd=client "));
+        assert!(lines[1].contains("id=9 addr=127.0.0.1:5000 "));
+        assert!(lines[1].contains(" flags=P db=0 sub=1 "));
+        assert!(lines[1].contains(" cmd=subscribe user=default resp=2"));
+
+        let list = session.run(0, &["CLIENT", "LIST", "TYPE", "pubsub"]);
+        assert!(list.contains("id=9 ") && !list.contains("id=4 "));
+        let list = session.run(0, &["CLIENT", "LIST", "ID", "4", "5"]);
+        assert!(list.contains("id=4 ") && !list.contains("id=9 "));
+        assert!(session.run(0, &["CLIENT", "INFO"]).contains("id=4 "));
+        assert_eq!(
+            session.run(0, &["CLIENT", "LIST", "TYPE", "nobody"]),
+            "-ERR Unknown client type 'nobody'\r\n"
+        );
+
+        assert_eq!(
+            session.run(0, &["CLIENT", "KILL", "127.0.0.1:6000"]),
+            "-ERR No such client\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["CLIENT", "KILL", "127.0.0.1:5000"]),
+            "+OK\r\n"
+        );
+        assert_eq!(session.run(0, &["CLIENT", "KILL", "ID", "4"]), ":0\r\n");
+        assert_eq!(
+            session.run(0, &["CLIENT", "KILL", "ID", "4", "SKIPME", "no"]),
+            ":1\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["CLIENT", "KILL", "USER", "default"]),
+            ":1\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["CLIENT", "KILL", "ID", "0"]),
+            "-ERR client-id should be greater than 0\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["CLIENT", "NOPE"]),
+            "-ERR unknown subcommand 'NOPE'. Try CLIENT HELP.\r\n"
+        );
+    }
+}
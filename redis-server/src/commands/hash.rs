@@ -0,0 +1,196 @@
+use crate::commands::{arg, arg_bytes, parse_int, wrong_arity};
+use crate::dictionary_server::DictionaryServer;
+use crate::parser::Value;
+
+/// `HSET key field value [field value ...]` replies the number of fields that
+/// were added, updated ones don't count.
+pub fn hset_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    if values.len().is_multiple_of(2) {
+        return wrong_arity("hset");
+    }
+    let key = arg_bytes(values, 0);
+    let hash = try_reply!(map.hash_mut(&key, true)).unwrap();
+    let mut added = 0;
+    for i in (1..values.len()).step_by(2) {
+        if hash
+            .insert(arg_bytes(values, i), arg_bytes(values, i + 1))
+            .is_none()
+        {
+            added += 1;
+        }
+    }
+    map.modified(&key);
+    Value::integer(added)
+}
+
+/// `HGET key field` value of the field, nil if either is missing.
+pub fn hget_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let hash = try_reply!(map.hash_mut(&key, false));
+    match hash.and_then(|hash| hash.get(&arg_bytes(values, 1))) {
+        Some(value) => Value::bulk_string(value),
+        None => Value::null(),
+    }
+}
+
+/// `HDEL key field [field ...]` replies how many fields were removed.
+pub fn hdel_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let hash = match try_reply!(map.hash_mut(&key, false)) {
+        Some(hash) => hash,
+        None => return Value::integer(0),
+    };
+    let removed = (1..values.len())
+        .filter(|i| hash.remove(&arg_bytes(values, *i)).is_some())
+        .count();
+    if removed > 0 {
+        map.modified(&key);
+        map.remove_if_empty(&key);
+    }
+    Value::integer(removed as i64)
+}
+
+/// `HGETALL key` every field followed by its value.
+pub fn hgetall_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let hash = try_reply!(map.hash_mut(&key, false));
+    let pairs = hash
+        .map(|hash| {
+            hash.iter()
+                .map(|(field, value)| (Value::bulk_string(field), Value::bulk_string(value)))
+                .collect()
+        })
+        .unwrap_or_default();
+    Value::map(pairs)
+}
+
+/// `HINCRBY key field increment` adds to the integer stored in the field, a
+/// missing field counts as 0. Replies the new value.
+pub fn hincrby_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let field = arg_bytes(values, 1);
+    let increment = try_reply!(parse_int(&arg(values, 2)));
+
+    // check the type before creating the hash so a failing command leaves
+    // no empty key behind
+    let hash = try_reply!(map.hash_mut(&key, false));
+    let current = match hash.and_then(|hash| hash.get(&field)) {
+        Some(value) => match std::str::from_utf8(value).map(str::parse::<i64>) {
+            Ok(Ok(current)) => current,
+            _ => return Value::error("ERR hash value is not an integer"),
+        },
+        None => 0,
+    };
+    let updated = match current.checked_add(increment) {
+        Some(updated) => updated,
+        None => return Value::error("ERR increment or decrement would overflow"),
+    };
+
+    let hash = try_reply!(map.hash_mut(&key, true)).unwrap();
+    hash.insert(field, updated.to_string().into_bytes());
+    map.modified(&key);
+    Value::integer(updated)
+}
+
+/// `HKEYS key` every field of the hash.
+pub fn hkeys_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let hash = try_reply!(map.hash_mut(&key, false));
+    let fields = hash
+        .map(|hash| hash.keys().map(Value::bulk_string).collect())
+        .unwrap_or_default();
+    Value::array(fields)
+}
+
+/// `HVALS key` every value of the hash.
+pub fn hvals_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let hash = try_reply!(map.hash_mut(&key, false));
+    let hash_values = hash
+        .map(|hash| hash.values().map(Value::bulk_string).collect())
+        .unwrap_or_default();
+    Value::array(hash_values)
+}
+
+/// `HLEN key` number of fields in the hash.
+pub fn hlen_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let len = try_reply!(map.hash_mut(&key, false)).map_or(0, |hash| hash.len());
+    Value::integer(len as i64)
+}
+
+/// `HEXISTS key field` 1 if the field exists.
+pub fn hexists_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let hash = try_reply!(map.hash_mut(&key, false));
+    let exists = hash.is_some_and(|hash| hash.contains_key(&arg_bytes(values, 1)));
+    Value::integer(exists as i64)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commands::test::run;
+    use crate::dictionary_server::DictionaryServer;
+
+    #[test]
+    fn test_hset_hget_hdel() {
+        let mut map = DictionaryServer::new();
+        assert_eq!(run(&mut map, &["HSET", "h", "a", "1", "b", "2"]), ":2\r\n");
+        assert_eq!(run(&mut map, &["HSET", "h", "a", "3", "c", "4"]), ":1\r\n");
+        assert_eq!(run(&mut map, &["HGET", "h", "a"]), "$1\r\n3\r\n");
+        assert_eq!(run(&mut map, &["HGET", "h", "x"]), "$-1\r\n");
+        assert_eq!(run(&mut map, &["HGET", "missing", "x"]), "$-1\r\n");
+        assert_eq!(run(&mut map, &["HLEN", "h"]), ":3\r\n");
+        assert_eq!(run(&mut map, &["HEXISTS", "h", "b"]), ":1\r\n");
+        assert_eq!(run(&mut map, &["HEXISTS", "h", "x"]), ":0\r\n");
+        assert_eq!(run(&mut map, &["HDEL", "h", "a", "b", "x"]), ":2\r\n");
+        assert_eq!(
+            run(&mut map, &["HGETALL", "h"]),
+            "*2\r\n$1\r\nc\r\n$1\r\n4\r\n"
+        );
+        assert_eq!(run(&mut map, &["HKEYS", "h"]), "*1\r\n$1\r\nc\r\n");
+        assert_eq!(run(&mut map, &["HVALS", "h"]), "*1\r\n$1\r\n4\r\n");
+        assert_eq!(run(&mut map, &["HDEL", "h", "c"]), ":1\r\n");
+        assert!(map.server.is_empty());
+        assert_eq!(
+            run(&mut map, &["HSET", "h", "a"]),
+            "-ERR wrong number of arguments for 'hset' command\r\n"
+        );
+    }
+
+    #[test]
+    fn test_hincrby() {
+        let mut map = DictionaryServer::new();
+        assert_eq!(run(&mut map, &["HINCRBY", "h", "n", "5"]), ":5\r\n");
+        assert_eq!(run(&mut map, &["HINCRBY", "h", "n", "-7"]), ":-2\r\n");
+        run(
+            &mut map,
+            &["HSET", "h", "s", "abc", "max", "9223372036854775807"],
+        );
+        assert_eq!(
+            run(&mut map, &["HINCRBY", "h", "s", "1"]),
+            "-ERR hash value is not an integer\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["HINCRBY", "h", "max", "1"]),
+            "-ERR increment or decrement would overflow\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["HINCRBY", "other", "n", "x"]),
+            "-ERR value is not an integer or out of range\r\n"
+        );
+        assert!(!map.server.contains_key(b"other".as_slice()));
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        let mut map = DictionaryServer::new();
+        run(&mut map, &["RPUSH", "q", "a"]);
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        assert_eq!(run(&mut map, &["HSET", "q", "a", "1"]), wrongtype);
+        assert_eq!(run(&mut map, &["HGETALL", "q"]), wrongtype);
+        assert_eq!(run(&mut map, &["HINCRBY", "q", "a", "1"]), wrongtype);
+        run(&mut map, &["HSET", "h", "a", "1"]);
+        assert_eq!(run(&mut map, &["LLEN", "h"]), wrongtype);
+    }
+}
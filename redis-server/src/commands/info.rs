@@ -0,0 +1,255 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use crate::commands::arg;
+use crate::dictionary_server::{now_ms, DictionaryServer};
+use crate::parser::Value;
+use crate::server::{lock, Server};
+
+/// Sections of `INFO` in the order they are listed.
+const SECTIONS: &[&str] = &[
+    "server",
+    "clients",
+    "memory",
+    "persistence",
+    "stats",
+    "replication",
+    "keyspace",
+];
+
+/// `1.50M` style amount of memory, as redis shows it next to the exact
+/// number of bytes.
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["K", "M", "G", "T", "P"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut amount = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while amount >= 1024.0 && unit + 1 < UNITS.len() {
+        amount /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2}{}", amount, UNITS[unit])
+}
+
+/// `key:value` lines of a section, the keyspace has one per database which
+/// has keys.
+fn section(name: &str, server: &Arc<Server>, map: &DictionaryServer) -> Vec<(String, String)> {
+    if name == "keyspace" {
+        return (0..map.databases())
+            .filter(|db| !map.entries(*db).is_empty())
+            .map(|db| {
+                let keys = map.entries(db).len();
+                let expires = map.volatile_keys(db);
+                (
+                    format!("db{}", db),
+                    format!("keys={},expires={},avg_ttl=0", keys, expires),
+                )
+            })
+            .collect();
+    }
+    fields(name, server, map)
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect()
+}
+
+/// Lines of the sections with a fixed set of fields.
+fn fields(name: &str, server: &Arc<Server>, map: &DictionaryServer) -> Vec<(&'static str, String)> {
+    match name {
+        "server" => {
+            let config = server.config();
+            let uptime = now_ms().saturating_sub(server.started_at) / 1000;
+            vec![
+                ("redis_version", env!("CARGO_PKG_VERSION").to_string()),
+                ("redis_mode", "standalone".to_string()),
+                ("os", std::env::consts::OS.to_string()),
+                ("arch_bits", (usize::BITS).to_string()),
+                ("process_id", std::process::id().to_string()),
+                ("tcp_port", config.port.to_string()),
+                ("uptime_in_seconds", uptime.to_string()),
+                ("uptime_in_days", (uptime / 86400).to_string()),
+                (
+                    "config_file",
+                    config
+                        .file
+                        .as_ref()
+                        .map(|file| file.display().to_string())
+                        .unwrap_or_default(),
+                ),
+            ]
+        }
+        "clients" => vec![
+            (
+                "connected_clients",
+                server.connected_clients.load(Ordering::Relaxed).to_string(),
+            ),
+            ("maxclients", server.config().maxclients.to_string()),
+            ("blocked_clients", map.blocked.len().to_string()),
+        ],
+        "memory" => {
+            let config = server.config();
+            vec![
+                ("used_memory", map.used_memory.to_string()),
+                ("used_memory_human", human_bytes(map.used_memory as u64)),
+                ("maxmemory", config.maxmemory.to_string()),
+                ("maxmemory_human", human_bytes(config.maxmemory)),
+                (
+                    "maxmemory_policy",
+                    config.get("maxmemory-policy").unwrap_or_default(),
+                ),
+            ]
+        }
+        "persistence" => {
+            let rdb = lock(&server.rdb);
+            let aof = lock(&server.aof);
+            let status = |ok: bool| if ok { "ok" } else { "err" }.to_string();
+            vec![
+                ("loading", "0".to_string()),
+                ("rdb_changes_since_last_save", map.dirty.to_string()),
+                (
+                    "rdb_bgsave_in_progress",
+                    (rdb.in_progress as u8).to_string(),
+                ),
+                ("rdb_last_save_time", rdb.last_save.to_string()),
+                ("rdb_last_bgsave_status", status(rdb.last_ok)),
+                ("aof_enabled", (aof.is_open() as u8).to_string()),
+                (
+                    "aof_rewrite_in_progress",
+                    (aof.is_rewriting() as u8).to_string(),
+                ),
+            ]
+        }
+        "stats" => {
+            let stats = &server.stats;
+            let pubsub = lock(&server.pubsub);
+            vec![
+                (
+                    "total_connections_received",
+                    stats
+                        .connections_received
+                        .load(Ordering::Relaxed)
+                        .to_string(),
+                ),
+                (
+                    "total_commands_processed",
+                    stats.commands_processed.load(Ordering::Relaxed).to_string(),
+                ),
+                (
+                    "rejected_connections",
+                    stats
+                        .rejected_connections
+                        .load(Ordering::Relaxed)
+                        .to_string(),
+                ),
+                ("expired_keys", map.expired_keys.to_string()),
+                ("evicted_keys", map.evicted_keys.to_string()),
+                (
+                    "latest_fork_usec",
+                    stats.latest_fork_usec.load(Ordering::Relaxed).to_string(),
+                ),
+                ("pubsub_channels", pubsub.channels(None).len().to_string()),
+                ("pubsub_patterns", pubsub.numpat().to_string()),
+            ]
+        }
+        "replication" => {
+            let replication = lock(&server.replication);
+            let mut fields = match &replication.master {
+                Some(link) => vec![
+                    ("role", "slave".to_string()),
+                    ("master_host", link.host.clone()),
+                    ("master_port", link.port.to_string()),
+                    ("master_link_status", link.state.name().to_string()),
+                ],
+                None => vec![("role", "master".to_string())],
+            };
+            fields.push(("connected_slaves", replication.replicas.len().to_string()));
+            fields.push(("master_replid", replication.replid.clone()));
+            fields.push(("master_repl_offset", replication.offset.to_string()));
+            fields
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// `INFO [section [section ...]]` describes the server in `# Section`
+/// headers followed by `key:value` lines. Without arguments, or with
+/// `default`, `all` or `everything`, every section is included.
+pub fn info_command(values: &[Value], server: &Arc<Server>, map: &DictionaryServer) -> Value {
+    let mut wanted: Vec<String> = (0..values.len())
+        .map(|i| arg(values, i).to_lowercase())
+        .collect();
+    if wanted.is_empty()
+        || wanted
+            .iter()
+            .any(|name| matches!(name.as_str(), "default" | "all" | "everything"))
+    {
+        wanted = SECTIONS.iter().map(|name| name.to_string()).collect();
+    }
+
+    let mut sections = Vec::new();
+    for name in SECTIONS
+        .iter()
+        .filter(|name| wanted.iter().any(|w| w == *name))
+    {
+        let mut text = format!("# {}{}\r\n", name[..1].to_uppercase(), &name[1..]);
+        for (key, value) in section(name, server, map) {
+            text.push_str(&format!("{}:{}\r\n", key, value));
+        }
+        sections.push(text);
+    }
+    Value::bulk_string(sections.join("\r\n"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::Session;
+
+    #[test]
+    fn test_info_sections() {
+        let mut session = Session::new(1);
+
+        let info = session.run(0, &["INFO"]);
+        for header in [
+            "# Server\r\n",
+            "# Clients\r\n",
+            "# Memory\r\n",
+            "# Persistence\r\n",
+            "# Stats\r\n",
+            "# Replication\r\n",
+            "# Keyspace\r\n",
+        ] {
+            assert!(info.contains(header), "missing {}", header);
+        }
+        assert!(info.contains("\r\nredis_mode:standalone\r\n"));
+        assert!(!info.contains("db0:"));
+
+        session.run(0, &["SET", "a", "1"]);
+        session.run(0, &["SET", "b", "2", "EX", "100"]);
+        let info = session.run(0, &["INFO", "keyspace", "STATS"]);
+        assert!(info.contains("# Keyspace\r\ndb0:keys=2,expires=1,avg_ttl=0\r\n"));
+        assert!(info.contains("\r\ntotal_commands_processed:3\r\n"));
+        assert!(!info.contains("# Server"));
+
+        session.run(0, &["SELECT", "3"]);
+        session.run(0, &["SET", "c", "3"]);
+        let info = session.run(0, &["INFO", "keyspace"]);
+        assert!(
+            info.contains("db0:keys=2,expires=1,avg_ttl=0\r\ndb3:keys=1,expires=0,avg_ttl=0\r\n")
+        );
+
+        let info = session.run(0, &["INFO", "memory"]);
+        assert!(info.contains("\r\nmaxmemory:0\r\nmaxmemory_human:0B\r\n"));
+        assert!(info.contains("\r\nmaxmemory_policy:noeviction\r\n"));
+        assert_eq!(session.run(0, &["INFO", "nothing"]), "$0\r\n\r\n");
+    }
+
+    #[test]
+    fn test_human_bytes() {
+        assert_eq!(human_bytes(1000), "1000B");
+        assert_eq!(human_bytes(1536), "1.50K");
+        assert_eq!(human_bytes(3 * 1024 * 1024 * 1024), "3.00G");
+    }
+}
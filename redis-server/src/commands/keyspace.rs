@@ -0,0 +1,612 @@
+use crate::client::Client;
+use crate::commands::{arg, arg_bytes, parse_int, wrong_arity, NOT_AN_INTEGER, SYNTAX_ERROR};
+use crate::dictionary_server::{now_ms, DictionaryServer};
+use crate::glob::glob_match;
+use crate::parser::Value;
+
+/// Keys `SCAN` looks at per call when no `COUNT` is given.
+const SCAN_DEFAULT_COUNT: usize = 10;
+
+/// Unit of the time argument for the `EXPIRE` family of commands.
+pub enum Expire {
+    Seconds,
+    Millis,
+    UnixSeconds,
+    UnixMillis,
+}
+
+impl Expire {
+    fn command(&self) -> &'static str {
+        match self {
+            Expire::Seconds => "expire",
+            Expire::Millis => "pexpire",
+            Expire::UnixSeconds => "expireat",
+            Expire::UnixMillis => "pexpireat",
+        }
+    }
+
+    /// Absolute deadline in unix milliseconds, `None` when it can't be
+    /// represented.
+    fn deadline(&self, amount: i64) -> Option<i64> {
+        let now = now_ms() as i64;
+        match self {
+            Expire::Seconds => amount.checked_mul(1000)?.checked_add(now),
+            Expire::Millis => amount.checked_add(now),
+            Expire::UnixSeconds => amount.checked_mul(1000),
+            Expire::UnixMillis => Some(amount),
+        }
+    }
+}
+
+/// `EXPIRE key seconds [NX | XX | GT | LT]` and its `PEXPIRE`, `EXPIREAT`,
+/// `PEXPIREAT` siblings. Replies 1 when the timeout was set, 0 when the key
+/// doesn't exist or the condition wasn't met. A deadline in the past deletes
+/// the key right away.
+pub fn expire_command(values: &[Value], map: &mut DictionaryServer, unit: Expire) -> Value {
+    let key = arg_bytes(values, 0);
+    let amount = try_reply!(parse_int(&arg(values, 1)));
+
+    let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+    for i in 2..values.len() {
+        let option = arg(values, i);
+        match option.to_uppercase().as_str() {
+            "NX" => nx = true,
+            "XX" => xx = true,
+            "GT" => gt = true,
+            "LT" => lt = true,
+            _ => return Value::error(&format!("ERR Unsupported option {}", option)),
+        }
+    }
+    if nx && (xx || gt || lt) {
+        return Value::error("ERR NX and XX, GT or LT options at the same time are not compatible");
+    }
+    if gt && lt {
+        return Value::error("ERR GT and LT options at the same time are not compatible");
+    }
+
+    let deadline = match unit.deadline(amount) {
+        Some(deadline) => deadline,
+        None => {
+            return Value::error(&format!(
+                "ERR invalid expire time in '{}' command",
+                unit.command()
+            ))
+        }
+    };
+
+    let current = match map.expiry(&key) {
+        Some(current) => current.map(|when| when as i64),
+        None => return Value::integer(0),
+    };
+    let allowed = match current {
+        // no TTL counts as an infinite one for GT and LT
+        None => !xx && !gt,
+        Some(current) => !nx && (!gt || deadline > current) && (!lt || deadline < current),
+    };
+    if !allowed {
+        return Value::integer(0);
+    }
+
+    if deadline <= now_ms() as i64 {
+        map.remove(&key);
+    } else {
+        map.set_expiry(&key, Some(deadline as u64));
+    }
+    Value::integer(1)
+}
+
+/// `TTL key` and `PTTL key`: remaining time to live, -2 if the key doesn't
+/// exist and -1 if it exists without a timeout.
+pub fn ttl_command(values: &[Value], map: &mut DictionaryServer, millis: bool) -> Value {
+    let key = arg_bytes(values, 0);
+    match map.expiry(&key) {
+        None => Value::integer(-2),
+        Some(None) => Value::integer(-1),
+        Some(Some(when)) => {
+            let remaining = when.saturating_sub(now_ms()) as i64;
+            if millis {
+                Value::integer(remaining)
+            } else {
+                Value::integer((remaining + 500) / 1000)
+            }
+        }
+    }
+}
+
+/// `PERSIST key` removes the timeout of a key, replies 1 if there was one.
+pub fn persist_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    match map.expiry(&key) {
+        Some(Some(_)) => {
+            map.set_expiry(&key, None);
+            Value::integer(1)
+        }
+        _ => Value::integer(0),
+    }
+}
+
+/// `DEL key [key ...]` and `UNLINK`, replies how many keys were removed.
+/// Values are freed right away in both cases.
+pub fn del_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let removed = (0..values.len())
+        .filter(|i| {
+            let key = arg_bytes(values, *i);
+            map.lookup(&key).is_some() && map.remove(&key).is_some()
+        })
+        .count();
+    Value::integer(removed as i64)
+}
+
+/// `EXISTS key [key ...]` how many of the keys exist, a key given twice is
+/// counted twice.
+pub fn exists_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let count = (0..values.len())
+        .filter(|i| map.lookup(&arg_bytes(values, *i)).is_some())
+        .count();
+    Value::integer(count as i64)
+}
+
+/// `TYPE key` name of the data type stored at `key`, `none` if it is missing.
+pub fn type_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let name = map
+        .lookup(&arg_bytes(values, 0))
+        .map_or("none", |entry| entry.value.type_name());
+    Value::simple_string(name)
+}
+
+/// `KEYS pattern` every live key matching the glob `pattern`.
+pub fn keys_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let pattern = arg_bytes(values, 0);
+    let now = now_ms();
+    let keys = map
+        .server
+        .iter()
+        .filter(|(_, entry)| entry.expires_at.is_none_or(|when| when > now))
+        .filter(|(key, _)| glob_match(&pattern, key))
+        .map(|(key, _)| Value::bulk_string(key))
+        .collect();
+    Value::array(keys)
+}
+
+/// `SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]` iterates the
+/// keyspace a few keys at a time. Replies the cursor for the next call, 0
+/// when the iteration is complete, and the keys found. A key which exists
+/// during the whole iteration is returned at least once.
+pub fn scan_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let cursor = match arg(values, 0).parse::<u64>() {
+        Ok(cursor) => cursor,
+        Err(_) => return Value::error("ERR invalid cursor"),
+    };
+    let mut pattern = None;
+    let mut count = SCAN_DEFAULT_COUNT;
+    let mut type_name = None;
+    let mut i = 1;
+    while i < values.len() {
+        if i + 1 >= values.len() {
+            return Value::error(SYNTAX_ERROR);
+        }
+        match arg(values, i).to_uppercase().as_str() {
+            "MATCH" => pattern = Some(arg_bytes(values, i + 1)),
+            "COUNT" => {
+                count = match parse_int(&arg(values, i + 1)) {
+                    Ok(count) if count >= 1 => count as usize,
+                    Ok(_) => return Value::error(SYNTAX_ERROR),
+                    Err(reply) => return reply,
+                }
+            }
+            "TYPE" => type_name = Some(arg(values, i + 1).to_lowercase()),
+            _ => return Value::error(SYNTAX_ERROR),
+        }
+        i += 2;
+    }
+
+    let (next, candidates) = map.scan(cursor, count);
+    let keys = candidates
+        .into_iter()
+        .filter(|key| {
+            pattern
+                .as_ref()
+                .is_none_or(|pattern| glob_match(pattern, key))
+        })
+        .filter(|key| match map.lookup(key) {
+            Some(entry) => type_name
+                .as_ref()
+                .is_none_or(|name| entry.value.type_name() == name),
+            None => false,
+        })
+        .map(|key| Value::bulk_string(&key))
+        .collect();
+    Value::array(vec![
+        Value::bulk_string(next.to_string()),
+        Value::array(keys),
+    ])
+}
+
+/// `RENAME key newkey` and `RENAMENX`, the value keeps its TTL. With `nx`
+/// nothing happens when `newkey` already exists and the reply is 0.
+pub fn rename_command(values: &[Value], map: &mut DictionaryServer, nx: bool) -> Value {
+    let key = arg_bytes(values, 0);
+    let new_key = arg_bytes(values, 1);
+    if map.lookup(&key).is_none() {
+        return Value::error("ERR no such key");
+    }
+    if nx && map.lookup(&new_key).is_some() {
+        return Value::integer(0);
+    }
+    if key != new_key {
+        let entry = map.remove(&key).unwrap();
+        map.insert(&new_key, entry.value, entry.expires_at);
+    }
+    if nx {
+        Value::integer(1)
+    } else {
+        Value::ok()
+    }
+}
+
+/// `DBSIZE` number of keys in the selected database.
+pub fn dbsize_command(map: &mut DictionaryServer) -> Value {
+    Value::integer(map.server.len() as i64)
+}
+
+/// `MEMORY USAGE key [SAMPLES count]` estimated bytes used by a key, the
+/// same estimate `maxmemory` is enforced with. Doesn't count as an access.
+pub fn memory_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    match arg(values, 0).to_uppercase().as_str() {
+        "USAGE" if values.len() == 2 || values.len() == 4 => {
+            if values.len() == 4 {
+                if !arg(values, 2).eq_ignore_ascii_case("SAMPLES") {
+                    return Value::error(SYNTAX_ERROR);
+                }
+                try_reply!(parse_int(&arg(values, 3)));
+            }
+            match map.server.get(&arg_bytes(values, 1)) {
+                Some(entry) if entry.expires_at.is_none_or(|when| when > now_ms()) => {
+                    Value::integer(entry.size as i64)
+                }
+                _ => Value::null(),
+            }
+        }
+        "USAGE" => Value::error(SYNTAX_ERROR),
+        _ => Value::error(&format!(
+            "ERR unknown subcommand '{}'. Try MEMORY HELP.",
+            arg(values, 0)
+        )),
+    }
+}
+
+/// `FLUSHDB [ASYNC | SYNC]` deletes every key of the selected database,
+/// `FLUSHALL` the ones of every database.
+pub fn flush_command(values: &[Value], map: &mut DictionaryServer, name: &str) -> Value {
+    if values.len() > 1 {
+        return wrong_arity(name);
+    }
+    if !values.is_empty() && !matches!(arg(values, 0).to_uppercase().as_str(), "ASYNC" | "SYNC") {
+        return Value::error(SYNTAX_ERROR);
+    }
+    match name {
+        "flushall" => map.clear_all(),
+        _ => map.clear(),
+    };
+    Value::ok()
+}
+
+/// Index of one of the `databases`, `error` is the reply when it isn't a
+/// number.
+fn parse_db(string: &str, databases: usize, error: &str) -> Result<usize, Value> {
+    let index = string.parse::<i64>().map_err(|_| Value::error(error))?;
+    usize::try_from(index)
+        .ok()
+        .filter(|index| *index < databases)
+        .ok_or_else(|| Value::error("ERR DB index is out of range"))
+}
+
+/// `SELECT index` makes the commands of the connection run against another
+/// database.
+pub fn select_command(values: &[Value], client: &mut Client, databases: usize) -> Value {
+    client.db = try_reply!(parse_db(&arg(values, 0), databases, NOT_AN_INTEGER));
+    Value::ok()
+}
+
+/// `MOVE key db` moves a key with its TTL from the selected database to
+/// `db`. Replies 0 when the key is missing or `db` already has it.
+pub fn move_command(
+    values: &[Value],
+    client: &Client,
+    map: &mut DictionaryServer,
+    databases: usize,
+) -> Value {
+    let db = try_reply!(parse_db(&arg(values, 1), databases, NOT_AN_INTEGER));
+    if db == client.db {
+        return Value::error("ERR source and destination objects are the same");
+    }
+    Value::integer(map.move_key(&arg_bytes(values, 0), db) as i64)
+}
+
+/// `SWAPDB index1 index2` exchanges the keys of two databases, the clients
+/// connected to one of them see the keys of the other right away.
+pub fn swapdb_command(values: &[Value], map: &mut DictionaryServer, databases: usize) -> Value {
+    let a = try_reply!(parse_db(
+        &arg(values, 0),
+        databases,
+        "ERR invalid first DB index"
+    ));
+    let b = try_reply!(parse_db(
+        &arg(values, 1),
+        databases,
+        "ERR invalid second DB index"
+    ));
+    map.swap_databases(a, b);
+    Value::ok()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commands::test::run;
+    use crate::dictionary_server::{now_ms, DictionaryServer};
+
+    #[test]
+    fn test_expire_ttl_and_persist() {
+        let mut map = DictionaryServer::new();
+        assert_eq!(run(&mut map, &["EXPIRE", "k", "10"]), ":0\r\n");
+        assert_eq!(run(&mut map, &["TTL", "k"]), ":-2\r\n");
+        run(&mut map, &["SET", "k", "v"]);
+        assert_eq!(run(&mut map, &["TTL", "k"]), ":-1\r\n");
+        assert_eq!(run(&mut map, &["PERSIST", "k"]), ":0\r\n");
+        assert_eq!(run(&mut map, &["EXPIRE", "k", "10"]), ":1\r\n");
+        assert_eq!(run(&mut map, &["TTL", "k"]), ":10\r\n");
+        assert_eq!(run(&mut map, &["PERSIST", "k"]), ":1\r\n");
+        assert_eq!(run(&mut map, &["PTTL", "k"]), ":-1\r\n");
+    }
+
+    #[test]
+    fn test_expire_conditions() {
+        let mut map = DictionaryServer::new();
+        run(&mut map, &["SET", "k", "v"]);
+        assert_eq!(run(&mut map, &["EXPIRE", "k", "100", "XX"]), ":0\r\n");
+        assert_eq!(run(&mut map, &["EXPIRE", "k", "100", "GT"]), ":0\r\n");
+        assert_eq!(run(&mut map, &["EXPIRE", "k", "100", "NX"]), ":1\r\n");
+        assert_eq!(run(&mut map, &["EXPIRE", "k", "50", "GT"]), ":0\r\n");
+        assert_eq!(run(&mut map, &["EXPIRE", "k", "50", "LT"]), ":1\r\n");
+        assert_eq!(run(&mut map, &["TTL", "k"]), ":50\r\n");
+        assert_eq!(
+            run(&mut map, &["EXPIRE", "k", "50", "GT", "LT"]),
+            "-ERR GT and LT options at the same time are not compatible\r\n"
+        );
+    }
+
+    #[test]
+    fn test_expire_in_the_past_deletes_key() {
+        let mut map = DictionaryServer::new();
+        run(&mut map, &["SET", "k", "v"]);
+        assert_eq!(run(&mut map, &["PEXPIREAT", "k", "1"]), ":1\r\n");
+        assert_eq!(run(&mut map, &["GET", "k"]), "$-1\r\n");
+        assert!(map.server.is_empty());
+    }
+
+    #[test]
+    fn test_expire_propagation() {
+        use crate::commands::test::Session;
+
+        let mut session = Session::new(1);
+
+        session.run(0, &["SET", "k", "v"]);
+        let after_set = session.backlog();
+        // commands which changed nothing are not propagated
+        session.run(0, &["EXPIRE", "k", "100", "XX"]);
+        session.run(0, &["PERSIST", "k"]);
+        session.run(0, &["DEL", "nope"]);
+        assert_eq!(session.backlog(), after_set);
+
+        session.run(0, &["PEXPIRE", "k", "-1"]);
+        assert!(session
+            .backlog()
+            .ends_with("*2\r\n$3\r\nDEL\r\n$1\r\nk\r\n"));
+
+        // a key found expired is deleted on the replicas as well
+        session.map.set(b"old", b"v", Some(now_ms() - 1));
+        session.run(0, &["GET", "old"]);
+        assert!(session
+            .backlog()
+            .ends_with("*2\r\n$3\r\nDEL\r\n$3\r\nold\r\n"));
+    }
+
+    #[test]
+    fn test_del_exists_and_type() {
+        let mut map = DictionaryServer::new();
+        run(&mut map, &["SET", "a", "1"]);
+        run(&mut map, &["RPUSH", "b", "x"]);
+        run(&mut map, &["ZADD", "z", "1", "x"]);
+        run(&mut map, &["SET", "gone", "1", "PXAT", "1"]);
+        assert_eq!(
+            run(&mut map, &["EXISTS", "a", "a", "b", "gone", "x"]),
+            ":3\r\n"
+        );
+        assert_eq!(run(&mut map, &["TYPE", "a"]), "+string\r\n");
+        assert_eq!(run(&mut map, &["TYPE", "b"]), "+list\r\n");
+        assert_eq!(run(&mut map, &["TYPE", "z"]), "+zset\r\n");
+        assert_eq!(run(&mut map, &["TYPE", "x"]), "+none\r\n");
+        assert_eq!(run(&mut map, &["DEL", "a", "b", "x"]), ":2\r\n");
+        assert_eq!(run(&mut map, &["UNLINK", "z"]), ":1\r\n");
+        assert_eq!(run(&mut map, &["DBSIZE"]), ":0\r\n");
+    }
+
+    #[test]
+    fn test_keys_pattern() {
+        let mut map = DictionaryServer::new();
+        run(
+            &mut map,
+            &["MSET", "user:1", "a", "user:2", "b", "item:1", "c"],
+        );
+        let reply = run(&mut map, &["KEYS", "user:*"]);
+        assert!(reply.starts_with("*2\r\n"));
+        assert!(reply.contains("user:1") && reply.contains("user:2"));
+        assert_eq!(run(&mut map, &["KEYS", "item:?"]), "*1\r\n$6\r\nitem:1\r\n");
+        assert_eq!(run(&mut map, &["KEYS", "nothing*"]), "*0\r\n");
+    }
+
+    #[test]
+    fn test_scan() {
+        let mut map = DictionaryServer::new();
+        for i in 0..50 {
+            run(&mut map, &["SET", &format!("key:{}", i), "v"]);
+        }
+        run(&mut map, &["SADD", "set", "a"]);
+
+        let mut cursor = "0".to_string();
+        let mut found = 0;
+        loop {
+            let reply = run(&mut map, &["SCAN", &cursor, "MATCH", "key:*", "COUNT", "5"]);
+            let lines: Vec<&str> = reply.split("\r\n").collect();
+            found += lines.iter().filter(|line| line.starts_with("key:")).count();
+            cursor = lines[2].to_string();
+            if cursor == "0" {
+                break;
+            }
+        }
+        assert_eq!(found, 50);
+
+        let reply = run(&mut map, &["SCAN", "0", "COUNT", "1000", "TYPE", "set"]);
+        assert_eq!(reply, "*2\r\n$1\r\n0\r\n*1\r\n$3\r\nset\r\n");
+        assert_eq!(run(&mut map, &["SCAN", "x"]), "-ERR invalid cursor\r\n");
+        assert_eq!(
+            run(&mut map, &["SCAN", "0", "COUNT", "0"]),
+            "-ERR syntax error\r\n"
+        );
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut map = DictionaryServer::new();
+        run(&mut map, &["SET", "a", "1", "EX", "100"]);
+        run(&mut map, &["SET", "b", "2"]);
+        assert_eq!(run(&mut map, &["RENAMENX", "a", "b"]), ":0\r\n");
+        assert_eq!(run(&mut map, &["RENAME", "a", "c"]), "+OK\r\n");
+        assert_eq!(run(&mut map, &["TTL", "c"]), ":100\r\n");
+        assert_eq!(run(&mut map, &["EXISTS", "a"]), ":0\r\n");
+        assert_eq!(run(&mut map, &["RENAME", "c", "b"]), "+OK\r\n");
+        assert_eq!(run(&mut map, &["GET", "b"]), "$1\r\n1\r\n");
+        assert_eq!(run(&mut map, &["RENAMENX", "b", "d"]), ":1\r\n");
+        assert_eq!(run(&mut map, &["RENAME", "x", "y"]), "-ERR no such key\r\n");
+    }
+
+    #[test]
+    fn test_flushdb() {
+        let mut map = DictionaryServer::new();
+        run(&mut map, &["MSET", "a", "1", "b", "2"]);
+        run(&mut map, &["EXPIRE", "a", "100"]);
+        assert_eq!(run(&mut map, &["FLUSHDB", "ASYNC"]), "+OK\r\n");
+        assert_eq!(run(&mut map, &["DBSIZE"]), ":0\r\n");
+        assert_eq!(map.expire_cycle(u64::MAX, 10), 0);
+        run(&mut map, &["SET", "c", "3"]);
+        assert_eq!(run(&mut map, &["FLUSHALL"]), "+OK\r\n");
+        assert!(map.server.is_empty());
+        assert_eq!(run(&mut map, &["FLUSHALL", "NOW"]), "-ERR syntax error\r\n");
+    }
+
+    #[test]
+    fn test_maxmemory() {
+        use crate::commands::test::Session;
+        use crate::config::{Config, MaxmemoryPolicy};
+
+        let config = Config {
+            maxmemory: 1000,
+            ..Config::default()
+        };
+        let mut session = Session::with_config(config, 1);
+        session.clients[0].user = Some("default".to_string());
+
+        for i in 0..10 {
+            assert_eq!(
+                session.run(0, &["SET", &format!("key{}", i), "v"]),
+                "+OK\r\n"
+            );
+        }
+        assert!(session.map.used_memory > 1000);
+        assert_eq!(
+            session.run(0, &["SET", "one", "more"]),
+            "-OOM command not allowed when used memory > 'maxmemory'.\r\n"
+        );
+        // reading and freeing memory still works
+        assert_eq!(session.run(0, &["GET", "key1"]), "$1\r\nv\r\n");
+        assert_eq!(session.run(0, &["MEMORY", "USAGE", "key1"]), ":105\r\n");
+        assert_eq!(session.run(0, &["MEMORY", "USAGE", "nope"]), "$-1\r\n");
+        assert_eq!(session.run(0, &["DEL", "key1"]), ":1\r\n");
+        assert_eq!(session.run(0, &["SET", "key1", "v"]), "+OK\r\n");
+        assert!(session.map.used_memory > 1000);
+
+        session.server.config().maxmemory_policy = MaxmemoryPolicy::AllKeysLru;
+        assert_eq!(session.run(0, &["SET", "one", "more"]), "+OK\r\n");
+        assert!(session.map.server.len() < 11);
+        assert!(session.map.used_memory <= 1000 + session.map.server[b"one".as_slice()].size);
+    }
+
+    #[test]
+    fn test_databases() {
+        use crate::commands::test::Session;
+        use crate::config::Config;
+
+        let config = Config {
+            databases: 4,
+            ..Config::default()
+        };
+        let mut session = Session::with_config(config, 1);
+
+        session.run(0, &["SET", "k", "zero"]);
+        assert_eq!(session.run(0, &["SELECT", "2"]), "+OK\r\n");
+        assert_eq!(session.run(0, &["GET", "k"]), "$-1\r\n");
+        session.run(0, &["SET", "k", "two", "EX", "100"]);
+        session.run(0, &["SET", "only", "two"]);
+        assert_eq!(session.run(0, &["DBSIZE"]), ":2\r\n");
+        assert_eq!(
+            session.run(0, &["SELECT", "4"]),
+            "-ERR DB index is out of range\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["SELECT", "one"]),
+            "-ERR value is not an integer or out of range\r\n"
+        );
+
+        // keys only move to databases which don't have them
+        assert_eq!(session.run(0, &["MOVE", "k", "0"]), ":0\r\n");
+        assert_eq!(session.run(0, &["MOVE", "k", "3"]), ":1\r\n");
+        assert_eq!(session.run(0, &["MOVE", "nope", "3"]), ":0\r\n");
+        assert_eq!(
+            session.run(0, &["MOVE", "only", "2"]),
+            "-ERR source and destination objects are the same\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["MOVE", "only", "-1"]),
+            "-ERR DB index is out of range\r\n"
+        );
+        session.run(0, &["SELECT", "3"]);
+        assert_eq!(session.run(0, &["GET", "k"]), "$3\r\ntwo\r\n");
+        assert_eq!(session.run(0, &["TTL", "k"]), ":100\r\n");
+
+        // the connection stays on its index and sees the other keys
+        assert_eq!(session.run(0, &["SWAPDB", "0", "3"]), "+OK\r\n");
+        assert_eq!(session.run(0, &["GET", "k"]), "$4\r\nzero\r\n");
+        assert_eq!(
+            session.run(0, &["SWAPDB", "x", "3"]),
+            "-ERR invalid first DB index\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["SWAPDB", "0", "9"]),
+            "-ERR DB index is out of range\r\n"
+        );
+        session.run(0, &["SELECT", "0"]);
+        assert_eq!(session.run(0, &["GET", "k"]), "$3\r\ntwo\r\n");
+
+        // the writes reach the replicas after the database they go to
+        let backlog = session.backlog();
+        assert!(backlog.contains("SELECT\r\n$1\r\n2\r\n*5\r\n$3\r\nSET\r\n$1\r\nk\r\n"));
+        assert!(backlog.ends_with(
+            "*3\r\n$4\r\nMOVE\r\n$1\r\nk\r\n$1\r\n3\r\n\
+             *2\r\n$6\r\nSELECT\r\n$1\r\n3\r\n*3\r\n$6\r\nSWAPDB\r\n$1\r\n0\r\n$1\r\n3\r\n"
+        ));
+
+        assert_eq!(session.run(0, &["FLUSHALL"]), "+OK\r\n");
+        session.run(0, &["SELECT", "2"]);
+        assert_eq!(session.run(0, &["DBSIZE"]), ":0\r\n");
+    }
+}
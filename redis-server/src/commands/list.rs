@@ -0,0 +1,593 @@
+use std::collections::VecDeque;
+
+use crate::blocking::BlockedOn;
+use crate::client::Client;
+use crate::commands::{
+    arg, arg_bytes, block, can_block, normalize_range, parse_int, parse_timeout, wrong_arity,
+    SYNTAX_ERROR,
+};
+use crate::dictionary_server::{DictionaryServer, WrongType};
+use crate::parser::Value;
+
+/// Side of the list a command works on.
+#[derive(Clone, Copy, PartialEq)]
+pub enum End {
+    Left,
+    Right,
+}
+
+impl End {
+    fn prefix(&self) -> &'static str {
+        match self {
+            End::Left => "l",
+            End::Right => "r",
+        }
+    }
+
+    /// `LEFT` or `RIGHT` as `LMOVE` takes them.
+    fn parse(string: &str) -> Option<End> {
+        match string.to_uppercase().as_str() {
+            "LEFT" => Some(End::Left),
+            "RIGHT" => Some(End::Right),
+            _ => None,
+        }
+    }
+
+    fn from_left(left: bool) -> End {
+        if left {
+            End::Left
+        } else {
+            End::Right
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            End::Left => "LEFT",
+            End::Right => "RIGHT",
+        }
+    }
+
+    fn pop(&self, list: &mut VecDeque<Vec<u8>>) -> Option<Vec<u8>> {
+        match self {
+            End::Left => list.pop_front(),
+            End::Right => list.pop_back(),
+        }
+    }
+
+    fn push(&self, list: &mut VecDeque<Vec<u8>>, element: Vec<u8>) {
+        match self {
+            End::Left => list.push_front(element),
+            End::Right => list.push_back(element),
+        }
+    }
+}
+
+/// `LPUSH key element [element ...]` and `RPUSH`, elements are inserted one
+/// after the other so `LPUSH list a b c` leaves `c` at the head. Replies the
+/// length of the list.
+pub fn push_command(values: &[Value], map: &mut DictionaryServer, end: End) -> Value {
+    let key = arg_bytes(values, 0);
+    let list = try_reply!(map.list_mut(&key, true)).unwrap();
+    for i in 1..values.len() {
+        end.push(list, arg_bytes(values, i));
+    }
+    let len = list.len();
+    map.modified(&key);
+    Value::integer(len as i64)
+}
+
+/// `LPOP key [count]` and `RPOP`. Without `count` the reply is the popped
+/// element, with it an array of up to `count` elements.
+pub fn pop_command(values: &[Value], map: &mut DictionaryServer, end: End) -> Value {
+    if values.len() > 2 {
+        return wrong_arity(&format!("{}pop", end.prefix()));
+    }
+    let key = arg_bytes(values, 0);
+    let count = match values.get(1) {
+        Some(_) => {
+            let count = try_reply!(parse_int(&arg(values, 1)));
+            if count < 0 {
+                return Value::error("ERR value is out of range, must be positive");
+            }
+            Some(count as usize)
+        }
+        None => None,
+    };
+
+    let list = match try_reply!(map.list_mut(&key, false)) {
+        Some(list) => list,
+        None => return Value::null(),
+    };
+    let mut popped = Vec::new();
+    while popped.len() < count.unwrap_or(1) {
+        match end.pop(list) {
+            Some(element) => popped.push(Value::bulk_string(&element)),
+            None => break,
+        }
+    }
+
+    if !popped.is_empty() {
+        map.modified(&key);
+        map.remove_if_empty(&key);
+    }
+    match count {
+        Some(_) => Value::array(popped),
+        None => popped.pop().unwrap_or_else(Value::null),
+    }
+}
+
+/// A popped element along with the key it came from.
+type Popped = (Vec<u8>, Vec<u8>);
+
+/// Pop an element from the first of `keys` holding a non empty list, along
+/// with the key it came from.
+fn pop_first(
+    keys: &[Vec<u8>],
+    map: &mut DictionaryServer,
+    end: End,
+) -> Result<Option<Popped>, WrongType> {
+    for key in keys {
+        if let Some(element) = map.list_mut(key, false)?.and_then(|list| end.pop(list)) {
+            map.modified(key);
+            map.remove_if_empty(key);
+            return Ok(Some((key.clone(), element)));
+        }
+    }
+    Ok(None)
+}
+
+/// `BLPOP key [key ...] timeout` and `BRPOP` pop from the first non empty
+/// list and reply the key along with the element. When all of them are
+/// empty the client waits up to `timeout` seconds for an element to be
+/// pushed, zero waits forever.
+pub fn blocking_pop_command(
+    values: &[Value],
+    client: &mut Client,
+    map: &mut DictionaryServer,
+    end: End,
+) -> Option<Value> {
+    let timeout = try_reply!(parse_timeout(&arg(values, values.len() - 1)));
+    let keys: Vec<Vec<u8>> = (0..values.len() - 1)
+        .map(|i| arg_bytes(values, i))
+        .collect();
+    if let Some((key, element)) = try_reply!(pop_first(&keys, map, end)) {
+        return Some(Value::array(vec![
+            Value::bulk_string(key),
+            Value::bulk_string(element),
+        ]));
+    }
+    if !can_block(client) {
+        return Some(Value::null_array());
+    }
+    let op = BlockedOn::Pop {
+        keys,
+        left: end == End::Left,
+    };
+    block(client, map, op, timeout);
+    None
+}
+
+/// Pop from `source` and push to `destination`, which may be the same list
+/// to rotate it. `None` when `source` is empty.
+fn move_element(
+    map: &mut DictionaryServer,
+    source: &[u8],
+    destination: &[u8],
+    from: End,
+    to: End,
+) -> Result<Option<Vec<u8>>, WrongType> {
+    if map.list_mut(source, false)?.is_none() {
+        return Ok(None);
+    }
+    // nothing is popped when it can't be pushed
+    map.list_mut(destination, false)?;
+    let element = match map.list_mut(source, false)?.and_then(|list| from.pop(list)) {
+        Some(element) => element,
+        None => return Ok(None),
+    };
+    map.modified(source);
+    map.remove_if_empty(source);
+    to.push(map.list_mut(destination, true)?.unwrap(), element.clone());
+    map.modified(destination);
+    Ok(Some(element))
+}
+
+fn parse_ends(values: &[Value]) -> Result<(End, End), Value> {
+    match (End::parse(&arg(values, 2)), End::parse(&arg(values, 3))) {
+        (Some(from), Some(to)) => Ok((from, to)),
+        _ => Err(Value::error(SYNTAX_ERROR)),
+    }
+}
+
+/// `LMOVE source destination LEFT|RIGHT LEFT|RIGHT` pops an element from one
+/// end of `source` and pushes it to one end of `destination`. Replies the
+/// element, nil when `source` is empty.
+pub fn lmove_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let (from, to) = try_reply!(parse_ends(values));
+    let (source, destination) = (arg_bytes(values, 0), arg_bytes(values, 1));
+    match try_reply!(move_element(map, &source, &destination, from, to)) {
+        Some(element) => Value::bulk_string(element),
+        None => Value::null(),
+    }
+}
+
+/// `BLMOVE source destination LEFT|RIGHT LEFT|RIGHT timeout` is `LMOVE`
+/// waiting up to `timeout` seconds for `source` to get an element.
+pub fn blmove_command(
+    values: &[Value],
+    client: &mut Client,
+    map: &mut DictionaryServer,
+) -> Option<Value> {
+    let (from, to) = try_reply!(parse_ends(values));
+    let timeout = try_reply!(parse_timeout(&arg(values, 4)));
+    let (source, destination) = (arg_bytes(values, 0), arg_bytes(values, 1));
+    if let Some(element) = try_reply!(move_element(map, &source, &destination, from, to)) {
+        return Some(Value::bulk_string(element));
+    }
+    if !can_block(client) {
+        return Some(Value::null());
+    }
+    let op = BlockedOn::Move {
+        source,
+        destination,
+        from_left: from == End::Left,
+        to_left: to == End::Left,
+    };
+    block(client, map, op, timeout);
+    None
+}
+
+/// Retry the pop of a client blocked by `BLPOP`, `BRPOP` or `BLMOVE`. Keys
+/// which don't hold a list (any more) are skipped like empty ones. Replies
+/// `None` while there is still nothing to pop, otherwise the reply along
+/// with the non blocking command to propagate.
+pub fn serve_blocked(
+    op: &BlockedOn,
+    map: &mut DictionaryServer,
+) -> Option<(Value, Option<Vec<Vec<u8>>>)> {
+    match op {
+        BlockedOn::Pop { keys, left } => {
+            let end = End::from_left(*left);
+            let keys: Vec<Vec<u8>> = keys
+                .iter()
+                .filter(|key| map.list_mut(key, false).is_ok())
+                .cloned()
+                .collect();
+            let (key, element) = pop_first(&keys, map, end).ok()??;
+            let command = vec![
+                format!("{}POP", end.prefix().to_uppercase()).into_bytes(),
+                key.clone(),
+            ];
+            let reply = Value::array(vec![Value::bulk_string(key), Value::bulk_string(element)]);
+            Some((reply, Some(command)))
+        }
+        BlockedOn::Move {
+            source,
+            destination,
+            from_left,
+            to_left,
+        } => {
+            let (from, to) = (End::from_left(*from_left), End::from_left(*to_left));
+            let element = move_element(map, source, destination, from, to).ok()??;
+            let command = vec![
+                b"LMOVE".to_vec(),
+                source.clone(),
+                destination.clone(),
+                from.name().as_bytes().to_vec(),
+                to.name().as_bytes().to_vec(),
+            ];
+            Some((Value::bulk_string(element), Some(command)))
+        }
+        _ => None,
+    }
+}
+
+/// `LRANGE key start stop` elements between the two inclusive indexes,
+/// negative indexes count from the tail.
+pub fn lrange_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let start = try_reply!(parse_int(&arg(values, 1)));
+    let stop = try_reply!(parse_int(&arg(values, 2)));
+
+    let list = match try_reply!(map.list_mut(&key, false)) {
+        Some(list) => list,
+        None => return Value::array(Vec::new()),
+    };
+    let elements = match normalize_range(start, stop, list.len()) {
+        Some((start, stop)) => list.range(start..=stop).map(Value::bulk_string).collect(),
+        None => Vec::new(),
+    };
+    Value::array(elements)
+}
+
+/// `LLEN key` length of the list, 0 if the key doesn't exist.
+pub fn llen_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let len = try_reply!(map.list_mut(&key, false)).map_or(0, |list| list.len());
+    Value::integer(len as i64)
+}
+
+/// `LINDEX key index` element at `index`, nil when it is out of range.
+pub fn lindex_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let index = try_reply!(parse_int(&arg(values, 1)));
+
+    let list = match try_reply!(map.list_mut(&key, false)) {
+        Some(list) => list,
+        None => return Value::null(),
+    };
+    let index = if index < 0 {
+        list.len() as i64 + index
+    } else {
+        index
+    };
+    if index < 0 {
+        return Value::null();
+    }
+    match list.get(index as usize) {
+        Some(element) => Value::bulk_string(element),
+        None => Value::null(),
+    }
+}
+
+/// `LREM key count element` removes the first `count` occurrences of
+/// `element` from the head (count > 0), from the tail (count < 0) or all of
+/// them (count = 0). Replies how many were removed.
+pub fn lrem_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let count = try_reply!(parse_int(&arg(values, 1)));
+    let element = arg_bytes(values, 2);
+
+    let list = match try_reply!(map.list_mut(&key, false)) {
+        Some(list) => list,
+        None => return Value::integer(0),
+    };
+    let limit = if count == 0 {
+        usize::MAX
+    } else {
+        count.unsigned_abs() as usize
+    };
+
+    let mut removed = 0;
+    if count >= 0 {
+        let mut i = 0;
+        while i < list.len() && removed < limit {
+            if list[i] == element {
+                list.remove(i);
+                removed += 1;
+            } else {
+                i += 1;
+            }
+        }
+    } else {
+        let mut i = list.len();
+        while i > 0 && removed < limit {
+            i -= 1;
+            if list[i] == element {
+                list.remove(i);
+                removed += 1;
+            }
+        }
+    }
+
+    if removed > 0 {
+        map.modified(&key);
+        map.remove_if_empty(&key);
+    }
+    Value::integer(removed as i64)
+}
+
+/// `LTRIM key start stop` keeps only the elements inside the inclusive range.
+pub fn ltrim_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let start = try_reply!(parse_int(&arg(values, 1)));
+    let stop = try_reply!(parse_int(&arg(values, 2)));
+
+    let list = match try_reply!(map.list_mut(&key, false)) {
+        Some(list) => list,
+        None => return Value::ok(),
+    };
+    match normalize_range(start, stop, list.len()) {
+        Some((start, stop)) => {
+            list.truncate(stop + 1);
+            list.drain(..start);
+        }
+        None => list.clear(),
+    }
+    map.modified(&key);
+    map.remove_if_empty(&key);
+    Value::ok()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commands::test::{run, Session};
+    use crate::dictionary_server::DictionaryServer;
+
+    #[test]
+    fn test_push_pop_and_range() {
+        let mut map = DictionaryServer::new();
+        assert_eq!(run(&mut map, &["RPUSH", "q", "a", "b"]), ":2\r\n");
+        assert_eq!(run(&mut map, &["LPUSH", "q", "c", "d"]), ":4\r\n");
+        assert_eq!(
+            run(&mut map, &["LRANGE", "q", "0", "-1"]),
+            "*4\r\n$1\r\nd\r\n$1\r\nc\r\n$1\r\na\r\n$1\r\nb\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["LRANGE", "q", "-2", "100"]),
+            "*2\r\n$1\r\na\r\n$1\r\nb\r\n"
+        );
+        assert_eq!(run(&mut map, &["LRANGE", "q", "5", "10"]), "*0\r\n");
+        assert_eq!(run(&mut map, &["LPOP", "q"]), "$1\r\nd\r\n");
+        assert_eq!(
+            run(&mut map, &["RPOP", "q", "2"]),
+            "*2\r\n$1\r\nb\r\n$1\r\na\r\n"
+        );
+        assert_eq!(run(&mut map, &["LLEN", "q"]), ":1\r\n");
+        assert_eq!(run(&mut map, &["RPOP", "q"]), "$1\r\nc\r\n");
+        assert_eq!(run(&mut map, &["RPOP", "q"]), "$-1\r\n");
+        assert!(map.server.is_empty());
+    }
+
+    #[test]
+    fn test_lindex() {
+        let mut map = DictionaryServer::new();
+        run(&mut map, &["RPUSH", "q", "a", "b", "c"]);
+        assert_eq!(run(&mut map, &["LINDEX", "q", "0"]), "$1\r\na\r\n");
+        assert_eq!(run(&mut map, &["LINDEX", "q", "-1"]), "$1\r\nc\r\n");
+        assert_eq!(run(&mut map, &["LINDEX", "q", "3"]), "$-1\r\n");
+        assert_eq!(run(&mut map, &["LINDEX", "q", "-4"]), "$-1\r\n");
+    }
+
+    #[test]
+    fn test_lrem() {
+        let mut map = DictionaryServer::new();
+        run(&mut map, &["RPUSH", "q", "a", "b", "a", "c", "a"]);
+        assert_eq!(run(&mut map, &["LREM", "q", "-1", "a"]), ":1\r\n");
+        assert_eq!(
+            run(&mut map, &["LRANGE", "q", "0", "-1"]),
+            "*4\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\na\r\n$1\r\nc\r\n"
+        );
+        assert_eq!(run(&mut map, &["LREM", "q", "1", "a"]), ":1\r\n");
+        assert_eq!(run(&mut map, &["LREM", "q", "0", "a"]), ":1\r\n");
+        assert_eq!(
+            run(&mut map, &["LRANGE", "q", "0", "-1"]),
+            "*2\r\n$1\r\nb\r\n$1\r\nc\r\n"
+        );
+    }
+
+    #[test]
+    fn test_ltrim() {
+        let mut map = DictionaryServer::new();
+        run(&mut map, &["RPUSH", "q", "a", "b", "c", "d"]);
+        assert_eq!(run(&mut map, &["LTRIM", "q", "1", "-2"]), "+OK\r\n");
+        assert_eq!(
+            run(&mut map, &["LRANGE", "q", "0", "-1"]),
+            "*2\r\n$1\r\nb\r\n$1\r\nc\r\n"
+        );
+        assert_eq!(run(&mut map, &["LTRIM", "q", "5", "10"]), "+OK\r\n");
+        assert!(map.server.is_empty());
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        let mut map = DictionaryServer::new();
+        run(&mut map, &["SET", "s", "v"]);
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        assert_eq!(run(&mut map, &["LPUSH", "s", "a"]), wrongtype);
+        assert_eq!(run(&mut map, &["LRANGE", "s", "0", "-1"]), wrongtype);
+        run(&mut map, &["RPUSH", "q", "a"]);
+        assert_eq!(run(&mut map, &["GET", "q"]), wrongtype);
+        assert_eq!(run(&mut map, &["SET", "q", "v", "GET"]), wrongtype);
+        assert_eq!(run(&mut map, &["SET", "q", "v"]), "+OK\r\n");
+    }
+
+    #[test]
+    fn test_lmove() {
+        let mut map = DictionaryServer::new();
+        run(&mut map, &["RPUSH", "a", "1", "2", "3"]);
+        assert_eq!(
+            run(&mut map, &["LMOVE", "a", "a", "LEFT", "RIGHT"]),
+            "$1\r\n1\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["LMOVE", "a", "b", "right", "left"]),
+            "$1\r\n1\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["LRANGE", "a", "0", "-1"]),
+            "*2\r\n$1\r\n2\r\n$1\r\n3\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["LMOVE", "c", "b", "LEFT", "LEFT"]),
+            "$-1\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["LMOVE", "a", "b", "UP", "LEFT"]),
+            "-ERR syntax error\r\n"
+        );
+        // nothing is popped when the destination isn't a list
+        run(&mut map, &["SET", "s", "v"]);
+        assert_eq!(
+            run(&mut map, &["LMOVE", "a", "s", "LEFT", "LEFT"]),
+            "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+        assert_eq!(run(&mut map, &["LLEN", "a"]), ":2\r\n");
+    }
+
+    #[test]
+    fn test_blocking_pops() {
+        let mut map = DictionaryServer::new();
+        run(&mut map, &["RPUSH", "b", "x", "y"]);
+        assert_eq!(
+            run(&mut map, &["BLPOP", "a", "b", "0"]),
+            "*2\r\n$1\r\nb\r\n$1\r\nx\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["BRPOP", "b", "0.5"]),
+            "*2\r\n$1\r\nb\r\n$1\r\ny\r\n"
+        );
+        // nothing to pop and no connection to block
+        assert_eq!(run(&mut map, &["BLPOP", "b", "0"]), "*-1\r\n");
+        assert_eq!(
+            run(&mut map, &["BLMOVE", "b", "c", "LEFT", "LEFT", "0"]),
+            "$-1\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["BLPOP", "b", "soon"]),
+            "-ERR timeout is not a float or out of range\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["BLPOP", "b", "-1"]),
+            "-ERR timeout is negative\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["BLPOP", "b", "1e30"]),
+            "-ERR timeout is out of range\r\n"
+        );
+    }
+
+    #[test]
+    fn test_waiters_are_served_in_order() {
+        let mut session = Session::new(4);
+        let inboxes: Vec<_> = (0..3).map(|i| session.connect(i)).collect();
+
+        // the BLMOVE feeds the list the last client waits on
+        assert_eq!(session.run(0, &["BRPOP", "jobs", "0"]), "");
+        assert_eq!(
+            session.run(1, &["BLMOVE", "jobs", "done", "LEFT", "RIGHT", "0"]),
+            ""
+        );
+        assert_eq!(session.run(2, &["BLPOP", "other", "done", "0"]), "");
+        assert_eq!(session.run(3, &["RPUSH", "jobs", "a", "b", "c"]), ":3\r\n");
+
+        let replies = [
+            "*2\r\n$4\r\njobs\r\n$1\r\nc\r\n",
+            "$1\r\na\r\n",
+            "*2\r\n$4\r\ndone\r\n$1\r\na\r\n",
+        ];
+        for ((client, inbox), reply) in session.clients.iter_mut().zip(&inboxes).zip(replies) {
+            assert_eq!(inbox.try_recv().unwrap(), reply.as_bytes());
+            assert!(client.blocked.take().unwrap().served.try_recv().is_ok());
+        }
+        assert_eq!(
+            session.run(3, &["LRANGE", "jobs", "0", "-1"]),
+            "*1\r\n$1\r\nb\r\n"
+        );
+        assert!(!session.map.server.contains_key(b"done".as_slice()));
+    }
+
+    #[test]
+    fn test_timeout_beyond_the_clock_waits_forever() {
+        let mut session = Session::new(1);
+        let _inbox = session.connect(0);
+        assert_eq!(session.run(0, &["BLPOP", "q", "1e19"]), "");
+        assert!(session.clients[0]
+            .blocked
+            .take()
+            .unwrap()
+            .deadline
+            .is_none());
+    }
+}
@@ -0,0 +1,691 @@
+use std::sync::atomic::Ordering;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+use crate::aof;
+use crate::blocking::{BlockedOn, Blocking};
+use crate::client::{Client, Wrapping};
+use crate::dictionary_server::{now_ms, DictionaryServer, Expired, WrongType, WRONGTYPE};
+use crate::parser::{Protocol, Value, ValueType};
+use crate::server::{lock, Server};
+use crate::slowlog::SlowLogEntry;
+
+/// Evaluates to the `Ok` value or returns the `Err` as the reply of the
+/// handler, e.g. `try_reply!(parse_int(&arg(values, 1)))`.
+macro_rules! try_reply {
+    ($result:expr) => {
+        match $result {
+            Ok(value) => value,
+            Err(reply) => return reply.into(),
+        }
+    };
+}
+
+mod acl;
+mod config;
+mod connection;
+mod hash;
+mod info;
+mod keyspace;
+mod list;
+mod persistence;
+mod pubsub;
+mod replication;
+mod scripting;
+mod set;
+mod slowlog;
+mod stream;
+mod string;
+pub mod table;
+mod transaction;
+mod zset;
+
+pub const SYNTAX_ERROR: &str = "ERR syntax error";
+pub const NOT_AN_INTEGER: &str = "ERR value is not an integer or out of range";
+/// The error for an unknown command quotes arguments up to about this length.
+const UNKNOWN_COMMAND_ARGS_LEN: usize = 128;
+
+/// Cleans up what the client left in the shared state when it disconnects.
+pub fn disconnect(server: &Arc<Server>, client: &mut Client) {
+    let mut map = server.lock();
+    transaction::unwatch_command(client, &mut map);
+    map.blocked.unblock(client.id);
+    drop(map);
+    pubsub::unsubscribe_all(server, client);
+    lock(&server.replication).replicas.remove(&client.id);
+    lock(&server.clients).remove(client.id);
+}
+
+/// Clients have to authenticate before running anything but `AUTH` and
+/// `HELLO`, after that the ACL of their user decides what they may run.
+fn authorize(
+    server: &Arc<Server>,
+    client: &Client,
+    command: &table::CommandInfo,
+    args: &[Value],
+) -> Result<(), Value> {
+    let name = match &client.user {
+        Some(name) => name,
+        None => return Ok(()),
+    };
+    if matches!(command.name, "AUTH" | "HELLO") {
+        return Ok(());
+    }
+    let acl = lock(&server.acl);
+    match acl.users.get(name) {
+        Some(user) if client.authenticated && user.enabled => user.check(command, args),
+        _ => Err(Value::error("NOAUTH Authentication required.")),
+    }
+}
+
+/// Once the dataset is over `maxmemory` keys are evicted by the configured
+/// policy before the command runs, the evictions are logged to the AOF as
+/// `DEL`s. Commands which may use more memory are refused when not enough
+/// could be freed. Internal clients are left alone so loading the AOF never
+/// loses data, and so are replicas, they follow the evictions of their
+/// master.
+fn reclaim_memory(
+    server: &Arc<Server>,
+    client: &Client,
+    command: &table::CommandInfo,
+    map: &mut DictionaryServer,
+) -> Result<(), Value> {
+    let (maxmemory, policy, samples) = {
+        let config = server.config();
+        let maxmemory = config.maxmemory as usize;
+        (maxmemory, config.maxmemory_policy, config.maxmemory_samples)
+    };
+    if client.user.is_none()
+        || maxmemory == 0
+        || map.used_memory <= maxmemory
+        || server.is_replica()
+    {
+        return Ok(());
+    }
+    for (db, key) in map.evict(maxmemory, policy, samples) {
+        server.propagate(db, &aof::encode_command(&[b"DEL".as_slice(), &key]));
+    }
+    if map.used_memory > maxmemory && command.has_flag(table::DENYOOM) {
+        return Err(Value::error(
+            "OOM command not allowed when used memory > 'maxmemory'.",
+        ));
+    }
+    Ok(())
+}
+
+/// Whether `value` is a command which modifies the dataset.
+pub fn is_write_command(value: &Value) -> bool {
+    table::lookup(&arg(&value.array, 0)).is_some_and(|command| command.has_flag(table::WRITE))
+}
+
+/// Add the command to the slow log if it ran for at least
+/// `slowlog-log-slower-than` microseconds.
+fn log_if_slow(server: &Arc<Server>, client: &Client, value: &Value, duration: Duration) {
+    let (threshold, max_len) = {
+        let config = server.config();
+        (config.slowlog_log_slower_than, config.slowlog_max_len)
+    };
+    let micros = duration.as_micros() as u64;
+    if u64::try_from(threshold).is_ok_and(|threshold| micros >= threshold)
+        && !table::lookup(&arg(&value.array, 0))
+            .is_some_and(|command| command.has_flag(table::SKIP_SLOWLOG))
+    {
+        let entry = SlowLogEntry {
+            id: 0,
+            timestamp: now_ms() / 1000,
+            duration: micros,
+            args: aof::command_args(value),
+            addr: client.addr.map(|addr| addr.to_string()).unwrap_or_default(),
+            name: client.name.clone().unwrap_or_default(),
+        };
+        lock(&server.slowlog).push(entry, max_len);
+    }
+}
+
+/// Execute a command sent by a client. Write commands which changed the
+/// dataset are propagated to the append only file and the replicas while the
+/// dictionary is still locked, so they see the same order in which commands
+/// were applied. Like redis it tells by the `dirty` counter, a `SREM` of
+/// missing members or an `EXPIRE` whose condition didn't hold isn't
+/// propagated. Slow commands are logged and `CLIENT LIST` gets to see what
+/// the client did.
+pub fn call(
+    value: &Value,
+    server: &Arc<Server>,
+    client: &mut Client,
+    map: &mut DictionaryServer,
+) -> Option<Value> {
+    // inside MULTI commands are only queued, `EXEC` propagates them
+    let queueing = client.multi.is_some();
+    let dirty = map.dirty;
+    let started = Instant::now();
+    let reply = execute_command(value, server, client, map);
+    let duration = started.elapsed();
+    // keys the command found expired are deleted before it ran
+    server.propagate_expired(map);
+    match &reply {
+        Some(reply)
+            if !queueing
+                && !client.is_master
+                && map.dirty != dirty
+                && reply.value_type != ValueType::Error
+                && is_write_command(value) =>
+        {
+            let entry = aof::log_entry(aof::command_args(value), reply, map);
+            if client.wrapping == Wrapping::Pending {
+                server.propagate(client.db, &aof::encode_command(&["MULTI"]));
+                client.wrapping = Wrapping::Started;
+            }
+            server.propagate(client.db, &aof::encode_command(&entry));
+        }
+        _ => {}
+    }
+    // commands of a transaction are followed up once it is done
+    if !client.deny_blocking {
+        serve_blocked(server, map);
+    }
+
+    server
+        .stats
+        .commands_processed
+        .fetch_add(1, Ordering::Relaxed);
+    if !queueing {
+        log_if_slow(server, client, value, duration);
+    }
+    lock(&server.clients).update(client, Some(&arg(&value.array, 0)));
+    reply
+}
+
+/// A busy script holds the dictionary, the only commands which run until
+/// it is done are `SCRIPT KILL` and `SHUTDOWN NOSAVE`.
+pub fn call_while_busy(value: &Value, server: &Arc<Server>, client: &mut Client) -> Value {
+    let name = arg(&value.array, 0);
+    let args = value.array.get(1..).unwrap_or_default();
+    let command = match table::lookup(&name) {
+        Some(command) => command,
+        None => return reject(client, unknown_command(&name, args)),
+    };
+    if !command.accepts(value.array.len()) {
+        return reject(client, wrong_arity(&command.name.to_lowercase()));
+    }
+    if let Err(e) = authorize(server, client, command, args) {
+        return reject(client, e);
+    }
+    let option = arg(args, 0).to_uppercase();
+    match (command.name, option.as_str(), args.len()) {
+        ("SCRIPT", "KILL", 1) => server.script.kill(),
+        ("SHUTDOWN", "NOSAVE", 1) => persistence::shutdown(server),
+        _ => reject(client, Value::error(crate::scripting::BUSY)),
+    }
+}
+
+/// Whether a blocking command may make the client wait. Clients which
+/// aren't connected to anything and transactions get what is there right
+/// away, like after a timeout.
+pub fn can_block(client: &Client) -> bool {
+    client.outbox.is_some() && !client.deny_blocking
+}
+
+/// Parse the timeout of a blocking command, in seconds with decimals.
+pub fn parse_timeout(string: &str) -> Result<Duration, Value> {
+    let seconds = string
+        .parse::<f64>()
+        .ok()
+        .filter(|seconds| seconds.is_finite())
+        .ok_or_else(|| Value::error("ERR timeout is not a float or out of range"))?;
+    if seconds < 0.0 {
+        return Err(Value::error("ERR timeout is negative"));
+    }
+    Duration::try_from_secs_f64(seconds).map_err(|_| Value::error("ERR timeout is out of range"))
+}
+
+/// Park the client until `op` can be served or `timeout` passed, zero or a
+/// timeout too far in the future waits forever. The connection waits for it
+/// once the dictionary is unlocked.
+pub fn block(client: &mut Client, map: &mut DictionaryServer, op: BlockedOn, timeout: Duration) {
+    let outbox = match &client.outbox {
+        Some(outbox) => outbox.clone(),
+        None => return,
+    };
+    let (served, receiver) = mpsc::channel();
+    map.blocked
+        .block(client.id, client.db, op, outbox, client.protocol, served);
+    client.blocked = Some(Blocking {
+        served: receiver,
+        deadline: (!timeout.is_zero())
+            .then(|| Instant::now().checked_add(timeout))
+            .flatten(),
+    });
+}
+
+/// The timeout of a blocked client expired, it gets the reply of an empty
+/// result unless another client served it meanwhile.
+pub fn time_out(server: &Arc<Server>, client: &Client) {
+    let waiter = server.lock().blocked.unblock(client.id);
+    if let Some(waiter) = waiter {
+        waiter.serve(&waiter.op.timeout_reply());
+    }
+}
+
+/// Serve the clients blocked on keys which changed, called after every
+/// command while the dictionary is still locked. Clients waiting on the same
+/// key are served in the order they blocked, serving one may change the key
+/// again (e.g. `XREADGROUP` moves the group on) so the next ones get another
+/// chance afterwards.
+pub fn serve_blocked(server: &Arc<Server>, map: &mut DictionaryServer) {
+    let selected = map.selected();
+    while let Some((db, key)) = map.blocked.next_ready() {
+        map.select(db);
+        for id in map.blocked.waiting(db, &key) {
+            let (op, protocol) = match map.blocked.waiter(id) {
+                Some(waiter) => (waiter.op.clone(), waiter.protocol),
+                None => continue,
+            };
+            let served = match op {
+                BlockedOn::Streams { .. } | BlockedOn::Group { .. } => {
+                    stream::serve_blocked(&op, map, protocol)
+                }
+                BlockedOn::Pop { .. } | BlockedOn::Move { .. } => list::serve_blocked(&op, map),
+            };
+            let (reply, propagated) = match served {
+                Some(served) => served,
+                None => continue,
+            };
+            if let Some(waiter) = map.blocked.unblock(id) {
+                waiter.serve(&reply);
+            }
+            if let Some(command) = propagated {
+                server.propagate_expired(map);
+                server.propagate(db, &aof::encode_command(&command));
+            }
+        }
+    }
+    map.select(selected);
+}
+
+/// Dispatch a single parsed command to its handler and return the reply which
+/// has to be sent back to the client.
+pub fn execute_command(
+    value: &Value,
+    server: &Arc<Server>,
+    client: &mut Client,
+    map: &mut DictionaryServer,
+) -> Option<Value> {
+    let name = arg(&value.array, 0);
+    let args = value.array.get(1..).unwrap_or_default();
+    let command = match table::lookup(&name) {
+        Some(command) => command,
+        None => return Some(reject(client, unknown_command(&name, args))),
+    };
+    if !command.accepts(value.array.len()) {
+        return Some(reject(client, wrong_arity(&command.name.to_lowercase())));
+    }
+    if let Err(e) = authorize(server, client, command, args) {
+        return Some(reject(client, e));
+    }
+    map.select(client.db);
+    // replicas wait for the `DEL` of their master, which sees what it sent
+    map.on_expired = match server.is_replica() {
+        false => Expired::Delete,
+        true if client.is_master => Expired::Keep,
+        true => Expired::Hide,
+    };
+    if let Err(e) = reclaim_memory(server, client, command, map) {
+        return Some(reject(client, e));
+    }
+    if command.has_flag(table::WRITE)
+        && client.user.is_some()
+        && server.config().replica_read_only
+        && server.is_replica()
+    {
+        return Some(reject(
+            client,
+            Value::error("READONLY You can't write against a read only replica."),
+        ));
+    }
+    if client.protocol == Protocol::Resp2
+        && client.subscriptions() > 0
+        && !matches!(
+            command.name,
+            "SUBSCRIBE" | "UNSUBSCRIBE" | "PSUBSCRIBE" | "PUNSUBSCRIBE" | "PING"
+        )
+    {
+        return Some(reject(
+            client,
+            Value::error(&format!(
+                "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+                command.name.to_lowercase()
+            )),
+        ));
+    }
+    if let Some(queued) = client.multi.as_mut() {
+        if !matches!(command.name, "MULTI" | "EXEC" | "DISCARD" | "WATCH") {
+            queued.push(value.clone());
+            return Some(Value::simple_string("QUEUED"));
+        }
+    }
+
+    match command.name {
+        "PING" => Some(connection::ping_command(args, client)),
+        "ECHO" => Some(connection::echo_command(args)),
+        "HELLO" => Some(connection::hello_command(args, server, client)),
+        "AUTH" => Some(acl::auth_command(args, server, client)),
+        "ACL" => Some(acl::acl_command(args, server, client)),
+        "CLIENT" => Some(connection::client_command(args, server, client, map)),
+        "SELECT" => Some(keyspace::select_command(
+            args,
+            client,
+            server.config().databases,
+        )),
+        "SET" => Some(string::set_command(args, map)),
+        "GET" => Some(string::get_command(args, map)),
+        "INCR" => Some(string::incr_command(args, map)),
+        "DECR" => Some(string::decr_command(args, map)),
+        "INCRBY" => Some(string::incrby_command(args, map)),
+        "DECRBY" => Some(string::decrby_command(args, map)),
+        "INCRBYFLOAT" => Some(string::incrbyfloat_command(args, map)),
+        "APPEND" => Some(string::append_command(args, map)),
+        "STRLEN" => Some(string::strlen_command(args, map)),
+        "GETRANGE" => Some(string::getrange_command(args, map)),
+        "SETRANGE" => Some(string::setrange_command(args, map)),
+        "MGET" => Some(string::mget_command(args, map)),
+        "MSET" => Some(string::mset_command(args, map)),
+        "MSETNX" => Some(string::msetnx_command(args, map)),
+        "DEL" | "UNLINK" => Some(keyspace::del_command(args, map)),
+        "EXISTS" => Some(keyspace::exists_command(args, map)),
+        "TYPE" => Some(keyspace::type_command(args, map)),
+        "KEYS" => Some(keyspace::keys_command(args, map)),
+        "SCAN" => Some(keyspace::scan_command(args, map)),
+        "RENAME" => Some(keyspace::rename_command(args, map, false)),
+        "RENAMENX" => Some(keyspace::rename_command(args, map, true)),
+        "DBSIZE" => Some(keyspace::dbsize_command(map)),
+        "MEMORY" => Some(keyspace::memory_command(args, map)),
+        "FLUSHDB" => Some(keyspace::flush_command(args, map, "flushdb")),
+        "FLUSHALL" => Some(keyspace::flush_command(args, map, "flushall")),
+        "MOVE" => Some(keyspace::move_command(
+            args,
+            client,
+            map,
+            server.config().databases,
+        )),
+        "SWAPDB" => Some(keyspace::swapdb_command(
+            args,
+            map,
+            server.config().databases,
+        )),
+        "EXPIRE" => Some(keyspace::expire_command(
+            args,
+            map,
+            keyspace::Expire::Seconds,
+        )),
+        "PEXPIRE" => Some(keyspace::expire_command(
+            args,
+            map,
+            keyspace::Expire::Millis,
+        )),
+        "EXPIREAT" => Some(keyspace::expire_command(
+            args,
+            map,
+            keyspace::Expire::UnixSeconds,
+        )),
+        "PEXPIREAT" => Some(keyspace::expire_command(
+            args,
+            map,
+            keyspace::Expire::UnixMillis,
+        )),
+        "TTL" => Some(keyspace::ttl_command(args, map, false)),
+        "PTTL" => Some(keyspace::ttl_command(args, map, true)),
+        "PERSIST" => Some(keyspace::persist_command(args, map)),
+        "LPUSH" => Some(list::push_command(args, map, list::End::Left)),
+        "RPUSH" => Some(list::push_command(args, map, list::End::Right)),
+        "LPOP" => Some(list::pop_command(args, map, list::End::Left)),
+        "RPOP" => Some(list::pop_command(args, map, list::End::Right)),
+        "LRANGE" => Some(list::lrange_command(args, map)),
+        "LLEN" => Some(list::llen_command(args, map)),
+        "LINDEX" => Some(list::lindex_command(args, map)),
+        "LREM" => Some(list::lrem_command(args, map)),
+        "LTRIM" => Some(list::ltrim_command(args, map)),
+        "LMOVE" => Some(list::lmove_command(args, map)),
+        "BLPOP" => list::blocking_pop_command(args, client, map, list::End::Left),
+        "BRPOP" => list::blocking_pop_command(args, client, map, list::End::Right),
+        "BLMOVE" => list::blmove_command(args, client, map),
+        "HSET" => Some(hash::hset_command(args, map)),
+        "HGET" => Some(hash::hget_command(args, map)),
+        "HDEL" => Some(hash::hdel_command(args, map)),
+        "HGETALL" => Some(hash::hgetall_command(args, map)),
+        "HINCRBY" => Some(hash::hincrby_command(args, map)),
+        "HKEYS" => Some(hash::hkeys_command(args, map)),
+        "HVALS" => Some(hash::hvals_command(args, map)),
+        "HLEN" => Some(hash::hlen_command(args, map)),
+        "HEXISTS" => Some(hash::hexists_command(args, map)),
+        "SADD" => Some(set::sadd_command(args, map)),
+        "SREM" => Some(set::srem_command(args, map)),
+        "SMEMBERS" => Some(set::smembers_command(args, map)),
+        "SISMEMBER" => Some(set::sismember_command(args, map)),
+        "SCARD" => Some(set::scard_command(args, map)),
+        "SINTER" => Some(set::set_operation_command(
+            args,
+            map,
+            set::SetOperation::Inter,
+        )),
+        "SUNION" => Some(set::set_operation_command(
+            args,
+            map,
+            set::SetOperation::Union,
+        )),
+        "SDIFF" => Some(set::set_operation_command(
+            args,
+            map,
+            set::SetOperation::Diff,
+        )),
+        "ZADD" => Some(zset::zadd_command(args, map)),
+        "ZREM" => Some(zset::zrem_command(args, map)),
+        "ZSCORE" => Some(zset::zscore_command(args, map)),
+        "ZRANK" => Some(zset::zrank_command(args, map, false)),
+        "ZREVRANK" => Some(zset::zrank_command(args, map, true)),
+        "ZRANGE" => Some(zset::zrange_command(args, map, client.protocol)),
+        "ZINCRBY" => Some(zset::zincrby_command(args, map)),
+        "ZCARD" => Some(zset::zcard_command(args, map)),
+        "XADD" => Some(stream::xadd_command(args, map)),
+        "XRANGE" => Some(stream::xrange_command(args, map, false)),
+        "XREVRANGE" => Some(stream::xrange_command(args, map, true)),
+        "XLEN" => Some(stream::xlen_command(args, map)),
+        "XTRIM" => Some(stream::xtrim_command(args, map)),
+        "XREAD" => stream::xread_command(args, client, map),
+        "XGROUP" => Some(stream::xgroup_command(args, map)),
+        "XREADGROUP" => stream::xreadgroup_command(args, client, map),
+        "XACK" => Some(stream::xack_command(args, map)),
+        "XPENDING" => Some(stream::xpending_command(args, map)),
+        "XCLAIM" => Some(stream::xclaim_command(args, map)),
+        "XSETID" => Some(stream::xsetid_command(args, map)),
+        "MULTI" => Some(transaction::multi_command(client)),
+        "EXEC" => Some(transaction::exec_command(server, client, map)),
+        "DISCARD" => Some(transaction::discard_command(client, map)),
+        "WATCH" => Some(transaction::watch_command(args, client, map)),
+        "UNWATCH" => Some(transaction::unwatch_command(client, map)),
+        "SUBSCRIBE" => pubsub::subscribe_command(args, server, client),
+        "UNSUBSCRIBE" => pubsub::unsubscribe_command(args, server, client),
+        "PSUBSCRIBE" => pubsub::psubscribe_command(args, server, client),
+        "PUNSUBSCRIBE" => pubsub::punsubscribe_command(args, server, client),
+        "PUBLISH" => Some(pubsub::publish_command(args, server)),
+        "PUBSUB" => Some(pubsub::pubsub_command(args, server)),
+        "COMMAND" => Some(table::command_command(args)),
+        "CONFIG" => Some(config::config_command(args, server, map)),
+        "SAVE" => Some(persistence::save_command(server, map)),
+        "BGSAVE" => Some(persistence::bgsave_command(args, server, map)),
+        "LASTSAVE" => Some(persistence::lastsave_command(server)),
+        "SHUTDOWN" => Some(persistence::shutdown_command(args, server, map)),
+        "EVAL" => Some(scripting::eval_command(args, server, client, map)),
+        "EVALSHA" => Some(scripting::evalsha_command(args, server, client, map)),
+        "SCRIPT" => Some(scripting::script_command(args, server)),
+        "INFO" => Some(info::info_command(args, server, map)),
+        "SLOWLOG" => Some(slowlog::slowlog_command(args, server)),
+        "BGREWRITEAOF" => Some(persistence::bgrewriteaof_command(server, map)),
+        "REPLICAOF" => Some(replication::replicaof_command(args, server, "replicaof")),
+        "SLAVEOF" => Some(replication::replicaof_command(args, server, "slaveof")),
+        "REPLCONF" => replication::replconf_command(args, server, client),
+        "PSYNC" => replication::psync_command(args, server, client, map),
+        "ROLE" => Some(replication::role_command(server)),
+        _ => Some(unknown_command(&name, args)),
+    }
+}
+
+/// An error for a command which couldn't even be queued makes the
+/// transaction the client is in fail on `EXEC`.
+fn reject(client: &mut Client, error: Value) -> Value {
+    if client.multi.is_some() {
+        client.multi_failed = true;
+    }
+    error
+}
+
+/// Reply for a command which isn't in the command table, like redis it
+/// quotes the first arguments to help spotting typos.
+fn unknown_command(name: &str, args: &[Value]) -> Value {
+    let mut message = format!("ERR unknown command '{}', with args beginning with: ", name);
+    for i in 0..args.len() {
+        let arg = arg(args, i);
+        if message.len() + arg.len() > UNKNOWN_COMMAND_ARGS_LEN {
+            break;
+        }
+        message += &format!("'{}' ", arg);
+    }
+    Value::error(&message)
+}
+
+/// Argument at `index` as text, empty if it is missing. Used for key names,
+/// options and numbers, invalid UTF-8 is replaced.
+pub fn arg(values: &[Value], index: usize) -> String {
+    String::from_utf8_lossy(&arg_bytes(values, index)).to_string()
+}
+
+/// Raw bytes of the argument at `index`, used for everything that gets
+/// stored so values stay binary safe.
+pub fn arg_bytes(values: &[Value], index: usize) -> Vec<u8> {
+    values
+        .get(index)
+        .and_then(|v| v.value.clone())
+        .unwrap_or_default()
+}
+
+impl From<WrongType> for Value {
+    fn from(_: WrongType) -> Value {
+        Value::error(WRONGTYPE)
+    }
+}
+
+/// For handlers which may not reply right away, e.g. blocking ones.
+impl From<WrongType> for Option<Value> {
+    fn from(wrong_type: WrongType) -> Option<Value> {
+        Some(wrong_type.into())
+    }
+}
+
+pub fn wrong_arity(command: &str) -> Value {
+    Value::error(&format!(
+        "ERR wrong number of arguments for '{}' command",
+        command
+    ))
+}
+
+/// Parse an integer argument, the `Err` holds the reply for the client.
+pub fn parse_int(string: &str) -> Result<i64, Value> {
+    string
+        .parse::<i64>()
+        .map_err(|_| Value::error(NOT_AN_INTEGER))
+}
+
+/// Translate redis style inclusive `start`/`stop` indexes, where negative
+/// ones count from the end, into a valid inclusive range over `len` items.
+/// `None` when the range is empty.
+pub fn normalize_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+    if start > stop || start >= len {
+        None
+    } else {
+        Some((start as usize, stop as usize))
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::config::Config;
+    use crate::parser;
+
+    /// Build the RESP array a client sends for `args`.
+    pub fn command(args: &[&str]) -> Value {
+        Value::array(args.iter().map(Value::bulk_string).collect())
+    }
+
+    /// A server for `config` which never saves on its own.
+    pub fn server(config: Config) -> Arc<Server> {
+        let config = Config {
+            save: Vec::new(),
+            ..config
+        };
+        Arc::new(Server::new(config, DictionaryServer::new()))
+    }
+
+    /// Run `args` as a command against `map` and return the RESP encoded reply.
+    pub fn run(map: &mut DictionaryServer, args: &[&str]) -> String {
+        let server = server(Config::default());
+        execute_command(&command(args), &server, &mut Client::new(0), map)
+            .map(|reply| String::from_utf8_lossy(&parser::stringify(&reply)).to_string())
+            .unwrap_or_default()
+    }
+
+    /// Runs commands of several clients against the same dictionary.
+    pub struct Session {
+        pub server: Arc<Server>,
+        pub map: DictionaryServer,
+        pub clients: Vec<Client>,
+    }
+
+    impl Session {
+        pub fn new(clients: u64) -> Session {
+            Session::with_config(Config::default(), clients)
+        }
+
+        pub fn with_config(config: Config, clients: u64) -> Session {
+            Session {
+                server: server(config),
+                map: DictionaryServer::new(),
+                clients: (1..=clients).map(Client::new).collect(),
+            }
+        }
+
+        /// Run `args` through `call` as `client` and return the RESP encoded
+        /// reply, empty when the client blocked or the reply went to its outbox.
+        pub fn run(&mut self, client: usize, args: &[&str]) -> String {
+            let client = &mut self.clients[client];
+            call(&command(args), &self.server, client, &mut self.map)
+                .map(|reply| String::from_utf8(parser::serialize(&reply, client.protocol)).unwrap())
+                .unwrap_or_default()
+        }
+
+        /// Have the replies `client` gets outside of `run` sent to the returned inbox.
+        pub fn connect(&mut self, client: usize) -> mpsc::Receiver<Vec<u8>> {
+            let (outbox, inbox) = mpsc::channel();
+            self.clients[client].outbox = Some(outbox);
+            inbox
+        }
+
+        /// Everything propagated to replicas since the server started.
+        pub fn backlog(&self) -> String {
+            let replication = lock(&self.server.replication);
+            let backlog = replication.backlog_from(&replication.replid, 1).unwrap();
+            String::from_utf8(backlog).unwrap()
+        }
+    }
+}
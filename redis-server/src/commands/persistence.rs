@@ -0,0 +1,112 @@
+use std::process;
+use std::sync::Arc;
+
+use crate::commands::{arg, wrong_arity, SYNTAX_ERROR};
+use crate::dictionary_server::DictionaryServer;
+use crate::parser::Value;
+use crate::server::{lock, Server};
+
+/// `SAVE` writes the RDB snapshot synchronously, every other client waits
+/// until it is done.
+pub fn save_command(server: &Arc<Server>, map: &mut DictionaryServer) -> Value {
+    match server.save(map) {
+        Ok(()) => Value::ok(),
+        Err(e) => Value::error(&format!("ERR {}", e)),
+    }
+}
+
+/// `BGSAVE` writes the RDB snapshot in the background.
+pub fn bgsave_command(values: &[Value], server: &Arc<Server>, map: &mut DictionaryServer) -> Value {
+    if values.len() > 1 {
+        return wrong_arity("bgsave");
+    }
+    match server.background_save(map) {
+        Ok(()) => Value::simple_string("Background saving started"),
+        Err(e) => Value::error(&format!("ERR {}", e)),
+    }
+}
+
+/// `BGREWRITEAOF` compacts the append only file in the background.
+pub fn bgrewriteaof_command(server: &Arc<Server>, map: &mut DictionaryServer) -> Value {
+    match server.background_rewrite_aof(map) {
+        Ok(()) => Value::simple_string("Background append only file rewriting started"),
+        Err(e) => Value::error(&format!("ERR {}", e)),
+    }
+}
+
+/// `LASTSAVE` unix time of the last successful snapshot.
+pub fn lastsave_command(server: &Arc<Server>) -> Value {
+    let state = lock(&server.rdb);
+    Value::integer(state.last_save as i64)
+}
+
+/// `SHUTDOWN [NOSAVE|SAVE]` stops the server, saving the snapshot first if
+/// asked to or when save points are configured.
+pub fn shutdown_command(
+    values: &[Value],
+    server: &Arc<Server>,
+    map: &mut DictionaryServer,
+) -> Value {
+    let save = match arg(values, 0).to_uppercase().as_str() {
+        _ if values.len() > 1 => return Value::error(SYNTAX_ERROR),
+        "" => !server.config().save.is_empty(),
+        "SAVE" => true,
+        "NOSAVE" => false,
+        _ => return Value::error(SYNTAX_ERROR),
+    };
+    if save {
+        if let Err(e) = server.save(map) {
+            eprintln!("Error trying to save the DB, can't exit: {}", e);
+            return Value::error("ERR Errors trying to SHUTDOWN. Check logs.");
+        }
+    }
+    shutdown(server)
+}
+
+/// Exit right away, what was appended to the AOF is flushed to disk first.
+/// A transaction or script cut short leaves a `MULTI` without `EXEC`
+/// behind, which isn't replayed.
+pub fn shutdown(server: &Server) -> ! {
+    let _ = lock(&server.aof).fsync();
+    println!("Redis is now ready to exit, bye bye...");
+    process::exit(0)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use crate::commands::test::Session;
+    use crate::config::Config;
+    use crate::rdb;
+
+    #[test]
+    fn test_save_writes_snapshot() {
+        let dir = env::temp_dir().join(format!("redis-save-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = Config {
+            dir: dir.to_string_lossy().to_string(),
+            ..Config::default()
+        };
+        let mut session = Session::with_config(config, 1);
+        session.run(0, &["SET", "k", "v"]);
+        assert_eq!(session.map.dirty, 1);
+
+        assert_eq!(session.run(0, &["SAVE"]), "+OK\r\n");
+        assert_eq!(session.map.dirty, 0);
+
+        let path = session.server.config().rdb_path();
+        let mut restored = rdb::load(&path, 16).unwrap().unwrap();
+        assert_eq!(restored.get(b"k"), Ok(Some(b"v".to_vec())));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            session.run(0, &["SHUTDOWN", "LATER"]),
+            "-ERR syntax error\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["SHUTDOWN", "NOSAVE", "SAVE"]),
+            "-ERR syntax error\r\n"
+        );
+    }
+}
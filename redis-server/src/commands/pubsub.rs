@@ -0,0 +1,246 @@
+use std::sync::Arc;
+
+use crate::client::Client;
+use crate::commands::{arg, arg_bytes, wrong_arity};
+use crate::parser::Value;
+use crate::server::{lock, Server};
+
+/// Confirmation pushed for every channel or pattern (un)subscribed, with the
+/// number of subscriptions the client has left.
+fn confirm(client: &Client, kind: &str, name: Option<&str>) {
+    client.send(&Value::push(vec![
+        Value::bulk_string(kind),
+        name.map_or_else(Value::null, Value::bulk_string),
+        Value::integer(client.subscriptions() as i64),
+    ]));
+}
+
+/// `SUBSCRIBE channel [channel ...]` puts the connection in subscriber mode,
+/// messages published to the channels are pushed to it from now on. Every
+/// channel is confirmed with its own push so there is no regular reply.
+pub fn subscribe_command(
+    values: &[Value],
+    server: &Arc<Server>,
+    client: &mut Client,
+) -> Option<Value> {
+    let subscriber = client.subscriber()?;
+    let mut pubsub = lock(&server.pubsub);
+    for i in 0..values.len() {
+        let channel = arg(values, i);
+        if client.channels.insert(channel.clone()) {
+            pubsub.subscribe(&channel, client.id, subscriber.clone());
+        }
+        confirm(client, "subscribe", Some(&channel));
+    }
+    None
+}
+
+/// `UNSUBSCRIBE [channel ...]`, without channels every subscription is
+/// dropped.
+pub fn unsubscribe_command(
+    values: &[Value],
+    server: &Arc<Server>,
+    client: &mut Client,
+) -> Option<Value> {
+    let channels: Vec<String> = if values.is_empty() {
+        client.channels.iter().cloned().collect()
+    } else {
+        (0..values.len()).map(|i| arg(values, i)).collect()
+    };
+    let mut pubsub = lock(&server.pubsub);
+    for channel in &channels {
+        if client.channels.remove(channel) {
+            pubsub.unsubscribe(channel, client.id);
+        }
+        confirm(client, "unsubscribe", Some(channel));
+    }
+    if channels.is_empty() {
+        confirm(client, "unsubscribe", None);
+    }
+    None
+}
+
+/// `PSUBSCRIBE pattern [pattern ...]` subscribes to every channel matching
+/// the glob style patterns.
+pub fn psubscribe_command(
+    values: &[Value],
+    server: &Arc<Server>,
+    client: &mut Client,
+) -> Option<Value> {
+    let subscriber = client.subscriber()?;
+    let mut pubsub = lock(&server.pubsub);
+    for i in 0..values.len() {
+        let pattern = arg(values, i);
+        if client.patterns.insert(pattern.clone()) {
+            pubsub.psubscribe(&pattern, client.id, subscriber.clone());
+        }
+        confirm(client, "psubscribe", Some(&pattern));
+    }
+    None
+}
+
+/// `PUNSUBSCRIBE [pattern ...]`, without patterns every pattern subscription
+/// is dropped.
+pub fn punsubscribe_command(
+    values: &[Value],
+    server: &Arc<Server>,
+    client: &mut Client,
+) -> Option<Value> {
+    let patterns: Vec<String> = if values.is_empty() {
+        client.patterns.iter().cloned().collect()
+    } else {
+        (0..values.len()).map(|i| arg(values, i)).collect()
+    };
+    let mut pubsub = lock(&server.pubsub);
+    for pattern in &patterns {
+        if client.patterns.remove(pattern) {
+            pubsub.punsubscribe(pattern, client.id);
+        }
+        confirm(client, "punsubscribe", Some(pattern));
+    }
+    if patterns.is_empty() {
+        confirm(client, "punsubscribe", None);
+    }
+    None
+}
+
+/// Drop every subscription of a client which disconnects.
+pub fn unsubscribe_all(server: &Arc<Server>, client: &mut Client) {
+    let mut pubsub = lock(&server.pubsub);
+    for channel in client.channels.drain() {
+        pubsub.unsubscribe(&channel, client.id);
+    }
+    for pattern in client.patterns.drain() {
+        pubsub.punsubscribe(&pattern, client.id);
+    }
+}
+
+/// `PUBLISH channel message` replies the number of clients which received
+/// the message.
+pub fn publish_command(values: &[Value], server: &Arc<Server>) -> Value {
+    let receivers = lock(&server.pubsub).publish(&arg(values, 0), &arg_bytes(values, 1));
+    Value::integer(receivers as i64)
+}
+
+/// `PUBSUB CHANNELS [pattern] | NUMSUB [channel ...] | NUMPAT`
+pub fn pubsub_command(values: &[Value], server: &Arc<Server>) -> Value {
+    let pubsub = lock(&server.pubsub);
+    match arg(values, 0).to_uppercase().as_str() {
+        "CHANNELS" if values.len() <= 2 => {
+            let pattern = (values.len() == 2).then(|| arg(values, 1));
+            Value::array(
+                pubsub
+                    .channels(pattern.as_deref())
+                    .into_iter()
+                    .map(Value::bulk_string)
+                    .collect(),
+            )
+        }
+        "CHANNELS" => wrong_arity("pubsub|channels"),
+        "NUMSUB" => {
+            let mut counts = Vec::new();
+            for i in 1..values.len() {
+                let channel = arg(values, i);
+                counts.push(Value::bulk_string(&channel));
+                counts.push(Value::integer(pubsub.numsub(&channel) as i64));
+            }
+            Value::array(counts)
+        }
+        "NUMPAT" if values.len() == 1 => Value::integer(pubsub.numpat() as i64),
+        "NUMPAT" => wrong_arity("pubsub|numpat"),
+        _ => Value::error(&format!(
+            "ERR unknown subcommand '{}'. Try PUBSUB HELP.",
+            arg(values, 0)
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc::Receiver;
+
+    use crate::commands::test::Session;
+
+    /// Everything queued for the client so far.
+    fn received(inbox: &Receiver<Vec<u8>>) -> String {
+        String::from_utf8(inbox.try_iter().flatten().collect()).unwrap()
+    }
+
+    /// Run `args` as `client`, the reply queued after what it got meanwhile.
+    fn run(session: &mut Session, client: usize, args: &[&str]) {
+        let reply = session.run(client, args);
+        let outbox = session.clients[client].outbox.as_ref().unwrap();
+        outbox.send(reply.into_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_subscribe_and_publish() {
+        let mut session = Session::new(2);
+        let inbox = session.connect(0);
+        let publisher_inbox = session.connect(1);
+
+        run(&mut session, 0, &["SUBSCRIBE", "a", "b"]);
+        assert_eq!(
+            received(&inbox),
+            "*3\r\n$9\r\nsubscribe\r\n$1\r\na\r\n:1\r\n*3\r\n$9\r\nsubscribe\r\n$1\r\nb\r\n:2\r\n"
+        );
+        run(&mut session, 0, &["PSUBSCRIBE", "c*"]);
+        assert_eq!(
+            received(&inbox),
+            "*3\r\n$10\r\npsubscribe\r\n$2\r\nc*\r\n:3\r\n"
+        );
+
+        // RESP2 subscribers are limited to the pub/sub commands
+        run(&mut session, 0, &["GET", "k"]);
+        assert!(received(&inbox).starts_with("-ERR Can't execute 'get'"));
+        run(&mut session, 0, &["PING"]);
+        assert_eq!(received(&inbox), "*2\r\n$4\r\npong\r\n$0\r\n\r\n");
+
+        run(&mut session, 1, &["PUBLISH", "a", "hello"]);
+        assert_eq!(received(&publisher_inbox), ":1\r\n");
+        assert_eq!(
+            received(&inbox),
+            "*3\r\n$7\r\nmessage\r\n$1\r\na\r\n$5\r\nhello\r\n"
+        );
+        run(&mut session, 1, &["PUBLISH", "cat", "meow"]);
+        assert_eq!(received(&publisher_inbox), ":1\r\n");
+        assert_eq!(
+            received(&inbox),
+            "*4\r\n$8\r\npmessage\r\n$2\r\nc*\r\n$3\r\ncat\r\n$4\r\nmeow\r\n"
+        );
+
+        run(&mut session, 1, &["PUBSUB", "NUMSUB", "a", "x"]);
+        assert_eq!(
+            received(&publisher_inbox),
+            "*4\r\n$1\r\na\r\n:1\r\n$1\r\nx\r\n:0\r\n"
+        );
+        run(&mut session, 1, &["PUBSUB", "NUMPAT"]);
+        assert_eq!(received(&publisher_inbox), ":1\r\n");
+        run(&mut session, 1, &["PUBSUB", "CHANNELS", "b*"]);
+        assert_eq!(received(&publisher_inbox), "*1\r\n$1\r\nb\r\n");
+
+        run(&mut session, 0, &["UNSUBSCRIBE", "a"]);
+        assert_eq!(
+            received(&inbox),
+            "*3\r\n$11\r\nunsubscribe\r\n$1\r\na\r\n:2\r\n"
+        );
+        run(&mut session, 0, &["PUNSUBSCRIBE"]);
+        run(&mut session, 0, &["UNSUBSCRIBE"]);
+        assert_eq!(
+            received(&inbox),
+            "*3\r\n$12\r\npunsubscribe\r\n$2\r\nc*\r\n:1\r\n\
+             *3\r\n$11\r\nunsubscribe\r\n$1\r\nb\r\n:0\r\n"
+        );
+        run(&mut session, 0, &["UNSUBSCRIBE"]);
+        assert_eq!(
+            received(&inbox),
+            "*3\r\n$11\r\nunsubscribe\r\n$-1\r\n:0\r\n"
+        );
+
+        // back to a regular client
+        run(&mut session, 0, &["PING"]);
+        assert_eq!(received(&inbox), "+PONG\r\n");
+        run(&mut session, 1, &["PUBLISH", "a", "hello"]);
+        assert_eq!(received(&publisher_inbox), ":0\r\n");
+    }
+}
@@ -0,0 +1,251 @@
+use std::sync::Arc;
+
+use crate::client::Client;
+use crate::commands::{arg, NOT_AN_INTEGER, SYNTAX_ERROR};
+use crate::dictionary_server::DictionaryServer;
+use crate::parser::Value;
+use crate::rdb;
+use crate::replication::{self, LinkState, Replica};
+use crate::server::{lock, Server};
+
+/// `REPLICAOF host port | NO ONE` (or `SLAVEOF`) starts replicating another
+/// server, or turns a replica into a master keeping its dataset.
+pub fn replicaof_command(values: &[Value], server: &Arc<Server>, name: &str) -> Value {
+    let (host, port) = (arg(values, 0), arg(values, 1));
+    if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+        replication::promote(server);
+        server.config().replicaof = None;
+        return Value::ok();
+    }
+    let port = match port.parse::<u16>() {
+        Ok(port) => port,
+        Err(_) => return Value::error("ERR Invalid master port"),
+    };
+    let current = lock(&server.replication)
+        .master
+        .as_ref()
+        .is_some_and(|link| link.host == host && link.port == port);
+    if current {
+        return Value::simple_string("OK Already connected to specified master");
+    }
+    if let Err(e) = replication::follow(server, &host, port) {
+        return Value::error(&format!("ERR {} failed: {}", name.to_uppercase(), e));
+    }
+    server.config().replicaof = Some((host, port));
+    Value::ok()
+}
+
+/// `REPLCONF option value [option value ...]` is sent by replicas during the
+/// handshake, and `REPLCONF ACK offset` afterwards which gets no reply.
+pub fn replconf_command(
+    values: &[Value],
+    server: &Arc<Server>,
+    client: &mut Client,
+) -> Option<Value> {
+    if !values.len().is_multiple_of(2) {
+        return Some(Value::error(SYNTAX_ERROR));
+    }
+    for i in (0..values.len()).step_by(2) {
+        let option = arg(values, i).to_lowercase();
+        let value = arg(values, i + 1);
+        match option.as_str() {
+            "listening-port" => match value.parse::<u16>() {
+                Ok(port) => client.replica_port = Some(port),
+                Err(_) => return Some(Value::error(NOT_AN_INTEGER)),
+            },
+            "ack" => {
+                if let Some(replica) = lock(&server.replication).replicas.get_mut(&client.id) {
+                    replica.ack_offset = value.parse().unwrap_or(replica.ack_offset);
+                }
+                return None;
+            }
+            // only a replica answers GETACK, the link to its master does
+            "getack" => return None,
+            "capa" | "ip-address" => {}
+            _ => {
+                return Some(Value::error(&format!(
+                    "ERR Unrecognized REPLCONF option: {}",
+                    option
+                )))
+            }
+        }
+    }
+    Some(Value::ok())
+}
+
+/// `PSYNC replid offset` turns the connection into a replica. When the
+/// backlog still holds the stream from `offset` on it continues there,
+/// otherwise the whole dataset is sent as an RDB snapshot first. From then on
+/// every write command is streamed to it.
+pub fn psync_command(
+    values: &[Value],
+    server: &Arc<Server>,
+    client: &mut Client,
+    map: &mut DictionaryServer,
+) -> Option<Value> {
+    let replid = arg(values, 0);
+    let offset = match arg(values, 1).parse::<i64>() {
+        Ok(offset) => offset,
+        Err(_) => return Some(Value::error(NOT_AN_INTEGER)),
+    };
+    let outbox = match &client.outbox {
+        Some(outbox) => outbox.clone(),
+        None => return Some(Value::error("ERR PSYNC needs a connection")),
+    };
+
+    // the snapshot and the offset it ends at are taken while the dictionary
+    // is locked, so no write can slip in between them
+    let mut replication = lock(&server.replication);
+    if replication
+        .master
+        .as_ref()
+        .is_some_and(|link| link.state != LinkState::Connected)
+    {
+        return Some(Value::error(
+            "NOMASTERLINK Can't SYNC while not connected with my master",
+        ));
+    }
+    let backlog = u64::try_from(offset)
+        .ok()
+        .and_then(|offset| replication.backlog_from(&replid, offset));
+    let sync = match backlog {
+        Some(backlog) => {
+            let mut sync = format!("+CONTINUE {}\r\n", replication.replid).into_bytes();
+            sync.extend(backlog);
+            sync
+        }
+        None => {
+            // the replica starts out in database 0, the stream has to tell it
+            // where the next write goes. A master selects it again, a replica
+            // passes on the stream of its own master as it is, so the
+            // snapshot tells where that stream is.
+            if replication.master.is_none() {
+                replication.selected_db = None;
+            }
+            let snapshot = rdb::dump_for_replica(map, replication.selected_db);
+            let mut sync = format!(
+                "+FULLRESYNC {} {}\r\n${}\r\n",
+                replication.replid,
+                replication.offset,
+                snapshot.len()
+            )
+            .into_bytes();
+            sync.extend(snapshot);
+            sync
+        }
+    };
+    let _ = outbox.send(sync);
+    replication.replicas.insert(
+        client.id,
+        Replica {
+            outbox,
+            ip: client
+                .addr
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_default(),
+            port: client.replica_port.unwrap_or(0),
+            ack_offset: 0,
+        },
+    );
+    None
+}
+
+/// `ROLE` a master replies its offset and replicas, a replica its master and
+/// the state of the link.
+pub fn role_command(server: &Arc<Server>) -> Value {
+    let replication = lock(&server.replication);
+    match &replication.master {
+        Some(link) => Value::array(vec![
+            Value::bulk_string("slave"),
+            Value::bulk_string(&link.host),
+            Value::integer(link.port as i64),
+            Value::bulk_string(link.state.name()),
+            Value::integer(replication.offset as i64),
+        ]),
+        None => Value::array(vec![
+            Value::bulk_string("master"),
+            Value::integer(replication.offset as i64),
+            Value::array(
+                replication
+                    .replicas
+                    .values()
+                    .map(|replica| {
+                        Value::array(vec![
+                            Value::bulk_string(&replica.ip),
+                            Value::bulk_string(replica.port.to_string()),
+                            Value::bulk_string(replica.ack_offset.to_string()),
+                        ])
+                    })
+                    .collect(),
+            ),
+        ]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::commands::test::Session;
+    use crate::parser::stringify;
+
+    fn received(inbox: &mpsc::Receiver<Vec<u8>>) -> Vec<u8> {
+        inbox.try_iter().flatten().collect()
+    }
+
+    #[test]
+    fn test_psync() {
+        let mut session = Session::new(3);
+        session.clients[0].user = Some("default".to_string());
+        session.run(0, &["SET", "a", "1"]);
+
+        let inbox = session.connect(1);
+        assert_eq!(
+            session.run(1, &["REPLCONF", "listening-port", "6380"]),
+            "+OK\r\n"
+        );
+        assert_eq!(session.run(1, &["PSYNC", "?", "-1"]), "");
+
+        // a full resync starts from a snapshot holding what was written so far
+        let sync = received(&inbox);
+        let (replid, offset) = {
+            let replication = lock(&session.server.replication);
+            (replication.replid.clone(), replication.offset)
+        };
+        let header = format!("+FULLRESYNC {} {}\r\n", replid, offset);
+        assert!(sync.starts_with(header.as_bytes()));
+        let rest = &sync[header.len()..];
+        let newline = rest.windows(2).position(|w| w == b"\r\n").unwrap();
+        let len: usize = String::from_utf8_lossy(&rest[1..newline]).parse().unwrap();
+        assert_eq!(rest.len(), newline + 2 + len);
+        let restored = rdb::restore(&rest[newline + 2..], 16).unwrap();
+        assert!(restored.server.contains_key(b"a".as_slice()));
+
+        // then every write is streamed, after the database it goes to
+        session.run(0, &["SET", "b", "2"]);
+        session.run(0, &["GET", "b"]);
+        let stream = "*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n*3\r\n$3\r\nSET\r\n$1\r\nb\r\n$1\r\n2\r\n";
+        assert_eq!(received(&inbox), stream.as_bytes());
+        let ack = (offset + 50).to_string();
+        assert_eq!(session.run(1, &["REPLCONF", "ACK", &ack]), "");
+        assert_eq!(
+            stringify(&role_command(&session.server)),
+            format!(
+                "*3\r\n$6\r\nmaster\r\n:{0}\r\n*1\r\n*3\r\n$0\r\n\r\n$4\r\n6380\r\n${1}\r\n{0}\r\n",
+                offset + 50,
+                (offset + 50).to_string().len()
+            )
+            .into_bytes()
+        );
+
+        // a replica which reconnects continues where it stopped
+        let inbox = session.connect(2);
+        let psync = ["PSYNC", &replid, &(offset + 1).to_string()];
+        assert_eq!(session.run(2, &psync), "");
+        assert_eq!(
+            received(&inbox),
+            format!("+CONTINUE {}\r\n{}", replid, stream).into_bytes()
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::aof;
+use crate::client::{Client, Wrapping};
+use crate::commands::{arg, arg_bytes, call, table, wrong_arity, NOT_AN_INTEGER};
+use crate::dictionary_server::DictionaryServer;
+use crate::parser::{Protocol, Value};
+use crate::scripting::{Scripts, NOSCRIPT};
+use crate::server::{lock, Server};
+
+/// The script's arguments, `numkeys key ... arg ...`, as they are passed to
+/// it in `KEYS` and `ARGV`.
+fn keys_and_args(values: &[Value]) -> Result<[Vec<Vec<u8>>; 2], Value> {
+    let numkeys = arg(values, 1)
+        .parse::<i64>()
+        .map_err(|_| Value::error(NOT_AN_INTEGER))?;
+    if numkeys < 0 {
+        return Err(Value::error("ERR Number of keys can't be negative"));
+    }
+    let mut keys: Vec<Vec<u8>> = (2..values.len()).map(|i| arg_bytes(values, i)).collect();
+    if numkeys as usize > keys.len() {
+        return Err(Value::error(
+            "ERR Number of keys can't be greater than number of args",
+        ));
+    }
+    let args = keys.split_off(numkeys as usize);
+    Ok([keys, args])
+}
+
+/// Run a cached script. The dictionary stays locked until it is done so no
+/// other client sees it half way, and the writes it makes are propagated
+/// wrapped in `MULTI`/`EXEC` instead of the script itself, so replaying
+/// them doesn't depend on the script cache. After `lua-time-limit` the
+/// script is busy, other clients may kill it with `SCRIPT KILL` as long as
+/// it didn't write anything.
+fn run_script(
+    scripts: &Scripts,
+    sha: &str,
+    values: &[Value],
+    server: &Arc<Server>,
+    client: &mut Client,
+    map: &mut DictionaryServer,
+) -> Value {
+    let [keys, args] = try_reply!(keys_and_args(values));
+    // scripts see RESP2 replies whatever the client speaks, and the
+    // commands they call can't block. A `SELECT` of the script doesn't
+    // outlive it.
+    let protocol = std::mem::replace(&mut client.protocol, Protocol::Resp2);
+    let db = client.db;
+    let time_limit = u64::try_from(server.config().lua_time_limit)
+        .ok()
+        .filter(|millis| *millis > 0)
+        .map(Duration::from_millis);
+    let dirty = map.dirty;
+    let deny_blocking = std::mem::replace(&mut client.deny_blocking, true);
+    // inside `EXEC` the transaction already wraps what the script writes
+    let wrap = client.wrapping == Wrapping::Off;
+    if wrap {
+        client.wrapping = Wrapping::Pending;
+    }
+    let reply = scripts.run(sha, &keys, &args, time_limit, &server.script, |command| {
+        let value = Value::array(command.iter().map(Value::bulk_string).collect());
+        match table::lookup(&arg(&value.array, 0)) {
+            None => return Value::error("ERR Unknown Redis command called from script"),
+            Some(command) if command.has_flag(table::NOSCRIPT) => {
+                return Value::error("ERR This Redis command is not allowed from script")
+            }
+            Some(command) if !command.accepts(value.array.len()) => {
+                return Value::error("ERR Wrong number of args calling Redis command from script")
+            }
+            Some(_) => {}
+        }
+        let reply = call(&value, server, client, map).unwrap_or_else(Value::null);
+        if map.dirty != dirty {
+            server.script.wrote();
+        }
+        reply
+    });
+    if wrap {
+        if client.wrapping == Wrapping::Started {
+            server.propagate(client.db, &aof::encode_command(&["EXEC"]));
+        }
+        client.wrapping = Wrapping::Off;
+    }
+    client.deny_blocking = deny_blocking;
+    client.protocol = protocol;
+    client.db = db;
+    reply
+}
+
+/// `EVAL script numkeys [key ...] [arg ...]` runs a Lua script, which is
+/// cached for `EVALSHA` as well.
+pub fn eval_command(
+    values: &[Value],
+    server: &Arc<Server>,
+    client: &mut Client,
+    map: &mut DictionaryServer,
+) -> Value {
+    let mut scripts = lock(&server.scripts);
+    let sha = try_reply!(scripts.load(&arg_bytes(values, 0)));
+    run_script(&scripts, &sha, values, server, client, map)
+}
+
+/// `EVALSHA sha1 numkeys [key ...] [arg ...]` runs a script cached by
+/// `EVAL` or `SCRIPT LOAD`.
+pub fn evalsha_command(
+    values: &[Value],
+    server: &Arc<Server>,
+    client: &mut Client,
+    map: &mut DictionaryServer,
+) -> Value {
+    let scripts = lock(&server.scripts);
+    let sha = arg(values, 0).to_lowercase();
+    if !scripts.exists(&sha) {
+        return Value::error(NOSCRIPT);
+    }
+    run_script(&scripts, &sha, values, server, client, map)
+}
+
+/// `SCRIPT LOAD script | EXISTS sha1 [sha1 ...] | FLUSH [ASYNC|SYNC] | KILL`
+/// manages the script cache. A script holds the dictionary while it runs,
+/// `KILL` only gets here when there is none, see `call_while_busy`.
+pub fn script_command(values: &[Value], server: &Arc<Server>) -> Value {
+    let subcommand = arg(values, 0).to_uppercase();
+    let mut scripts = lock(&server.scripts);
+    match subcommand.as_str() {
+        "LOAD" if values.len() == 2 => match scripts.load(&arg_bytes(values, 1)) {
+            Ok(sha) => Value::bulk_string(sha),
+            Err(e) => e,
+        },
+        "EXISTS" if values.len() > 1 => Value::array(
+            (1..values.len())
+                .map(|i| Value::integer(scripts.exists(&arg(values, i).to_lowercase()) as i64))
+                .collect(),
+        ),
+        "FLUSH" if values.len() <= 2 => {
+            if values.len() == 2
+                && !matches!(arg(values, 1).to_uppercase().as_str(), "ASYNC" | "SYNC")
+            {
+                return Value::error("ERR SCRIPT FLUSH only support SYNC|ASYNC option");
+            }
+            scripts.flush();
+            Value::ok()
+        }
+        "KILL" if values.len() == 1 => server.script.kill(),
+        "LOAD" | "EXISTS" | "FLUSH" | "KILL" => {
+            wrong_arity(&format!("script|{}", subcommand.to_lowercase()))
+        }
+        _ => Value::error(&format!(
+            "ERR unknown subcommand '{}'. Try SCRIPT HELP.",
+            arg(values, 0)
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::Session;
+    use crate::digest::{sha1, to_hex};
+
+    #[test]
+    fn test_eval() {
+        let mut session = Session::new(1);
+
+        let script = "redis.call('SET', KEYS[1], ARGV[1]) return redis.call('GET', KEYS[1])";
+        assert_eq!(
+            session.run(0, &["EVAL", script, "1", "k", "v"]),
+            "$1\r\nv\r\n"
+        );
+        let sha = to_hex(&sha1(script.as_bytes()));
+        assert_eq!(
+            session.run(0, &["EVALSHA", &sha, "1", "k", "w"]),
+            "$1\r\nw\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["EVAL", "return {1, 'two', {3}}", "0"]),
+            "*3\r\n:1\r\n$3\r\ntwo\r\n*1\r\n:3\r\n"
+        );
+
+        // the writes are propagated instead of the scripts, read only ones
+        // propagate nothing
+        session.run(0, &["EVAL", "return redis.call('GET', 'k')", "0"]);
+        let set = |value: &str| format!("*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\n{}\r\n", value);
+        let expected = format!(
+            "*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n*1\r\n$5\r\nMULTI\r\n{}*1\r\n$4\r\nEXEC\r\n*1\r\n$5\r\nMULTI\r\n{}*1\r\n$4\r\nEXEC\r\n",
+            set("v"),
+            set("w")
+        );
+        assert_eq!(session.backlog(), expected);
+
+        assert_eq!(
+            session.run(0, &["EVAL", "return redis.call('LPUSH', 'k', 'x')", "0"]),
+            "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+        assert_eq!(
+            session.run(
+                0,
+                &[
+                    "EVAL",
+                    "return redis.pcall('LPUSH', 'k', 'x').err ~= nil",
+                    "0"
+                ]
+            ),
+            ":1\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["EVAL", "return redis.call('MULTI')", "0"]),
+            "-ERR This Redis command is not allowed from script\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["EVAL", "return redis.call('NOPE')", "0"]),
+            "-ERR Unknown Redis command called from script\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["EVAL", "return 1", "2", "k"]),
+            "-ERR Number of keys can't be greater than number of args\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["EVAL", "return 1", "-1"]),
+            "-ERR Number of keys can't be negative\r\n"
+        );
+        assert_eq!(
+            session.run(
+                0,
+                &["EVALSHA", "ffffffffffffffffffffffffffffffffffffffff", "0"]
+            ),
+            format!("-{}\r\n", NOSCRIPT)
+        );
+    }
+
+    #[test]
+    fn test_globals_are_protected() {
+        let mut session = Session::new(1);
+
+        assert!(session
+            .run(0, &["EVAL", "leak = 1", "0"])
+            .ends_with("user_script:1: Attempt to modify a readonly table\r\n"));
+        assert!(session.run(0, &["EVAL", "return leak", "0"]).ends_with(
+            "user_script:1: Script attempted to access nonexistent global variable 'leak'\r\n"
+        ));
+        // `KEYS` and `ARGV` are replaced on every run
+        assert_eq!(
+            session.run(0, &["EVAL", "return KEYS[1]", "1", "k"]),
+            "$1\r\nk\r\n"
+        );
+        assert_eq!(session.run(0, &["EVAL", "return KEYS[1]", "0"]), "$-1\r\n");
+    }
+
+    #[test]
+    fn test_script_cache() {
+        let mut session = Session::new(1);
+
+        let sha = "e0e1f9fabfc9d4800c877a703b823ac0578ff8db";
+        assert_eq!(
+            session.run(0, &["SCRIPT", "LOAD", "return 1"]),
+            format!("$40\r\n{}\r\n", sha)
+        );
+        assert_eq!(
+            session.run(0, &["SCRIPT", "EXISTS", &sha.to_uppercase(), "nope"]),
+            "*2\r\n:1\r\n:0\r\n"
+        );
+        assert_eq!(session.run(0, &["EVALSHA", sha, "0"]), ":1\r\n");
+        assert!(session
+            .run(0, &["SCRIPT", "LOAD", "return +"])
+            .starts_with("-ERR Error compiling script (new function): user_script:1:"));
+        assert_eq!(session.run(0, &["SCRIPT", "FLUSH"]), "+OK\r\n");
+        assert_eq!(session.run(0, &["SCRIPT", "EXISTS", sha]), "*1\r\n:0\r\n");
+        assert_eq!(
+            session.run(0, &["SCRIPT", "FLUSH", "LATER"]),
+            "-ERR SCRIPT FLUSH only support SYNC|ASYNC option\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["SCRIPT", "KILL"]),
+            "-NOTBUSY No scripts in execution right now.\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["SCRIPT", "NOPE"]),
+            "-ERR unknown subcommand 'NOPE'. Try SCRIPT HELP.\r\n"
+        );
+    }
+}
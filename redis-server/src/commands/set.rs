@@ -0,0 +1,196 @@
+use std::collections::HashSet;
+
+use crate::commands::arg_bytes;
+use crate::dictionary_server::{DictionaryServer, RedisValue};
+use crate::parser::Value;
+
+/// Operation `SINTER`, `SUNION` and `SDIFF` apply to their keys.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SetOperation {
+    Inter,
+    Union,
+    Diff,
+}
+
+/// `SADD key member [member ...]` replies how many members were added.
+pub fn sadd_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let set = try_reply!(map.set_mut(&key, true)).unwrap();
+    let added = (1..values.len())
+        .filter(|i| set.insert(arg_bytes(values, *i)))
+        .count();
+    map.modified(&key);
+    Value::integer(added as i64)
+}
+
+/// `SREM key member [member ...]` replies how many members were removed.
+pub fn srem_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let set = match try_reply!(map.set_mut(&key, false)) {
+        Some(set) => set,
+        None => return Value::integer(0),
+    };
+    let removed = (1..values.len())
+        .filter(|i| set.remove(&arg_bytes(values, *i)))
+        .count();
+    if removed > 0 {
+        map.modified(&key);
+        map.remove_if_empty(&key);
+    }
+    Value::integer(removed as i64)
+}
+
+/// `SMEMBERS key` every member of the set, in no particular order.
+pub fn smembers_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let set = try_reply!(map.set_mut(&key, false));
+    let members = set
+        .map(|set| set.iter().map(Value::bulk_string).collect())
+        .unwrap_or_default();
+    Value::set(members)
+}
+
+/// `SISMEMBER key member` 1 if `member` is in the set.
+pub fn sismember_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let set = try_reply!(map.set_mut(&key, false));
+    let member = set.is_some_and(|set| set.contains(&arg_bytes(values, 1)));
+    Value::integer(member as i64)
+}
+
+/// `SCARD key` number of members in the set.
+pub fn scard_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let len = try_reply!(map.set_mut(&key, false)).map_or(0, |set| set.len());
+    Value::integer(len as i64)
+}
+
+/// `SINTER key [key ...]`, `SUNION` and `SDIFF`. Missing keys count as empty
+/// sets, `SDIFF` removes the members of every other set from the first one.
+pub fn set_operation_command(
+    values: &[Value],
+    map: &mut DictionaryServer,
+    operation: SetOperation,
+) -> Value {
+    let keys: Vec<Vec<u8>> = (0..values.len()).map(|i| arg_bytes(values, i)).collect();
+    // type check and expire every key first, after that the sets can be
+    // borrowed together straight from the dictionary
+    for key in &keys {
+        try_reply!(map.set_mut(key, false));
+    }
+    let sets: Vec<Option<&HashSet<Vec<u8>>>> = keys
+        .iter()
+        .map(|key| {
+            match map
+                .server
+                .get(key.as_slice())
+                .map(|entry| entry.value.as_ref())
+            {
+                Some(RedisValue::Set(set)) => Some(set),
+                _ => None,
+            }
+        })
+        .collect();
+
+    let members: Vec<&Vec<u8>> = match operation {
+        SetOperation::Inter => {
+            if sets.iter().any(|set| set.is_none()) {
+                Vec::new()
+            } else {
+                let mut sets: Vec<&HashSet<Vec<u8>>> = sets.into_iter().flatten().collect();
+                // probe the smallest set against the others
+                sets.sort_by_key(|set| set.len());
+                sets[0]
+                    .iter()
+                    .filter(|member| sets[1..].iter().all(|set| set.contains(*member)))
+                    .collect()
+            }
+        }
+        SetOperation::Union => {
+            let union: HashSet<&Vec<u8>> = sets.into_iter().flatten().flatten().collect();
+            union.into_iter().collect()
+        }
+        SetOperation::Diff => match sets[0] {
+            Some(first) => first
+                .iter()
+                .filter(|member| sets[1..].iter().flatten().all(|set| !set.contains(*member)))
+                .collect(),
+            None => Vec::new(),
+        },
+    };
+    Value::set(members.into_iter().map(Value::bulk_string).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commands::test::run;
+    use crate::dictionary_server::DictionaryServer;
+
+    /// Members of an array reply sorted, sets have no order.
+    fn sorted_members(reply: &str) -> Vec<String> {
+        let mut members: Vec<String> = reply
+            .split("\r\n")
+            .skip(1)
+            .filter(|line| !line.is_empty() && !line.starts_with('$'))
+            .map(|line| line.to_string())
+            .collect();
+        members.sort();
+        members
+    }
+
+    #[test]
+    fn test_sadd_srem_and_members() {
+        let mut map = DictionaryServer::new();
+        assert_eq!(run(&mut map, &["SADD", "s", "a", "b", "a"]), ":2\r\n");
+        assert_eq!(run(&mut map, &["SADD", "s", "b", "c"]), ":1\r\n");
+        assert_eq!(run(&mut map, &["SCARD", "s"]), ":3\r\n");
+        assert_eq!(run(&mut map, &["SISMEMBER", "s", "c"]), ":1\r\n");
+        assert_eq!(run(&mut map, &["SISMEMBER", "s", "x"]), ":0\r\n");
+        assert_eq!(
+            sorted_members(&run(&mut map, &["SMEMBERS", "s"])),
+            ["a", "b", "c"]
+        );
+        assert_eq!(run(&mut map, &["SREM", "s", "a", "x"]), ":1\r\n");
+        assert_eq!(run(&mut map, &["SREM", "s", "b", "c"]), ":2\r\n");
+        assert!(map.server.is_empty());
+        assert_eq!(run(&mut map, &["SMEMBERS", "s"]), "*0\r\n");
+        assert_eq!(run(&mut map, &["SCARD", "s"]), ":0\r\n");
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let mut map = DictionaryServer::new();
+        run(&mut map, &["SADD", "a", "1", "2", "3", "4"]);
+        run(&mut map, &["SADD", "b", "3", "4", "5"]);
+        run(&mut map, &["SADD", "c", "4", "6"]);
+        assert_eq!(
+            sorted_members(&run(&mut map, &["SINTER", "a", "b"])),
+            ["3", "4"]
+        );
+        assert_eq!(
+            sorted_members(&run(&mut map, &["SINTER", "a", "b", "c"])),
+            ["4"]
+        );
+        assert_eq!(run(&mut map, &["SINTER", "a", "missing"]), "*0\r\n");
+        assert_eq!(
+            sorted_members(&run(&mut map, &["SUNION", "b", "c", "missing"])),
+            ["3", "4", "5", "6"]
+        );
+        assert_eq!(
+            sorted_members(&run(&mut map, &["SDIFF", "a", "b", "missing"])),
+            ["1", "2"]
+        );
+        assert_eq!(run(&mut map, &["SDIFF", "missing", "a"]), "*0\r\n");
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        let mut map = DictionaryServer::new();
+        run(&mut map, &["SET", "str", "v"]);
+        run(&mut map, &["SADD", "s", "a"]);
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        assert_eq!(run(&mut map, &["SADD", "str", "a"]), wrongtype);
+        assert_eq!(run(&mut map, &["SUNION", "s", "str"]), wrongtype);
+        assert_eq!(run(&mut map, &["GET", "s"]), wrongtype);
+    }
+}
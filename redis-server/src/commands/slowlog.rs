@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use crate::commands::{arg, wrong_arity};
+use crate::parser::Value;
+use crate::server::{lock, Server};
+
+/// Entries `SLOWLOG GET` replies without a count.
+const DEFAULT_COUNT: usize = 10;
+
+/// `SLOWLOG GET [count] | LEN | RESET` reads and clears the log of commands
+/// which ran longer than `slowlog-log-slower-than` microseconds.
+pub fn slowlog_command(values: &[Value], server: &Arc<Server>) -> Value {
+    let subcommand = arg(values, 0).to_uppercase();
+    match subcommand.as_str() {
+        "GET" if values.len() <= 2 => {
+            let count = match values.len() {
+                1 => DEFAULT_COUNT,
+                _ => match arg(values, 1).parse::<i64>() {
+                    Ok(-1) => usize::MAX,
+                    Ok(count) if count >= 0 => count as usize,
+                    _ => return Value::error("ERR count should be greater than or equal to -1"),
+                },
+            };
+            let slowlog = lock(&server.slowlog);
+            Value::array(
+                slowlog
+                    .get(count)
+                    .map(|entry| {
+                        Value::array(vec![
+                            Value::integer(entry.id as i64),
+                            Value::integer(entry.timestamp as i64),
+                            Value::integer(entry.duration as i64),
+                            Value::array(entry.args.iter().map(Value::bulk_string).collect()),
+                            Value::bulk_string(&entry.addr),
+                            Value::bulk_string(&entry.name),
+                        ])
+                    })
+                    .collect(),
+            )
+        }
+        "LEN" if values.len() == 1 => Value::integer(lock(&server.slowlog).len() as i64),
+        "RESET" if values.len() == 1 => {
+            lock(&server.slowlog).reset();
+            Value::ok()
+        }
+        "GET" | "LEN" | "RESET" => wrong_arity(&format!("slowlog|{}", subcommand.to_lowercase())),
+        _ => Value::error(&format!(
+            "ERR unknown subcommand '{}'. Try SLOWLOG HELP.",
+            arg(values, 0)
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commands::test::Session;
+    use crate::config::Config;
+
+    #[test]
+    fn test_slowlog_command() {
+        let config = Config {
+            slowlog_log_slower_than: 0,
+            ..Config::default()
+        };
+        let mut session = Session::with_config(config, 1);
+        session.clients[0].name = Some("app".to_string());
+
+        session.run(0, &["SET", "k", "v"]);
+        session.run(0, &["GET", "k"]);
+        // SLOWLOG itself is logged once it's done
+        assert_eq!(session.run(0, &["SLOWLOG", "LEN"]), ":2\r\n");
+        let reply = session.run(0, &["SLOWLOG", "GET", "1"]);
+        assert!(reply.starts_with("*1\r\n*6\r\n:2\r\n"));
+        assert!(reply.ends_with("*2\r\n$7\r\nSLOWLOG\r\n$3\r\nLEN\r\n$0\r\n\r\n$3\r\napp\r\n"));
+        assert!(session
+            .run(0, &["SLOWLOG", "GET", "-1"])
+            .starts_with("*4\r\n"));
+        assert!(session.run(0, &["SLOWLOG", "GET"]).starts_with("*5\r\n"));
+        assert_eq!(
+            session.run(0, &["SLOWLOG", "GET", "-2"]),
+            "-ERR count should be greater than or equal to -1\r\n"
+        );
+
+        assert_eq!(session.run(0, &["SLOWLOG", "RESET"]), "+OK\r\n");
+        assert_eq!(session.run(0, &["SLOWLOG", "LEN"]), ":1\r\n");
+        session.run(0, &["CONFIG", "SET", "slowlog-log-slower-than", "-1"]);
+        session.run(0, &["SET", "k", "v"]);
+        assert_eq!(session.run(0, &["SLOWLOG", "LEN"]), ":2\r\n");
+        assert_eq!(
+            session.run(0, &["SLOWLOG", "LEN", "x"]),
+            "-ERR wrong number of arguments for 'slowlog|len' command\r\n"
+        );
+    }
+}
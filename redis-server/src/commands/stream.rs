@@ -0,0 +1,1170 @@
+use std::time::Duration;
+
+use crate::blocking::BlockedOn;
+use crate::client::Client;
+use crate::commands::{arg, arg_bytes, block, can_block, parse_int, wrong_arity, SYNTAX_ERROR};
+use crate::dictionary_server::{now_ms, DictionaryServer};
+use crate::parser::{Protocol, Value};
+use crate::stream::{ConsumerGroup, Fields, PendingEntry, Stream, StreamId};
+
+const INVALID_ID: &str = "ERR Invalid stream ID specified as stream command argument";
+
+/// Parse an id argument, a bare `ms` gets `default_seq` as sequence number.
+fn parse_id(string: &str, default_seq: u64) -> Result<StreamId, Value> {
+    StreamId::parse(string, default_seq).ok_or_else(|| Value::error(INVALID_ID))
+}
+
+/// Bound of `XRANGE` and `XPENDING`: `-` and `+` are the smallest and
+/// greatest ids, a `(` prefix excludes the id itself.
+fn parse_bound(string: &str, start: bool) -> Result<StreamId, Value> {
+    match string {
+        "-" => return Ok(StreamId::MIN),
+        "+" => return Ok(StreamId::MAX),
+        _ => {}
+    }
+    let default_seq = if start { 0 } else { u64::MAX };
+    let id = match string.strip_prefix('(') {
+        Some(exclusive) => {
+            let id = parse_id(exclusive, default_seq)?;
+            if start {
+                id.next()
+            } else {
+                id.prev()
+            }
+        }
+        None => Some(parse_id(string, default_seq)?),
+    };
+    id.ok_or_else(|| {
+        Value::error(&format!(
+            "ERR invalid {} ID for the interval",
+            if start { "start" } else { "end" }
+        ))
+    })
+}
+
+fn no_group(key: &[u8], group: &str) -> Value {
+    Value::error(&format!(
+        "NOGROUP No such key '{}' or consumer group '{}'",
+        String::from_utf8_lossy(key),
+        group
+    ))
+}
+
+/// An entry as `[id, [field, value, ...]]`, the fields are a null array for
+/// an entry which was pending but is gone from the stream.
+fn entry_reply(id: StreamId, fields: Option<&Fields>) -> Value {
+    let fields = match fields {
+        Some(fields) => Value::array(
+            fields
+                .iter()
+                .flat_map(|(field, value)| [Value::bulk_string(field), Value::bulk_string(value)])
+                .collect(),
+        ),
+        None => Value::null_array(),
+    };
+    Value::array(vec![Value::bulk_string(id.to_string()), fields])
+}
+
+fn entries_reply<F: std::borrow::Borrow<Fields>>(entries: Vec<(StreamId, F)>) -> Value {
+    Value::array(
+        entries
+            .iter()
+            .map(|(id, fields)| entry_reply(*id, Some(fields.borrow())))
+            .collect(),
+    )
+}
+
+/// Reply of `XREAD` and `XREADGROUP`: the entries of every stream, a map
+/// keyed by the stream with RESP3 and pairs with RESP2.
+fn streams_reply(streams: Vec<(Value, Value)>, protocol: Protocol) -> Value {
+    match protocol {
+        Protocol::Resp3 => Value::map(streams),
+        Protocol::Resp2 => Value::array(
+            streams
+                .into_iter()
+                .map(|(key, entries)| Value::array(vec![key, entries]))
+                .collect(),
+        ),
+    }
+}
+
+/// How `XADD` and `XTRIM` trim the stream.
+enum Threshold {
+    MaxLen(usize),
+    MinId(StreamId),
+}
+
+#[derive(Default)]
+struct AddOptions {
+    nomkstream: bool,
+    threshold: Option<Threshold>,
+    /// `~`, the trimming may stop early. It is always exact here, but
+    /// `LIMIT` is only allowed with it.
+    approximate: bool,
+    limit: Option<usize>,
+}
+
+impl AddOptions {
+    /// Trim `stream`, returns how many entries were removed.
+    fn trim(&self, stream: &mut Stream) -> usize {
+        match self.threshold {
+            Some(Threshold::MaxLen(maxlen)) => stream.trim_maxlen(maxlen, self.limit),
+            Some(Threshold::MinId(minid)) => stream.trim_minid(minid, self.limit),
+            None => 0,
+        }
+    }
+}
+
+/// Parse `[NOMKSTREAM] [<MAXLEN | MINID> [= | ~] threshold [LIMIT count]]`
+/// after the key. `XADD` stops at its id and gets its position back.
+fn parse_add_options(values: &[Value], xadd: bool) -> Result<(AddOptions, usize), Value> {
+    let mut options = AddOptions::default();
+    let mut i = 1;
+    while i < values.len() {
+        let option = arg(values, i).to_uppercase();
+        let has_value = i + 1 < values.len();
+        match option.as_str() {
+            "NOMKSTREAM" if xadd => options.nomkstream = true,
+            "MAXLEN" | "MINID" if has_value => {
+                let maxlen = option == "MAXLEN";
+                if matches!(
+                    (&options.threshold, maxlen),
+                    (Some(Threshold::MinId(_)), true) | (Some(Threshold::MaxLen(_)), false)
+                ) {
+                    return Err(Value::error(
+                        "ERR syntax error, MAXLEN and MINID options at the same time are not compatible",
+                    ));
+                }
+                i += 1;
+                match arg(values, i).as_str() {
+                    "~" => {
+                        options.approximate = true;
+                        i += 1;
+                    }
+                    "=" => i += 1,
+                    _ => {}
+                }
+                if i >= values.len() {
+                    return Err(Value::error(SYNTAX_ERROR));
+                }
+                let threshold = arg(values, i);
+                options.threshold = Some(if maxlen {
+                    let maxlen = parse_int(&threshold)?;
+                    if maxlen < 0 {
+                        return Err(Value::error("ERR The MAXLEN argument must be >= 0."));
+                    }
+                    Threshold::MaxLen(maxlen as usize)
+                } else {
+                    Threshold::MinId(parse_id(&threshold, 0)?)
+                });
+            }
+            "LIMIT" if has_value => {
+                let limit = parse_int(&arg(values, i + 1))?;
+                if limit < 0 {
+                    return Err(Value::error("ERR The LIMIT argument must be >= 0."));
+                }
+                options.limit = Some(limit as usize);
+                i += 1;
+            }
+            _ if xadd => break,
+            _ => return Err(Value::error(SYNTAX_ERROR)),
+        }
+        i += 1;
+    }
+    if options.limit.is_some() && !options.approximate {
+        return Err(Value::error(
+            "ERR syntax error, LIMIT cannot be used without the special ~ option",
+        ));
+    }
+    Ok((options, i))
+}
+
+/// Id of the entry `XADD` adds: `*` picks one out of the current time,
+/// `ms-*` only the sequence number.
+fn added_id(stream: &Stream, requested: &str) -> Result<StreamId, Value> {
+    const TOO_SMALL: &str =
+        "ERR The ID specified in XADD is equal or smaller than the target stream top item";
+    if requested == "*" {
+        return stream.next_id(now_ms(), false).ok_or_else(|| {
+            Value::error(
+                "ERR The stream has exhausted the last possible ID, unable to add more items",
+            )
+        });
+    }
+    if let Some(ms) = requested.strip_suffix("-*") {
+        let ms = ms.parse().map_err(|_| Value::error(INVALID_ID))?;
+        return stream
+            .next_id(ms, true)
+            .ok_or_else(|| Value::error(TOO_SMALL));
+    }
+    let id = parse_id(requested, 0)?;
+    if id == StreamId::MIN {
+        return Err(Value::error(
+            "ERR The ID specified in XADD must be greater than 0-0",
+        ));
+    }
+    if id <= stream.last_id {
+        return Err(Value::error(TOO_SMALL));
+    }
+    Ok(id)
+}
+
+/// `XADD key [NOMKSTREAM] [<MAXLEN | MINID> [= | ~] threshold [LIMIT count]]
+/// <* | id> field value [field value ...]` appends an entry and replies its
+/// id, a null reply when the stream doesn't exist and `NOMKSTREAM` is given.
+pub fn xadd_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let (options, i) = try_reply!(parse_add_options(values, true));
+    let pairs = values.len().saturating_sub(i + 1);
+    if pairs == 0 || !pairs.is_multiple_of(2) {
+        return wrong_arity("xadd");
+    }
+
+    // the id is checked before a missing stream gets created
+    let empty = Stream::new();
+    let id = match try_reply!(map.stream_mut(&key, false)) {
+        Some(stream) => try_reply!(added_id(stream, &arg(values, i))),
+        None if options.nomkstream => return Value::null(),
+        None => try_reply!(added_id(&empty, &arg(values, i))),
+    };
+    let fields = (i + 1..values.len())
+        .step_by(2)
+        .map(|j| (arg_bytes(values, j), arg_bytes(values, j + 1)))
+        .collect();
+    let stream = try_reply!(map.stream_mut(&key, true)).unwrap();
+    stream.add(id, fields);
+    options.trim(stream);
+    map.modified(&key);
+    Value::bulk_string(id.to_string())
+}
+
+/// `XRANGE key start end [COUNT count]` and `XREVRANGE key end start [COUNT
+/// count]` reply the entries between the two ids.
+pub fn xrange_command(values: &[Value], map: &mut DictionaryServer, reverse: bool) -> Value {
+    let key = arg_bytes(values, 0);
+    let (start, end) = match reverse {
+        true => (arg(values, 2), arg(values, 1)),
+        false => (arg(values, 1), arg(values, 2)),
+    };
+    let start = try_reply!(parse_bound(&start, true));
+    let end = try_reply!(parse_bound(&end, false));
+    let count = match values.len() {
+        3 => None,
+        5 if arg(values, 3).eq_ignore_ascii_case("COUNT") => {
+            Some(try_reply!(parse_int(&arg(values, 4))).max(0) as usize)
+        }
+        _ => return Value::error(SYNTAX_ERROR),
+    };
+    match try_reply!(map.stream_mut(&key, false)) {
+        Some(stream) => entries_reply(stream.range(start, end, count, reverse)),
+        None => Value::array(Vec::new()),
+    }
+}
+
+/// `XLEN key` replies the number of entries.
+pub fn xlen_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let stream = try_reply!(map.stream_mut(&arg_bytes(values, 0), false));
+    Value::integer(stream.map_or(0, |stream| stream.len()) as i64)
+}
+
+/// `XTRIM key <MAXLEN | MINID> [= | ~] threshold [LIMIT count]` replies how
+/// many entries were removed.
+pub fn xtrim_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let (options, _) = try_reply!(parse_add_options(values, false));
+    if options.threshold.is_none() {
+        return Value::error(SYNTAX_ERROR);
+    }
+    let removed = match try_reply!(map.stream_mut(&key, false)) {
+        Some(stream) => options.trim(stream),
+        None => return Value::integer(0),
+    };
+    if removed > 0 {
+        map.modified(&key);
+    }
+    Value::integer(removed as i64)
+}
+
+/// Arguments of `XREAD` and `XREADGROUP`.
+struct ReadOptions {
+    /// group and consumer of `XREADGROUP`
+    group: Option<(String, String)>,
+    count: Option<usize>,
+    /// how long to wait for entries, zero waits forever
+    block: Option<Duration>,
+    noack: bool,
+    /// the streams along with the id to read after
+    streams: Vec<(Vec<u8>, String)>,
+}
+
+/// Parse `[GROUP group consumer] [COUNT count] [BLOCK milliseconds] [NOACK]
+/// STREAMS key [key ...] id [id ...]`.
+fn parse_read(values: &[Value], xreadgroup: bool) -> Result<ReadOptions, Value> {
+    let name = if xreadgroup { "xreadgroup" } else { "xread" };
+    let mut options = ReadOptions {
+        group: None,
+        count: None,
+        block: None,
+        noack: false,
+        streams: Vec::new(),
+    };
+    let mut i = 0;
+    loop {
+        if i >= values.len() {
+            return Err(Value::error(SYNTAX_ERROR));
+        }
+        let option = arg(values, i).to_uppercase();
+        let remaining = values.len() - i - 1;
+        match option.as_str() {
+            "STREAMS" => {
+                if remaining == 0 || !remaining.is_multiple_of(2) {
+                    return Err(Value::error(&format!(
+                        "ERR Unbalanced '{}' list of streams: for each stream key an ID or '$' must be specified.",
+                        name
+                    )));
+                }
+                let keys = i + 1;
+                let ids = keys + remaining / 2;
+                options.streams = (0..remaining / 2)
+                    .map(|j| (arg_bytes(values, keys + j), arg(values, ids + j)))
+                    .collect();
+                break;
+            }
+            "COUNT" if remaining >= 1 => {
+                let count = parse_int(&arg(values, i + 1))?;
+                // like redis a count of zero means no limit
+                options.count = (count > 0).then_some(count as usize);
+                i += 1;
+            }
+            "BLOCK" if remaining >= 1 => {
+                let timeout = parse_int(&arg(values, i + 1))?;
+                if timeout < 0 {
+                    return Err(Value::error("ERR timeout is negative"));
+                }
+                options.block = Some(Duration::from_millis(timeout as u64));
+                i += 1;
+            }
+            "GROUP" if remaining >= 2 && xreadgroup => {
+                options.group = Some((arg(values, i + 1), arg(values, i + 2)));
+                i += 2;
+            }
+            "GROUP" if !xreadgroup => return Err(Value::error(
+                "ERR The GROUP option is only supported by XREADGROUP. You called XREAD instead.",
+            )),
+            "NOACK" if xreadgroup => options.noack = true,
+            _ => return Err(Value::error(SYNTAX_ERROR)),
+        }
+        i += 1;
+    }
+    if xreadgroup && options.group.is_none() {
+        return Err(Value::error("ERR Missing GROUP option for XREADGROUP"));
+    }
+    Ok(options)
+}
+
+/// Entries after the given id of every stream, `None` when none of them
+/// has any.
+fn read_streams(
+    streams: &[(Vec<u8>, StreamId)],
+    count: Option<usize>,
+    map: &mut DictionaryServer,
+    protocol: Protocol,
+) -> Result<Option<Value>, Value> {
+    let mut replies = Vec::new();
+    for (key, after) in streams {
+        if let Some(stream) = map.stream_mut(key, false)? {
+            let entries = stream.after(*after, count);
+            if !entries.is_empty() {
+                replies.push((Value::bulk_string(key), entries_reply(entries)));
+            }
+        }
+    }
+    Ok((!replies.is_empty()).then(|| streams_reply(replies, protocol)))
+}
+
+/// `XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id
+/// ...]` replies the entries added after the ids, `$` being the last entry
+/// so far. With `BLOCK` the client waits for new entries when there are
+/// none yet, zero waits forever.
+pub fn xread_command(
+    values: &[Value],
+    client: &mut Client,
+    map: &mut DictionaryServer,
+) -> Option<Value> {
+    let options = try_reply!(parse_read(values, false));
+    let mut streams = Vec::new();
+    for (key, id) in options.streams {
+        let after = match id.as_str() {
+            "$" => try_reply!(map.stream_mut(&key, false)).map_or(StreamId::MIN, |s| s.last_id),
+            ">" => {
+                return Some(Value::error(
+                    "ERR The > ID can be specified only when calling XREADGROUP using the GROUP <group> <consumer> option.",
+                ))
+            }
+            _ => try_reply!(parse_id(&id, 0)),
+        };
+        streams.push((key, after));
+    }
+
+    if let Some(reply) = try_reply!(read_streams(&streams, options.count, map, client.protocol)) {
+        return Some(reply);
+    }
+    match options.block {
+        Some(timeout) if can_block(client) => {
+            let op = BlockedOn::Streams {
+                streams,
+                count: options.count,
+            };
+            block(client, map, op, timeout);
+            None
+        }
+        _ => Some(Value::null_array()),
+    }
+}
+
+/// Deliver the new entries of every stream to the consumer, returns the
+/// reply for each stream which had some.
+fn deliver(
+    keys: &[Vec<u8>],
+    group: &str,
+    consumer: &str,
+    count: Option<usize>,
+    noack: bool,
+    map: &mut DictionaryServer,
+) -> Result<Vec<(Value, Value)>, Value> {
+    let now = now_ms();
+    let mut replies = Vec::new();
+    for key in keys {
+        let delivered = map
+            .stream_mut(key, false)?
+            .and_then(|stream| stream.deliver(group, consumer, count, noack, now))
+            .ok_or_else(|| no_group(key, group))?;
+        if !delivered.is_empty() {
+            map.modified(key);
+            replies.push((Value::bulk_string(key), entries_reply(delivered)));
+        }
+    }
+    Ok(replies)
+}
+
+/// `XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds]
+/// [NOACK] STREAMS key [key ...] id [id ...]`. With `>` the consumer gets
+/// the entries never delivered to the group, which stay pending until
+/// `XACK`ed unless `NOACK` is given. With another id it gets its own pending
+/// entries after it again.
+pub fn xreadgroup_command(
+    values: &[Value],
+    client: &mut Client,
+    map: &mut DictionaryServer,
+) -> Option<Value> {
+    let options = try_reply!(parse_read(values, true));
+    let (group, consumer) = options.group.unwrap_or_default();
+    // every stream and id is checked before anything is delivered
+    let mut history = Vec::new();
+    for (key, id) in &options.streams {
+        if id != ">" {
+            history.push((key, try_reply!(parse_id(id, 0))));
+        }
+        let exists = try_reply!(map.stream_mut(key, false))
+            .is_some_and(|stream| stream.groups.contains_key(&group));
+        if !exists {
+            return Some(Value::error(&format!(
+                "NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                String::from_utf8_lossy(key),
+                group
+            )));
+        }
+    }
+
+    if !history.is_empty() {
+        let now = now_ms();
+        let mut replies = Vec::new();
+        for (key, after) in history {
+            let stream = try_reply!(map.stream_mut(key, false)).unwrap();
+            let count = options.count.unwrap_or(usize::MAX);
+            let consumers = stream.groups.get_mut(&group).unwrap();
+            consumers.touch_consumer(&consumer, now);
+            let entries = consumers
+                .pending_of(&consumer, after, count)
+                .into_iter()
+                .map(|id| entry_reply(id, stream.get(id)))
+                .collect();
+            replies.push((Value::bulk_string(key), Value::array(entries)));
+            map.modified(key);
+        }
+        return Some(streams_reply(replies, client.protocol));
+    }
+
+    let keys: Vec<Vec<u8>> = options.streams.into_iter().map(|(key, _)| key).collect();
+    let replies = try_reply!(deliver(
+        &keys,
+        &group,
+        &consumer,
+        options.count,
+        options.noack,
+        map
+    ));
+    if !replies.is_empty() {
+        return Some(streams_reply(replies, client.protocol));
+    }
+    // the consumer may have just been created
+    for key in &keys {
+        map.modified(key);
+    }
+    match options.block {
+        Some(timeout) if can_block(client) => {
+            let op = BlockedOn::Group {
+                group,
+                consumer,
+                keys,
+                count: options.count,
+                noack: options.noack,
+            };
+            block(client, map, op, timeout);
+            None
+        }
+        _ => Some(Value::null_array()),
+    }
+}
+
+/// Retry the read of a client blocked by `XREAD` or `XREADGROUP`. Replies
+/// `None` while there is still nothing for it, otherwise its reply along
+/// with the command to propagate if it changed the dataset.
+pub fn serve_blocked(
+    op: &BlockedOn,
+    map: &mut DictionaryServer,
+    protocol: Protocol,
+) -> Option<(Value, Option<Vec<Vec<u8>>>)> {
+    match op {
+        BlockedOn::Streams { streams, count } => match read_streams(streams, *count, map, protocol)
+        {
+            Ok(reply) => reply.map(|reply| (reply, None)),
+            Err(e) => Some((e, None)),
+        },
+        BlockedOn::Group {
+            group,
+            consumer,
+            keys,
+            count,
+            noack,
+        } => match deliver(keys, group, consumer, *count, *noack, map) {
+            Ok(replies) if replies.is_empty() => None,
+            Ok(replies) => {
+                // replaying the read without BLOCK delivers the same entries
+                let mut command: Vec<&[u8]> = vec![
+                    b"XREADGROUP",
+                    b"GROUP",
+                    group.as_bytes(),
+                    consumer.as_bytes(),
+                ];
+                let count = count.map(|count| count.to_string());
+                if let Some(count) = &count {
+                    command.extend([b"COUNT", count.as_bytes()]);
+                }
+                if *noack {
+                    command.push(b"NOACK");
+                }
+                command.push(b"STREAMS");
+                command.extend(keys.iter().map(Vec::as_slice));
+                command.extend(keys.iter().map(|_| b">".as_slice()));
+                let command = command.into_iter().map(<[u8]>::to_vec).collect();
+                Some((streams_reply(replies, protocol), Some(command)))
+            }
+            Err(e) => Some((e, None)),
+        },
+        _ => None,
+    }
+}
+
+/// `XGROUP CREATE | SETID | DESTROY | CREATECONSUMER | DELCONSUMER`
+pub fn xgroup_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let subcommand = arg(values, 0).to_uppercase();
+    match subcommand.as_str() {
+        "CREATE" if (4..=5).contains(&values.len()) => create_group(values, map),
+        "SETID" if values.len() == 4 => setid_group(values, map),
+        "DESTROY" if values.len() == 3 => destroy_group(values, map),
+        "CREATECONSUMER" if values.len() == 4 => create_consumer(values, map),
+        "DELCONSUMER" if values.len() == 4 => delete_consumer(values, map),
+        "CREATE" | "SETID" | "DESTROY" | "CREATECONSUMER" | "DELCONSUMER" => {
+            wrong_arity(&format!("xgroup|{}", subcommand.to_lowercase()))
+        }
+        _ => Value::error(&format!(
+            "ERR unknown subcommand '{}'. Try XGROUP HELP.",
+            arg(values, 0)
+        )),
+    }
+}
+
+const KEY_REQUIRED: &str = "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.";
+
+/// Group `name` of the stream at `key`, the `Err` is the reply when the
+/// stream or the group doesn't exist.
+fn group_mut<'a>(
+    map: &'a mut DictionaryServer,
+    key: &[u8],
+    name: &str,
+) -> Result<&'a mut ConsumerGroup, Value> {
+    let stream = map
+        .stream_mut(key, false)?
+        .ok_or(Value::error(KEY_REQUIRED))?;
+    stream.groups.get_mut(name).ok_or_else(|| {
+        Value::error(&format!(
+            "NOGROUP No such consumer group '{}' for key name '{}'",
+            name,
+            String::from_utf8_lossy(key)
+        ))
+    })
+}
+
+/// Id a group starts from, `$` is the last entry of the stream.
+fn group_start(stream: Option<&mut Stream>, id: &str) -> Result<StreamId, Value> {
+    match id {
+        "$" => Ok(stream.map_or(StreamId::MIN, |stream| stream.last_id)),
+        _ => parse_id(id, 0),
+    }
+}
+
+/// `XGROUP CREATE key group <id | $> [MKSTREAM]`
+fn create_group(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let (key, name) = (arg_bytes(values, 1), arg(values, 2));
+    let mkstream = match values.get(4) {
+        Some(_) if arg(values, 4).eq_ignore_ascii_case("MKSTREAM") => true,
+        Some(_) => return Value::error(SYNTAX_ERROR),
+        None => false,
+    };
+    let stream = try_reply!(map.stream_mut(&key, false));
+    if stream.is_none() && !mkstream {
+        return Value::error(KEY_REQUIRED);
+    }
+    let start = try_reply!(group_start(stream, &arg(values, 3)));
+    let stream = try_reply!(map.stream_mut(&key, true)).unwrap();
+    if stream.groups.contains_key(&name) {
+        return Value::error("BUSYGROUP Consumer Group name already exists");
+    }
+    stream.groups.insert(name, ConsumerGroup::new(start));
+    map.modified(&key);
+    Value::ok()
+}
+
+/// `XGROUP SETID key group <id | $>`
+fn setid_group(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 1);
+    let start = try_reply!(group_start(
+        try_reply!(map.stream_mut(&key, false)),
+        &arg(values, 3)
+    ));
+    try_reply!(group_mut(map, &key, &arg(values, 2))).last_delivered = start;
+    map.modified(&key);
+    Value::ok()
+}
+
+/// `XGROUP DESTROY key group` replies 1 if the group existed.
+fn destroy_group(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 1);
+    let stream = match try_reply!(map.stream_mut(&key, false)) {
+        Some(stream) => stream,
+        None => return Value::error(KEY_REQUIRED),
+    };
+    if stream.groups.remove(&arg(values, 2)).is_none() {
+        return Value::integer(0);
+    }
+    map.modified(&key);
+    Value::integer(1)
+}
+
+/// `XGROUP CREATECONSUMER key group consumer` replies 1 if the consumer was
+/// created.
+fn create_consumer(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 1);
+    let group = try_reply!(group_mut(map, &key, &arg(values, 2)));
+    if !group.create_consumer(&arg(values, 3), now_ms()) {
+        return Value::integer(0);
+    }
+    map.modified(&key);
+    Value::integer(1)
+}
+
+/// `XGROUP DELCONSUMER key group consumer` replies how many entries were
+/// pending for the consumer, they are dropped with it.
+fn delete_consumer(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 1);
+    let group = try_reply!(group_mut(map, &key, &arg(values, 2)));
+    let pending = match group.delete_consumer(&arg(values, 3)) {
+        Some(pending) => pending,
+        None => return Value::integer(0),
+    };
+    map.modified(&key);
+    Value::integer(pending as i64)
+}
+
+/// `XACK key group id [id ...]` removes the entries from the pending entries
+/// list of the group, replies how many were pending.
+pub fn xack_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let ids = try_reply!((2..values.len())
+        .map(|i| parse_id(&arg(values, i), 0))
+        .collect::<Result<Vec<_>, _>>());
+    let group = match try_reply!(map.stream_mut(&key, false))
+        .and_then(|stream| stream.groups.get_mut(&arg(values, 1)))
+    {
+        Some(group) => group,
+        None => return Value::integer(0),
+    };
+    let acked = ids.into_iter().filter(|id| group.ack(*id)).count();
+    if acked > 0 {
+        map.modified(&key);
+    }
+    Value::integer(acked as i64)
+}
+
+/// `XPENDING key group [[IDLE min-idle-time] start end count [consumer]]`.
+/// The short form summarizes the pending entries of the group, the extended
+/// one lists them with their consumer, idle time and delivery count.
+pub fn xpending_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let (key, name) = (arg_bytes(values, 0), arg(values, 1));
+    let mut i = 2;
+    let mut min_idle = 0;
+    if values.len() > 2 && arg(values, 2).eq_ignore_ascii_case("IDLE") {
+        min_idle = try_reply!(parse_int(&arg(values, 3))).max(0) as u64;
+        i = 4;
+    }
+    let extended = values.len() > 2;
+    if extended && !(3..=4).contains(&values.len().saturating_sub(i)) {
+        return Value::error(SYNTAX_ERROR);
+    }
+    let range = match extended {
+        true => Some((
+            try_reply!(parse_bound(&arg(values, i), true)),
+            try_reply!(parse_bound(&arg(values, i + 1), false)),
+            try_reply!(parse_int(&arg(values, i + 2))).max(0) as usize,
+            values.get(i + 3).map(|_| arg(values, i + 3)),
+        )),
+        false => None,
+    };
+
+    let group =
+        match try_reply!(map.stream_mut(&key, false)).and_then(|stream| stream.groups.get(&name)) {
+            Some(group) => group,
+            None => return no_group(&key, &name),
+        };
+    let (start, end, count, consumer) = match range {
+        Some(range) => range,
+        None => return pending_summary(group),
+    };
+    if start > end {
+        return Value::array(Vec::new());
+    }
+    let now = now_ms();
+    Value::array(
+        group
+            .pending
+            .range(start..=end)
+            .filter(|(_, entry)| consumer.as_ref().is_none_or(|name| *name == entry.consumer))
+            .filter(|(_, entry)| now.saturating_sub(entry.delivered_at) >= min_idle)
+            .take(count)
+            .map(|(id, entry)| {
+                Value::array(vec![
+                    Value::bulk_string(id.to_string()),
+                    Value::bulk_string(&entry.consumer),
+                    Value::integer(now.saturating_sub(entry.delivered_at) as i64),
+                    Value::integer(entry.delivery_count as i64),
+                ])
+            })
+            .collect(),
+    )
+}
+
+/// Number of pending entries, the smallest and greatest of their ids and
+/// how many each consumer has.
+fn pending_summary(group: &ConsumerGroup) -> Value {
+    let (first, last) = match (
+        group.pending.first_key_value(),
+        group.pending.last_key_value(),
+    ) {
+        (Some((first, _)), Some((last, _))) => (first, last),
+        _ => {
+            return Value::array(vec![
+                Value::integer(0),
+                Value::null(),
+                Value::null(),
+                Value::null_array(),
+            ])
+        }
+    };
+    let consumers = group
+        .pending_per_consumer()
+        .into_iter()
+        .map(|(consumer, count)| {
+            Value::array(vec![
+                Value::bulk_string(consumer),
+                Value::bulk_string(count.to_string()),
+            ])
+        })
+        .collect();
+    Value::array(vec![
+        Value::integer(group.pending.len() as i64),
+        Value::bulk_string(first.to_string()),
+        Value::bulk_string(last.to_string()),
+        Value::array(consumers),
+    ])
+}
+
+/// `XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms] [TIME
+/// unix-time-milliseconds] [RETRYCOUNT count] [FORCE] [JUSTID] [LASTID
+/// id]` hands pending entries idle for at least `min-idle-time` over to
+/// `consumer`. Replies the claimed entries, or only their ids with
+/// `JUSTID` which also leaves the delivery count alone.
+pub fn xclaim_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let (key, name, consumer) = (arg_bytes(values, 0), arg(values, 1), arg(values, 2));
+    let min_idle = match arg(values, 3).parse::<i64>() {
+        Ok(min_idle) => min_idle.max(0) as u64,
+        Err(_) => return Value::error("ERR Invalid min-idle-time argument for XCLAIM"),
+    };
+    let mut i = 4;
+    let mut ids = Vec::new();
+    while let Some(id) = values
+        .get(i)
+        .and_then(|_| StreamId::parse(&arg(values, i), 0))
+    {
+        ids.push(id);
+        i += 1;
+    }
+
+    let now = now_ms();
+    let mut delivered_at = now;
+    let mut retry_count = None;
+    let (mut force, mut justid, mut last_id) = (false, false, None);
+    while i < values.len() {
+        let option = arg(values, i).to_uppercase();
+        let has_value = i + 1 < values.len();
+        match option.as_str() {
+            "IDLE" if has_value => {
+                let idle = try_reply!(parse_int(&arg(values, i + 1))).max(0) as u64;
+                delivered_at = now.saturating_sub(idle);
+                i += 1;
+            }
+            "TIME" if has_value => {
+                delivered_at = try_reply!(parse_int(&arg(values, i + 1))).max(0) as u64;
+                i += 1;
+            }
+            "RETRYCOUNT" if has_value => {
+                retry_count = Some(try_reply!(parse_int(&arg(values, i + 1))).max(0) as u64);
+                i += 1;
+            }
+            "LASTID" if has_value => {
+                last_id = Some(try_reply!(parse_id(&arg(values, i + 1), 0)));
+                i += 1;
+            }
+            "FORCE" => force = true,
+            "JUSTID" => justid = true,
+            _ => {
+                return Value::error(&format!(
+                    "ERR Unrecognized XCLAIM option '{}'",
+                    arg(values, i)
+                ))
+            }
+        }
+        i += 1;
+    }
+    // a delivery in the future doesn't make sense
+    let delivered_at = delivered_at.min(now);
+
+    let stream = match try_reply!(map.stream_mut(&key, false)) {
+        Some(stream) if stream.groups.contains_key(&name) => stream,
+        _ => return no_group(&key, &name),
+    };
+    let entries: Vec<Option<Fields>> = ids.iter().map(|id| stream.get(*id).cloned()).collect();
+    let group = stream.groups.get_mut(&name).unwrap();
+    let mut changed = false;
+    if let Some(last_id) = last_id.filter(|id| *id > group.last_delivered) {
+        group.last_delivered = last_id;
+        changed = true;
+    }
+
+    let mut claimed = Vec::new();
+    for (id, fields) in ids.into_iter().zip(entries) {
+        let fields = match fields {
+            Some(fields) => fields,
+            // the entry was trimmed away, there is nothing left to claim
+            None => {
+                changed |= group.pending.remove(&id).is_some();
+                continue;
+            }
+        };
+        if force && !group.pending.contains_key(&id) {
+            let entry = PendingEntry {
+                consumer: consumer.clone(),
+                delivered_at: now,
+                delivery_count: 1,
+            };
+            group.pending.insert(id, entry);
+        }
+        let pending = match group.pending.get_mut(&id) {
+            Some(pending) => pending,
+            None => continue,
+        };
+        if now.saturating_sub(pending.delivered_at) < min_idle {
+            continue;
+        }
+        pending.consumer = consumer.clone();
+        pending.delivered_at = delivered_at;
+        match retry_count {
+            Some(count) => pending.delivery_count = count,
+            None if !justid => pending.delivery_count += 1,
+            None => {}
+        }
+        claimed.push(match justid {
+            true => Value::bulk_string(id.to_string()),
+            false => entry_reply(id, Some(&fields)),
+        });
+    }
+    if !claimed.is_empty() {
+        group.touch_consumer(&consumer, now);
+        changed = true;
+    }
+    if changed {
+        map.modified(&key);
+    }
+    Value::array(claimed)
+}
+
+/// `XSETID key last-id` sets the id new entries have to be greater than.
+pub fn xsetid_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let id = try_reply!(parse_id(&arg(values, 1), 0));
+    let stream = match try_reply!(map.stream_mut(&key, false)) {
+        Some(stream) => stream,
+        None => return Value::error("ERR no such key"),
+    };
+    let top = stream.range(StreamId::MIN, StreamId::MAX, Some(1), true);
+    if top.first().is_some_and(|(top, _)| id < *top) {
+        return Value::error(
+            "ERR The ID specified in XSETID is smaller than the target stream top item",
+        );
+    }
+    stream.last_id = id;
+    map.modified(&key);
+    Value::ok()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commands::test::{run, Session};
+    use crate::dictionary_server::DictionaryServer;
+
+    #[test]
+    fn test_add_and_range() {
+        let mut map = DictionaryServer::new();
+        assert_eq!(
+            run(&mut map, &["XADD", "s", "1-1", "a", "1"]),
+            "$3\r\n1-1\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["XADD", "s", "1-*", "b", "2"]),
+            "$3\r\n1-2\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["XADD", "s", "5", "c", "3"]),
+            "$3\r\n5-0\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["XADD", "s", "5-0", "d", "4"]),
+            "-ERR The ID specified in XADD is equal or smaller than the target stream top item\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["XADD", "t", "0-0", "d", "4"]),
+            "-ERR The ID specified in XADD must be greater than 0-0\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["XADD", "t", "NOMKSTREAM", "*", "d", "4"]),
+            "$-1\r\n"
+        );
+        assert_eq!(run(&mut map, &["XLEN", "s"]), ":3\r\n");
+        assert_eq!(run(&mut map, &["TYPE", "s"]), "+stream\r\n");
+
+        assert_eq!(
+            run(&mut map, &["XRANGE", "s", "-", "+"]),
+            "*3\r\n*2\r\n$3\r\n1-1\r\n*2\r\n$1\r\na\r\n$1\r\n1\r\n\
+             *2\r\n$3\r\n1-2\r\n*2\r\n$1\r\nb\r\n$1\r\n2\r\n\
+             *2\r\n$3\r\n5-0\r\n*2\r\n$1\r\nc\r\n$1\r\n3\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["XRANGE", "s", "(1-1", "1", "COUNT", "5"]),
+            "*1\r\n*2\r\n$3\r\n1-2\r\n*2\r\n$1\r\nb\r\n$1\r\n2\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["XREVRANGE", "s", "+", "-", "COUNT", "1"]),
+            "*1\r\n*2\r\n$3\r\n5-0\r\n*2\r\n$1\r\nc\r\n$1\r\n3\r\n"
+        );
+
+        assert_eq!(run(&mut map, &["XTRIM", "s", "MAXLEN", "1"]), ":2\r\n");
+        assert_eq!(
+            run(&mut map, &["XADD", "s", "MINID", "6", "7-0", "e", "5"]),
+            "$3\r\n7-0\r\n"
+        );
+        assert_eq!(run(&mut map, &["XLEN", "s"]), ":1\r\n");
+        assert_eq!(
+            run(&mut map, &["XADD", "s", "MAXLEN", "x", "*", "e", "5"]),
+            "-ERR value is not an integer or out of range\r\n"
+        );
+    }
+
+    #[test]
+    fn test_read() {
+        let mut map = DictionaryServer::new();
+        run(&mut map, &["XADD", "a", "1-0", "f", "1"]);
+        run(&mut map, &["XADD", "a", "2-0", "f", "2"]);
+        run(&mut map, &["XADD", "b", "1-0", "g", "1"]);
+        assert_eq!(
+            run(
+                &mut map,
+                &["XREAD", "COUNT", "1", "STREAMS", "a", "b", "1", "0"]
+            ),
+            "*2\r\n*2\r\n$1\r\na\r\n*1\r\n*2\r\n$3\r\n2-0\r\n*2\r\n$1\r\nf\r\n$1\r\n2\r\n\
+             *2\r\n$1\r\nb\r\n*1\r\n*2\r\n$3\r\n1-0\r\n*2\r\n$1\r\ng\r\n$1\r\n1\r\n"
+        );
+        // nothing new and no connection to block
+        assert_eq!(
+            run(&mut map, &["XREAD", "BLOCK", "0", "STREAMS", "a", "$"]),
+            "*-1\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["XREAD", "STREAMS", "a", "b", "0"]),
+            "-ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.\r\n"
+        );
+    }
+
+    #[test]
+    fn test_consumer_groups() {
+        let mut map = DictionaryServer::new();
+        assert_eq!(
+            run(&mut map, &["XGROUP", "CREATE", "s", "g", "$"]),
+            "-ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"]),
+            "+OK\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["XGROUP", "CREATE", "s", "g", "$"]),
+            "-BUSYGROUP Consumer Group name already exists\r\n"
+        );
+        run(&mut map, &["XADD", "s", "1-0", "f", "1"]);
+        run(&mut map, &["XADD", "s", "2-0", "f", "2"]);
+        run(&mut map, &["XADD", "s", "3-0", "f", "3"]);
+
+        let read = [
+            "XREADGROUP",
+            "GROUP",
+            "g",
+            "alice",
+            "COUNT",
+            "2",
+            "STREAMS",
+            "s",
+            ">",
+        ];
+        assert_eq!(
+            run(&mut map, &read),
+            "*1\r\n*2\r\n$1\r\ns\r\n*2\r\n\
+             *2\r\n$3\r\n1-0\r\n*2\r\n$1\r\nf\r\n$1\r\n1\r\n\
+             *2\r\n$3\r\n2-0\r\n*2\r\n$1\r\nf\r\n$1\r\n2\r\n"
+        );
+        let read = ["XREADGROUP", "GROUP", "g", "bob", "STREAMS", "s", ">"];
+        assert_eq!(
+            run(&mut map, &read),
+            "*1\r\n*2\r\n$1\r\ns\r\n*1\r\n*2\r\n$3\r\n3-0\r\n*2\r\n$1\r\nf\r\n$1\r\n3\r\n"
+        );
+        assert_eq!(run(&mut map, &read), "*-1\r\n");
+
+        assert_eq!(
+            run(&mut map, &["XPENDING", "s", "g"]),
+            "*4\r\n:3\r\n$3\r\n1-0\r\n$3\r\n3-0\r\n\
+             *2\r\n*2\r\n$5\r\nalice\r\n$1\r\n2\r\n*2\r\n$3\r\nbob\r\n$1\r\n1\r\n"
+        );
+        assert_eq!(run(&mut map, &["XACK", "s", "g", "1-0", "9-0"]), ":1\r\n");
+        assert_eq!(run(&mut map, &["XACK", "s", "g", "1-0"]), ":0\r\n");
+        // the history of a consumer is what it was delivered but didn't ack
+        let history = ["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", "0"];
+        assert_eq!(
+            run(&mut map, &history),
+            "*1\r\n*2\r\n$1\r\ns\r\n*1\r\n*2\r\n$3\r\n2-0\r\n*2\r\n$1\r\nf\r\n$1\r\n2\r\n"
+        );
+        let pending = run(&mut map, &["XPENDING", "s", "g", "-", "+", "10", "bob"]);
+        assert!(pending.starts_with("*1\r\n*4\r\n$3\r\n3-0\r\n$3\r\nbob\r\n:"));
+        assert!(pending.ends_with("\r\n:1\r\n"));
+
+        // bob's entry goes to alice, the delivery count only grows with
+        // RETRYCOUNT or when it isn't JUSTID
+        assert_eq!(
+            run(
+                &mut map,
+                &["XCLAIM", "s", "g", "alice", "0", "3-0", "JUSTID"]
+            ),
+            "*1\r\n$3\r\n3-0\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["XCLAIM", "s", "g", "bob", "3600000", "3-0"]),
+            "*0\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["XPENDING", "s", "g"]),
+            "*4\r\n:2\r\n$3\r\n2-0\r\n$3\r\n3-0\r\n*1\r\n*2\r\n$5\r\nalice\r\n$1\r\n2\r\n"
+        );
+        assert_eq!(
+            run(
+                &mut map,
+                &["XREADGROUP", "GROUP", "nope", "c", "STREAMS", "s", ">"]
+            ),
+            "-NOGROUP No such key 's' or consumer group 'nope' in XREADGROUP with GROUP option\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["XGROUP", "DELCONSUMER", "s", "g", "alice"]),
+            ":2\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["XPENDING", "s", "g"]),
+            "*4\r\n:0\r\n$-1\r\n$-1\r\n*-1\r\n"
+        );
+        assert_eq!(run(&mut map, &["XGROUP", "DESTROY", "s", "g"]), ":1\r\n");
+    }
+
+    #[test]
+    fn test_blocking_read() {
+        let mut session = Session::new(3);
+        let inboxes: Vec<_> = (0..2).map(|i| session.connect(i)).collect();
+        session.run(0, &["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"]);
+
+        // both wait, the group reader gets the entry and the plain reader sees
+        // it as well
+        let read = [
+            "XREADGROUP",
+            "GROUP",
+            "g",
+            "c",
+            "BLOCK",
+            "0",
+            "STREAMS",
+            "s",
+            ">",
+        ];
+        assert_eq!(session.run(0, &read), "");
+        let read = ["XREAD", "BLOCK", "0", "STREAMS", "s", "$"];
+        assert_eq!(session.run(1, &read), "");
+        assert_eq!(
+            session.run(2, &["XADD", "s", "1-0", "f", "v"]),
+            "$3\r\n1-0\r\n"
+        );
+        let entry = "*1\r\n*2\r\n$1\r\ns\r\n*1\r\n*2\r\n$3\r\n1-0\r\n*2\r\n$1\r\nf\r\n$1\r\nv\r\n";
+        for (client, inbox) in session.clients.iter_mut().zip(&inboxes) {
+            assert_eq!(inbox.try_recv().unwrap(), entry.as_bytes());
+            let blocking = client.blocked.take().unwrap();
+            assert!(blocking.served.try_recv().is_ok());
+        }
+        assert!(session.map.blocked.waiting(0, b"s").is_empty());
+        assert_eq!(
+            session.run(2, &["XPENDING", "s", "g"]),
+            "*4\r\n:1\r\n$3\r\n1-0\r\n$3\r\n1-0\r\n*1\r\n*2\r\n$1\r\nc\r\n$1\r\n1\r\n"
+        );
+    }
+}
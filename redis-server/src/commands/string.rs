@@ -0,0 +1,596 @@
+use crate::commands::{arg, arg_bytes, parse_int, wrong_arity, SYNTAX_ERROR};
+use crate::dictionary_server::{now_ms, DictionaryServer};
+use crate::parser::Value;
+
+/// Condition under which `SET` is allowed to write the key.
+#[derive(PartialEq)]
+enum Condition {
+    Always,
+    IfMissing,
+    IfExists,
+}
+
+/// wrapper around the dictionary i.e. `HashMap` to set the key, value and reply back
+/// in RESP protocol to the client. Supports the redis options
+/// `[NX | XX] [GET] [EX seconds | PX milliseconds | EXAT unix-time-seconds | PXAT unix-time-milliseconds | KEEPTTL]`.
+/// Reply is "OK" on success, nil when `NX`/`XX` prevented the write and the
+/// old value when `GET` is given.
+pub fn set_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let val = arg_bytes(values, 1);
+
+    let mut condition = Condition::Always;
+    let mut get = false;
+    let mut keep_ttl = false;
+    let mut expires_at: Option<u64> = None;
+
+    let mut i = 2;
+    while i < values.len() {
+        let option = arg(values, i).to_uppercase();
+        match option.as_str() {
+            "NX" if condition == Condition::Always => condition = Condition::IfMissing,
+            "XX" if condition == Condition::Always => condition = Condition::IfExists,
+            "GET" => get = true,
+            "KEEPTTL" if expires_at.is_none() => keep_ttl = true,
+            "EX" | "PX" | "EXAT" | "PXAT"
+                if !keep_ttl && expires_at.is_none() && i + 1 < values.len() =>
+            {
+                let amount = try_reply!(parse_int(&arg(values, i + 1)));
+                match deadline(&option, amount) {
+                    Some(when) => expires_at = Some(when),
+                    None => return Value::error("ERR invalid expire time in 'set' command"),
+                }
+                i += 1;
+            }
+            _ => return Value::error(SYNTAX_ERROR),
+        }
+        i += 1;
+    }
+
+    // only `GET` cares about the type of the key, a plain SET overwrites
+    // whatever was stored
+    let old = if get { try_reply!(map.get(&key)) } else { None };
+    let exists = map.lookup(&key).is_some();
+    let apply = match condition {
+        Condition::Always => true,
+        Condition::IfMissing => !exists,
+        Condition::IfExists => exists,
+    };
+    if apply {
+        if keep_ttl {
+            expires_at = map.expiry(&key).flatten();
+        }
+        map.set(&key, &val, expires_at);
+    }
+
+    match (get, old) {
+        (true, Some(old)) => Value::bulk_string(&old),
+        (true, None) => Value::null(),
+        (false, _) if apply => Value::ok(),
+        (false, _) => Value::null(),
+    }
+}
+
+/// Converts the amount given with `EX`, `PX`, `EXAT` or `PXAT` to an absolute
+/// deadline in unix milliseconds. `None` if it isn't a positive time or it
+/// overflows.
+fn deadline(option: &str, amount: i64) -> Option<u64> {
+    if amount <= 0 {
+        return None;
+    }
+    let amount = amount as u64;
+    match option {
+        "EX" => amount.checked_mul(1000)?.checked_add(now_ms()),
+        "PX" => amount.checked_add(now_ms()),
+        "EXAT" => amount.checked_mul(1000),
+        _ => Some(amount),
+    }
+}
+
+/// wrapper around the dictionary i.e. `HashMap` to retrive the key and reply back
+/// in RESP protocol. If key is not present in the dictionary then return `nil` as response.
+pub fn get_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    match try_reply!(map.get(&arg_bytes(values, 0))) {
+        Some(val) => Value::bulk_string(&val),
+        None => Value::null(),
+    }
+}
+
+/// Largest string `APPEND` and `SETRANGE` may produce, same as redis'
+/// default `proto-max-bulk-len`.
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+const TOO_LARGE: &str = "ERR string exceeds maximum allowed size (proto-max-bulk-len)";
+
+/// Adds `increment` to the integer stored at `key`, a missing key counts as
+/// 0. The TTL of the key is kept.
+fn incr_by(map: &mut DictionaryServer, key: &[u8], increment: i64) -> Value {
+    // check the value before creating the key so a failing command leaves
+    // nothing behind
+    let current = match try_reply!(map.get(key)) {
+        Some(value) => try_reply!(parse_int(&String::from_utf8_lossy(&value))),
+        None => 0,
+    };
+    let updated = match current.checked_add(increment) {
+        Some(updated) => updated,
+        None => return Value::error("ERR increment or decrement would overflow"),
+    };
+    *try_reply!(map.string_mut(key, true)).unwrap() = updated.to_string().into_bytes();
+    map.modified(key);
+    Value::integer(updated)
+}
+
+/// `INCR key` adds one to the integer stored at `key`.
+pub fn incr_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    incr_by(map, &arg_bytes(values, 0), 1)
+}
+
+/// `DECR key` subtracts one from the integer stored at `key`.
+pub fn decr_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    incr_by(map, &arg_bytes(values, 0), -1)
+}
+
+/// `INCRBY key increment`
+pub fn incrby_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let increment = try_reply!(parse_int(&arg(values, 1)));
+    incr_by(map, &arg_bytes(values, 0), increment)
+}
+
+/// `DECRBY key decrement`
+pub fn decrby_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let decrement = try_reply!(parse_int(&arg(values, 1)));
+    match decrement.checked_neg() {
+        Some(increment) => incr_by(map, &arg_bytes(values, 0), increment),
+        None => Value::error("ERR decrement would overflow"),
+    }
+}
+
+/// Number `mantissa / 10^scale` as typed by the client. Redis adds long
+/// doubles and prints the sum with 17 decimals, which hides the rounding of
+/// e.g. 0.1 + 0.2 that `f64` can't; adding the decimals exactly gives the
+/// same text.
+struct Decimal {
+    mantissa: i128,
+    scale: u32,
+}
+
+impl Decimal {
+    /// Most decimals `INCRBYFLOAT` replies with, like `%.17Lf`.
+    const PRECISION: u32 = 17;
+
+    /// `None` unless `number` is a plain or exponent notation whose digits
+    /// fit.
+    fn parse(number: &str) -> Option<Decimal> {
+        let (number, exponent) = match number.find(['e', 'E']) {
+            Some(i) => (&number[..i], number[i + 1..].parse::<i32>().ok()?),
+            None => (number, 0),
+        };
+        let (sign, number) = match number.as_bytes().first()? {
+            b'-' => (-1, &number[1..]),
+            b'+' => (1, &number[1..]),
+            _ => (1, number),
+        };
+        let (int, fraction) = number.split_once('.').unwrap_or((number, ""));
+        if int.is_empty() && fraction.is_empty() {
+            return None;
+        }
+        let mut mantissa: i128 = 0;
+        for digit in int.bytes().chain(fraction.bytes()) {
+            if !digit.is_ascii_digit() {
+                return None;
+            }
+            mantissa = mantissa
+                .checked_mul(10)?
+                .checked_add((digit - b'0') as i128)?;
+        }
+        let mantissa = sign * mantissa;
+        match fraction.len() as i64 - exponent as i64 {
+            scale @ 0..=36 => Some(Decimal {
+                mantissa,
+                scale: scale as u32,
+            }),
+            scale if scale < 0 => Some(Decimal {
+                mantissa: mantissa.checked_mul(pow10(-scale)?)?,
+                scale: 0,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The same number with `digits` more decimals.
+    fn rescale(&self, digits: u32) -> Option<Decimal> {
+        Some(Decimal {
+            mantissa: self.mantissa.checked_mul(pow10(digits as i64)?)?,
+            scale: self.scale + digits,
+        })
+    }
+
+    fn checked_add(self, other: Decimal) -> Option<Decimal> {
+        let scale = self.scale.max(other.scale);
+        let a = self.rescale(scale - self.scale)?;
+        let b = other.rescale(scale - other.scale)?;
+        Some(Decimal {
+            mantissa: a.mantissa.checked_add(b.mantissa)?,
+            scale,
+        })
+    }
+
+    /// Rounded to `PRECISION` decimals, then without its trailing zeros.
+    fn format(self) -> String {
+        let mut mantissa = self.mantissa;
+        let mut scale = self.scale;
+        if scale > Self::PRECISION {
+            let divisor = pow10((scale - Self::PRECISION) as i64).unwrap();
+            let rest = mantissa % divisor;
+            mantissa /= divisor;
+            if rest.abs() * 2 >= divisor {
+                mantissa += rest.signum();
+            }
+            scale = Self::PRECISION;
+        }
+        let digits = format!("{:0>width$}", mantissa.abs(), width = scale as usize + 1);
+        let (int, fraction) = digits.split_at(digits.len() - scale as usize);
+        let sign = if mantissa < 0 { "-" } else { "" };
+        trim_fraction(format!("{}{}.{}", sign, int, fraction))
+    }
+}
+
+fn pow10(digits: i64) -> Option<i128> {
+    10i128.checked_pow(u32::try_from(digits).ok()?)
+}
+
+/// `number` without the zeros ending its decimals nor a lone dot, and `-0`
+/// as `0` like redis does.
+fn trim_fraction(number: String) -> String {
+    let number = match number.contains('.') {
+        true => number.trim_end_matches('0').trim_end_matches('.'),
+        false => &number,
+    };
+    match number {
+        "-0" => "0".to_string(),
+        _ => number.to_string(),
+    }
+}
+
+/// `INCRBYFLOAT key increment` adds a floating point number to the value
+/// stored at `key` and replies the result as a bulk string.
+pub fn incrbyfloat_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let not_a_float = || Value::error("ERR value is not a valid float");
+    let increment = match arg(values, 1).parse::<f64>() {
+        Ok(increment) if increment.is_finite() => increment,
+        _ => return not_a_float(),
+    };
+    let current =
+        try_reply!(map.get(&key)).map(|value| String::from_utf8_lossy(&value).to_string());
+    let current_text = current.as_deref().unwrap_or("0");
+    let current = match current_text.parse::<f64>() {
+        Ok(current) if current.is_finite() => current,
+        _ => return not_a_float(),
+    };
+    let updated = current + increment;
+    if !updated.is_finite() {
+        return Value::error("ERR increment would produce NaN or Infinity");
+    }
+    let exact = Decimal::parse(current_text)
+        .zip(Decimal::parse(&arg(values, 1)))
+        .and_then(|(current, increment)| current.checked_add(increment));
+    let updated = match exact {
+        Some(exact) => exact.format(),
+        None => trim_fraction(format!("{:.*}", Decimal::PRECISION as usize, updated)),
+    };
+    *try_reply!(map.string_mut(&key, true)).unwrap() = updated.clone().into_bytes();
+    map.modified(&key);
+    Value::bulk_string(&updated)
+}
+
+/// `APPEND key value` appends to the string, creating it when missing.
+/// Replies the new length.
+pub fn append_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let suffix = arg_bytes(values, 1);
+    let len = try_reply!(map.get(&key)).map_or(0, |value| value.len());
+    if len + suffix.len() > MAX_STRING_LEN {
+        return Value::error(TOO_LARGE);
+    }
+    let string = try_reply!(map.string_mut(&key, true)).unwrap();
+    string.extend_from_slice(&suffix);
+    let len = string.len();
+    map.modified(&key);
+    Value::integer(len as i64)
+}
+
+/// `STRLEN key` length of the string, 0 if the key doesn't exist.
+pub fn strlen_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let len = try_reply!(map.get(&arg_bytes(values, 0))).map_or(0, |value| value.len());
+    Value::integer(len as i64)
+}
+
+/// `GETRANGE key start end` substring between the two inclusive byte
+/// offsets, negative ones count from the end.
+pub fn getrange_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let start = try_reply!(parse_int(&arg(values, 1)));
+    let end = try_reply!(parse_int(&arg(values, 2)));
+    let value = try_reply!(map.get(&arg_bytes(values, 0))).unwrap_or_default();
+
+    let len = value.len() as i64;
+    let start = if start < 0 { len + start } else { start }.max(0);
+    let end = if end < 0 { len + end } else { end }.max(0).min(len - 1);
+    if len == 0 || start > end {
+        return Value::bulk_string("");
+    }
+    Value::bulk_string(&value[start as usize..=end as usize])
+}
+
+/// `SETRANGE key offset value` overwrites part of the string starting at
+/// `offset`, padding it with zero bytes when it is too short. Replies the
+/// new length.
+pub fn setrange_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let offset = try_reply!(parse_int(&arg(values, 1)));
+    let patch = arg_bytes(values, 2);
+    if offset < 0 {
+        return Value::error("ERR offset is out of range");
+    }
+    let offset = offset as usize;
+
+    let current = try_reply!(map.get(&key));
+    // an empty value changes nothing, not even a missing key is created
+    if patch.is_empty() {
+        return Value::integer(current.map_or(0, |value| value.len()) as i64);
+    }
+    if offset.saturating_add(patch.len()) > MAX_STRING_LEN {
+        return Value::error(TOO_LARGE);
+    }
+
+    let mut bytes = current.unwrap_or_default();
+    if bytes.len() < offset + patch.len() {
+        bytes.resize(offset + patch.len(), 0);
+    }
+    bytes[offset..offset + patch.len()].copy_from_slice(&patch);
+    let len = bytes.len();
+    *try_reply!(map.string_mut(&key, true)).unwrap() = bytes;
+    map.modified(&key);
+    Value::integer(len as i64)
+}
+
+/// `MGET key [key ...]` value of every key, nil for missing keys and ones
+/// which don't hold a string.
+pub fn mget_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let strings = (0..values.len())
+        .map(|i| match map.get(&arg_bytes(values, i)) {
+            Ok(Some(value)) => Value::bulk_string(&value),
+            _ => Value::null(),
+        })
+        .collect();
+    Value::array(strings)
+}
+
+/// `MSET key value [key value ...]` sets every pair, removing any TTL.
+pub fn mset_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    if !values.len().is_multiple_of(2) {
+        return wrong_arity("mset");
+    }
+    for i in (0..values.len()).step_by(2) {
+        map.set(&arg_bytes(values, i), &arg_bytes(values, i + 1), None);
+    }
+    Value::ok()
+}
+
+/// `MSETNX key value [key value ...]` sets the pairs only when none of the
+/// keys exist. Replies 1 if they were set.
+pub fn msetnx_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    if !values.len().is_multiple_of(2) {
+        return wrong_arity("msetnx");
+    }
+    if (0..values.len())
+        .step_by(2)
+        .any(|i| map.lookup(&arg_bytes(values, i)).is_some())
+    {
+        return Value::integer(0);
+    }
+    mset_command(values, map);
+    Value::integer(1)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commands::test::run;
+    use crate::dictionary_server::DictionaryServer;
+
+    #[test]
+    fn test_set_nx_and_xx() {
+        let mut map = DictionaryServer::new();
+        assert_eq!(run(&mut map, &["SET", "k", "1", "XX"]), "$-1\r\n");
+        assert_eq!(run(&mut map, &["SET", "k", "1", "NX"]), "+OK\r\n");
+        assert_eq!(run(&mut map, &["SET", "k", "2", "NX"]), "$-1\r\n");
+        assert_eq!(run(&mut map, &["SET", "k", "3", "xx"]), "+OK\r\n");
+        assert_eq!(run(&mut map, &["GET", "k"]), "$1\r\n3\r\n");
+        assert_eq!(
+            run(&mut map, &["SET", "k", "3", "NX", "XX"]),
+            "-ERR syntax error\r\n"
+        );
+    }
+
+    #[test]
+    fn test_set_get_returns_old_value() {
+        let mut map = DictionaryServer::new();
+        assert_eq!(run(&mut map, &["SET", "k", "1", "GET"]), "$-1\r\n");
+        assert_eq!(run(&mut map, &["SET", "k", "2", "GET"]), "$1\r\n1\r\n");
+        assert_eq!(run(&mut map, &["GET", "k"]), "$1\r\n2\r\n");
+    }
+
+    #[test]
+    fn test_set_expiry_options() {
+        let mut map = DictionaryServer::new();
+        assert_eq!(run(&mut map, &["SET", "k", "v", "EX", "100"]), "+OK\r\n");
+        assert_eq!(run(&mut map, &["TTL", "k"]), ":100\r\n");
+        assert_eq!(run(&mut map, &["SET", "k", "v", "KEEPTTL"]), "+OK\r\n");
+        assert_eq!(run(&mut map, &["TTL", "k"]), ":100\r\n");
+        assert_eq!(run(&mut map, &["SET", "k", "v"]), "+OK\r\n");
+        assert_eq!(run(&mut map, &["TTL", "k"]), ":-1\r\n");
+        assert_eq!(run(&mut map, &["SET", "k", "v", "PXAT", "1"]), "+OK\r\n");
+        assert_eq!(run(&mut map, &["GET", "k"]), "$-1\r\n");
+    }
+
+    #[test]
+    fn test_set_invalid_expiry() {
+        let mut map = DictionaryServer::new();
+        assert_eq!(
+            run(&mut map, &["SET", "k", "v", "EX", "0"]),
+            "-ERR invalid expire time in 'set' command\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["SET", "k", "v", "PX", "abc"]),
+            "-ERR value is not an integer or out of range\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["SET", "k", "v", "EX", "10", "KEEPTTL"]),
+            "-ERR syntax error\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["SET", "k", "v", "EX"]),
+            "-ERR syntax error\r\n"
+        );
+    }
+
+    #[test]
+    fn test_incr_and_decr() {
+        let mut map = DictionaryServer::new();
+        assert_eq!(run(&mut map, &["INCR", "n"]), ":1\r\n");
+        assert_eq!(run(&mut map, &["INCRBY", "n", "10"]), ":11\r\n");
+        assert_eq!(run(&mut map, &["DECR", "n"]), ":10\r\n");
+        assert_eq!(run(&mut map, &["DECRBY", "n", "15"]), ":-5\r\n");
+        assert_eq!(run(&mut map, &["GET", "n"]), "$2\r\n-5\r\n");
+
+        run(&mut map, &["SET", "s", "abc"]);
+        assert_eq!(
+            run(&mut map, &["INCR", "s"]),
+            "-ERR value is not an integer or out of range\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["INCRBY", "n", "1.5"]),
+            "-ERR value is not an integer or out of range\r\n"
+        );
+        run(&mut map, &["SET", "max", "9223372036854775807"]);
+        assert_eq!(
+            run(&mut map, &["INCR", "max"]),
+            "-ERR increment or decrement would overflow\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["DECRBY", "n", "-9223372036854775808"]),
+            "-ERR decrement would overflow\r\n"
+        );
+    }
+
+    #[test]
+    fn test_incr_keeps_ttl() {
+        let mut map = DictionaryServer::new();
+        run(&mut map, &["SET", "n", "1", "EX", "100"]);
+        assert_eq!(run(&mut map, &["INCR", "n"]), ":2\r\n");
+        assert_eq!(run(&mut map, &["TTL", "n"]), ":100\r\n");
+    }
+
+    #[test]
+    fn test_incrbyfloat() {
+        let mut map = DictionaryServer::new();
+        assert_eq!(
+            run(&mut map, &["INCRBYFLOAT", "f", "10.5"]),
+            "$4\r\n10.5\r\n"
+        );
+        assert_eq!(run(&mut map, &["INCRBYFLOAT", "f", "-5.5"]), "$1\r\n5\r\n");
+        assert_eq!(run(&mut map, &["INCR", "f"]), ":6\r\n");
+        assert_eq!(
+            run(&mut map, &["INCRBYFLOAT", "f", "abc"]),
+            "-ERR value is not a valid float\r\n"
+        );
+        // the sum is printed with 17 decimals at most like redis does
+        run(&mut map, &["SET", "f", "0.1"]);
+        assert_eq!(run(&mut map, &["INCRBYFLOAT", "f", "0.2"]), "$3\r\n0.3\r\n");
+        assert_eq!(run(&mut map, &["INCRBYFLOAT", "f", "-3e-1"]), "$1\r\n0\r\n");
+        assert_eq!(run(&mut map, &["INCRBYFLOAT", "f", "1e-18"]), "$1\r\n0\r\n");
+        assert_eq!(
+            run(&mut map, &["INCRBYFLOAT", "f", "5.0e3"]),
+            "$4\r\n5000\r\n"
+        );
+        run(&mut map, &["SET", "f", "10.50"]);
+        assert_eq!(
+            run(&mut map, &["INCRBYFLOAT", "f", "0.1"]),
+            "$4\r\n10.6\r\n"
+        );
+        run(&mut map, &["SET", "f", "1.7e308"]);
+        assert_eq!(
+            run(&mut map, &["INCRBYFLOAT", "f", "1e308"]),
+            "-ERR increment would produce NaN or Infinity\r\n"
+        );
+    }
+
+    #[test]
+    fn test_append_and_strlen() {
+        let mut map = DictionaryServer::new();
+        assert_eq!(run(&mut map, &["APPEND", "s", "Hello"]), ":5\r\n");
+        assert_eq!(run(&mut map, &["APPEND", "s", " World"]), ":11\r\n");
+        assert_eq!(run(&mut map, &["STRLEN", "s"]), ":11\r\n");
+        assert_eq!(run(&mut map, &["STRLEN", "missing"]), ":0\r\n");
+        run(&mut map, &["RPUSH", "q", "a"]);
+        assert_eq!(
+            run(&mut map, &["APPEND", "q", "a"]),
+            "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+    }
+
+    #[test]
+    fn test_getrange_and_setrange() {
+        let mut map = DictionaryServer::new();
+        run(&mut map, &["SET", "s", "This is a string"]);
+        assert_eq!(
+            run(&mut map, &["GETRANGE", "s", "0", "3"]),
+            "$4\r\nThis\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["GETRANGE", "s", "-3", "-1"]),
+            "$3\r\ning\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["GETRANGE", "s", "10", "100"]),
+            "$6\r\nstring\r\n"
+        );
+        assert_eq!(run(&mut map, &["GETRANGE", "s", "5", "1"]), "$0\r\n\r\n");
+        assert_eq!(
+            run(&mut map, &["GETRANGE", "missing", "0", "-1"]),
+            "$0\r\n\r\n"
+        );
+
+        run(&mut map, &["SET", "k", "Hello World"]);
+        assert_eq!(run(&mut map, &["SETRANGE", "k", "6", "Redis"]), ":11\r\n");
+        assert_eq!(run(&mut map, &["GET", "k"]), "$11\r\nHello Redis\r\n");
+        assert_eq!(run(&mut map, &["SETRANGE", "pad", "3", "x"]), ":4\r\n");
+        assert_eq!(run(&mut map, &["GET", "pad"]), "$4\r\n\0\0\0x\r\n");
+        assert_eq!(run(&mut map, &["SETRANGE", "empty", "3", ""]), ":0\r\n");
+        assert!(!map.server.contains_key(b"empty".as_slice()));
+        assert_eq!(
+            run(&mut map, &["SETRANGE", "k", "-1", "x"]),
+            "-ERR offset is out of range\r\n"
+        );
+    }
+
+    #[test]
+    fn test_mget_mset_msetnx() {
+        let mut map = DictionaryServer::new();
+        run(&mut map, &["SET", "a", "old", "EX", "100"]);
+        assert_eq!(run(&mut map, &["MSET", "a", "1", "b", "2"]), "+OK\r\n");
+        assert_eq!(run(&mut map, &["TTL", "a"]), ":-1\r\n");
+        run(&mut map, &["RPUSH", "q", "x"]);
+        assert_eq!(
+            run(&mut map, &["MGET", "a", "missing", "q", "b"]),
+            "*4\r\n$1\r\n1\r\n$-1\r\n$-1\r\n$1\r\n2\r\n"
+        );
+        assert_eq!(run(&mut map, &["MSETNX", "c", "3", "a", "x"]), ":0\r\n");
+        assert_eq!(run(&mut map, &["GET", "c"]), "$-1\r\n");
+        assert_eq!(run(&mut map, &["MSETNX", "c", "3", "d", "4"]), ":1\r\n");
+        assert_eq!(run(&mut map, &["GET", "d"]), "$1\r\n4\r\n");
+        assert_eq!(
+            run(&mut map, &["MSET", "a"]),
+            "-ERR wrong number of arguments for 'mset' command\r\n"
+        );
+    }
+}
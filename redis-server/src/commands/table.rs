@@ -0,0 +1,572 @@
+use crate::commands::{arg, wrong_arity};
+use crate::parser::Value;
+
+/// The command modifies the dataset, it gets propagated to the AOF.
+pub const WRITE: u32 = 1 << 0;
+/// The command only reads the dataset.
+pub const READONLY: u32 = 1 << 1;
+/// The command may use more memory, it is refused when out of memory.
+pub const DENYOOM: u32 = 1 << 2;
+/// Administrative command like `SAVE`.
+pub const ADMIN: u32 = 1 << 3;
+/// The command can't be called from scripts.
+pub const NOSCRIPT: u32 = 1 << 4;
+/// The command runs in O(1) or O(log n).
+pub const FAST: u32 = 1 << 5;
+/// The key specification doesn't tell where the keys are, like the streams
+/// of `XREAD` which follow its options.
+pub const MOVABLEKEYS: u32 = 1 << 6;
+/// The command may make the client wait, like `BLPOP`.
+pub const BLOCKING: u32 = 1 << 7;
+/// The command never goes to the slow log, e.g. `AUTH` and its password.
+pub const SKIP_SLOWLOG: u32 = 1 << 8;
+/// The command isn't a write but may propagate some, like `EVAL`.
+pub const MAY_REPLICATE: u32 = 1 << 9;
+
+/// Names `COMMAND INFO` reports for the flags.
+const FLAG_NAMES: &[(u32, &str)] = &[
+    (WRITE, "write"),
+    (READONLY, "readonly"),
+    (DENYOOM, "denyoom"),
+    (ADMIN, "admin"),
+    (NOSCRIPT, "noscript"),
+    (FAST, "fast"),
+    (MOVABLEKEYS, "movablekeys"),
+    (BLOCKING, "blocking"),
+    (SKIP_SLOWLOG, "skip_slowlog"),
+    (MAY_REPLICATE, "may_replicate"),
+];
+
+/// ACL categories, the ones of a command follow from its group and flags.
+pub const CATEGORIES: &[&str] = &[
+    "keyspace",
+    "read",
+    "write",
+    "set",
+    "sortedset",
+    "list",
+    "stream",
+    "hash",
+    "string",
+    "pubsub",
+    "admin",
+    "fast",
+    "slow",
+    "dangerous",
+    "connection",
+    "transaction",
+    "scripting",
+];
+
+/// Where the keys are in the arguments, counting the command name as
+/// argument 0. `last` may be negative to count from the end and `step` is
+/// the distance between two keys, e.g. 2 for the key value pairs of `MSET`.
+pub struct KeySpec {
+    pub first: i64,
+    pub last: i64,
+    pub step: i64,
+}
+
+const NO_KEYS: KeySpec = keys(0, 0, 0);
+const ONE_KEY: KeySpec = keys(1, 1, 1);
+const ALL_KEYS: KeySpec = keys(1, -1, 1);
+const KEY_VALUE_PAIRS: KeySpec = keys(1, -1, 2);
+
+const fn keys(first: i64, last: i64, step: i64) -> KeySpec {
+    KeySpec { first, last, step }
+}
+
+/// Static description of a command, used to reject unknown commands and
+/// invalid argument counts before a handler runs and to answer `COMMAND`.
+pub struct CommandInfo {
+    pub name: &'static str,
+    /// Number of arguments counting the command name, same convention as
+    /// redis: `n` means exactly `n`, `-n` means at least `n`.
+    pub arity: i64,
+    pub flags: u32,
+    pub keys: KeySpec,
+    pub summary: &'static str,
+}
+
+impl CommandInfo {
+    /// Whether `argc` arguments, the name included, satisfy the arity.
+    pub fn accepts(&self, argc: usize) -> bool {
+        let argc = argc as i64;
+        if self.arity < 0 {
+            argc >= -self.arity
+        } else {
+            argc == self.arity
+        }
+    }
+
+    pub fn has_flag(&self, flag: u32) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Positions of the keys in a call with `argc` arguments.
+    pub fn key_positions(&self, argc: usize) -> Vec<usize> {
+        let KeySpec { first, last, step } = self.keys;
+        if first == 0 {
+            return Vec::new();
+        }
+        let last = if last < 0 { argc as i64 + last } else { last };
+        (first..=last.min(argc as i64 - 1))
+            .step_by(step as usize)
+            .map(|position| position as usize)
+            .collect()
+    }
+
+    /// Positions of the keys in a call with the arguments `args`, the name
+    /// not included. Commands with movable keys have theirs after `STREAMS`,
+    /// followed by as many ids, or after their number like `EVAL`.
+    pub fn key_args(&self, args: &[Value]) -> Vec<usize> {
+        if !self.has_flag(MOVABLEKEYS) {
+            return self.key_positions(args.len() + 1);
+        }
+        if matches!(self.name, "EVAL" | "EVALSHA") {
+            let numkeys = arg(args, 1).parse::<usize>().unwrap_or_default();
+            return (3..3 + numkeys.min(args.len().saturating_sub(2))).collect();
+        }
+        match (0..args.len()).find(|i| arg(args, *i).eq_ignore_ascii_case("STREAMS")) {
+            Some(streams) => {
+                let first = streams + 2;
+                (first..first + (args.len() - streams - 1) / 2).collect()
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+const fn command(
+    name: &'static str,
+    arity: i64,
+    flags: u32,
+    keys: KeySpec,
+    summary: &'static str,
+) -> CommandInfo {
+    CommandInfo {
+        name,
+        arity,
+        flags,
+        keys,
+        summary,
+    }
+}
+
+#[rustfmt::skip]
+const CONNECTION: &[CommandInfo] = &[
+    command("PING", -1, FAST, NO_KEYS, "Returns the server's liveliness response."),
+    command("ECHO", 2, FAST, NO_KEYS, "Returns the given string."),
+    command("HELLO", -1, NOSCRIPT | FAST | SKIP_SLOWLOG, NO_KEYS, "Handshakes with the server."),
+    command("AUTH", -2, NOSCRIPT | FAST | SKIP_SLOWLOG, NO_KEYS, "Authenticates the connection."),
+    command("CLIENT", -2, NOSCRIPT, NO_KEYS, "Inspects and manages client connections."),
+    command("SELECT", 2, FAST, NO_KEYS, "Changes the selected database."),
+];
+
+#[rustfmt::skip]
+const STRING: &[CommandInfo] = &[
+    command("SET", -3, WRITE | DENYOOM, ONE_KEY, "Sets the string value of a key."),
+    command("GET", 2, READONLY | FAST, ONE_KEY, "Returns the string value of a key."),
+    command("INCR", 2, WRITE | DENYOOM | FAST, ONE_KEY, "Increments a number by one."),
+    command("DECR", 2, WRITE | DENYOOM | FAST, ONE_KEY, "Decrements a number by one."),
+    command("INCRBY", 3, WRITE | DENYOOM | FAST, ONE_KEY, "Increments a number."),
+    command("DECRBY", 3, WRITE | DENYOOM | FAST, ONE_KEY, "Decrements a number."),
+    command("INCRBYFLOAT", 3, WRITE | DENYOOM | FAST, ONE_KEY, "Increments a float."),
+    command("APPEND", 3, WRITE | DENYOOM | FAST, ONE_KEY, "Appends to a string."),
+    command("STRLEN", 2, READONLY | FAST, ONE_KEY, "Returns the length of a string."),
+    command("GETRANGE", 4, READONLY, ONE_KEY, "Returns a substring."),
+    command("SETRANGE", 4, WRITE | DENYOOM, ONE_KEY, "Overwrites part of a string."),
+    command("MGET", -2, READONLY | FAST, ALL_KEYS, "Returns the values of keys."),
+    command("MSET", -3, WRITE | DENYOOM, KEY_VALUE_PAIRS, "Sets multiple keys."),
+    command("MSETNX", -3, WRITE | DENYOOM, KEY_VALUE_PAIRS, "Sets keys if none exist."),
+];
+
+#[rustfmt::skip]
+const GENERIC: &[CommandInfo] = &[
+    command("DEL", -2, WRITE, ALL_KEYS, "Deletes keys."),
+    command("UNLINK", -2, WRITE | FAST, ALL_KEYS, "Deletes keys asynchronously."),
+    command("EXISTS", -2, READONLY | FAST, ALL_KEYS, "Counts existing keys."),
+    command("TYPE", 2, READONLY | FAST, ONE_KEY, "Returns the type of a key."),
+    command("KEYS", 2, READONLY, NO_KEYS, "Returns keys matching a pattern."),
+    command("SCAN", -2, READONLY, NO_KEYS, "Iterates over the keys."),
+    command("RENAME", 3, WRITE, keys(1, 2, 1), "Renames a key."),
+    command("RENAMENX", 3, WRITE | FAST, keys(1, 2, 1), "Renames a key if free."),
+    command("DBSIZE", 1, READONLY | FAST, NO_KEYS, "Returns the number of keys."),
+    command("FLUSHDB", -1, WRITE, NO_KEYS, "Removes all keys of the database."),
+    command("FLUSHALL", -1, WRITE, NO_KEYS, "Removes all keys."),
+    command("MOVE", 3, WRITE | FAST, ONE_KEY, "Moves a key to another database."),
+    command("SWAPDB", 3, WRITE | FAST, NO_KEYS, "Swaps two databases."),
+    command("EXPIRE", -3, WRITE | FAST, ONE_KEY, "Sets a timeout in seconds."),
+    command("PEXPIRE", -3, WRITE | FAST, ONE_KEY, "Sets a timeout in milliseconds."),
+    command("EXPIREAT", -3, WRITE | FAST, ONE_KEY, "Sets a unix time expiry."),
+    command("PEXPIREAT", -3, WRITE | FAST, ONE_KEY, "Sets a unix ms expiry."),
+    command("TTL", 2, READONLY | FAST, ONE_KEY, "Returns the seconds left to live."),
+    command("PTTL", 2, READONLY | FAST, ONE_KEY, "Returns the ms left to live."),
+    command("PERSIST", 2, WRITE | FAST, ONE_KEY, "Removes the timeout of a key."),
+];
+
+#[rustfmt::skip]
+const LIST: &[CommandInfo] = &[
+    command("LPUSH", -3, WRITE | DENYOOM | FAST, ONE_KEY, "Prepends elements."),
+    command("RPUSH", -3, WRITE | DENYOOM | FAST, ONE_KEY, "Appends elements."),
+    command("LPOP", -2, WRITE | FAST, ONE_KEY, "Removes the first elements."),
+    command("RPOP", -2, WRITE | FAST, ONE_KEY, "Removes the last elements."),
+    command("LRANGE", 4, READONLY, ONE_KEY, "Returns a range of elements."),
+    command("LLEN", 2, READONLY | FAST, ONE_KEY, "Returns the length of a list."),
+    command("LINDEX", 3, READONLY, ONE_KEY, "Returns an element by its index."),
+    command("LREM", 4, WRITE, ONE_KEY, "Removes elements from a list."),
+    command("LTRIM", 4, WRITE, ONE_KEY, "Trims a list to a range."),
+    command("LMOVE", 5, WRITE | DENYOOM, keys(1, 2, 1), "Moves an element to another list."),
+    command("BLPOP", -3, WRITE | BLOCKING, keys(1, -2, 1), "Pops the first element, may block."),
+    command("BRPOP", -3, WRITE | BLOCKING, keys(1, -2, 1), "Pops the last element, may block."),
+    command("BLMOVE", 6, WRITE | DENYOOM | BLOCKING, keys(1, 2, 1), "Moves an element, may block."),
+];
+
+#[rustfmt::skip]
+const HASH: &[CommandInfo] = &[
+    command("HSET", -4, WRITE | DENYOOM | FAST, ONE_KEY, "Sets fields of a hash."),
+    command("HGET", 3, READONLY | FAST, ONE_KEY, "Returns the value of a field."),
+    command("HDEL", -3, WRITE | FAST, ONE_KEY, "Deletes fields of a hash."),
+    command("HGETALL", 2, READONLY, ONE_KEY, "Returns all fields and values."),
+    command("HINCRBY", 4, WRITE | DENYOOM | FAST, ONE_KEY, "Increments a field."),
+    command("HKEYS", 2, READONLY, ONE_KEY, "Returns all fields of a hash."),
+    command("HVALS", 2, READONLY, ONE_KEY, "Returns all values of a hash."),
+    command("HLEN", 2, READONLY | FAST, ONE_KEY, "Returns the number of fields."),
+    command("HEXISTS", 3, READONLY | FAST, ONE_KEY, "Checks whether a field exists."),
+];
+
+#[rustfmt::skip]
+const SET: &[CommandInfo] = &[
+    command("SADD", -3, WRITE | DENYOOM | FAST, ONE_KEY, "Adds members to a set."),
+    command("SREM", -3, WRITE | FAST, ONE_KEY, "Removes members from a set."),
+    command("SMEMBERS", 2, READONLY, ONE_KEY, "Returns all members of a set."),
+    command("SISMEMBER", 3, READONLY | FAST, ONE_KEY, "Checks set membership."),
+    command("SCARD", 2, READONLY | FAST, ONE_KEY, "Returns the size of a set."),
+    command("SINTER", -2, READONLY, ALL_KEYS, "Returns the intersection of sets."),
+    command("SUNION", -2, READONLY, ALL_KEYS, "Returns the union of sets."),
+    command("SDIFF", -2, READONLY, ALL_KEYS, "Returns the difference of sets."),
+];
+
+#[rustfmt::skip]
+const SORTED_SET: &[CommandInfo] = &[
+    command("ZADD", -4, WRITE | DENYOOM | FAST, ONE_KEY, "Adds or updates members."),
+    command("ZREM", -3, WRITE | FAST, ONE_KEY, "Removes members."),
+    command("ZSCORE", 3, READONLY | FAST, ONE_KEY, "Returns the score of a member."),
+    command("ZRANK", -3, READONLY | FAST, ONE_KEY, "Returns the rank of a member."),
+    command("ZREVRANK", -3, READONLY | FAST, ONE_KEY, "Returns the reverse rank."),
+    command("ZRANGE", -4, READONLY, ONE_KEY, "Returns a range of members."),
+    command("ZINCRBY", 4, WRITE | DENYOOM | FAST, ONE_KEY, "Increments a score."),
+    command("ZCARD", 2, READONLY | FAST, ONE_KEY, "Returns the number of members."),
+];
+
+#[rustfmt::skip]
+const STREAM: &[CommandInfo] = &[
+    command("XADD", -5, WRITE | DENYOOM | FAST, ONE_KEY, "Appends a new entry to a stream."),
+    command("XRANGE", -4, READONLY, ONE_KEY, "Returns the entries within a range of ids."),
+    command("XREVRANGE", -4, READONLY, ONE_KEY, "Returns the entries within a range in reverse."),
+    command("XLEN", 2, READONLY | FAST, ONE_KEY, "Returns the number of entries in a stream."),
+    command("XTRIM", -4, WRITE, ONE_KEY, "Deletes the oldest entries of a stream."),
+    command("XREAD", -4, READONLY | MOVABLEKEYS | BLOCKING, NO_KEYS, "Returns new entries of streams, may block."),
+    command("XGROUP", -2, WRITE | DENYOOM, keys(2, 2, 1), "Manages the consumer groups of a stream."),
+    command("XREADGROUP", -7, WRITE | MOVABLEKEYS | BLOCKING, NO_KEYS, "Reads streams as a consumer of a group."),
+    command("XACK", -4, WRITE | FAST, ONE_KEY, "Acknowledges entries of a consumer group."),
+    command("XPENDING", -3, READONLY, ONE_KEY, "Inspects the pending entries of a group."),
+    command("XCLAIM", -6, WRITE | FAST, ONE_KEY, "Changes the owner of pending entries."),
+    command("XSETID", 3, WRITE | DENYOOM | FAST, ONE_KEY, "Sets the last id of a stream."),
+];
+
+#[rustfmt::skip]
+const PUBSUB: &[CommandInfo] = &[
+    command("SUBSCRIBE", -2, NOSCRIPT, NO_KEYS, "Listens for messages on channels."),
+    command("UNSUBSCRIBE", -1, NOSCRIPT, NO_KEYS, "Stops listening to channels."),
+    command("PSUBSCRIBE", -2, NOSCRIPT, NO_KEYS, "Listens on channels matching patterns."),
+    command("PUNSUBSCRIBE", -1, NOSCRIPT, NO_KEYS, "Stops listening to patterns."),
+    command("PUBLISH", 3, FAST, NO_KEYS, "Posts a message to a channel."),
+    command("PUBSUB", -2, 0, NO_KEYS, "Inspects the state of pub/sub."),
+];
+
+#[rustfmt::skip]
+const TRANSACTIONS: &[CommandInfo] = &[
+    command("MULTI", 1, NOSCRIPT | FAST, NO_KEYS, "Starts a transaction."),
+    command("EXEC", 1, NOSCRIPT | SKIP_SLOWLOG, NO_KEYS, "Executes all commands in a transaction."),
+    command("DISCARD", 1, NOSCRIPT | FAST, NO_KEYS, "Discards a transaction."),
+    command("WATCH", -2, NOSCRIPT | FAST, ALL_KEYS, "Monitors changes to keys."),
+    command("UNWATCH", 1, NOSCRIPT | FAST, NO_KEYS, "Forgets about watched keys."),
+];
+
+#[rustfmt::skip]
+const SCRIPTING: &[CommandInfo] = &[
+    command("EVAL", -3, NOSCRIPT | MOVABLEKEYS | MAY_REPLICATE, NO_KEYS, "Executes a Lua script."),
+    command("EVALSHA", -3, NOSCRIPT | MOVABLEKEYS | MAY_REPLICATE, NO_KEYS, "Executes a cached Lua script."),
+    command("SCRIPT", -2, NOSCRIPT, NO_KEYS, "Manages the Lua script cache."),
+];
+
+#[rustfmt::skip]
+const SERVER: &[CommandInfo] = &[
+    command("COMMAND", -1, 0, NO_KEYS, "Returns details about commands."),
+    command("MEMORY", -2, READONLY, keys(2, 2, 1), "Reports the memory usage of a key."),
+    command("ACL", -2, ADMIN | NOSCRIPT, NO_KEYS, "Manages the users and their permissions."),
+    command("CONFIG", -2, ADMIN | NOSCRIPT, NO_KEYS, "Gets or sets configuration parameters."),
+    command("SAVE", 1, ADMIN | NOSCRIPT, NO_KEYS, "Saves the dataset to disk."),
+    command("BGSAVE", -1, ADMIN | NOSCRIPT, NO_KEYS, "Saves in the background."),
+    command("LASTSAVE", 1, FAST, NO_KEYS, "Returns the time of the last save."),
+    command("SHUTDOWN", -1, ADMIN | NOSCRIPT, NO_KEYS, "Saves the dataset and stops the server."),
+    command("BGREWRITEAOF", 1, ADMIN | NOSCRIPT, NO_KEYS, "Rewrites the AOF."),
+    command("REPLICAOF", 3, ADMIN | NOSCRIPT, NO_KEYS, "Configures a server as replica of another."),
+    command("SLAVEOF", 3, ADMIN | NOSCRIPT, NO_KEYS, "Configures a server as replica of another."),
+    command("REPLCONF", -1, ADMIN | NOSCRIPT, NO_KEYS, "Configures the replication link."),
+    command("PSYNC", -3, ADMIN | NOSCRIPT, NO_KEYS, "Synchronizes a replica with its master."),
+    command("ROLE", 1, NOSCRIPT | FAST, NO_KEYS, "Returns the replication role."),
+    command("INFO", -1, 0, NO_KEYS, "Returns information and statistics about the server."),
+    command("SLOWLOG", -2, ADMIN, NO_KEYS, "Inspects the log of slow commands."),
+];
+
+/// Every command the server understands grouped like the redis docs.
+const GROUPS: &[(&str, &[CommandInfo])] = &[
+    ("connection", CONNECTION),
+    ("string", STRING),
+    ("generic", GENERIC),
+    ("list", LIST),
+    ("hash", HASH),
+    ("set", SET),
+    ("sorted-set", SORTED_SET),
+    ("stream", STREAM),
+    ("pubsub", PUBSUB),
+    ("transactions", TRANSACTIONS),
+    ("scripting", SCRIPTING),
+    ("server", SERVER),
+];
+
+/// Every command along with its group.
+pub fn commands() -> impl Iterator<Item = (&'static str, &'static CommandInfo)> {
+    GROUPS
+        .iter()
+        .flat_map(|(group, commands)| commands.iter().map(move |command| (*group, command)))
+}
+
+/// Entry for `name`, which is matched ignoring case.
+pub fn lookup(name: &str) -> Option<&'static CommandInfo> {
+    commands()
+        .map(|(_, command)| command)
+        .find(|command| command.name.eq_ignore_ascii_case(name))
+}
+
+/// ACL categories of a command of `group`.
+pub fn categories(group: &str, command: &CommandInfo) -> Vec<&'static str> {
+    let mut categories = Vec::new();
+    if command.has_flag(WRITE) {
+        categories.push("write");
+    }
+    if command.has_flag(READONLY) {
+        categories.push("read");
+    }
+    if command.has_flag(ADMIN) {
+        categories.extend(["admin", "dangerous"]);
+    }
+    match group {
+        "connection" => categories.push("connection"),
+        "string" => categories.push("string"),
+        "generic" => categories.push("keyspace"),
+        "list" => categories.push("list"),
+        "hash" => categories.push("hash"),
+        "set" => categories.push("set"),
+        "sorted-set" => categories.push("sortedset"),
+        "stream" => categories.push("stream"),
+        "pubsub" => categories.push("pubsub"),
+        "transactions" => categories.push("transaction"),
+        "scripting" => categories.push("scripting"),
+        _ => {}
+    }
+    categories.push(if command.has_flag(FAST) {
+        "fast"
+    } else {
+        "slow"
+    });
+    categories
+}
+
+/// Reply of `COMMAND INFO` for a single command, laid out like redis 7.
+fn info_reply(group: &str, command: &CommandInfo) -> Value {
+    let flags = FLAG_NAMES
+        .iter()
+        .filter(|(flag, _)| command.has_flag(*flag))
+        .map(|(_, name)| Value::simple_string(name))
+        .collect();
+    let KeySpec { first, last, step } = command.keys;
+    Value::array(vec![
+        Value::bulk_string(command.name.to_lowercase()),
+        Value::integer(command.arity),
+        Value::set(flags),
+        Value::integer(first),
+        Value::integer(last),
+        Value::integer(step),
+        Value::set(
+            categories(group, command)
+                .into_iter()
+                .map(|category| Value::simple_string(&format!("@{}", category)))
+                .collect(),
+        ),
+        // tips, key specs and subcommands
+        Value::set(Vec::new()),
+        Value::array(Vec::new()),
+        Value::array(Vec::new()),
+    ])
+}
+
+/// Reply of `COMMAND DOCS` for a single command.
+fn docs_reply(group: &str, command: &CommandInfo) -> Value {
+    Value::map(vec![
+        (
+            Value::bulk_string("summary"),
+            Value::bulk_string(command.summary),
+        ),
+        (Value::bulk_string("group"), Value::bulk_string(group)),
+    ])
+}
+
+/// `COMMAND [COUNT | INFO [name ...] | DOCS [name ...] | GETKEYS command
+/// [arg ...]]` introspection of the command table, redis-cli asks for it
+/// when it connects.
+pub fn command_command(values: &[Value]) -> Value {
+    if values.is_empty() {
+        return Value::array(
+            commands()
+                .map(|(group, command)| info_reply(group, command))
+                .collect(),
+        );
+    }
+    let names = (1..values.len()).map(|i| arg(values, i));
+    match arg(values, 0).to_uppercase().as_str() {
+        "COUNT" if values.len() == 1 => Value::integer(commands().count() as i64),
+        "COUNT" => wrong_arity("command|count"),
+        "INFO" if values.len() == 1 => command_command(&[]),
+        "INFO" => Value::array(
+            names
+                .map(|name| {
+                    commands()
+                        .find(|(_, command)| command.name.eq_ignore_ascii_case(&name))
+                        .map_or_else(Value::null, |(group, command)| info_reply(group, command))
+                })
+                .collect(),
+        ),
+        "DOCS" => {
+            let wanted: Vec<String> = names.collect();
+            let docs = commands()
+                .filter(|(_, command)| {
+                    wanted.is_empty()
+                        || wanted
+                            .iter()
+                            .any(|name| command.name.eq_ignore_ascii_case(name))
+                })
+                .map(|(group, command)| {
+                    (
+                        Value::bulk_string(command.name.to_lowercase()),
+                        docs_reply(group, command),
+                    )
+                })
+                .collect();
+            Value::map(docs)
+        }
+        "GETKEYS" if values.len() == 1 => wrong_arity("command|getkeys"),
+        "GETKEYS" => {
+            let command = match lookup(&arg(values, 1)) {
+                Some(command) => command,
+                None => return Value::error("ERR Invalid command specified"),
+            };
+            let argc = values.len() - 1;
+            if !command.accepts(argc) {
+                return Value::error("ERR Invalid number of arguments specified for command");
+            }
+            let positions = command.key_args(&values[2..]);
+            if positions.is_empty() {
+                return Value::error("ERR The command has no key arguments");
+            }
+            Value::array(
+                positions
+                    .into_iter()
+                    .map(|position| Value::bulk_string(arg(values, position + 1)))
+                    .collect(),
+            )
+        }
+        _ => Value::error(&format!(
+            "ERR unknown subcommand '{}'. Try COMMAND HELP.",
+            arg(values, 0)
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{command, run};
+    use crate::dictionary_server::DictionaryServer;
+
+    #[test]
+    fn test_lookup_and_arity() {
+        let get = lookup("get").unwrap();
+        assert_eq!(get.name, "GET");
+        assert!(get.accepts(2));
+        assert!(!get.accepts(1));
+        assert!(!get.accepts(3));
+
+        let set = lookup("Set").unwrap();
+        assert!(!set.accepts(2));
+        assert!(set.accepts(3));
+        assert!(set.accepts(6));
+        assert!(set.has_flag(WRITE));
+        assert!(!set.has_flag(READONLY));
+
+        assert!(lookup("nosuchcommand").is_none());
+    }
+
+    #[test]
+    fn test_key_positions() {
+        assert_eq!(lookup("GET").unwrap().key_positions(2), [1]);
+        assert_eq!(lookup("MSET").unwrap().key_positions(5), [1, 3]);
+        assert_eq!(lookup("DEL").unwrap().key_positions(4), [1, 2, 3]);
+        assert_eq!(lookup("RENAME").unwrap().key_positions(3), [1, 2]);
+        assert!(lookup("PING").unwrap().key_positions(1).is_empty());
+
+        let xread = lookup("XREAD").unwrap();
+        let args = command(&["COUNT", "1", "STREAMS", "a", "b", "0", "0"]);
+        assert_eq!(xread.key_args(&args.array), [4, 5]);
+        assert!(xread.key_args(&command(&["COUNT", "1"]).array).is_empty());
+
+        let eval = lookup("EVAL").unwrap();
+        let args = command(&["return 1", "2", "a", "b", "arg"]);
+        assert_eq!(eval.key_args(&args.array), [3, 4]);
+        assert_eq!(eval.key_args(&command(&["return 1", "3", "a"]).array), [3]);
+    }
+
+    #[test]
+    fn test_command_introspection() {
+        let mut map = DictionaryServer::new();
+        let count = commands().count();
+        assert_eq!(
+            run(&mut map, &["COMMAND", "COUNT"]),
+            format!(":{}\r\n", count)
+        );
+        assert!(run(&mut map, &["command"]).starts_with(&format!("*{}\r\n", count)));
+        assert_eq!(
+            run(&mut map, &["COMMAND", "INFO", "get", "nope"]),
+            "*2\r\n*10\r\n$3\r\nget\r\n:2\r\n*2\r\n+readonly\r\n+fast\r\n:1\r\n:1\r\n:1\r\n\
+             *3\r\n+@read\r\n+@string\r\n+@fast\r\n*0\r\n*0\r\n*0\r\n$-1\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["COMMAND", "DOCS", "echo"]),
+            "*2\r\n$4\r\necho\r\n*4\r\n$7\r\nsummary\r\n$25\r\nReturns the given string.\r\n\
+             $5\r\ngroup\r\n$10\r\nconnection\r\n"
+        );
+        assert_eq!(
+            run(
+                &mut map,
+                &["COMMAND", "GETKEYS", "MSET", "a", "1", "b", "2"]
+            ),
+            "*2\r\n$1\r\na\r\n$1\r\nb\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["COMMAND", "GETKEYS", "PING"]),
+            "-ERR The command has no key arguments\r\n"
+        );
+    }
+}
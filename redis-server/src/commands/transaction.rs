@@ -0,0 +1,218 @@
+use std::sync::Arc;
+
+use crate::aof;
+use crate::client::{Client, Wrapping};
+use crate::commands::{arg_bytes, call, reject};
+use crate::dictionary_server::DictionaryServer;
+use crate::parser::Value;
+use crate::server::Server;
+
+/// `MULTI` starts queueing the commands of the client until `EXEC`.
+pub fn multi_command(client: &mut Client) -> Value {
+    if client.multi.is_some() {
+        return Value::error("ERR MULTI calls can not be nested");
+    }
+    client.multi = Some(Vec::new());
+    client.multi_failed = false;
+    Value::ok()
+}
+
+/// `EXEC` runs the queued commands one after the other without releasing the
+/// dictionary, so no other client sees the transaction half done. Replies
+/// the reply of every command, or a null array when a watched key changed.
+pub fn exec_command(
+    server: &Arc<Server>,
+    client: &mut Client,
+    map: &mut DictionaryServer,
+) -> Value {
+    let queued = match client.multi.take() {
+        Some(queued) => queued,
+        None => return Value::error("ERR EXEC without MULTI"),
+    };
+    let failed = std::mem::take(&mut client.multi_failed);
+    let watch_failed = map.watch_failed(client.id, &client.watched);
+    unwatch_command(client, map);
+    if failed {
+        return Value::error("EXECABORT Transaction discarded because of previous errors.");
+    }
+    if watch_failed {
+        return Value::null_array();
+    }
+
+    // wrap the writes in MULTI/EXEC so replaying the log or the replicas
+    // apply them atomically as well, `call` sends MULTI before the first one
+    client.wrapping = Wrapping::Pending;
+    // a blocking command can't wait in the middle of a transaction
+    client.deny_blocking = true;
+    let replies = queued
+        .iter()
+        .map(|value| call(value, server, client, map).unwrap_or_else(Value::null))
+        .collect();
+    client.deny_blocking = false;
+    if client.wrapping == Wrapping::Started {
+        server.propagate(client.db, &aof::encode_command(&["EXEC"]));
+    }
+    client.wrapping = Wrapping::Off;
+    Value::array(replies)
+}
+
+/// `DISCARD` drops the queued commands and the watched keys.
+pub fn discard_command(client: &mut Client, map: &mut DictionaryServer) -> Value {
+    if client.multi.take().is_none() {
+        return Value::error("ERR DISCARD without MULTI");
+    }
+    client.multi_failed = false;
+    unwatch_command(client, map);
+    Value::ok()
+}
+
+/// `WATCH key [key ...]` makes the next `EXEC` fail if any of the keys is
+/// modified before it runs. Inside `MULTI` it aborts the transaction.
+pub fn watch_command(values: &[Value], client: &mut Client, map: &mut DictionaryServer) -> Value {
+    if client.multi.is_some() {
+        return reject(
+            client,
+            Value::error("ERR WATCH inside MULTI is not allowed"),
+        );
+    }
+    for i in 0..values.len() {
+        let key = (client.db, arg_bytes(values, i));
+        if !client.watched.contains(&key) {
+            map.watch(client.id, &key.1);
+            client.watched.push(key);
+        }
+    }
+    Value::ok()
+}
+
+/// `UNWATCH` forgets every watched key, also done when the client
+/// disconnects.
+pub fn unwatch_command(client: &mut Client, map: &mut DictionaryServer) -> Value {
+    map.unwatch(client.id, &client.watched);
+    client.watched.clear();
+    Value::ok()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commands::test::Session;
+
+    #[test]
+    fn test_multi_exec() {
+        let mut session = Session::new(1);
+        assert_eq!(session.run(0, &["MULTI"]), "+OK\r\n");
+        assert_eq!(session.run(0, &["SET", "a", "1"]), "+QUEUED\r\n");
+        assert_eq!(session.run(0, &["INCR", "a"]), "+QUEUED\r\n");
+        assert_eq!(session.run(0, &["GET", "a"]), "+QUEUED\r\n");
+        assert!(session.map.server.is_empty());
+        assert_eq!(session.run(0, &["EXEC"]), "*3\r\n+OK\r\n:2\r\n$1\r\n2\r\n");
+        assert_eq!(session.run(0, &["EXEC"]), "-ERR EXEC without MULTI\r\n");
+        assert_eq!(
+            session.run(0, &["DISCARD"]),
+            "-ERR DISCARD without MULTI\r\n"
+        );
+    }
+
+    #[test]
+    fn test_discard_and_errors() {
+        let mut session = Session::new(1);
+        session.run(0, &["MULTI"]);
+        session.run(0, &["SET", "a", "1"]);
+        assert_eq!(
+            session.run(0, &["MULTI"]),
+            "-ERR MULTI calls can not be nested\r\n"
+        );
+        assert_eq!(session.run(0, &["DISCARD"]), "+OK\r\n");
+        assert!(session.map.server.is_empty());
+
+        // errors while queueing abort the whole transaction
+        session.run(0, &["MULTI"]);
+        session.run(0, &["SET", "a", "1"]);
+        assert_eq!(
+            session.run(0, &["GET"]),
+            "-ERR wrong number of arguments for 'get' command\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["EXEC"]),
+            "-EXECABORT Transaction discarded because of previous errors.\r\n"
+        );
+        assert!(session.map.server.is_empty());
+
+        // errors while executing don't stop the other commands
+        session.run(0, &["SET", "s", "v"]);
+        session.run(0, &["MULTI"]);
+        session.run(0, &["INCR", "s"]);
+        session.run(0, &["SET", "a", "1"]);
+        assert_eq!(
+            session.run(0, &["EXEC"]),
+            "*2\r\n-ERR value is not an integer or out of range\r\n+OK\r\n"
+        );
+    }
+
+    #[test]
+    fn test_watch() {
+        let mut session = Session::new(2);
+        session.run(0, &["SET", "balance", "10"]);
+        assert_eq!(session.run(0, &["WATCH", "balance"]), "+OK\r\n");
+        session.run(0, &["MULTI"]);
+        assert_eq!(
+            session.run(0, &["WATCH", "balance"]),
+            "-ERR WATCH inside MULTI is not allowed\r\n"
+        );
+        assert_eq!(
+            session.run(0, &["EXEC"]),
+            "-EXECABORT Transaction discarded because of previous errors.\r\n"
+        );
+
+        session.run(0, &["WATCH", "balance"]);
+        session.run(0, &["MULTI"]);
+        session.run(0, &["INCRBY", "balance", "5"]);
+        // another client changes the watched key before EXEC
+        session.run(1, &["INCRBY", "balance", "100"]);
+        assert_eq!(session.run(0, &["EXEC"]), "*-1\r\n");
+        assert_eq!(session.run(0, &["GET", "balance"]), "$3\r\n110\r\n");
+
+        // EXEC unwatched the key, the retry goes through
+        session.run(0, &["WATCH", "balance"]);
+        session.run(0, &["MULTI"]);
+        session.run(0, &["INCRBY", "balance", "5"]);
+        assert_eq!(session.run(0, &["EXEC"]), "*1\r\n:115\r\n");
+
+        // UNWATCH forgets about the key
+        session.run(0, &["WATCH", "balance"]);
+        session.run(1, &["DEL", "balance"]);
+        session.run(0, &["UNWATCH"]);
+        session.run(0, &["MULTI"]);
+        session.run(0, &["SET", "balance", "1"]);
+        assert_eq!(session.run(0, &["EXEC"]), "*1\r\n+OK\r\n");
+
+        // FLUSHDB touches every watched key that existed
+        session.run(0, &["WATCH", "balance"]);
+        session.run(1, &["FLUSHDB"]);
+        session.run(0, &["MULTI"]);
+        assert_eq!(session.run(0, &["EXEC"]), "*-1\r\n");
+    }
+
+    #[test]
+    fn test_propagation() {
+        let mut session = Session::new(1);
+        // nothing changed, nothing to wrap
+        session.run(0, &["MULTI"]);
+        session.run(0, &["DEL", "missing"]);
+        session.run(0, &["GET", "a"]);
+        session.run(0, &["EXEC"]);
+        session.run(0, &["EVAL", "return redis.call('DEL', 'missing')", "0"]);
+        assert_eq!(session.backlog(), "");
+
+        // a script inside a transaction doesn't wrap its writes again
+        session.run(0, &["MULTI"]);
+        session.run(0, &["DEL", "missing"]);
+        session.run(0, &["EVAL", "return redis.call('SET', 'a', '1')", "0"]);
+        session.run(0, &["EXEC"]);
+        assert_eq!(
+            session.backlog(),
+            "*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n*1\r\n$5\r\nMULTI\r\n\
+             *3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n*1\r\n$4\r\nEXEC\r\n"
+        );
+    }
+}
@@ -0,0 +1,438 @@
+use crate::commands::{arg, arg_bytes, normalize_range, parse_int, wrong_arity, SYNTAX_ERROR};
+use crate::dictionary_server::DictionaryServer;
+use crate::parser::{Protocol, Value};
+use crate::sorted_set::{parse_score, ScoreBound};
+
+const NOT_A_FLOAT: &str = "ERR value is not a valid float";
+const SCORE_IS_NAN: &str = "ERR resulting score is not a number (NaN)";
+
+fn parse_float(string: &str) -> Result<f64, Value> {
+    parse_score(string).ok_or_else(|| Value::error(NOT_A_FLOAT))
+}
+
+/// Members of a range reply, each followed by its score with `WITHSCORES`.
+/// RESP3 clients get every member paired with its score in a nested array.
+fn range_reply(range: Vec<(Vec<u8>, f64)>, with_scores: bool, protocol: Protocol) -> Value {
+    let mut elements = Vec::new();
+    for (member, score) in range {
+        let member = Value::bulk_string(&member);
+        match (with_scores, protocol) {
+            (false, _) => elements.push(member),
+            (true, Protocol::Resp2) => elements.extend([member, Value::double(score)]),
+            (true, Protocol::Resp3) => {
+                elements.push(Value::array(vec![member, Value::double(score)]))
+            }
+        }
+    }
+    Value::array(elements)
+}
+
+/// `ZADD key [NX | XX] [GT | LT] [CH] [INCR] score member [score member ...]`
+/// replies how many members were added, with `CH` updated ones count too.
+/// `INCR` works like `ZINCRBY` and replies the new score, nil when one of the
+/// conditions stopped the update.
+pub fn zadd_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let (mut nx, mut xx, mut gt, mut lt, mut ch, mut incr) =
+        (false, false, false, false, false, false);
+    let mut i = 1;
+    while i < values.len() {
+        match arg(values, i).to_uppercase().as_str() {
+            "NX" => nx = true,
+            "XX" => xx = true,
+            "GT" => gt = true,
+            "LT" => lt = true,
+            "CH" => ch = true,
+            "INCR" => incr = true,
+            _ => break,
+        }
+        i += 1;
+    }
+
+    let pairs = &values[i..];
+    if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+        return Value::error(SYNTAX_ERROR);
+    }
+    if nx && xx {
+        return Value::error("ERR XX and NX options at the same time are not compatible");
+    }
+    if (gt && lt) || (nx && (gt || lt)) {
+        return Value::error("ERR GT, LT, and/or NX options at the same time are not compatible");
+    }
+    if incr && pairs.len() > 2 {
+        return Value::error("ERR INCR option supports a single increment-element pair");
+    }
+    // every score is checked before touching the set
+    let mut elements = Vec::new();
+    for pair in pairs.chunks(2) {
+        let score = try_reply!(parse_float(&arg(pair, 0)));
+        elements.push((score, arg_bytes(pair, 1)));
+    }
+
+    if try_reply!(map.zset_mut(&key, false)).is_none() && xx {
+        return if incr {
+            Value::null()
+        } else {
+            Value::integer(0)
+        };
+    }
+    let zset = try_reply!(map.zset_mut(&key, true)).unwrap();
+    let (mut added, mut updated) = (0, 0);
+    let mut result = None;
+    for (score, member) in elements {
+        match zset.score(&member) {
+            Some(current) => {
+                if nx {
+                    continue;
+                }
+                let score = if incr { current + score } else { score };
+                if score.is_nan() {
+                    map.remove_if_empty(&key);
+                    return Value::error(SCORE_IS_NAN);
+                }
+                if (gt && score <= current) || (lt && score >= current) {
+                    continue;
+                }
+                if score != current {
+                    zset.insert(&member, score);
+                    updated += 1;
+                }
+                result = Some(score);
+            }
+            None => {
+                if xx {
+                    continue;
+                }
+                zset.insert(&member, score);
+                added += 1;
+                result = Some(score);
+            }
+        }
+    }
+
+    if added + updated > 0 {
+        map.modified(&key);
+    }
+    map.remove_if_empty(&key);
+    if incr {
+        match result {
+            Some(score) => Value::double(score),
+            None => Value::null(),
+        }
+    } else if ch {
+        Value::integer(added + updated)
+    } else {
+        Value::integer(added)
+    }
+}
+
+/// `ZREM key member [member ...]` replies how many members were removed.
+pub fn zrem_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let zset = match try_reply!(map.zset_mut(&key, false)) {
+        Some(zset) => zset,
+        None => return Value::integer(0),
+    };
+    let removed = (1..values.len())
+        .filter(|i| zset.remove(&arg_bytes(values, *i)))
+        .count();
+    if removed > 0 {
+        map.modified(&key);
+        map.remove_if_empty(&key);
+    }
+    Value::integer(removed as i64)
+}
+
+/// `ZSCORE key member` score of the member, nil if it isn't in the set.
+pub fn zscore_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let zset = try_reply!(map.zset_mut(&key, false));
+    match zset.and_then(|zset| zset.score(&arg_bytes(values, 1))) {
+        Some(score) => Value::double(score),
+        None => Value::null(),
+    }
+}
+
+/// `ZRANK key member [WITHSCORE]` and `ZREVRANK`, 0 based position of the
+/// member ordered by ascending (descending) score.
+pub fn zrank_command(values: &[Value], map: &mut DictionaryServer, reverse: bool) -> Value {
+    let name = if reverse { "zrevrank" } else { "zrank" };
+    if values.len() > 3 {
+        return wrong_arity(name);
+    }
+    let with_score = match values.get(2) {
+        Some(_) if arg(values, 2).eq_ignore_ascii_case("WITHSCORE") => true,
+        Some(_) => return Value::error(SYNTAX_ERROR),
+        None => false,
+    };
+    let key = arg_bytes(values, 0);
+    let member = arg_bytes(values, 1);
+    let zset = match try_reply!(map.zset_mut(&key, false)) {
+        Some(zset) => zset,
+        None => return Value::null(),
+    };
+    match zset.rank(&member, reverse) {
+        Some(rank) if with_score => Value::array(vec![
+            Value::integer(rank as i64),
+            Value::double(zset.score(&member).unwrap()),
+        ]),
+        Some(rank) => Value::integer(rank as i64),
+        None => Value::null(),
+    }
+}
+
+/// `ZRANGE key start stop [BYSCORE] [REV] [LIMIT offset count] [WITHSCORES]`
+/// members between two ranks, or with `BYSCORE` between two scores where
+/// `(` makes a bound exclusive. With `REV` the order is descending and
+/// `start` is the higher bound.
+pub fn zrange_command(values: &[Value], map: &mut DictionaryServer, protocol: Protocol) -> Value {
+    let key = arg_bytes(values, 0);
+    let (mut by_score, mut reverse, mut with_scores) = (false, false, false);
+    let mut limit = None;
+    let mut i = 3;
+    while i < values.len() {
+        match arg(values, i).to_uppercase().as_str() {
+            "BYSCORE" => by_score = true,
+            "REV" => reverse = true,
+            "WITHSCORES" => with_scores = true,
+            "LIMIT" if i + 2 < values.len() => {
+                let offset = try_reply!(parse_int(&arg(values, i + 1)));
+                let count = try_reply!(parse_int(&arg(values, i + 2)));
+                limit = Some((offset, count));
+                i += 2;
+            }
+            _ => return Value::error(SYNTAX_ERROR),
+        }
+        i += 1;
+    }
+    if limit.is_some() && !by_score {
+        return Value::error(
+            "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX",
+        );
+    }
+
+    if by_score {
+        let (min, max) = if reverse {
+            (arg(values, 2), arg(values, 1))
+        } else {
+            (arg(values, 1), arg(values, 2))
+        };
+        let (min, max) = match (ScoreBound::parse(&min), ScoreBound::parse(&max)) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return Value::error("ERR min or max is not a float"),
+        };
+        let (offset, count) = limit.unwrap_or((0, -1));
+        let zset = match try_reply!(map.zset_mut(&key, false)) {
+            Some(zset) if offset >= 0 => zset,
+            _ => return Value::array(Vec::new()),
+        };
+        // a negative count returns every member after the offset
+        let count = (count >= 0).then_some(count as usize);
+        let range = zset.range_by_score(&min, &max, reverse, offset as usize, count);
+        return range_reply(range, with_scores, protocol);
+    }
+
+    let start = try_reply!(parse_int(&arg(values, 1)));
+    let stop = try_reply!(parse_int(&arg(values, 2)));
+    let zset = match try_reply!(map.zset_mut(&key, false)) {
+        Some(zset) => zset,
+        None => return Value::array(Vec::new()),
+    };
+    match normalize_range(start, stop, zset.len()) {
+        Some((start, stop)) => range_reply(
+            zset.range_by_rank(start, stop, reverse),
+            with_scores,
+            protocol,
+        ),
+        None => Value::array(Vec::new()),
+    }
+}
+
+/// `ZINCRBY key increment member` adds to the score of the member, a missing
+/// member starts at 0. Replies the new score.
+pub fn zincrby_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let increment = try_reply!(parse_float(&arg(values, 1)));
+    let member = arg_bytes(values, 2);
+
+    let current = try_reply!(map.zset_mut(&key, false)).and_then(|zset| zset.score(&member));
+    let score = current.unwrap_or(0.0) + increment;
+    if score.is_nan() {
+        return Value::error(SCORE_IS_NAN);
+    }
+    let zset = try_reply!(map.zset_mut(&key, true)).unwrap();
+    zset.insert(&member, score);
+    map.modified(&key);
+    Value::double(score)
+}
+
+/// `ZCARD key` number of members in the sorted set.
+pub fn zcard_command(values: &[Value], map: &mut DictionaryServer) -> Value {
+    let key = arg_bytes(values, 0);
+    let len = try_reply!(map.zset_mut(&key, false)).map_or(0, |zset| zset.len());
+    Value::integer(len as i64)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commands::test::run;
+    use crate::dictionary_server::DictionaryServer;
+
+    fn leaderboard() -> DictionaryServer {
+        let mut map = DictionaryServer::new();
+        run(
+            &mut map,
+            &[
+                "ZADD", "z", "1", "a", "2", "b", "3", "c", "4", "d", "5", "e",
+            ],
+        );
+        map
+    }
+
+    #[test]
+    fn test_zadd_zscore_zrem() {
+        let mut map = DictionaryServer::new();
+        assert_eq!(
+            run(&mut map, &["ZADD", "z", "1", "a", "2.5", "b"]),
+            ":2\r\n"
+        );
+        assert_eq!(run(&mut map, &["ZADD", "z", "3", "a", "1", "c"]), ":1\r\n");
+        assert_eq!(run(&mut map, &["ZSCORE", "z", "a"]), "$1\r\n3\r\n");
+        assert_eq!(run(&mut map, &["ZSCORE", "z", "b"]), "$3\r\n2.5\r\n");
+        assert_eq!(run(&mut map, &["ZSCORE", "z", "x"]), "$-1\r\n");
+        assert_eq!(run(&mut map, &["ZCARD", "z"]), ":3\r\n");
+        assert_eq!(
+            run(&mut map, &["ZADD", "z", "x", "a"]),
+            "-ERR value is not a valid float\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["ZADD", "z", "NX", "1"]),
+            "-ERR syntax error\r\n"
+        );
+        assert_eq!(run(&mut map, &["ZREM", "z", "a", "x"]), ":1\r\n");
+        assert_eq!(run(&mut map, &["ZREM", "z", "b", "c"]), ":2\r\n");
+        assert!(map.server.is_empty());
+    }
+
+    #[test]
+    fn test_zadd_options() {
+        let mut map = leaderboard();
+        assert_eq!(
+            run(&mut map, &["ZADD", "z", "NX", "10", "a", "6", "f"]),
+            ":1\r\n"
+        );
+        assert_eq!(run(&mut map, &["ZSCORE", "z", "a"]), "$1\r\n1\r\n");
+        assert_eq!(
+            run(&mut map, &["ZADD", "z", "XX", "10", "a", "7", "g"]),
+            ":0\r\n"
+        );
+        assert_eq!(run(&mut map, &["ZSCORE", "z", "g"]), "$-1\r\n");
+        assert_eq!(
+            run(&mut map, &["ZADD", "z", "GT", "CH", "1", "a", "20", "b"]),
+            ":1\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["ZADD", "z", "INCR", "5", "a"]),
+            "$2\r\n15\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["ZADD", "z", "LT", "INCR", "1", "a"]),
+            "$-1\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["ZADD", "z", "NX", "XX", "1", "a"]),
+            "-ERR XX and NX options at the same time are not compatible\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["ZADD", "missing", "XX", "1", "a"]),
+            ":0\r\n"
+        );
+        assert!(!map.server.contains_key(b"missing".as_slice()));
+    }
+
+    #[test]
+    fn test_zrank() {
+        let mut map = leaderboard();
+        assert_eq!(run(&mut map, &["ZRANK", "z", "a"]), ":0\r\n");
+        assert_eq!(run(&mut map, &["ZRANK", "z", "d"]), ":3\r\n");
+        assert_eq!(run(&mut map, &["ZREVRANK", "z", "d"]), ":1\r\n");
+        assert_eq!(
+            run(&mut map, &["ZRANK", "z", "b", "WITHSCORE"]),
+            "*2\r\n:1\r\n$1\r\n2\r\n"
+        );
+        assert_eq!(run(&mut map, &["ZRANK", "z", "x"]), "$-1\r\n");
+        assert_eq!(run(&mut map, &["ZRANK", "missing", "x"]), "$-1\r\n");
+    }
+
+    #[test]
+    fn test_zrange_by_rank() {
+        let mut map = leaderboard();
+        assert_eq!(
+            run(&mut map, &["ZRANGE", "z", "0", "1"]),
+            "*2\r\n$1\r\na\r\n$1\r\nb\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["ZRANGE", "z", "-2", "-1", "WITHSCORES"]),
+            "*4\r\n$1\r\nd\r\n$1\r\n4\r\n$1\r\ne\r\n$1\r\n5\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["ZRANGE", "z", "0", "1", "REV"]),
+            "*2\r\n$1\r\ne\r\n$1\r\nd\r\n"
+        );
+        assert_eq!(run(&mut map, &["ZRANGE", "z", "10", "20"]), "*0\r\n");
+        assert_eq!(
+            run(&mut map, &["ZRANGE", "z", "0", "1", "LIMIT", "0", "1"]),
+            "-ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX\r\n"
+        );
+    }
+
+    #[test]
+    fn test_zrange_by_score() {
+        let mut map = leaderboard();
+        assert_eq!(
+            run(&mut map, &["ZRANGE", "z", "(2", "4", "BYSCORE"]),
+            "*2\r\n$1\r\nc\r\n$1\r\nd\r\n"
+        );
+        assert_eq!(
+            run(
+                &mut map,
+                &["ZRANGE", "z", "+inf", "-inf", "BYSCORE", "REV", "LIMIT", "1", "2"]
+            ),
+            "*2\r\n$1\r\nd\r\n$1\r\nc\r\n"
+        );
+        assert_eq!(
+            run(
+                &mut map,
+                &["ZRANGE", "z", "-inf", "(2", "BYSCORE", "WITHSCORES"]
+            ),
+            "*2\r\n$1\r\na\r\n$1\r\n1\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["ZRANGE", "z", "a", "2", "BYSCORE"]),
+            "-ERR min or max is not a float\r\n"
+        );
+    }
+
+    #[test]
+    fn test_zincrby() {
+        let mut map = DictionaryServer::new();
+        assert_eq!(run(&mut map, &["ZINCRBY", "z", "2", "a"]), "$1\r\n2\r\n");
+        assert_eq!(
+            run(&mut map, &["ZINCRBY", "z", "-0.5", "a"]),
+            "$3\r\n1.5\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["ZINCRBY", "z", "inf", "a"]),
+            "$3\r\ninf\r\n"
+        );
+        assert_eq!(
+            run(&mut map, &["ZINCRBY", "z", "-inf", "a"]),
+            "-ERR resulting score is not a number (NaN)\r\n"
+        );
+        run(&mut map, &["SET", "s", "v"]);
+        assert_eq!(
+            run(&mut map, &["ZINCRBY", "s", "1", "a"]),
+            "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+    }
+}
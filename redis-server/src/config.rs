@@ -0,0 +1,681 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::{fs, io};
+
+/// `save <seconds> <changes>` rule, a snapshot is taken once at least
+/// `changes` writes happened and `seconds` passed since the last one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SaveRule {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+/// How often the append only file is flushed to the disk (`appendfsync`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FsyncPolicy {
+    /// after every write command, slow but nothing is lost
+    Always,
+    /// once per second, at most a second of writes can be lost
+    EverySec,
+    /// let the operating system decide
+    No,
+}
+
+/// Which keys are evicted once `maxmemory` is reached.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MaxmemoryPolicy {
+    /// writes fail with an OOM error, nothing is evicted
+    NoEviction,
+    AllKeysLru,
+    AllKeysLfu,
+    AllKeysRandom,
+    /// like the `allkeys` policies but only keys with a TTL are candidates
+    VolatileLru,
+    VolatileLfu,
+    VolatileRandom,
+    /// keys with a TTL, the ones closest to expire first
+    VolatileTtl,
+}
+
+const MAXMEMORY_POLICIES: &[(&str, MaxmemoryPolicy)] = &[
+    ("noeviction", MaxmemoryPolicy::NoEviction),
+    ("allkeys-lru", MaxmemoryPolicy::AllKeysLru),
+    ("allkeys-lfu", MaxmemoryPolicy::AllKeysLfu),
+    ("allkeys-random", MaxmemoryPolicy::AllKeysRandom),
+    ("volatile-lru", MaxmemoryPolicy::VolatileLru),
+    ("volatile-lfu", MaxmemoryPolicy::VolatileLfu),
+    ("volatile-random", MaxmemoryPolicy::VolatileRandom),
+    ("volatile-ttl", MaxmemoryPolicy::VolatileTtl),
+];
+
+/// Settings which can be read with `CONFIG GET`, changed with `CONFIG SET`
+/// and written back by `CONFIG REWRITE`.
+pub const PARAMETERS: &[&str] = &[
+    "bind",
+    "port",
+    "maxclients",
+    "databases",
+    "requirepass",
+    "dir",
+    "dbfilename",
+    "save",
+    "appendonly",
+    "appendfilename",
+    "appendfsync",
+    "maxmemory",
+    "maxmemory-policy",
+    "maxmemory-samples",
+    "replicaof",
+    "masterauth",
+    "replica-read-only",
+    "repl-backlog-size",
+    "slowlog-log-slower-than",
+    "slowlog-max-len",
+    "lua-time-limit",
+];
+
+/// Settings which only take effect at startup, `replicaof` is changed with
+/// the `REPLICAOF` command.
+const IMMUTABLE: &[&str] = &["bind", "port", "databases", "replicaof"];
+
+/// Server settings, the defaults match the ones of redis.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// addresses to listen on
+    pub bind: Vec<String>,
+    pub port: u16,
+    pub maxclients: usize,
+    /// number of databases clients can `SELECT`
+    pub databases: usize,
+    /// password clients have to `AUTH` with, empty when there is none
+    pub requirepass: String,
+    pub dir: String,
+    pub dbfilename: String,
+    pub save: Vec<SaveRule>,
+    pub appendonly: bool,
+    pub appendfilename: String,
+    pub appendfsync: FsyncPolicy,
+    /// memory limit of the dataset in bytes, 0 for no limit
+    pub maxmemory: u64,
+    pub maxmemory_policy: MaxmemoryPolicy,
+    /// keys sampled to pick the one to evict
+    pub maxmemory_samples: usize,
+    /// host and port of the master when the server is a replica
+    pub replicaof: Option<(String, u16)>,
+    /// password to `AUTH` with against the master
+    pub masterauth: String,
+    /// clients of a replica can't run write commands
+    pub replica_read_only: bool,
+    /// bytes of the replication stream kept for replicas which reconnect
+    pub repl_backlog_size: u64,
+    /// commands taking at least this many microseconds go to the slow log,
+    /// negative turns it off
+    pub slowlog_log_slower_than: i64,
+    /// entries kept in the slow log
+    pub slowlog_max_len: usize,
+    /// milliseconds after which a running script is busy and may be killed,
+    /// zero or negative never makes it busy
+    pub lua_time_limit: i64,
+    /// configuration file the server was started with, `CONFIG REWRITE`
+    /// writes to it
+    pub file: Option<PathBuf>,
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(format!("argument must be 'yes' or 'no' for '{}'", name)),
+    }
+}
+
+/// Memory sizes like `100mb` or `1gb`, `k`, `m` and `g` are powers of 1000,
+/// `kb`, `mb` and `gb` powers of 1024.
+fn parse_memory(name: &str, value: &str) -> Result<u64, String> {
+    let value = value.to_lowercase();
+    let digits = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = match &value[digits.len()..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err(format!("argument must be a memory value for '{}'", name)),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| format!("argument must be a memory value for '{}'", name))
+}
+
+/// `<host> <port>` of the master, `no one` when the server isn't a replica.
+fn parse_replicaof(value: &str) -> Result<Option<(String, u16)>, String> {
+    let args: Vec<&str> = value.split_whitespace().collect();
+    match args.as_slice() {
+        [no, one] if no.eq_ignore_ascii_case("no") && one.eq_ignore_ascii_case("one") => Ok(None),
+        [host, port] => match port.parse() {
+            Ok(port) => Ok(Some((host.to_string(), port))),
+            Err(_) => Err("Invalid master port".to_string()),
+        },
+        _ => Err("wrong number of arguments for 'replicaof'".to_string()),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("argument couldn't be parsed into an integer for '{}'", name))
+}
+
+/// Split a line of the configuration file into its arguments. Arguments are
+/// separated by spaces unless they're quoted, double quoted ones support
+/// backslash escapes.
+fn split_line(line: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let quote = match chars.peek() {
+            None => return Ok(args),
+            Some(&c) if c == '"' || c == '\'' => chars.next(),
+            Some(_) => None,
+        };
+        let mut arg = String::new();
+        loop {
+            match (chars.next(), quote) {
+                (None, None) => break,
+                (None, Some(_)) => return Err("Unbalanced quotes in configuration line".into()),
+                (Some(c), Some(q)) if c == q => {
+                    // the closing quote has to end the argument
+                    if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                        return Err("Unbalanced quotes in configuration line".into());
+                    }
+                    break;
+                }
+                (Some('\\'), Some('"')) => match chars.next() {
+                    Some('n') => arg.push('\n'),
+                    Some('t') => arg.push('\t'),
+                    Some(c) => arg.push(c),
+                    None => return Err("Unbalanced quotes in configuration line".into()),
+                },
+                (Some(c), None) if c.is_whitespace() => break,
+                (Some(c), _) => arg.push(c),
+            }
+        }
+        args.push(arg);
+    }
+}
+
+/// Quote `value` if reading it back with `split_line` wouldn't give it as a
+/// single argument.
+fn quote(value: &str) -> String {
+    if !value.is_empty() && !value.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'') {
+        return value.to_string();
+    }
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    format!("\"{}\"", escaped)
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: vec!["127.0.0.1".to_string()],
+            port: 6379,
+            maxclients: 10000,
+            databases: 16,
+            requirepass: String::new(),
+            dir: ".".to_string(),
+            dbfilename: "dump.rdb".to_string(),
+            save: vec![
+                SaveRule {
+                    seconds: 3600,
+                    changes: 1,
+                },
+                SaveRule {
+                    seconds: 300,
+                    changes: 100,
+                },
+                SaveRule {
+                    seconds: 60,
+                    changes: 10000,
+                },
+            ],
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_string(),
+            appendfsync: FsyncPolicy::EverySec,
+            maxmemory: 0,
+            maxmemory_policy: MaxmemoryPolicy::NoEviction,
+            maxmemory_samples: 5,
+            replicaof: None,
+            masterauth: String::new(),
+            replica_read_only: true,
+            repl_backlog_size: 1024 * 1024,
+            slowlog_log_slower_than: 10000,
+            slowlog_max_len: 128,
+            lua_time_limit: 5000,
+            file: None,
+        }
+    }
+}
+
+impl Config {
+    /// Build the configuration out of command line flags in the same form
+    /// `redis-server` accepts them e.g. `--dir /tmp --save "900 1 300 10"`.
+    /// The first argument may be the path of a configuration file, flags
+    /// override what it sets.
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut args = args.peekable();
+        // every `save` directive adds rules, the first one replaces the
+        // defaults
+        let mut default_save = true;
+
+        if let Some(path) = args.next_if(|arg| !arg.starts_with("--")) {
+            let contents = fs::read_to_string(&path)
+                .map_err(|e| format!("Fatal error, can't open config file '{}': {}", path, e))?;
+            config.load(&contents, &mut default_save)?;
+            config.file = Some(PathBuf::from(path));
+        }
+
+        while let Some(flag) = args.next() {
+            let name = match flag.strip_prefix("--") {
+                Some(name) => name.to_lowercase(),
+                None => return Err(format!("Invalid argument '{}'", flag)),
+            };
+            let mut params = Vec::new();
+            while let Some(param) = args.next_if(|arg| !arg.starts_with("--")) {
+                params.push(param);
+            }
+            config.apply(&name, &params.join(" "), &mut default_save)?;
+        }
+        Ok(config)
+    }
+
+    /// Apply the directives of a redis.conf style file, one per line. Empty
+    /// lines and `#` comments are skipped.
+    fn load(&mut self, contents: &str, default_save: &mut bool) -> Result<(), String> {
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parsed = split_line(line).and_then(|args| {
+                let name = args[0].to_lowercase();
+                self.apply(&name, &args[1..].join(" "), default_save)
+            });
+            if let Err(e) = parsed {
+                return Err(format!(
+                    "*** FATAL CONFIG FILE ERROR ***\nReading the configuration file, at line {}\n>>> '{}'\n{}",
+                    number + 1,
+                    line,
+                    e
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, name: &str, value: &str, default_save: &mut bool) -> Result<(), String> {
+        if name == "save" && *default_save {
+            self.save.clear();
+            *default_save = false;
+        }
+        self.set(name, value)
+    }
+
+    /// Change a single setting, `save` appends to the existing rules unless
+    /// the value is empty which disables snapshots.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "bind" => self.bind = value.split_whitespace().map(String::from).collect(),
+            "port" => self.port = parse_number(name, value)?,
+            "maxclients" => self.maxclients = parse_number(name, value)?,
+            "databases" => match parse_number(name, value)? {
+                0 => return Err("Invalid number of databases".to_string()),
+                databases => self.databases = databases,
+            },
+            "requirepass" => self.requirepass = value.to_string(),
+            "dir" => self.dir = value.to_string(),
+            "dbfilename" => self.dbfilename = value.to_string(),
+            "save" => {
+                if value.trim().is_empty() {
+                    self.save.clear();
+                }
+                let numbers = value
+                    .split_whitespace()
+                    .map(|n| n.parse::<u64>())
+                    .collect::<Result<Vec<u64>, _>>()
+                    .map_err(|_| format!("Invalid save parameters '{}'", value))?;
+                if numbers.len() % 2 != 0 {
+                    return Err(format!("Invalid save parameters '{}'", value));
+                }
+                for pair in numbers.chunks(2) {
+                    self.save.push(SaveRule {
+                        seconds: pair[0],
+                        changes: pair[1],
+                    });
+                }
+            }
+            "appendonly" => self.appendonly = parse_bool(name, value)?,
+            "appendfilename" => self.appendfilename = value.to_string(),
+            "appendfsync" => {
+                self.appendfsync = match value.to_lowercase().as_str() {
+                    "always" => FsyncPolicy::Always,
+                    "everysec" => FsyncPolicy::EverySec,
+                    "no" => FsyncPolicy::No,
+                    _ => return Err(format!("Invalid appendfsync policy '{}'", value)),
+                }
+            }
+            "maxmemory" => self.maxmemory = parse_memory(name, value)?,
+            "maxmemory-policy" => {
+                self.maxmemory_policy = MAXMEMORY_POLICIES
+                    .iter()
+                    .find(|(policy, _)| policy.eq_ignore_ascii_case(value))
+                    .map(|(_, policy)| *policy)
+                    .ok_or_else(|| format!("Invalid maxmemory-policy '{}'", value))?
+            }
+            "maxmemory-samples" => self.maxmemory_samples = parse_number(name, value)?,
+            "replicaof" | "slaveof" => self.replicaof = parse_replicaof(value)?,
+            "masterauth" => self.masterauth = value.to_string(),
+            "replica-read-only" | "slave-read-only" => {
+                self.replica_read_only = parse_bool(name, value)?
+            }
+            "repl-backlog-size" => self.repl_backlog_size = parse_memory(name, value)?,
+            "slowlog-log-slower-than" => self.slowlog_log_slower_than = parse_number(name, value)?,
+            "slowlog-max-len" => self.slowlog_max_len = parse_number(name, value)?,
+            "lua-time-limit" | "busy-reply-threshold" => {
+                self.lua_time_limit = parse_number(name, value)?
+            }
+            _ => {
+                return Err(format!(
+                    "Bad directive or wrong number of arguments '{}'",
+                    name
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// `CONFIG SET`: unlike the configuration file `save` replaces the rules
+    /// instead of adding to them.
+    pub fn update(&mut self, name: &str, value: &str) -> Result<(), String> {
+        if IMMUTABLE.contains(&name) {
+            return Err("can't set immutable config".to_string());
+        }
+        if !PARAMETERS.contains(&name) {
+            return Err("Unknown option or number of arguments".to_string());
+        }
+        if name == "save" {
+            self.save.clear();
+        }
+        self.set(name, value)
+    }
+
+    /// Current value of the parameter `name` as `CONFIG GET` shows it.
+    pub fn get(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "bind" => self.bind.join(" "),
+            "port" => self.port.to_string(),
+            "maxclients" => self.maxclients.to_string(),
+            "databases" => self.databases.to_string(),
+            "requirepass" => self.requirepass.clone(),
+            "dir" => self.dir.clone(),
+            "dbfilename" => self.dbfilename.clone(),
+            "save" => self
+                .save
+                .iter()
+                .map(|rule| format!("{} {}", rule.seconds, rule.changes))
+                .collect::<Vec<_>>()
+                .join(" "),
+            "appendonly" => if self.appendonly { "yes" } else { "no" }.to_string(),
+            "appendfilename" => self.appendfilename.clone(),
+            "appendfsync" => match self.appendfsync {
+                FsyncPolicy::Always => "always",
+                FsyncPolicy::EverySec => "everysec",
+                FsyncPolicy::No => "no",
+            }
+            .to_string(),
+            "maxmemory" => self.maxmemory.to_string(),
+            "maxmemory-policy" => MAXMEMORY_POLICIES
+                .iter()
+                .find(|(_, policy)| *policy == self.maxmemory_policy)
+                .map(|(name, _)| name.to_string())
+                .unwrap_or_default(),
+            "maxmemory-samples" => self.maxmemory_samples.to_string(),
+            "replicaof" => self
+                .replicaof
+                .as_ref()
+                .map(|(host, port)| format!("{} {}", host, port))
+                .unwrap_or_default(),
+            "masterauth" => self.masterauth.clone(),
+            "replica-read-only" => if self.replica_read_only { "yes" } else { "no" }.to_string(),
+            "repl-backlog-size" => self.repl_backlog_size.to_string(),
+            "slowlog-log-slower-than" => self.slowlog_log_slower_than.to_string(),
+            "slowlog-max-len" => self.slowlog_max_len.to_string(),
+            "lua-time-limit" => self.lua_time_limit.to_string(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Lines of the configuration file setting `name` to its current value.
+    fn directives(&self, name: &str) -> Vec<String> {
+        match name {
+            "save" if self.save.is_empty() => vec!["save \"\"".to_string()],
+            "save" => self
+                .save
+                .iter()
+                .map(|rule| format!("save {} {}", rule.seconds, rule.changes))
+                .collect(),
+            "bind" => vec![format!("bind {}", self.bind.join(" "))],
+            "replicaof" => match &self.replicaof {
+                Some((host, port)) => vec![format!("replicaof {} {}", quote(host), port)],
+                None => Vec::new(),
+            },
+            _ => vec![format!(
+                "{} {}",
+                name,
+                quote(&self.get(name).unwrap_or_default())
+            )],
+        }
+    }
+
+    /// `CONFIG REWRITE`: update the configuration file with the current
+    /// settings. Comments and unknown lines are kept, the first directive of
+    /// a parameter is replaced with its value and repeated ones are dropped.
+    /// Parameters which aren't in the file yet are appended unless they have
+    /// the default value.
+    pub fn rewrite(&self) -> io::Result<()> {
+        let path = match &self.file {
+            Some(path) => path,
+            None => {
+                return Err(io::Error::other(
+                    "The server is running without a config file",
+                ))
+            }
+        };
+        let old = match fs::read_to_string(path) {
+            Ok(old) => old,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        let mut lines = Vec::new();
+        let mut written = HashSet::new();
+        for line in old.lines() {
+            let directive = line
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_lowercase();
+            match PARAMETERS.iter().find(|name| **name == directive) {
+                Some(name) => {
+                    if written.insert(*name) {
+                        lines.extend(self.directives(name));
+                    }
+                }
+                None => lines.push(line.to_string()),
+            }
+        }
+        let defaults = Config::default();
+        for name in PARAMETERS {
+            if !written.contains(name) && self.get(name) != defaults.get(name) {
+                lines.extend(self.directives(name));
+            }
+        }
+
+        let temp = path.with_extension(format!("tmp-{}", std::process::id()));
+        fs::write(&temp, lines.join("\n") + "\n")?;
+        fs::rename(&temp, path)
+    }
+
+    /// Location of the RDB snapshot i.e. `dir/dbfilename`.
+    pub fn rdb_path(&self) -> PathBuf {
+        PathBuf::from(&self.dir).join(&self.dbfilename)
+    }
+
+    /// Location of the append only file i.e. `dir/appendfilename`.
+    pub fn aof_path(&self) -> PathBuf {
+        PathBuf::from(&self.dir).join(&self.appendfilename)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn test_save_rules_from_args() {
+        let config = Config::from_args(args(&["--save", "900 1", "--save", "60", "100"])).unwrap();
+        assert_eq!(
+            config.save,
+            vec![
+                SaveRule {
+                    seconds: 900,
+                    changes: 1
+                },
+                SaveRule {
+                    seconds: 60,
+                    changes: 100
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_save_disables_snapshots() {
+        let config = Config::from_args(args(&["--save", "", "--dir", "/tmp"])).unwrap();
+        assert!(config.save.is_empty());
+        assert_eq!(config.rdb_path(), PathBuf::from("/tmp/dump.rdb"));
+    }
+
+    #[test]
+    fn test_append_only_args() {
+        let config =
+            Config::from_args(args(&["--appendonly", "yes", "--appendfsync", "always"])).unwrap();
+        assert!(config.appendonly);
+        assert_eq!(config.appendfsync, FsyncPolicy::Always);
+        assert!(Config::from_args(args(&["--appendonly", "maybe"])).is_err());
+        assert!(Config::from_args(args(&["--appendfsync", "sometimes"])).is_err());
+    }
+
+    #[test]
+    fn test_invalid_args() {
+        assert!(Config::from_args(args(&["--save", "900"])).is_err());
+        assert!(Config::from_args(args(&["--unknown", "1"])).is_err());
+        assert!(Config::from_args(args(&["dir"])).is_err());
+        assert!(Config::from_args(args(&["--databases", "0"])).is_err());
+    }
+
+    #[test]
+    fn test_config_file_and_overrides() {
+        let mut config = Config::default();
+        let mut default_save = true;
+        config
+            .load(
+                "# network\n\
+                 bind 127.0.0.1 ::1\n\
+                 port 7000\n\
+                 requirepass \"secret pass\"\n\
+                 save 900 1\n\
+                 save 60 100\n\
+                 MAXMEMORY 100mb\n\
+                 maxmemory-policy allkeys-lru\n\
+                 slaveof 10.0.0.1 6380\n",
+                &mut default_save,
+            )
+            .unwrap();
+        assert_eq!(config.bind, ["127.0.0.1", "::1"]);
+        assert_eq!(config.port, 7000);
+        assert_eq!(config.requirepass, "secret pass");
+        assert_eq!(config.get("save").unwrap(), "900 1 60 100");
+        assert_eq!(config.maxmemory, 100 * 1024 * 1024);
+        assert_eq!(config.maxmemory_policy, MaxmemoryPolicy::AllKeysLru);
+        assert_eq!(config.replicaof, Some(("10.0.0.1".to_string(), 6380)));
+        assert!(config.set("replicaof", "10.0.0.1 port").is_err());
+        config.set("replicaof", "no one").unwrap();
+        assert_eq!(config.replicaof, None);
+
+        let error = Config::default()
+            .load("port 7000\nport many\n", &mut true)
+            .unwrap_err();
+        assert!(error.contains("at line 2"));
+        assert!(Config::default().load("dir \"/tmp\n", &mut true).is_err());
+
+        let path = env::temp_dir().join(format!("redis-args-test-{}.conf", std::process::id()));
+        fs::write(&path, "port 7000\nmaxclients 10\n").unwrap();
+        let config = Config::from_args(args(&[path.to_str().unwrap(), "--port", "7001"])).unwrap();
+        assert_eq!(config.port, 7001);
+        assert_eq!(config.maxclients, 10);
+        assert_eq!(config.file, Some(path.clone()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_memory_values() {
+        assert_eq!(parse_memory("maxmemory", "100"), Ok(100));
+        assert_eq!(parse_memory("maxmemory", "1k"), Ok(1000));
+        assert_eq!(parse_memory("maxmemory", "1KB"), Ok(1024));
+        assert_eq!(parse_memory("maxmemory", "2gb"), Ok(2 << 30));
+        assert!(parse_memory("maxmemory", "1tb").is_err());
+        assert!(parse_memory("maxmemory", "mb").is_err());
+    }
+
+    #[test]
+    fn test_rewrite_keeps_comments() {
+        let path = env::temp_dir().join(format!("redis-rewrite-test-{}.conf", std::process::id()));
+        fs::write(
+            &path,
+            "# my server\nport 7000\nsave 900 1\nsave 60 100\nport 7001\n",
+        )
+        .unwrap();
+        let mut config = Config::from_args(args(&[path.to_str().unwrap()])).unwrap();
+        config.update("save", "").unwrap();
+        config.update("requirepass", "my secret").unwrap();
+        config.rewrite().unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# my server\nport 7001\nsave \"\"\nrequirepass \"my secret\"\n"
+        );
+        let reloaded = Config::from_args(args(&[path.to_str().unwrap()])).unwrap();
+        assert_eq!(reloaded.requirepass, "my secret");
+        assert!(reloaded.save.is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+}
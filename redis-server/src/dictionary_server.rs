@@ -1,29 +1,1092 @@
-use std::{collections::HashMap, io::Result};
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeSet, HashMap, HashSet, VecDeque},
+    hash::{Hash, Hasher},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+use crate::blocking::Blocked;
+use crate::config::MaxmemoryPolicy;
+use crate::sorted_set::SortedSet;
+use crate::stream::{Stream, StreamId};
+
+/// Current unix time in milliseconds, every expiry inside the dictionary is
+/// stored as an absolute timestamp in this unit.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+pub const WRONGTYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+/// Returned when a command runs against a key holding another data type.
+#[derive(Debug, PartialEq)]
+pub struct WrongType;
+
+/// Fields of a hash mapped to their values.
+pub type HashFields = HashMap<Vec<u8>, Vec<u8>>;
+
+/// Data types a key can hold.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RedisValue {
+    String(Vec<u8>),
+    List(VecDeque<Vec<u8>>),
+    Hash(HashFields),
+    Set(HashSet<Vec<u8>>),
+    SortedSet(SortedSet),
+    Stream(Stream),
+}
+
+impl RedisValue {
+    /// Name reported by `TYPE`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            RedisValue::String(_) => "string",
+            RedisValue::List(_) => "list",
+            RedisValue::Hash(_) => "hash",
+            RedisValue::Set(_) => "set",
+            RedisValue::SortedSet(_) => "zset",
+            RedisValue::Stream(_) => "stream",
+        }
+    }
+}
+
+/// Bytes a key costs besides its value: the entry, the key stored in the
+/// dictionary and in the scan order.
+const KEY_OVERHEAD: usize = 96;
+/// Bytes every element of a collection costs besides its contents.
+const ELEMENT_OVERHEAD: usize = 24;
+/// Elements looked at to estimate the size of a collection.
+const SIZE_SAMPLES: usize = 8;
+/// LFU counter of a new key, so it isn't evicted before it had a chance to
+/// be used.
+const LFU_INIT_VAL: u8 = 5;
+/// The higher, the more accesses it takes to increment the LFU counter.
+const LFU_LOG_FACTOR: f64 = 10.0;
+/// The LFU counter decreases by one for every minute a key isn't used.
+const LFU_DECAY_MS: u64 = 60_000;
+
+/// Estimated bytes used by a key. Collections are sized out of a few of
+/// their elements, walking all of them on every change would make writes to
+/// big collections slow.
+fn entry_size(key: &[u8], value: &RedisValue) -> usize {
+    fn estimate(len: usize, sizes: impl Iterator<Item = usize>) -> usize {
+        let (count, total) = sizes
+            .take(SIZE_SAMPLES)
+            .fold((0, 0), |(count, total), size| (count + 1, total + size));
+        match count {
+            0 => 0,
+            _ => len * (ELEMENT_OVERHEAD + total / count),
+        }
+    }
+
+    let contents = match value {
+        RedisValue::String(string) => string.len(),
+        RedisValue::List(list) => estimate(list.len(), list.iter().map(Vec::len)),
+        RedisValue::Hash(hash) => estimate(
+            hash.len(),
+            hash.iter().map(|(field, value)| field.len() + value.len()),
+        ),
+        RedisValue::Set(set) => estimate(set.len(), set.iter().map(Vec::len)),
+        // members are kept both in the score map and in the ordered list
+        RedisValue::SortedSet(zset) => estimate(
+            zset.len(),
+            zset.range_by_rank(0, SIZE_SAMPLES - 1, false)
+                .iter()
+                .map(|(member, _)| 2 * member.len() + 8),
+        ),
+        // entries cost their id besides the fields
+        RedisValue::Stream(stream) => estimate(
+            stream.len(),
+            stream
+                .range(StreamId::MIN, StreamId::MAX, Some(SIZE_SAMPLES), false)
+                .iter()
+                .map(|(_, fields)| {
+                    16 + fields
+                        .iter()
+                        .map(|(field, value)| field.len() + value.len())
+                        .sum::<usize>()
+                }),
+        ),
+    };
+    KEY_OVERHEAD + 2 * key.len() + contents
+}
+
+/// Logarithmic increment of an LFU counter, the more a key was used the less
+/// likely another access counts. `random` is uniform in `[0, 1)`.
+fn lfu_increment(counter: u8, random: f64) -> u8 {
+    let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+    if counter < u8::MAX && random < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+        counter + 1
+    } else {
+        counter
+    }
+}
+
+/// Order in which `policy` evicts keys, the lower the sooner. The LFU
+/// policies break ties between equally used keys by the last access.
+fn eviction_rank(policy: MaxmemoryPolicy, entry: &Entry, now: u64) -> u64 {
+    match policy {
+        MaxmemoryPolicy::AllKeysLfu | MaxmemoryPolicy::VolatileLfu => {
+            ((entry.decayed_frequency(now) as u64) << 48) | (entry.accessed_at >> 16)
+        }
+        MaxmemoryPolicy::VolatileTtl => entry.expires_at.unwrap_or(u64::MAX),
+        _ => entry.accessed_at,
+    }
+}
+
+/// Position of a key in the `SCAN` order. The hasher is created with fixed
+/// keys so cursors stay valid for the lifetime of the process.
+fn scan_hash(key: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Value stored against a key along with its optional expiry time (unix ms).
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// shared with the copies `BGSAVE` and `BGREWRITEAOF` write out, a
+    /// change copies it first while one of them still holds it
+    pub value: Arc<RedisValue>,
+    pub expires_at: Option<u64>,
+    /// estimated bytes used by the key, updated on every change
+    pub size: usize,
+    /// unix time (ms) of the last access, for the LRU policies
+    pub accessed_at: u64,
+    /// logarithmic access counter, for the LFU policies
+    pub frequency: u8,
+}
+
+impl Entry {
+    /// The LFU counter once the minutes the key wasn't used are taken off.
+    pub fn decayed_frequency(&self, now: u64) -> u8 {
+        let periods = now.saturating_sub(self.accessed_at) / LFU_DECAY_MS;
+        self.frequency
+            .saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+}
+
+/// What looking up a key whose deadline passed does.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Expired {
+    /// the key is deleted, and queued to be propagated as a `DEL`
+    #[default]
+    Delete,
+    /// the key is treated as missing but left for the `DEL` of the master,
+    /// on replicas
+    Hide,
+    /// the key is treated as live, while applying what the master sent
+    Keep,
+}
+
+/// Keys of a logical database while another one is selected.
+#[derive(Debug, Clone, Default)]
+struct Database {
+    server: HashMap<Vec<u8>, Entry>,
+    expires: BTreeSet<(u64, Vec<u8>)>,
+    scan_order: BTreeSet<(u64, Vec<u8>)>,
+    watched_keys: HashMap<Vec<u8>, HashSet<u64>>,
+}
+
+/// The dataset, split into numbered databases. The keys of the selected
+/// database are kept in the fields below, the others are parked in
+/// `databases` until they are selected, so commands never have to look up
+/// which database they run against.
 #[derive(Debug, Clone)]
 pub struct DictionaryServer {
-    pub server: HashMap<String, String>,
+    pub server: HashMap<Vec<u8>, Entry>,
+    /// keys with a TTL ordered by their deadline, lets the background sweep
+    /// find expired keys without scanning the whole dictionary
+    expires: BTreeSet<(u64, Vec<u8>)>,
+    /// every key ordered by its hash, `SCAN` hands out the hash to resume from
+    /// as the cursor so keys added or removed between calls don't make it
+    /// skip the others
+    scan_order: BTreeSet<(u64, Vec<u8>)>,
+    /// ids of the clients watching each key
+    watched_keys: HashMap<Vec<u8>, HashSet<u64>>,
+    /// index of the database in the fields above
+    selected: usize,
+    /// every database used so far, the slot of the selected one is empty
+    databases: Vec<Database>,
+    /// number of changes since the last successful snapshot
+    pub dirty: u64,
+    /// clients one of whose watched keys changed, their `EXEC` fails
+    dirty_cas: HashSet<u64>,
+    /// estimated bytes used by all keys, compared against `maxmemory`
+    pub used_memory: usize,
+    /// state of the random generator used to sample keys to evict
+    seed: u64,
+    /// clients blocked until one of the keys gets something for them
+    pub blocked: Blocked,
+    /// keys deleted because their TTL passed, and by eviction
+    pub expired_keys: u64,
+    pub evicted_keys: u64,
+    /// what `lookup` does with keys whose deadline passed
+    pub on_expired: Expired,
+    /// keys which expired and still have to be propagated as `DEL`s, with
+    /// their database
+    pub expired: Vec<(usize, Vec<u8>)>,
 }
 
 impl DictionaryServer {
     pub fn new() -> DictionaryServer {
         DictionaryServer {
             server: HashMap::new(),
+            expires: BTreeSet::new(),
+            scan_order: BTreeSet::new(),
+            watched_keys: HashMap::new(),
+            selected: 0,
+            databases: vec![Database::default()],
+            dirty: 0,
+            dirty_cas: HashSet::new(),
+            used_memory: 0,
+            seed: now_ms() | 1,
+            blocked: Blocked::new(),
+            expired_keys: 0,
+            evicted_keys: 0,
+            on_expired: Expired::Delete,
+            expired: Vec::new(),
+        }
+    }
+
+    /// xorshift64, good enough to pick keys at random.
+    fn random(&mut self) -> u64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+
+    /// Put `database` in place of the selected keys, returns them.
+    fn exchange(&mut self, mut database: Database) -> Database {
+        std::mem::swap(&mut self.server, &mut database.server);
+        std::mem::swap(&mut self.expires, &mut database.expires);
+        std::mem::swap(&mut self.scan_order, &mut database.scan_order);
+        std::mem::swap(&mut self.watched_keys, &mut database.watched_keys);
+        database
+    }
+
+    /// Make `index` the database commands run against, it is created the
+    /// first time it is selected.
+    pub fn select(&mut self, index: usize) {
+        if index == self.selected {
+            return;
+        }
+        if index >= self.databases.len() {
+            self.databases.resize_with(index + 1, Database::default);
+        }
+        let next = std::mem::take(&mut self.databases[index]);
+        self.databases[self.selected] = self.exchange(next);
+        self.selected = index;
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Number of databases selected so far, the ones after them are empty.
+    pub fn databases(&self) -> usize {
+        self.databases.len()
+    }
+
+    /// Keys of database `index`, which doesn't have to be selected.
+    pub fn entries(&self, index: usize) -> &HashMap<Vec<u8>, Entry> {
+        match index == self.selected {
+            true => &self.server,
+            false => &self.databases[index].server,
+        }
+    }
+
+    /// Number of keys with a TTL in database `index`.
+    pub fn volatile_keys(&self, index: usize) -> usize {
+        match index == self.selected {
+            true => self.expires.len(),
+            false => self.databases[index].expires.len(),
+        }
+    }
+
+    /// `SWAPDB`: the keys of databases `a` and `b` trade places. Watchers of
+    /// keys existing in either of them see a change, and so do the clients
+    /// blocked on them.
+    pub fn swap_databases(&mut self, a: usize, b: usize) {
+        if a == b {
+            return;
+        }
+        let selected = self.selected;
+        self.select(a.max(b));
+        self.select(a);
+        let parked = &mut self.databases[b];
+        std::mem::swap(&mut self.server, &mut parked.server);
+        std::mem::swap(&mut self.expires, &mut parked.expires);
+        std::mem::swap(&mut self.scan_order, &mut parked.scan_order);
+        for watched in [&self.watched_keys, &parked.watched_keys] {
+            for (key, clients) in watched {
+                if self.server.contains_key(key) || parked.server.contains_key(key) {
+                    self.dirty_cas.extend(clients);
+                }
+            }
+        }
+        self.blocked.signal_database(a);
+        self.blocked.signal_database(b);
+        self.dirty += 1;
+        self.select(selected);
+    }
+
+    /// `MOVE`: move a live key along with its TTL to database `db`. Returns
+    /// `false` when the key is missing or `db` has one of the same name.
+    pub fn move_key(&mut self, key: &[u8], db: usize) -> bool {
+        let source = self.selected;
+        if self.lookup(key).is_none() {
+            return false;
+        }
+        self.select(db);
+        let exists = self.lookup(key).is_some();
+        self.select(source);
+        if exists {
+            return false;
+        }
+        let entry = match self.remove(key) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        self.select(db);
+        self.insert(key, entry.value, entry.expires_at);
+        self.select(source);
+        true
+    }
+
+    /// Store the string `value` against `key`. `expires_at` replaces whatever
+    /// TTL the key had before, pass the current one to keep it.
+    pub fn set(&mut self, key: &[u8], value: &[u8], expires_at: Option<u64>) {
+        self.insert(key, RedisValue::String(value.to_vec()), expires_at);
+    }
+
+    /// Store a value of any type against `key`, overwriting the old one.
+    pub fn insert(
+        &mut self,
+        key: &[u8],
+        value: impl Into<Arc<RedisValue>>,
+        expires_at: Option<u64>,
+    ) {
+        if let Some(when) = expires_at {
+            self.expires.insert((when, key.to_vec()));
+        }
+        let entry = Entry {
+            value: value.into(),
+            expires_at,
+            size: 0,
+            accessed_at: now_ms(),
+            frequency: LFU_INIT_VAL,
+        };
+        let previous = self.server.insert(key.to_vec(), entry);
+        // sizes the new entry
+        self.modified(key);
+        match previous {
+            Some(previous) => {
+                self.used_memory -= previous.size;
+                if let Some(when) = previous.expires_at {
+                    if Some(when) != expires_at {
+                        self.expires.remove(&(when, key.to_vec()));
+                    }
+                }
+            }
+            None => {
+                self.scan_order.insert((scan_hash(key), key.to_vec()));
+            }
+        }
+    }
+
+    /// String stored at `key`, fails if the key holds another type.
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, WrongType> {
+        match self.lookup(key).map(|entry| entry.value.as_ref()) {
+            Some(RedisValue::String(value)) => Ok(Some(value.clone())),
+            Some(_) => Err(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Value of the type picked by `extract` stored at `key`. When `empty` is
+    /// given a missing key is created with it, the caller is expected to fill
+    /// the new collection.
+    fn typed_mut<T>(
+        &mut self,
+        key: &[u8],
+        empty: Option<RedisValue>,
+        extract: fn(&mut RedisValue) -> Option<&mut T>,
+    ) -> Result<Option<&mut T>, WrongType> {
+        if let Some(empty) = empty {
+            if self.lookup(key).is_none() {
+                self.insert(key, empty, None);
+            }
+        }
+        match self.lookup(key) {
+            Some(entry) => extract(Arc::make_mut(&mut entry.value))
+                .map(Some)
+                .ok_or(WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// String stored at `key` for in place updates which keep the TTL,
+    /// `create` makes an empty one for a missing key.
+    pub fn string_mut(
+        &mut self,
+        key: &[u8],
+        create: bool,
+    ) -> Result<Option<&mut Vec<u8>>, WrongType> {
+        let empty = create.then(|| RedisValue::String(Vec::new()));
+        self.typed_mut(key, empty, |value| match value {
+            RedisValue::String(string) => Some(string),
+            _ => None,
+        })
+    }
+
+    /// List stored at `key`, `create` makes an empty one for a missing key.
+    pub fn list_mut(
+        &mut self,
+        key: &[u8],
+        create: bool,
+    ) -> Result<Option<&mut VecDeque<Vec<u8>>>, WrongType> {
+        let empty = create.then(|| RedisValue::List(VecDeque::new()));
+        self.typed_mut(key, empty, |value| match value {
+            RedisValue::List(list) => Some(list),
+            _ => None,
+        })
+    }
+
+    /// Hash stored at `key`, `create` makes an empty one for a missing key.
+    pub fn hash_mut(
+        &mut self,
+        key: &[u8],
+        create: bool,
+    ) -> Result<Option<&mut HashFields>, WrongType> {
+        let empty = create.then(|| RedisValue::Hash(HashMap::new()));
+        self.typed_mut(key, empty, |value| match value {
+            RedisValue::Hash(hash) => Some(hash),
+            _ => None,
+        })
+    }
+
+    /// Set stored at `key`, `create` makes an empty one for a missing key.
+    pub fn set_mut(
+        &mut self,
+        key: &[u8],
+        create: bool,
+    ) -> Result<Option<&mut HashSet<Vec<u8>>>, WrongType> {
+        let empty = create.then(|| RedisValue::Set(HashSet::new()));
+        self.typed_mut(key, empty, |value| match value {
+            RedisValue::Set(set) => Some(set),
+            _ => None,
+        })
+    }
+
+    /// Sorted set stored at `key`, `create` makes an empty one for a missing
+    /// key.
+    pub fn zset_mut(
+        &mut self,
+        key: &[u8],
+        create: bool,
+    ) -> Result<Option<&mut SortedSet>, WrongType> {
+        let empty = create.then(|| RedisValue::SortedSet(SortedSet::new()));
+        self.typed_mut(key, empty, |value| match value {
+            RedisValue::SortedSet(zset) => Some(zset),
+            _ => None,
+        })
+    }
+
+    /// Stream stored at `key`, `create` makes an empty one for a missing key.
+    pub fn stream_mut(
+        &mut self,
+        key: &[u8],
+        create: bool,
+    ) -> Result<Option<&mut Stream>, WrongType> {
+        let empty = create.then(|| RedisValue::Stream(Stream::new()));
+        self.typed_mut(key, empty, |value| match value {
+            RedisValue::Stream(stream) => Some(stream),
+            _ => None,
+        })
+    }
+
+    /// Collections never stay empty in redis, commands popping or removing
+    /// elements call this so the key disappears with its last element.
+    pub fn remove_if_empty(&mut self, key: &[u8]) {
+        let empty = match self.server.get(key).map(|entry| entry.value.as_ref()) {
+            Some(RedisValue::List(list)) => list.is_empty(),
+            Some(RedisValue::Hash(hash)) => hash.is_empty(),
+            Some(RedisValue::Set(set)) => set.is_empty(),
+            Some(RedisValue::SortedSet(zset)) => zset.is_empty(),
+            _ => false,
+        };
+        if empty {
+            self.remove(key);
+        }
+    }
+
+    /// Every change to a key goes through here, it counts the changes for the
+    /// snapshot save rules, fails the transactions watching the key, wakes
+    /// up the clients blocked on it and updates the memory used by the key.
+    pub fn modified(&mut self, key: &[u8]) {
+        self.dirty += 1;
+        self.touched(key);
+    }
+
+    /// `modified` without counting a change, expiry doesn't count as one so
+    /// it can't make a command which did nothing look like a write.
+    fn touched(&mut self, key: &[u8]) {
+        if let Some(clients) = self.watched_keys.get(key) {
+            self.dirty_cas.extend(clients);
+        }
+        self.blocked.signal(self.selected, key);
+        if let Some(entry) = self.server.get_mut(key) {
+            let size = entry_size(key, &entry.value);
+            self.used_memory = self.used_memory + size - entry.size;
+            entry.size = size;
+        }
+    }
+
+    /// `WATCH`: `EXEC` of `client` fails if `key` of the selected database
+    /// changes from now on.
+    pub fn watch(&mut self, client: u64, key: &[u8]) {
+        // a key which already expired must not count as a change later
+        self.lookup(key);
+        self.watched_keys
+            .entry(key.to_vec())
+            .or_default()
+            .insert(client);
+    }
+
+    /// Forget every key `client` watched, given with their database.
+    pub fn unwatch(&mut self, client: u64, keys: &[(usize, Vec<u8>)]) {
+        let selected = self.selected;
+        for (db, key) in keys {
+            self.select(*db);
+            if let Some(clients) = self.watched_keys.get_mut(key) {
+                clients.remove(&client);
+                if clients.is_empty() {
+                    self.watched_keys.remove(key);
+                }
+            }
+        }
+        self.select(selected);
+        self.dirty_cas.remove(&client);
+    }
+
+    /// Whether a key watched by `client` changed. Watched keys which expired
+    /// in the meantime count as changed.
+    pub fn watch_failed(&mut self, client: u64, keys: &[(usize, Vec<u8>)]) -> bool {
+        let selected = self.selected;
+        for (db, key) in keys {
+            self.select(*db);
+            self.lookup(key);
         }
+        self.select(selected);
+        self.dirty_cas.contains(&client)
     }
 
-    pub fn set(&mut self, key: &String, value: &String) -> Result<()> {
-        self.server
-            .insert(key.to_string(), value.to_string());
-        Ok(())
+    /// Returns the entry of a live key and records the access for the LRU
+    /// and LFU eviction policies. Keys whose deadline already passed are
+    /// removed here (lazy expiry) and treated as missing, unless `on_expired`
+    /// says otherwise.
+    pub fn lookup(&mut self, key: &[u8]) -> Option<&mut Entry> {
+        let now = now_ms();
+        let expired = match self.server.get(key) {
+            Some(entry) => entry.expires_at.is_some_and(|when| when <= now),
+            None => return None,
+        };
+        match self.on_expired {
+            _ if !expired => {}
+            Expired::Delete => {
+                self.expire(key);
+                return None;
+            }
+            Expired::Hide => return None,
+            Expired::Keep => {}
+        }
+        let random = (self.random() >> 11) as f64 / (1u64 << 53) as f64;
+        let entry = self.server.get_mut(key)?;
+        entry.frequency = lfu_increment(entry.decayed_frequency(now), random);
+        entry.accessed_at = now;
+        Some(entry)
+    }
+
+    /// Delete a key along with its TTL, returns the removed entry.
+    pub fn remove(&mut self, key: &[u8]) -> Option<Entry> {
+        let entry = self.unlink(key)?;
+        self.modified(key);
+        Some(entry)
+    }
+
+    /// Take a key out of the selected database.
+    fn unlink(&mut self, key: &[u8]) -> Option<Entry> {
+        let entry = self.server.remove(key)?;
+        self.used_memory -= entry.size;
+        if let Some(when) = entry.expires_at {
+            self.expires.remove(&(when, key.to_vec()));
+        }
+        self.scan_order.remove(&(scan_hash(key), key.to_vec()));
+        Some(entry)
     }
 
-    pub fn get(&mut self, key: &String) -> String {
-        let val = self.server.get(key);
-        if val.is_some() {
-            val.unwrap().to_string()
+    /// Delete a key whose deadline passed and queue its `DEL`.
+    fn expire(&mut self, key: &[u8]) {
+        if self.unlink(key).is_some() {
+            self.touched(key);
+            self.expired_keys += 1;
+            self.expired.push((self.selected, key.to_vec()));
+        }
+    }
+
+    /// Set (or clear with `None`) the deadline of an existing key. Returns
+    /// `false` if the key doesn't exist.
+    pub fn set_expiry(&mut self, key: &[u8], expires_at: Option<u64>) -> bool {
+        let previous = match self.lookup(key) {
+            Some(entry) => std::mem::replace(&mut entry.expires_at, expires_at),
+            None => return false,
+        };
+        if let Some(when) = previous {
+            self.expires.remove(&(when, key.to_vec()));
+        }
+        if let Some(when) = expires_at {
+            self.expires.insert((when, key.to_vec()));
+        }
+        self.modified(key);
+        true
+    }
+
+    /// Deadline of a live key, `None` when the key is missing and `Some(None)`
+    /// when it exists without a TTL.
+    pub fn expiry(&mut self, key: &[u8]) -> Option<Option<u64>> {
+        self.lookup(key).map(|entry| entry.expires_at)
+    }
+
+    /// Active expiry: delete at most `limit` keys whose deadline is before
+    /// `now`, going through the databases in order, and queue their `DEL`s.
+    /// Returns how many keys were removed so the caller knows whether it
+    /// should run another round.
+    pub fn expire_cycle(&mut self, now: u64, limit: usize) -> usize {
+        let selected = self.selected;
+        let mut removed = 0;
+        for db in 0..self.databases.len() {
+            self.select(db);
+            removed += self.expire_selected(now, limit - removed);
+        }
+        self.select(selected);
+        removed
+    }
+
+    /// Active expiry of the selected database.
+    fn expire_selected(&mut self, now: u64, limit: usize) -> usize {
+        let mut removed = 0;
+        while removed < limit {
+            match self.expires.first() {
+                Some((when, _)) if *when <= now => {
+                    let (_, key) = self.expires.pop_first().unwrap();
+                    self.expire(&key);
+                    removed += 1;
+                }
+                _ => break,
+            }
+        }
+        removed
+    }
+
+    /// Keys from the `SCAN` position `cursor` on, at least `count` of them
+    /// unless the end is reached. Returns the cursor to continue from, 0 once
+    /// every key was visited. Keys sharing a hash are never split between two
+    /// calls, otherwise the cursor couldn't move past them. Expired keys are
+    /// included, callers filter them.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<Vec<u8>>) {
+        let mut keys = Vec::new();
+        let mut last = None;
+        for (hash, key) in self.scan_order.range((cursor, Vec::new())..) {
+            if keys.len() >= count && last != Some(*hash) {
+                return (*hash, keys);
+            }
+            keys.push(key.clone());
+            last = Some(*hash);
+        }
+        (0, keys)
+    }
+
+    /// Delete every key of the selected database, returns how many there
+    /// were.
+    pub fn clear(&mut self) -> usize {
+        let removed = self.server.len();
+        for (key, clients) in self.watched_keys.iter() {
+            if self.server.contains_key(key) {
+                self.dirty_cas.extend(clients);
+            }
+        }
+        self.used_memory -= self.server.values().map(|entry| entry.size).sum::<usize>();
+        self.server.clear();
+        self.expires.clear();
+        self.scan_order.clear();
+        self.dirty += removed as u64;
+        removed
+    }
+
+    /// `FLUSHALL`: delete the keys of every database, returns how many there
+    /// were.
+    pub fn clear_all(&mut self) -> usize {
+        let selected = self.selected;
+        let mut removed = 0;
+        for db in 0..self.databases.len() {
+            self.select(db);
+            removed += self.clear();
+        }
+        self.select(selected);
+        removed
+    }
+
+    /// Replace every database with the ones of `other`, e.g. the snapshot of
+    /// a full resync. Unlike assigning it, the watchers and the blocked
+    /// clients are kept and told about the changes.
+    pub fn replace(&mut self, mut other: DictionaryServer) {
+        self.clear_all();
+        let selected = self.selected;
+        for db in 0..other.databases.len() {
+            other.select(db);
+            self.select(db);
+            for (key, entry) in std::mem::take(&mut other.server) {
+                self.insert(&key, entry.value, entry.expires_at);
+            }
+        }
+        self.select(selected);
+    }
+
+    /// A key picked at random, among the ones with a TTL when `volatile` is
+    /// set. Keys aren't all equally likely but close enough for sampling.
+    fn random_key(&mut self, volatile: bool) -> Option<Vec<u8>> {
+        let random = self.random();
+        let keys = if volatile {
+            &self.expires
         } else {
-            "nil".to_string()
+            &self.scan_order
+        };
+        let (first, last) = (keys.first()?.0, keys.last()?.0);
+        let start = match volatile {
+            // deadlines are spread over a range much smaller than the hashes
+            true => first + random % (last - first + 1),
+            false => random,
+        };
+        keys.range((start, Vec::new())..)
+            .next()
+            .or(keys.first())
+            .map(|(_, key)| key.clone())
+    }
+
+    /// The key of the selected database `policy` evicts first out of
+    /// `samples` keys picked at random, along with its rank. Like redis this
+    /// approximates the policy without keeping every key ordered by it.
+    fn eviction_candidate(
+        &mut self,
+        policy: MaxmemoryPolicy,
+        samples: usize,
+    ) -> Option<(u64, Vec<u8>)> {
+        use MaxmemoryPolicy::*;
+        let volatile = matches!(
+            policy,
+            VolatileLru | VolatileLfu | VolatileRandom | VolatileTtl
+        );
+        let samples = match policy {
+            NoEviction => return None,
+            AllKeysRandom | VolatileRandom => 1,
+            _ => samples.max(1),
+        };
+        let now = now_ms();
+        let mut best: Option<(u64, Vec<u8>)> = None;
+        for _ in 0..samples {
+            let key = self.random_key(volatile)?;
+            let rank = match policy {
+                // so the pick between databases stays random too
+                AllKeysRandom | VolatileRandom => self.random(),
+                _ => eviction_rank(policy, &self.server[&key], now),
+            };
+            if best.as_ref().is_none_or(|(lowest, _)| rank < *lowest) {
+                best = Some((rank, key));
+            }
         }
+        best
+    }
+
+    /// Evict keys chosen by `policy` until at most `maxmemory` bytes are
+    /// used, each time the best candidate out of every database goes.
+    /// Returns the evicted keys with their database, memory may still be
+    /// over the limit when there's nothing left the policy may evict.
+    pub fn evict(
+        &mut self,
+        maxmemory: usize,
+        policy: MaxmemoryPolicy,
+        samples: usize,
+    ) -> Vec<(usize, Vec<u8>)> {
+        let selected = self.selected;
+        let mut evicted = Vec::new();
+        while self.used_memory > maxmemory {
+            let mut best: Option<(u64, usize, Vec<u8>)> = None;
+            for db in 0..self.databases.len() {
+                self.select(db);
+                if let Some((rank, key)) = self.eviction_candidate(policy, samples) {
+                    if best.as_ref().is_none_or(|(lowest, ..)| rank < *lowest) {
+                        best = Some((rank, db, key));
+                    }
+                }
+            }
+            match best {
+                Some((_, db, key)) => {
+                    self.select(db);
+                    self.remove(&key);
+                    self.evicted_keys += 1;
+                    evicted.push((db, key));
+                }
+                None => break,
+            }
+        }
+        self.select(selected);
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lazy_expiry_on_access() {
+        let mut map = DictionaryServer::new();
+        let key = b"key".to_vec();
+        map.set(&key, b"value", Some(now_ms() - 1));
+
+        // replicas keep the key for the master, which still sees it
+        map.on_expired = Expired::Hide;
+        assert_eq!(map.get(&key), Ok(None));
+        map.on_expired = Expired::Keep;
+        assert_eq!(map.get(&key), Ok(Some(b"value".to_vec())));
+        assert!(map.expired.is_empty());
+
+        map.on_expired = Expired::Delete;
+        let dirty = map.dirty;
+        assert_eq!(map.get(&key), Ok(None));
+        assert!(map.server.is_empty());
+        assert_eq!(map.expired, vec![(0, key)]);
+        assert_eq!(map.dirty, dirty);
+    }
+
+    #[test]
+    fn test_set_clears_previous_expiry() {
+        let mut map = DictionaryServer::new();
+        let key = b"key".to_vec();
+        map.set(&key, b"value", Some(now_ms() + 10_000));
+        map.set(&key, b"other", None);
+        assert_eq!(map.expiry(&key), Some(None));
+        assert_eq!(map.expire_cycle(u64::MAX, 10), 0);
+        assert_eq!(map.get(&key), Ok(Some(b"other".to_vec())));
+    }
+
+    #[test]
+    fn test_expire_cycle_removes_only_expired_keys() {
+        let mut map = DictionaryServer::new();
+        let now = now_ms();
+        for i in 0..10 {
+            map.set(format!("old{}", i).as_bytes(), b"v", Some(now - 1));
+        }
+        map.set(b"fresh", b"v", Some(now + 60_000));
+        map.set(b"forever", b"v", None);
+
+        assert_eq!(map.expire_cycle(now, 4), 4);
+        assert_eq!(map.expire_cycle(now, 100), 6);
+        assert_eq!(map.server.len(), 2);
+        assert_eq!(map.expired.len(), 10);
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        let mut map = DictionaryServer::new();
+        let key = b"key".to_vec();
+        map.list_mut(&key, true)
+            .unwrap()
+            .unwrap()
+            .push_back(b"a".to_vec());
+        assert_eq!(map.get(&key), Err(WrongType));
+
+        map.set(&key, b"value", None);
+        assert_eq!(map.list_mut(&key, true), Err(WrongType));
+    }
+
+    #[test]
+    fn test_scan_visits_every_key() {
+        let mut map = DictionaryServer::new();
+        for i in 0..100 {
+            map.set(format!("key{}", i).as_bytes(), b"v", None);
+        }
+        let mut seen = HashSet::new();
+        let mut cursor = 0;
+        loop {
+            let (next, keys) = map.scan(cursor, 7);
+            seen.extend(keys);
+            // keys removed or added while scanning don't affect the others
+            map.remove(format!("key{}", seen.len() % 3).as_bytes());
+            map.set(format!("new{}", seen.len()).as_bytes(), b"v", None);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        for i in 3..100 {
+            assert!(seen.contains(format!("key{}", i).as_bytes()));
+        }
+    }
+
+    #[test]
+    fn test_copies_share_values() {
+        let mut map = DictionaryServer::new();
+        let key = b"list".to_vec();
+        map.list_mut(&key, true)
+            .unwrap()
+            .unwrap()
+            .push_back(b"a".to_vec());
+        let snapshot = map.clone();
+        assert!(Arc::ptr_eq(
+            &map.server[&key].value,
+            &snapshot.server[&key].value
+        ));
+
+        // a change copies the value, the copy keeps the old one
+        map.list_mut(&key, false)
+            .unwrap()
+            .unwrap()
+            .push_back(b"b".to_vec());
+        let list = |elements: &[&[u8]]| {
+            RedisValue::List(elements.iter().map(|element| element.to_vec()).collect())
+        };
+        assert_eq!(*snapshot.server[&key].value, list(&[b"a"]));
+        assert_eq!(*map.server[&key].value, list(&[b"a", b"b"]));
+    }
+
+    #[test]
+    fn test_remove_if_empty() {
+        let mut map = DictionaryServer::new();
+        let key = b"key".to_vec();
+        map.list_mut(&key, true).unwrap();
+        map.remove_if_empty(&key);
+        assert!(map.server.is_empty());
+    }
+
+    #[test]
+    fn test_memory_accounting() {
+        let mut map = DictionaryServer::new();
+        let key = b"list".to_vec();
+        map.set(b"string", b"value", None);
+        let string_size = map.used_memory;
+        assert_eq!(string_size, KEY_OVERHEAD + 2 * "string".len() + 5);
+
+        map.list_mut(&key, true)
+            .unwrap()
+            .unwrap()
+            .extend([b"a".to_vec(), b"b".to_vec()]);
+        map.modified(&key);
+        let list_size = KEY_OVERHEAD + 2 * key.len() + 2 * (ELEMENT_OVERHEAD + 1);
+        assert_eq!(map.used_memory, string_size + list_size);
+        assert_eq!(map.server[&key].size, list_size);
+
+        map.set(b"string", b"v", Some(now_ms() - 1));
+        assert_eq!(map.get(b"string"), Ok(None));
+        assert_eq!(map.used_memory, list_size);
+        map.clear();
+        assert_eq!(map.used_memory, 0);
+    }
+
+    #[test]
+    fn test_eviction_rank() {
+        let now = now_ms();
+        let entry = |accessed_at, frequency, expires_at| Entry {
+            value: Arc::new(RedisValue::String(Vec::new())),
+            expires_at,
+            size: 0,
+            accessed_at,
+            frequency,
+        };
+        let old = entry(now - 10_000, 20, Some(now + 5_000));
+        let recent = entry(now, 6, Some(now + 1_000));
+        use MaxmemoryPolicy::*;
+        assert!(eviction_rank(AllKeysLru, &old, now) < eviction_rank(AllKeysLru, &recent, now));
+        assert!(eviction_rank(AllKeysLfu, &recent, now) < eviction_rank(AllKeysLfu, &old, now));
+        assert!(eviction_rank(VolatileTtl, &recent, now) < eviction_rank(VolatileTtl, &old, now));
+
+        // counters decay while a key isn't used and grow ever slower
+        assert_eq!(
+            entry(now - 3 * LFU_DECAY_MS, 20, None).decayed_frequency(now),
+            17
+        );
+        assert_eq!(lfu_increment(LFU_INIT_VAL, 0.99), LFU_INIT_VAL + 1);
+        assert_eq!(lfu_increment(100, 0.01), 100);
+        assert_eq!(lfu_increment(u8::MAX, 0.0), u8::MAX);
+    }
+
+    #[test]
+    fn test_evict() {
+        let mut map = DictionaryServer::new();
+        for i in 0..20 {
+            map.set(format!("key{}", i).as_bytes(), b"v", None);
+        }
+        map.set(b"volatile", b"v", Some(now_ms() + 60_000));
+        let key_size = map.server[b"key0".as_slice()].size;
+        let used = map.used_memory;
+
+        assert!(map.evict(0, MaxmemoryPolicy::NoEviction, 5).is_empty());
+        assert_eq!(map.used_memory, used);
+
+        // only keys with a TTL are candidates for the volatile policies
+        assert_eq!(
+            map.evict(0, MaxmemoryPolicy::VolatileLru, 5),
+            [(0, b"volatile".to_vec())]
+        );
+        assert_eq!(map.server.len(), 20);
+
+        let evicted = map.evict(used - 6 * key_size, MaxmemoryPolicy::AllKeysLru, 5);
+        assert_eq!(evicted.len(), 5);
+        assert_eq!(map.server.len(), 15);
+        assert!(evicted.iter().all(|(_, key)| !map.server.contains_key(key)));
+        map.evict(0, MaxmemoryPolicy::AllKeysRandom, 5);
+        assert!(map.server.is_empty());
+    }
+
+    #[test]
+    fn test_databases() {
+        let mut map = DictionaryServer::new();
+        let key = b"key".to_vec();
+        map.set(&key, b"zero", None);
+        map.select(2);
+        assert_eq!(map.get(&key), Ok(None));
+        map.set(&key, b"two", Some(now_ms() - 1));
+        map.set(b"other", b"two", None);
+        assert_eq!(map.databases(), 3);
+        assert_eq!(map.entries(0).len(), 1);
+        assert_eq!(map.volatile_keys(2), 1);
+
+        // every database is swept, whichever is selected
+        map.select(1);
+        assert_eq!(map.expire_cycle(now_ms(), 10), 1);
+        assert_eq!(map.selected(), 1);
+
+        // watchers of a key which exists on either side see the swap
+        map.select(0);
+        map.watch(7, &key);
+        map.swap_databases(0, 2);
+        assert!(map.watch_failed(7, &[(0, key.clone())]));
+        assert_eq!(map.get(&key), Ok(None));
+        assert!(map.entries(2).contains_key(b"key".as_slice()));
+
+        let other = b"other".to_vec();
+        assert!(map.move_key(&other, 2));
+        assert!(!map.move_key(&other, 2));
+        map.select(2);
+        assert_eq!(map.entries(2).len(), 2);
+        let used = map.used_memory;
+        assert_eq!(map.clear(), 2);
+        assert_eq!(map.used_memory, 0);
+        assert!(used > 0);
     }
 }
@@ -1,129 +1,695 @@
-use std::io::{self, BufRead, Write};
+use std::io::{self, Read, Write};
 use std::net::{TcpListener, TcpStream};
-use std::sync::{Arc, Mutex};
-use std::thread;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{env, process, thread};
 
+use acl::DEFAULT_USER;
+use client::Client;
+use config::Config;
 use dictionary_server::DictionaryServer;
-use parser::Value;
+use parser::{ParseError, RequestParser, Value};
+use server::{lock, Server};
 
+mod acl;
+mod aof;
+mod blocking;
+mod client;
+mod commands;
+mod config;
 mod dictionary_server;
+mod digest;
+mod glob;
 mod parser;
+mod pubsub;
+mod rdb;
+mod replication;
+mod scripting;
+mod server;
+mod slowlog;
+mod sorted_set;
+mod stream;
 
-/// Basic setup on how to handle the connections and reply accordingly
-fn handle_connection(stream: TcpStream, map: &mut DictionaryServer) {
-    let mut reader = io::BufReader::new(stream.try_clone().expect("fail to clone tcpstream..."));
-    let received: Vec<u8> = reader.fill_buf().unwrap().to_vec();
-    reader.consume(received.len());
+/// How often a blocked client is checked for having hung up.
+const HANG_UP_CHECK: Duration = Duration::from_millis(100);
 
-    // println!("{}", String::from_utf8_lossy(&received[0..received.len()]));
-    let command_str = String::from_utf8_lossy(&received[0..received.len()]).to_string();
-    if command_str.is_empty() {
-        return;
-    }
-    let mut parser = parser::Parser::new(command_str);
-    let value: Value = parser.parse();
+/// Basic setup on how to handle the connections and reply accordingly. Replies
+/// and messages published to the client are queued in its outbox and written
+/// by a second thread, so a publisher never waits on a slow subscriber. Once
+/// the client is gone, whatever it left in the shared state is cleaned up.
+fn handle_connection(mut stream: TcpStream, server: Arc<Server>) {
+    let (outbox, inbox) = mpsc::channel();
+    let writer = stream.try_clone().and_then(|writer| {
+        thread::Builder::new()
+            .name("redis-writer".to_string())
+            .spawn(move || write_replies(writer, inbox))
+    });
+    let writer = match writer {
+        Ok(writer) => writer,
+        Err(e) => {
+            eprintln!("Unable to start client writer: {}", e);
+            return;
+        }
+    };
 
-    let command = value.array[0].value.clone().unwrap_or("".to_string());
+    let mut client = Client::new(server.next_client_id());
+    client.outbox = Some(outbox);
+    client.addr = stream.peer_addr().ok();
+    client.user = Some(DEFAULT_USER.to_string());
+    client.authenticated = lock(&server.acl).default_login();
+    lock(&server.clients).register(&client, stream.try_clone().ok());
+    serve_client(&mut stream, &server, &mut client);
+    commands::disconnect(&server, &mut client);
+    // dropping the last sender lets the writer flush what's left and stop
+    drop(client);
+    let _ = writer.join();
+    server.connected_clients.fetch_sub(1, Ordering::Relaxed);
+}
 
-    match command.as_str() {
-        "PING" => {
-            ping_command(stream);
+/// Write everything queued in `inbox` to the socket until every sender is
+/// gone or the client stops reading.
+fn write_replies(mut stream: TcpStream, inbox: Receiver<Vec<u8>>) {
+    while let Ok(mut buffer) = inbox.recv() {
+        // pipelined replies queued meanwhile go out in a single write
+        while let Ok(more) = inbox.try_recv() {
+            buffer.extend(more);
         }
-        "ECHO" => {
-            echo_command(stream, value.array[1..].to_vec());
+        if stream.write_all(&buffer).is_err() {
+            return;
         }
-        "SET" | "set" => {
-            set_command(stream, value.array[1..].to_vec(), map);
-        }
-        "GET" | "get" => {
-            get_command(stream, value.array[1].clone(), map);
-        }
-        _ => {
-            println!("Invalid command {}", command);
+    }
+}
+
+/// The connection stays open until the client hangs up, every chunk read from
+/// the socket is fed to the request parser and each complete request is
+/// executed in order. A partial request waits for the next read, the parser
+/// keeps what it got of it. The dictionary is only locked while a single
+/// command runs so other clients can interleave their commands in between,
+/// unless a script is busy holding it.
+fn serve_client(stream: &mut TcpStream, server: &Arc<Server>, client: &mut Client) {
+    let mut requests = RequestParser::new();
+    let mut chunk = [0u8; 4096];
+
+    loop {
+        let read = match stream.read(&mut chunk) {
+            Ok(0) | Err(_) => return,
+            Ok(n) => n,
+        };
+        requests.feed(&chunk[..read]);
+
+        loop {
+            let value: Value = match requests.next() {
+                Ok(value) => value,
+                Err(ParseError::Incomplete) => break,
+                Err(ParseError::Protocol(msg)) => {
+                    // there's no telling where the next command starts
+                    client.send(&Value::error(&format!("ERR Protocol error: {}", msg)));
+                    return;
+                }
+            };
+            // an empty inline line is just ignored
+            if value.array.is_empty() {
+                continue;
+            }
+
+            let mut map = match server.lock_unless_busy() {
+                Some(map) => map,
+                None => {
+                    let reply = commands::call_while_busy(&value, server, client);
+                    client.send(&reply);
+                    continue;
+                }
+            };
+            if let Some(reply) = commands::call(&value, server, client, &mut map) {
+                client.send(&reply);
+            }
+            drop(map);
+            if !wait_until_served(stream, server, client) {
+                return;
+            }
         }
     }
 }
 
-/// Below method replies the `PING` command sent by redis client
-fn ping_command(mut stream: TcpStream) {
-    let pong = Value {
-        value: Some("PONG".to_string()),
-        value_type: parser::ValueType::SimpleString,
-        null: false,
-        array: Vec::new(),
+/// A blocked client waits here, outside of the dictionary lock, until another
+/// client serves it or its timeout expires. The commands it pipelined wait
+/// too. Returns `false` when the client hung up meanwhile.
+fn wait_until_served(stream: &TcpStream, server: &Arc<Server>, client: &mut Client) -> bool {
+    let blocking = match client.blocked.take() {
+        Some(blocking) => blocking,
+        None => return true,
     };
-    let _ = stream.write_all(parser::stringify(&pong).as_bytes());
+    loop {
+        let wait = blocking.deadline.map_or(HANG_UP_CHECK, |deadline| {
+            HANG_UP_CHECK.min(deadline.saturating_duration_since(Instant::now()))
+        });
+        match blocking.served.recv_timeout(wait) {
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => return true,
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        if blocking
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            commands::time_out(server, client);
+            return true;
+        }
+        if hung_up(stream) {
+            return false;
+        }
+    }
 }
 
-/// Method to echo the same string which was sent by the client. NOTE
-/// `ECHO` command considers that the input will be only ECHO "<string>" where
-/// `<string>` can have n characters but inside the quotes. There are no other strings
-/// after that.
-fn echo_command(mut stream: TcpStream, values: Vec<Value>) {
-    let string = values[0].value.clone().unwrap_or("".to_string());
-    let reply = Value {
-        value: Some(string),
-        value_type: parser::ValueType::SimpleString,
-        null: false,
-        array: Vec::new(),
+/// Whether the peer closed the connection, without consuming what it sent.
+fn hung_up(stream: &TcpStream) -> bool {
+    let _ = stream.set_read_timeout(Some(Duration::from_millis(1)));
+    let closed = match stream.peek(&mut [0u8; 1]) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(e) => !matches!(
+            e.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ),
     };
-    let _ = stream.write_all(parser::stringify(&reply).as_bytes());
+    let _ = stream.set_read_timeout(None);
+    closed
 }
 
-/// wrapper around the dictionary i.e. `HashMap` to set the key, value and reply back
-/// in RESP protocol to the client. If it is success reply will be "OK" else it should panic
-fn set_command(mut stream: TcpStream, values: Vec<Value>, map: &mut DictionaryServer) {
-    let key = values[0]
-        .value
-        .clone()
-        .expect("Unable to extract key from SET command");
-    let val = values[1]
-        .value
-        .clone()
-        .expect("Unable to extract value from SET command");
-
-    let _ = map.set(&key, &val);
-    let ok = Value {
-        value: Some("OK".to_string()),
-        value_type: parser::ValueType::SimpleString,
-        null: false,
-        array: Vec::new(),
-    };
-    let _ = stream.write_all(parser::stringify(&ok).as_bytes());
+/// Accept clients forever, every connection gets its own thread which lives
+/// as long as the client stays connected. Threads share the dictionary and
+/// lock it per command, so one slow client doesn't block the others. Beyond
+/// `maxclients` connections new clients get an error and are disconnected.
+fn serve(listener: TcpListener, server: Arc<Server>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(mut stream) => {
+                let maxclients = server.config().maxclients;
+                if server.connected_clients.fetch_add(1, Ordering::Relaxed) >= maxclients {
+                    server.connected_clients.fetch_sub(1, Ordering::Relaxed);
+                    server
+                        .stats
+                        .rejected_connections
+                        .fetch_add(1, Ordering::Relaxed);
+                    let _ = stream.write_all(b"-ERR max number of clients reached\r\n");
+                    continue;
+                }
+                server
+                    .stats
+                    .connections_received
+                    .fetch_add(1, Ordering::Relaxed);
+                let s = server.clone();
+                let spawned = thread::Builder::new()
+                    .name("redis-client".to_string())
+                    .spawn(move || handle_connection(stream, s));
+                if let Err(e) = spawned {
+                    server.connected_clients.fetch_sub(1, Ordering::Relaxed);
+                    eprintln!("Unable to spawn client thread: {}", e);
+                }
+            }
+            Err(e) => eprintln!("Unable to accept connection: {}", e),
+        }
+    }
 }
 
-/// wrapper around the dictionary i.e. `HashMap` to retrive the key and reply back
-/// in RESP protocol. If key is not present in the dictionary then return `nil` as response.
-fn get_command(mut stream: TcpStream, value: Value, map: &mut DictionaryServer) {
-    let key = value
-        .value
-        .clone()
-        .expect("Unable to extract key from GET command");
-    let reply = Value {
-        value: Some(map.get(&key)),
-        value_type: parser::ValueType::SimpleString,
-        null: false,
-        array: Vec::new(),
-    };
-    let _ = stream.write_all(parser::stringify(&reply).as_bytes());
+/// Restore the dataset saved by a previous run. With `appendonly yes` the AOF
+/// is the source of truth, if it doesn't exist yet it is created out of the
+/// RDB snapshot so no data is left behind when turning it on.
+fn load_data(server: &Arc<Server>) -> io::Result<()> {
+    let config = server.config().clone();
+    if config.appendonly {
+        let path = config.aof_path();
+        if aof::load(&path, server)? {
+            println!("DB loaded from append only file");
+            lock(&server.aof).open(&path)?;
+        } else {
+            if let Some(map) = rdb::load(&config.rdb_path(), config.databases)? {
+                *server.lock() = map;
+            }
+            server.start_aof(&server.lock())?;
+        }
+    } else if let Some(map) = rdb::load(&config.rdb_path(), config.databases)? {
+        let keys: usize = (0..map.databases()).map(|db| map.entries(db).len()).sum();
+        println!("DB loaded from disk: {} keys", keys);
+        *server.lock() = map;
+    }
+    Ok(())
 }
 
-/// Main entry point of the program, here in the code we're creating a server
-/// listening to the default redis port `6379`
+/// Main entry point of the program, the settings come from the configuration
+/// file and flags given on the command line. The server listens on every
+/// `bind` address, persisted data, if there is any, is loaded before
+/// accepting clients.
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:6379").expect("Unable to bind address ::6379");
+    let config = Config::from_args(env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    if config.bind.is_empty() {
+        eprintln!("No address to listen on, check the 'bind' setting.");
+        process::exit(1);
+    }
 
-    let map: Arc<Mutex<DictionaryServer>> = Arc::new(Mutex::new(DictionaryServer::new()));
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                let m = map.clone();
-                let _ = thread::spawn(move || {
-                    handle_connection(stream, &mut m.lock().unwrap());
+    let listeners: Vec<TcpListener> = config
+        .bind
+        .iter()
+        .map(|addr| {
+            TcpListener::bind((addr.as_str(), config.port)).unwrap_or_else(|e| {
+                eprintln!(
+                    "Could not create server TCP listening socket {}:{}: {}",
+                    addr, config.port, e
+                );
+                process::exit(1);
+            })
+        })
+        .collect();
+
+    let replicaof = config.replicaof.clone();
+    let server = Arc::new(Server::new(config, DictionaryServer::new()));
+    if let Err(e) = load_data(&server) {
+        eprintln!("Fatal error loading the DB: {}. Exiting.", e);
+        process::exit(1);
+    }
+    if let Some((host, port)) = replicaof {
+        if let Err(e) = replication::follow(&server, &host, port) {
+            eprintln!("Unable to replicate {}:{}: {}", host, port, e);
+            process::exit(1);
+        }
+    }
+
+    for listener in listeners {
+        let s = server.clone();
+        thread::spawn(move || serve(listener, s));
+    }
+    server.cron();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+
+    /// Start a server on a random local port and return its address.
+    fn start_server() -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = commands::test::server(Config::default());
+        thread::spawn(move || serve(listener, server));
+        addr
+    }
+
+    /// Send a command as RESP array and wait for one complete reply frame.
+    fn send(stream: &mut TcpStream, args: &[&str]) -> String {
+        let args: Vec<&[u8]> = args.iter().map(|arg| arg.as_bytes()).collect();
+        String::from_utf8(send_bytes(stream, &args)).unwrap()
+    }
+
+    fn send_bytes(stream: &mut TcpStream, args: &[&[u8]]) -> Vec<u8> {
+        let command = Value::array(args.iter().map(Value::bulk_string).collect());
+        stream.write_all(&parser::stringify(&command)).unwrap();
+        read_reply(stream)
+    }
+
+    fn command_bytes(args: &[&str]) -> Vec<u8> {
+        parser::stringify(&Value::array(args.iter().map(Value::bulk_string).collect()))
+    }
+
+    /// Reads until a whole reply arrived.
+    fn read_reply(stream: &mut TcpStream) -> Vec<u8> {
+        let mut received = Vec::new();
+        let mut chunk = [0u8; 512];
+        while let Err(ParseError::Incomplete) = Parser::new(&received).parse() {
+            let n = stream.read(&mut chunk).unwrap();
+            assert!(n > 0, "connection closed before reply");
+            received.extend_from_slice(&chunk[..n]);
+        }
+        received
+    }
+
+    #[test]
+    fn test_connection_serves_multiple_commands() {
+        let addr = start_server();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        assert_eq!(send(&mut stream, &["PING"]), "+PONG\r\n");
+        assert_eq!(send(&mut stream, &["SET", "name", "redis"]), "+OK\r\n");
+        assert_eq!(send(&mut stream, &["GET", "name"]), "$5\r\nredis\r\n");
+    }
+
+    #[test]
+    fn test_protocol_is_chosen_per_connection() {
+        let addr = start_server();
+        let mut resp3 = TcpStream::connect(addr).unwrap();
+        let mut resp2 = TcpStream::connect(addr).unwrap();
+        assert!(send(&mut resp3, &["HELLO", "3"]).starts_with("%7\r\n"));
+        send(&mut resp3, &["HSET", "h", "f", "v"]);
+        assert_eq!(
+            send(&mut resp3, &["HGETALL", "h"]),
+            "%1\r\n$1\r\nf\r\n$1\r\nv\r\n"
+        );
+        assert_eq!(send(&mut resp3, &["GET", "missing"]), "_\r\n");
+        assert_eq!(
+            send(&mut resp2, &["HGETALL", "h"]),
+            "*2\r\n$1\r\nf\r\n$1\r\nv\r\n"
+        );
+        assert_eq!(send(&mut resp2, &["GET", "missing"]), "$-1\r\n");
+    }
+
+    #[test]
+    fn test_values_are_binary_safe() {
+        let addr = start_server();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let blob: &[u8] = b"\x89PNG\r\n\x1a\n\x00\xff\r\n";
+        assert_eq!(send_bytes(&mut stream, &[b"SET", b"img", blob]), b"+OK\r\n");
+        let mut expected = format!("${}\r\n", blob.len()).into_bytes();
+        expected.extend_from_slice(blob);
+        expected.extend_from_slice(b"\r\n");
+        assert_eq!(send_bytes(&mut stream, &[b"GET", b"img"]), expected);
+        assert_eq!(send(&mut stream, &["STRLEN", "img"]), ":12\r\n");
+
+        // so are keys
+        let key: &[u8] = b"k\xff\x00\r\n";
+        assert_eq!(send_bytes(&mut stream, &[b"SET", key, b"v"]), b"+OK\r\n");
+        assert_eq!(send_bytes(&mut stream, &[b"GET", key]), b"$1\r\nv\r\n");
+        assert_eq!(
+            send_bytes(&mut stream, &[b"GET", b"k\xfe\x00\r\n"]),
+            b"$-1\r\n"
+        );
+        let mut expected = b"*1\r\n$5\r\n".to_vec();
+        expected.extend_from_slice(key);
+        expected.extend_from_slice(b"\r\n");
+        assert_eq!(send_bytes(&mut stream, &[b"KEYS", b"k?\x00*"]), expected);
+        let mut expected = b"*2\r\n$1\r\n0\r\n".to_vec();
+        expected.extend_from_slice(b"*1\r\n$5\r\n");
+        expected.extend_from_slice(key);
+        expected.extend_from_slice(b"\r\n");
+        assert_eq!(
+            send_bytes(&mut stream, &[b"SCAN", b"0", b"MATCH", b"k\xff*"]),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_busy_script() {
+        let addr = start_server();
+        let mut admin = TcpStream::connect(addr).unwrap();
+        let mut script = TcpStream::connect(addr).unwrap();
+        let busy = format!("-{}\r\n", scripting::BUSY);
+        let wait_until_busy = |admin: &mut TcpStream| {
+            while send(admin, &["PING"]) != busy {
+                thread::sleep(Duration::from_millis(1));
+            }
+        };
+        send(&mut admin, &["CONFIG", "SET", "lua-time-limit", "10"]);
+        assert_eq!(
+            send(&mut admin, &["SCRIPT", "KILL"]),
+            "-NOTBUSY No scripts in execution right now.\r\n"
+        );
+
+        script
+            .write_all(&command_bytes(&["EVAL", "while true do end", "0"]))
+            .unwrap();
+        wait_until_busy(&mut admin);
+        assert_eq!(send(&mut admin, &["GET", "k"]), busy);
+        assert_eq!(send(&mut admin, &["SCRIPT", "KILL"]), "+OK\r\n");
+        let reply = String::from_utf8(read_reply(&mut script)).unwrap();
+        assert!(reply.ends_with("Script killed by user with SCRIPT KILL...\r\n"));
+        assert_eq!(send(&mut admin, &["GET", "k"]), "$-1\r\n");
+
+        // a script which wrote can't be killed, it runs to its end
+        let body =
+            "redis.call('SET', 'k', 'v') local n = 0 for i = 1, 30000000 do n = n + 1 end return n";
+        script
+            .write_all(&command_bytes(&["EVAL", body, "0"]))
+            .unwrap();
+        wait_until_busy(&mut admin);
+        assert!(send(&mut admin, &["SCRIPT", "KILL"]).starts_with("-UNKILLABLE"));
+        assert_eq!(read_reply(&mut script), b":30000000\r\n");
+        assert_eq!(send(&mut admin, &["GET", "k"]), "$1\r\nv\r\n");
+    }
+
+    #[test]
+    fn test_inline_and_split_commands() {
+        let addr = start_server();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"SET k v\r\n").unwrap();
+        assert_eq!(read_reply(&mut stream), b"+OK\r\n");
+        // a command sent one byte at a time
+        for byte in b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n" {
+            stream.write_all(&[*byte]).unwrap();
+        }
+        assert_eq!(read_reply(&mut stream), b"$1\r\nv\r\n");
+    }
+
+    #[test]
+    fn test_errors_keep_connection_open() {
+        let addr = start_server();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        assert_eq!(
+            send(&mut stream, &["FOO", "bar", "baz"]),
+            "-ERR unknown command 'FOO', with args beginning with: 'bar' 'baz' \r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["GET"]),
+            "-ERR wrong number of arguments for 'get' command\r\n"
+        );
+        assert_eq!(
+            send(&mut stream, &["set", "k"]),
+            "-ERR wrong number of arguments for 'set' command\r\n"
+        );
+        assert_eq!(send(&mut stream, &["sEt", "k", "v"]), "+OK\r\n");
+        assert_eq!(send(&mut stream, &["ECHO", "hi"]), "$2\r\nhi\r\n");
+        assert_eq!(send(&mut stream, &["PING", "hi"]), "$2\r\nhi\r\n");
+        assert_eq!(send(&mut stream, &["PING"]), "+PONG\r\n");
+    }
+
+    #[test]
+    fn test_protocol_error_closes_connection() {
+        let addr = start_server();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"*1\r\n$abc\r\n").unwrap();
+        assert_eq!(
+            read_reply(&mut stream),
+            b"-ERR Protocol error: invalid bulk length\r\n"
+        );
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn test_messages_are_pushed_to_subscribers() {
+        let addr = start_server();
+        let mut subscriber = TcpStream::connect(addr).unwrap();
+        let mut publisher = TcpStream::connect(addr).unwrap();
+
+        assert_eq!(
+            send(&mut subscriber, &["SUBSCRIBE", "news"]),
+            "*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n"
+        );
+        assert_eq!(send(&mut publisher, &["PUBLISH", "news", "hi"]), ":1\r\n");
+        assert_eq!(
+            read_reply(&mut subscriber),
+            b"*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$2\r\nhi\r\n"
+        );
+
+        // a subscriber which hung up no longer counts as a receiver
+        drop(subscriber);
+        let mut receivers = send(&mut publisher, &["PUBLISH", "news", "hi"]);
+        while receivers != ":0\r\n" {
+            thread::sleep(std::time::Duration::from_millis(10));
+            receivers = send(&mut publisher, &["PUBLISH", "news", "hi"]);
+        }
+    }
+
+    #[test]
+    fn test_blocked_client_is_served() {
+        let addr = start_server();
+        let mut reader = TcpStream::connect(addr).unwrap();
+        let mut writer = TcpStream::connect(addr).unwrap();
+
+        assert_eq!(
+            send(&mut reader, &["XREAD", "BLOCK", "50", "STREAMS", "s", "0"]),
+            "*-1\r\n"
+        );
+        // the pipelined PING waits for the blocked read
+        let read = command_bytes(&["XREAD", "BLOCK", "0", "STREAMS", "s", "0"]);
+        reader.write_all(&read).unwrap();
+        reader.write_all(&command_bytes(&["PING"])).unwrap();
+        thread::sleep(std::time::Duration::from_millis(20));
+        assert_eq!(
+            send(&mut writer, &["XADD", "s", "1-0", "f", "v"]),
+            "$3\r\n1-0\r\n"
+        );
+        assert_eq!(
+            read_reply(&mut reader),
+            b"*1\r\n*2\r\n$1\r\ns\r\n*1\r\n*2\r\n$3\r\n1-0\r\n*2\r\n$1\r\nf\r\n$1\r\nv\r\n"
+        );
+        assert_eq!(read_reply(&mut reader), b"+PONG\r\n");
+
+        assert_eq!(send(&mut reader, &["BLPOP", "q", "0.05"]), "*-1\r\n");
+        reader
+            .write_all(&command_bytes(&["BLPOP", "q", "0"]))
+            .unwrap();
+        thread::sleep(std::time::Duration::from_millis(20));
+        assert_eq!(send(&mut writer, &["RPUSH", "q", "job"]), ":1\r\n");
+        assert_eq!(read_reply(&mut reader), b"*2\r\n$1\r\nq\r\n$3\r\njob\r\n");
+    }
+
+    /// Repeat `args` until the reply is `expected`, replication is
+    /// asynchronous.
+    fn wait_for(stream: &mut TcpStream, args: &[&str], expected: &str) {
+        for _ in 0..500 {
+            if send(stream, args) == expected {
+                return;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        panic!("{:?} never replied {:?}", args, expected);
+    }
+
+    #[test]
+    fn test_replication() {
+        let master_addr = start_server();
+        let replica_addr = start_server();
+        let mut master = TcpStream::connect(master_addr).unwrap();
+        let mut replica = TcpStream::connect(replica_addr).unwrap();
+        send(&mut master, &["SET", "before", "1"]);
+        send(&mut replica, &["SET", "stale", "1"]);
+
+        let port = master_addr.port().to_string();
+        assert_eq!(
+            send(&mut replica, &["REPLICAOF", "127.0.0.1", &port]),
+            "+OK\r\n"
+        );
+        assert_eq!(
+            send(&mut replica, &["REPLICAOF", "127.0.0.1", &port]),
+            "+OK Already connected to specified master\r\n"
+        );
+        // the full resync replaces the dataset of the replica
+        wait_for(&mut replica, &["GET", "before"], "$1\r\n1\r\n");
+        assert_eq!(send(&mut replica, &["EXISTS", "stale"]), ":0\r\n");
+
+        // then the writes are streamed
+        send(&mut master, &["SET", "after", "2", "EX", "100"]);
+        send(&mut master, &["MULTI"]);
+        send(&mut master, &["INCR", "counter"]);
+        send(&mut master, &["RPUSH", "list", "a", "b"]);
+        send(&mut master, &["EXEC"]);
+        wait_for(&mut replica, &["LLEN", "list"], ":2\r\n");
+        assert_eq!(send(&mut replica, &["GET", "counter"]), "$1\r\n1\r\n");
+        assert_eq!(send(&mut replica, &["GET", "after"]), "$1\r\n2\r\n");
+        assert_eq!(
+            send(&mut replica, &["SET", "k", "v"]),
+            "-READONLY You can't write against a read only replica.\r\n"
+        );
+        let role = format!(
+            "*5\r\n$5\r\nslave\r\n$9\r\n127.0.0.1\r\n:{}\r\n$9\r\nconnected\r\n",
+            port
+        );
+        assert!(send(&mut replica, &["ROLE"]).starts_with(&role));
+        assert!(send(&mut master, &["ROLE"]).contains("*1\r\n*3\r\n$9\r\n127.0.0.1\r\n"));
+
+        // once promoted the replica accepts writes, the master no longer
+        // reaches it
+        assert_eq!(send(&mut replica, &["REPLICAOF", "NO", "ONE"]), "+OK\r\n");
+        assert_eq!(send(&mut replica, &["SET", "k", "v"]), "+OK\r\n");
+        send(&mut master, &["SET", "before", "changed"]);
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(send(&mut replica, &["GET", "before"]), "$1\r\n1\r\n");
+    }
+
+    #[test]
+    fn test_chained_replication() {
+        let master_addr = start_server();
+        let replica_addr = start_server();
+        let sub_replica_addr = start_server();
+        let mut master = TcpStream::connect(master_addr).unwrap();
+        let mut replica = TcpStream::connect(replica_addr).unwrap();
+        let mut sub_replica = TcpStream::connect(sub_replica_addr).unwrap();
+
+        // the replica is in sync once it has the key written before
+        send(&mut master, &["SET", "synced", "1"]);
+        let port = master_addr.port().to_string();
+        send(&mut replica, &["REPLICAOF", "127.0.0.1", &port]);
+        wait_for(&mut replica, &["GET", "synced"], "$1\r\n1\r\n");
+        send(&mut master, &["SELECT", "3"]);
+        send(&mut master, &["SET", "a", "1"]);
+        send(&mut replica, &["SELECT", "3"]);
+        wait_for(&mut replica, &["GET", "a"], "$1\r\n1\r\n");
+
+        // the stream the sub-replica gets from the replica already selected
+        // database 3, the snapshot has to tell it
+        let port = replica_addr.port().to_string();
+        send(&mut sub_replica, &["REPLICAOF", "127.0.0.1", &port]);
+        send(&mut sub_replica, &["SELECT", "3"]);
+        wait_for(&mut sub_replica, &["GET", "a"], "$1\r\n1\r\n");
+        send(&mut master, &["SET", "b", "2"]);
+        wait_for(&mut sub_replica, &["GET", "b"], "$1\r\n2\r\n");
+        send(&mut sub_replica, &["SELECT", "0"]);
+        assert_eq!(send(&mut sub_replica, &["EXISTS", "a", "b"]), ":0\r\n");
+    }
+
+    #[test]
+    fn test_many_concurrent_clients() {
+        let addr = start_server();
+
+        // open every connection first so all clients are alive at once
+        let clients: Vec<TcpStream> = (0..200)
+            .map(|_| TcpStream::connect(addr).unwrap())
+            .collect();
+
+        let handles: Vec<_> = clients
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut stream)| {
+                thread::spawn(move || {
+                    let key = format!("key{}", i);
+                    let val = format!("value{}", i);
+                    for _ in 0..10 {
+                        assert_eq!(send(&mut stream, &["SET", &key, &val]), "+OK\r\n");
+                        assert_eq!(
+                            send(&mut stream, &["GET", &key]),
+                            format!("${}\r\n{}\r\n", val.len(), val)
+                        );
+                    }
                 })
-                .join();
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_client_kill_closes_connection() {
+        let addr = start_server();
+        let mut admin = TcpStream::connect(addr).unwrap();
+        let mut victim = TcpStream::connect(addr).unwrap();
+        assert_eq!(
+            send(&mut victim, &["CLIENT", "SETNAME", "victim"]),
+            "+OK\r\n"
+        );
+        let id = send(&mut victim, &["CLIENT", "ID"]);
+        let id = id.trim_start_matches(':').trim_end();
+
+        let list = send(&mut admin, &["CLIENT", "LIST"]);
+        assert!(list.contains(&format!("id={} ", id)));
+        assert!(list.contains(" name=victim "));
+        assert_eq!(send(&mut admin, &["CLIENT", "KILL", "ID", id]), ":1\r\n");
+
+        let mut buf = [0u8; 16];
+        assert_eq!(victim.read(&mut buf).unwrap(), 0);
+        // it's gone from the list once its connection is closed
+        let mut list = String::new();
+        for _ in 0..50 {
+            list = send(&mut admin, &["CLIENT", "LIST"]);
+            if !list.contains("name=victim") {
+                break;
             }
-            Err(e) => panic!("{}", e),
+            thread::sleep(Duration::from_millis(10));
         }
+        assert!(!list.contains("name=victim"));
     }
 }
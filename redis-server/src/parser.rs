@@ -8,177 +8,504 @@ pub enum ValueType {
     Null,
     BulkString,
     Array,
+    // RESP3 only types, `stringify` downgrades them for RESP2 clients
+    Double,
+    Boolean,
+    BigNumber,
+    VerbatimString,
+    Map,
+    Set,
+    Attribute,
+    Push,
 }
 
+/// Protocol version a connection speaks, picked by the client with `HELLO`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Resp2,
+    Resp3,
+}
+
+impl Protocol {
+    pub fn version(&self) -> i64 {
+        match self {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        }
+    }
+}
+
+/// A RESP value. `value` holds the raw bytes of scalar types, bulk strings
+/// may contain anything including `\r\n` and invalid UTF-8.
 #[derive(Debug, Clone)]
 pub struct Value {
-    pub value: Option<String>,
+    pub value: Option<Vec<u8>>,
     pub value_type: ValueType,
     pub null: bool,
     pub array: Vec<Value>,
 }
 
-pub struct Parser {
+/// Largest bulk string a client may send, same as redis' default
+/// `proto-max-bulk-len`.
+const MAX_BULK_LEN: i64 = 512 * 1024 * 1024;
+/// Most elements a single aggregate may announce.
+const MAX_AGGREGATE_LEN: i64 = 1024 * 1024;
+/// Deepest aggregates may be nested, they are parsed recursively.
+const MAX_AGGREGATE_DEPTH: usize = 64;
+/// Longest line (inline command or frame header) without a `\r\n`.
+const MAX_INLINE_LEN: usize = 64 * 1024;
+
+/// Why `Parser::parse` couldn't return a value.
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    /// the frame is only partially received, parse again once more bytes
+    /// arrived
+    Incomplete,
+    /// the bytes are not valid RESP, there is no way to find where the next
+    /// frame starts so the connection has to be closed
+    Protocol(String),
+}
+
+/// Incremental RESP parser over raw bytes. Bulk strings are read by their
+/// length prefix, so their content is never scanned for terminators.
+pub struct Parser<'a> {
     cursor: usize,
-    buf: String,
+    buf: &'a [u8],
 }
-pub fn stringify(value: &Value) -> String {
-    let mut result = String::new();
 
-    // if value type is array then we need to recurse
-    // else we can directly append values to the result
-    match value.value_type {
-        ValueType::Array => {
-            result += format!("*{}\r\n", value.array.len()).as_str();
-            for v in value.array.iter() {
-                let val = stringify(v);
-                result += &val;
-            }
+/// Shorthands to build the replies sent back to the client.
+impl Value {
+    pub fn simple_string(string: &str) -> Value {
+        Value {
+            value: Some(string.as_bytes().to_vec()),
+            value_type: ValueType::SimpleString,
+            null: false,
+            array: Vec::new(),
         }
-        ValueType::SimpleString => {
-            result += format!("+{}", value.value.clone().unwrap()).as_str();
-            result.push_str("\r\n");
+    }
+
+    pub fn ok() -> Value {
+        Value::simple_string("OK")
+    }
+
+    pub fn error(message: &str) -> Value {
+        Value {
+            value: Some(message.as_bytes().to_vec()),
+            value_type: ValueType::Error,
+            null: false,
+            array: Vec::new(),
         }
-        ValueType::Null => {
-            result += "$-1\r\n";
+    }
+
+    pub fn integer(number: i64) -> Value {
+        Value {
+            value: Some(number.to_string().into_bytes()),
+            value_type: ValueType::Integer,
+            null: false,
+            array: Vec::new(),
         }
-        ValueType::Integer => {
-            result += format!(":{}\r\n", value.value.clone().unwrap()).as_str();
+    }
+
+    pub fn bulk_string(bytes: impl AsRef<[u8]>) -> Value {
+        Value {
+            value: Some(bytes.as_ref().to_vec()),
+            value_type: ValueType::BulkString,
+            null: false,
+            array: Vec::new(),
+        }
+    }
+
+    pub fn null() -> Value {
+        Value {
+            value: None,
+            value_type: ValueType::Null,
+            null: true,
+            array: Vec::new(),
         }
-        ValueType::BulkString => {
-            result += format!(
-                "${}\r\n{}\r\n",
-                value.value.clone().unwrap().len(),
-                value.value.clone().unwrap()
-            )
-            .as_str();
+    }
+
+    /// Missing array, e.g. the reply of an aborted `EXEC`.
+    pub fn null_array() -> Value {
+        Value {
+            value: None,
+            value_type: ValueType::Array,
+            null: true,
+            array: Vec::new(),
         }
-        ValueType::Error => {
-            result += format!("-{}\r\n", value.value.clone().unwrap()).as_str();
+    }
+
+    pub fn array(values: Vec<Value>) -> Value {
+        Value {
+            value: None,
+            value_type: ValueType::Array,
+            null: false,
+            array: values,
         }
     }
 
+    /// Value with `array` but another aggregate type.
+    fn aggregate(value_type: ValueType, values: Vec<Value>) -> Value {
+        Value {
+            value_type,
+            ..Value::array(values)
+        }
+    }
+
+    fn scalar(value_type: ValueType, bytes: &[u8]) -> Value {
+        Value {
+            value_type,
+            ..Value::bulk_string(bytes)
+        }
+    }
+
+    pub fn double(number: f64) -> Value {
+        let string = if number.is_nan() {
+            "nan".to_string()
+        } else if number.is_infinite() {
+            if number > 0.0 { "inf" } else { "-inf" }.to_string()
+        } else {
+            number.to_string()
+        };
+        Value::scalar(ValueType::Double, string.as_bytes())
+    }
+
+    pub fn boolean(boolean: bool) -> Value {
+        Value::scalar(ValueType::Boolean, if boolean { b"t" } else { b"f" })
+    }
+
+    pub fn big_number(digits: &str) -> Value {
+        Value::scalar(ValueType::BigNumber, digits.as_bytes())
+    }
+
+    /// Text with a three letter `format` hint, e.g. `txt` or `mkd`.
+    pub fn verbatim_string(format: &str, text: &str) -> Value {
+        let string = format!("{}:{}", format, text);
+        Value::scalar(ValueType::VerbatimString, string.as_bytes())
+    }
+
+    /// Map from its key value pairs, stored flattened in `array`.
+    pub fn map(pairs: Vec<(Value, Value)>) -> Value {
+        let flat = pairs.into_iter().flat_map(|(k, v)| [k, v]).collect();
+        Value::aggregate(ValueType::Map, flat)
+    }
+
+    pub fn set(values: Vec<Value>) -> Value {
+        Value::aggregate(ValueType::Set, values)
+    }
+
+    /// Auxiliary data sent before a reply, dropped for RESP2 clients.
+    pub fn attribute(pairs: Vec<(Value, Value)>) -> Value {
+        Value {
+            value_type: ValueType::Attribute,
+            ..Value::map(pairs)
+        }
+    }
+
+    /// Out of band data e.g. pub/sub messages.
+    pub fn push(values: Vec<Value>) -> Value {
+        Value::aggregate(ValueType::Push, values)
+    }
+}
+
+/// RESP2 encoding of `value`.
+pub fn stringify(value: &Value) -> Vec<u8> {
+    serialize(value, Protocol::Resp2)
+}
+
+/// Encode `value` for a client speaking `protocol`. RESP3 types sent to a
+/// RESP2 client are replaced with their closest RESP2 type the same way redis
+/// does: maps become flat arrays, doubles bulk strings, booleans integers.
+pub fn serialize(value: &Value, protocol: Protocol) -> Vec<u8> {
+    let mut result = Vec::new();
+    write_value(&mut result, value, protocol);
     result
 }
 
-impl Parser {
-    pub fn new(input: String) -> Self {
+/// `<prefix><content>\r\n`
+fn write_line(out: &mut Vec<u8>, prefix: u8, content: &[u8]) {
+    out.push(prefix);
+    out.extend_from_slice(content);
+    out.extend_from_slice(b"\r\n");
+}
+
+/// `<prefix><length>\r\n<content>\r\n`
+fn write_blob(out: &mut Vec<u8>, prefix: u8, content: &[u8]) {
+    write_line(out, prefix, content.len().to_string().as_bytes());
+    out.extend_from_slice(content);
+    out.extend_from_slice(b"\r\n");
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value, protocol: Protocol) {
+    let resp3 = protocol == Protocol::Resp3;
+    let content = value.value.as_deref().unwrap_or_default();
+
+    // if value type is an aggregate then we need to recurse
+    // else we can directly append values to the result
+    match value.value_type {
+        ValueType::Array if value.null && resp3 => out.extend_from_slice(b"_\r\n"),
+        ValueType::Array if value.null => out.extend_from_slice(b"*-1\r\n"),
+        ValueType::Array | ValueType::Set | ValueType::Push | ValueType::Map => {
+            let prefix = match value.value_type {
+                ValueType::Set if resp3 => b'~',
+                ValueType::Push if resp3 => b'>',
+                ValueType::Map if resp3 => b'%',
+                _ => b'*',
+            };
+            let len = if prefix == b'%' {
+                value.array.len() / 2
+            } else {
+                value.array.len()
+            };
+            write_line(out, prefix, len.to_string().as_bytes());
+            for v in value.array.iter() {
+                write_value(out, v, protocol);
+            }
+        }
+        ValueType::Attribute => {
+            if resp3 {
+                let len = value.array.len() / 2;
+                write_line(out, b'|', len.to_string().as_bytes());
+                for v in value.array.iter() {
+                    write_value(out, v, protocol);
+                }
+            }
+        }
+        ValueType::SimpleString => write_line(out, b'+', content),
+        ValueType::Error => write_line(out, b'-', content),
+        ValueType::Integer => write_line(out, b':', content),
+        ValueType::BulkString => write_blob(out, b'$', content),
+        ValueType::Null if resp3 => out.extend_from_slice(b"_\r\n"),
+        ValueType::Null => out.extend_from_slice(b"$-1\r\n"),
+        ValueType::Double if resp3 => write_line(out, b',', content),
+        ValueType::Boolean if resp3 => write_line(out, b'#', content),
+        ValueType::Boolean => write_line(out, b':', if content == b"t" { b"1" } else { b"0" }),
+        ValueType::BigNumber if resp3 => write_line(out, b'(', content),
+        ValueType::VerbatimString if resp3 => write_blob(out, b'=', content),
+        ValueType::VerbatimString => write_blob(out, b'$', content.get(4..).unwrap_or_default()),
+        ValueType::Double | ValueType::BigNumber => write_blob(out, b'$', content),
+    }
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
         Parser {
             cursor: 0,
             buf: input,
         }
     }
 
-    /// Iterate over next character
-    /// it doesn't check if cursor > buf.len(), be careful!
-    pub fn next(&mut self) -> char {
-        if self.cursor >= self.buf.len() {
-            panic!("Cannot parse input!");
-        }
-        let mut chars = self.buf.chars();
-        let ch = chars.nth(self.cursor).unwrap();
-        self.cursor += 1;
-        ch
+    /// Number of bytes taken by the values parsed so far, i.e. where the next
+    /// frame starts.
+    pub fn position(&self) -> usize {
+        self.cursor
     }
 
-    /// Return next `command` String til `\r\n`
-    pub fn next_command(&mut self) -> String {
-        let mut command = String::new();
-        let mut ch = self.next();
-        let mut found_carriage = false;
-        while ch != '\n' {
-            if ch == '\r' {
-                found_carriage = true;
-            } else {
-                command.push(ch);
+    /// Bytes up to the next `\r\n`, the cursor moves past the terminator.
+    fn line(&mut self) -> Result<&'a [u8], ParseError> {
+        let rest = &self.buf[self.cursor..];
+        match rest.windows(2).position(|w| w == b"\r\n") {
+            Some(end) => {
+                self.cursor += end + 2;
+                Ok(&rest[..end])
+            }
+            None if rest.len() > MAX_INLINE_LEN => {
+                Err(ParseError::Protocol("too big inline request".to_string()))
             }
-            ch = self.next();
+            None => Err(ParseError::Incomplete),
         }
+    }
 
-        // if command terminator is found correctly
-        // else return an empty string
-        if found_carriage && ch == '\n' {
-            command
-        } else {
-            String::new()
+    /// Length header of a bulk string or an aggregate, `-1` means null.
+    fn length(&mut self, what: &str, max: i64) -> Result<i64, ParseError> {
+        let line = self.line()?;
+        match std::str::from_utf8(line)
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok())
+        {
+            Some(len) if (-1..=max).contains(&len) => Ok(len),
+            _ => Err(ParseError::Protocol(format!("invalid {} length", what))),
         }
     }
 
-    pub fn parse(&mut self) -> Value {
-        let first_char = self.next();
-        // dbg!(first_char.clone());
+    /// `len` bytes of content followed by `\r\n`.
+    fn blob(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
+        let end = self.cursor + len;
+        if self.buf.len() < end + 2 {
+            return Err(ParseError::Incomplete);
+        }
+        if &self.buf[end..end + 2] != b"\r\n" {
+            return Err(ParseError::Protocol("expected CRLF after bulk".to_string()));
+        }
+        let content = &self.buf[self.cursor..end];
+        self.cursor = end + 2;
+        Ok(content)
+    }
 
-        match first_char {
-            '+' => {
-                let string = self.next_command();
-                Value {
-                    null: false,
-                    value: Some(string),
-                    value_type: ValueType::SimpleString,
-                    array: Vec::new(),
-                }
+    /// Parse the next value. On `Incomplete` the cursor may have moved, the
+    /// caller should start a new parser on the same bytes once more arrived.
+    /// Lines which don't start with a RESP type byte are inline commands
+    /// like the ones typed into telnet, `PING` or `SET key value`.
+    pub fn parse(&mut self) -> Result<Value, ParseError> {
+        let first = match self.buf.get(self.cursor) {
+            Some(first) => *first,
+            None => return Err(ParseError::Incomplete),
+        };
+        match first {
+            b'+' | b'-' | b':' | b',' | b'#' | b'(' | b'_' | b'$' | b'=' | b'*' | b'~' | b'>'
+            | b'%' | b'|' => self.parse_value(0),
+            _ => self.inline(),
+        }
+    }
+
+    /// Inline command, its arguments are separated by whitespace.
+    fn inline(&mut self) -> Result<Value, ParseError> {
+        let line = self.line()?;
+        let args = line
+            .split(|c| c.is_ascii_whitespace())
+            .filter(|arg| !arg.is_empty())
+            .map(Value::bulk_string)
+            .collect();
+        Ok(Value::array(args))
+    }
+
+    /// `depth` is the number of aggregates the value is nested in.
+    fn parse_value(&mut self, depth: usize) -> Result<Value, ParseError> {
+        let first = match self.buf.get(self.cursor) {
+            Some(first) => *first,
+            None => return Err(ParseError::Incomplete),
+        };
+        self.cursor += 1;
+
+        match first {
+            b'+' => Ok(Value::scalar(ValueType::SimpleString, self.line()?)),
+            b'-' => Ok(Value::scalar(ValueType::Error, self.line()?)),
+            b':' => Ok(Value::scalar(ValueType::Integer, self.line()?)),
+            b',' => Ok(Value::scalar(ValueType::Double, self.line()?)),
+            b'#' => Ok(Value::scalar(ValueType::Boolean, self.line()?)),
+            b'(' => Ok(Value::scalar(ValueType::BigNumber, self.line()?)),
+            b'_' => {
+                self.line()?;
+                Ok(Value::null())
             }
-            '-' => {
-                let string = self.next_command();
-                Value {
-                    value: Some(string),
-                    value_type: ValueType::Error,
-                    array: Vec::new(),
-                    null: false,
+            b'$' | b'=' => {
+                // $<length>\r\n<data>\r\n
+                let len = self.length("bulk", MAX_BULK_LEN)?;
+                if len < 0 {
+                    return Ok(Value::null());
                 }
+                let content = self.blob(len as usize)?;
+                let value_type = if first == b'$' {
+                    ValueType::BulkString
+                } else {
+                    ValueType::VerbatimString
+                };
+                Ok(Value::scalar(value_type, content))
             }
-            ':' => {
-                let integer_str = self.next_command();
-                Value {
-                    value: Some(integer_str),
-                    value_type: ValueType::Integer,
-                    array: Vec::new(),
-                    null: false,
+            b'*' | b'~' | b'>' | b'%' | b'|' => {
+                // *<number-of-elements>\r\n<element-1>...<element-n>
+                if depth >= MAX_AGGREGATE_DEPTH {
+                    return Err(ParseError::Protocol(
+                        "too deeply nested multibulk".to_string(),
+                    ));
                 }
-            }
-            '$' => {
-                let string = self.next_command();
-                match string.as_str() {
-                    "-1" => Value {
-                        value: None,
-                        value_type: ValueType::Null,
-                        null: true,
-                        array: Vec::new(),
-                    },
-                    _ => {
-                        let len = string.parse::<usize>().unwrap_or(0);
-                        // $<length>\r\n<data>\r\n
-                        let mut string = self.next_command();
-                        string = string.get(0..len).unwrap().to_string();
-                        Value {
-                            value: Some(string),
-                            value_type: ValueType::BulkString,
-                            null: false,
-                            array: Vec::new(),
-                        }
-                    }
+                let len = self.length("multibulk", MAX_AGGREGATE_LEN)?;
+                if len < 0 {
+                    return Ok(Value::null());
                 }
-            }
-            '*' => {
-                // if `buf` is an array
-                // *<number-of-elements>\r\n<element-1>...<element-n>
-                let len = self.next_command().parse::<usize>().unwrap_or(0);
-                //<element-1>...<element-n>
-                let mut arr: Vec<Value> = Vec::with_capacity(len);
+                let (value_type, len) = match first {
+                    b'%' => (ValueType::Map, len * 2),
+                    b'|' => (ValueType::Attribute, len * 2),
+                    b'~' => (ValueType::Set, len),
+                    b'>' => (ValueType::Push, len),
+                    _ => (ValueType::Array, len),
+                };
+                // the length is only trusted once the elements arrived
+                let mut values = Vec::new();
                 for _ in 0..len {
-                    let v = self.parse();
-                    arr.push(v);
+                    values.push(self.parse_value(depth + 1)?);
                 }
+                Ok(Value::aggregate(value_type, values))
+            }
+            other => Err(ParseError::Protocol(format!(
+                "unexpected type byte '{}'",
+                other as char
+            ))),
+        }
+    }
+}
 
-                Value {
-                    value: None,
-                    value_type: ValueType::Array,
-                    array: arr,
-                    null: false,
+/// Parser for the requests of a client, which are inline commands or arrays
+/// of bulk strings, nothing else. Bytes are fed as they are read from the
+/// socket and the arguments of a partially received request are kept, so a
+/// big request isn't parsed from its start again every time more of it
+/// arrives.
+#[derive(Debug, Default)]
+pub struct RequestParser {
+    buf: Vec<u8>,
+    /// bytes of `buf` parsed already
+    cursor: usize,
+    /// arguments of the request being received
+    args: Vec<Value>,
+    /// number of arguments of the request which didn't arrive yet
+    missing: usize,
+}
+
+impl RequestParser {
+    pub fn new() -> Self {
+        RequestParser::default()
+    }
+
+    /// Append bytes read from the client.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buf.drain(..self.cursor);
+        self.cursor = 0;
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Parse the next request, `Incomplete` until enough bytes were fed.
+    /// An empty array stands for a request without arguments, e.g. an empty
+    /// line.
+    pub fn next(&mut self) -> Result<Value, ParseError> {
+        if self.missing == 0 {
+            let mut parser = Parser::new(&self.buf[self.cursor..]);
+            match parser.buf.first() {
+                None => return Err(ParseError::Incomplete),
+                Some(b'*') => parser.cursor += 1,
+                Some(_) => {
+                    let value = parser.inline()?;
+                    self.cursor += parser.position();
+                    return Ok(value);
+                }
+            }
+            let len = parser.length("multibulk", MAX_AGGREGATE_LEN)?;
+            self.cursor += parser.position();
+            if len <= 0 {
+                return Ok(Value::array(Vec::new()));
+            }
+            self.missing = len as usize;
+        }
+        while self.missing > 0 {
+            let mut parser = Parser::new(&self.buf[self.cursor..]);
+            match parser.buf.first() {
+                None => return Err(ParseError::Incomplete),
+                Some(b'$') => parser.cursor += 1,
+                Some(other) => {
+                    return Err(ParseError::Protocol(format!(
+                        "expected '$', got '{}'",
+                        *other as char
+                    )))
                 }
             }
-            _ => todo!(),
+            let len = parser.length("bulk", MAX_BULK_LEN)?;
+            if len < 0 {
+                return Err(ParseError::Protocol("invalid bulk length".to_string()));
+            }
+            let content = parser.blob(len as usize)?;
+            self.args.push(Value::bulk_string(content));
+            self.cursor += parser.position();
+            self.missing -= 1;
         }
+        Ok(Value::array(std::mem::take(&mut self.args)))
     }
 }
 
@@ -186,170 +513,268 @@ impl Parser {
 mod test {
     use super::*;
 
+    fn parse(input: &[u8]) -> Value {
+        let mut p = Parser::new(input);
+        let val = p.parse().unwrap();
+        assert_eq!(p.position(), input.len());
+        val
+    }
+
+    fn text(value: &Value) -> String {
+        String::from_utf8(value.value.clone().unwrap()).unwrap()
+    }
+
     #[test]
     fn test_array_parse_ping_array() {
-        let input = "*1\r\n$4\r\nping\r\n".to_string();
-        let mut p = Parser::new(input);
-        let val = p.parse();
-        dbg!(val);
+        let val = parse(b"*1\r\n$4\r\nping\r\n");
+        assert_eq!(val.value_type, ValueType::Array);
+        assert_eq!(text(&val.array[0]), "ping");
     }
 
     #[test]
     fn test_array_two_elements_parse_array() {
-        let input = "*2\r\n$3\r\nget\r\n$3\r\nkey\r\n".to_string();
-        let mut p = Parser::new(input);
-        let val = p.parse();
-        dbg!(val);
+        let val = parse(b"*2\r\n$3\r\nget\r\n$3\r\nkey\r\n");
+        assert_eq!(val.array.len(), 2);
+        assert_eq!(text(&val.array[1]), "key");
     }
 
     #[test]
     fn test_array_two_elements_parse_respect_length_array() {
-        let input = "*2\r\n$4\r\necho\r\n$5\r\nhello world\r\n".to_string();
-        let mut p = Parser::new(input);
-        let val = p.parse();
-        dbg!(val);
+        let val = parse(b"*2\r\n$4\r\necho\r\n$11\r\nhello world\r\n");
+        assert_eq!(text(&val.array[1]), "hello world");
     }
 
     #[test]
     fn test_empty_bulk_string() {
-        let input = "$0\r\n\r\n".to_string();
-        let mut p = Parser::new(input);
-        let val = p.parse();
-        dbg!(val);
+        let val = parse(b"$0\r\n\r\n");
+        assert_eq!(val.value, Some(Vec::new()));
     }
 
     #[test]
     fn test_null_case() {
-        let input = "$-1\r\n".to_string();
-        let mut p = Parser::new(input);
-        let val = p.parse();
-        dbg!(val);
+        let val = parse(b"$-1\r\n");
+        assert!(val.null);
     }
 
     #[test]
     fn test_simple_string() {
-        let input = "+OK\r\n".to_string();
-        let mut p = Parser::new(input);
-        let val = p.parse();
-        dbg!(val);
+        let val = parse(b"+OK\r\n");
+        assert_eq!(val.value_type, ValueType::SimpleString);
+        assert_eq!(text(&val), "OK");
     }
 
     #[test]
     fn test_simple_string_hello_world() {
-        let input = "+hello world\r\n".to_string();
-        let mut p = Parser::new(input);
-        let val = p.parse();
-        dbg!(val);
+        assert_eq!(text(&parse(b"+hello world\r\n")), "hello world");
     }
 
     #[test]
     fn test_basic_error_message() {
-        let input = "-Error message\r\n".to_string();
-        let mut p = Parser::new(input);
-        let val = p.parse();
-        dbg!(val);
+        let val = parse(b"-Error message\r\n");
+        assert_eq!(val.value_type, ValueType::Error);
+        assert_eq!(text(&val), "Error message");
     }
 
     #[test]
     fn test_negative_integer() {
-        let input = ":-333\r\n".to_string();
-        let mut p = Parser::new(input);
-        let val = p.parse();
-        dbg!(val);
+        assert_eq!(text(&parse(b":-333\r\n")), "-333");
     }
 
     #[test]
     fn test_postive_integer() {
-        let input = ":89\r\n".to_string();
-        let mut p = Parser::new(input);
-        let val = p.parse();
-        dbg!(val);
+        assert_eq!(text(&parse(b":89\r\n")), "89");
     }
 
     #[test]
     fn test_mixed_array() {
-        let input = "*4\r\n$5\r\nhello\r\n:-33\r\n:69\r\n+MIXED\r\n".to_string();
-        let mut p = Parser::new(input);
-        let val = p.parse();
-        dbg!(val);
+        let val = parse(b"*4\r\n$5\r\nhello\r\n:-33\r\n:69\r\n+MIXED\r\n");
+        assert_eq!(val.array.len(), 4);
+        assert_eq!(val.array[3].value_type, ValueType::SimpleString);
     }
 
     #[test]
-    #[should_panic]
     fn test_invalid_mixed_array() {
-        let input = "*5\r\n$5\r\nhello\r\n:-33\r\n:69\r\n+MIXED\r\n".to_string();
+        // one element short, the rest may still arrive
+        let input = b"*5\r\n$5\r\nhello\r\n:-33\r\n:69\r\n+MIXED\r\n";
+        assert_eq!(
+            Parser::new(input).parse().unwrap_err(),
+            ParseError::Incomplete
+        );
+    }
+
+    #[test]
+    fn test_binary_bulk_string() {
+        let input = b"*2\r\n$4\r\n\r\n\xff\x00\r\n$6\r\n\xe2\x82\xac\xe2\x82\xac\r\n";
+        let val = parse(input);
+        assert_eq!(val.array[0].value, Some(b"\r\n\xff\x00".to_vec()));
+        assert_eq!(text(&val.array[1]), "€€");
+        assert_eq!(stringify(&val), input.to_vec());
+    }
+
+    #[test]
+    fn test_partial_frames_need_more_data() {
+        let input = b"*2\r\n$3\r\nget\r\n$3\r\nkey\r\n";
+        for end in 0..input.len() {
+            assert_eq!(
+                Parser::new(&input[..end]).parse().unwrap_err(),
+                ParseError::Incomplete
+            );
+        }
+    }
+
+    #[test]
+    fn test_pipelined_frames() {
+        let input = b"*1\r\n$4\r\nPING\r\n*2\r\n$3\r\nGET\r\n$1\r\na\r\n";
         let mut p = Parser::new(input);
-        let val = p.parse();
-        dbg!(val);
+        assert_eq!(text(&p.parse().unwrap().array[0]), "PING");
+        assert_eq!(p.position(), 14);
+        assert_eq!(text(&p.parse().unwrap().array[1]), "a");
+        assert_eq!(p.position(), input.len());
+        assert_eq!(p.parse().unwrap_err(), ParseError::Incomplete);
     }
 
     #[test]
-    fn test_simple_string_stringify() {
-        let val = Value {
-            value: Some("Hello".to_string()),
-            value_type: ValueType::SimpleString,
-            null: false,
-            array: Vec::new(),
+    fn test_protocol_errors() {
+        let invalid = |input: &[u8]| match Parser::new(input).parse() {
+            Err(ParseError::Protocol(message)) => message,
+            other => panic!("expected a protocol error, got {:?}", other),
         };
+        assert_eq!(invalid(b"*x\r\n"), "invalid multibulk length");
+        assert_eq!(invalid(b"*1\r\n$-5\r\n"), "invalid bulk length");
+        assert_eq!(invalid(b"$3\r\nabcd\r\n"), "expected CRLF after bulk");
+        assert_eq!(invalid(b"*1\r\n!1\r\n"), "unexpected type byte '!'");
+        assert_eq!(
+            invalid(&b"*1\r\n".repeat(MAX_AGGREGATE_DEPTH + 1)),
+            "too deeply nested multibulk"
+        );
+        assert_eq!(
+            invalid(&vec![b'a'; MAX_INLINE_LEN + 1]),
+            "too big inline request"
+        );
+    }
 
-        let s = stringify(&val);
-        dbg!(s);
+    #[test]
+    fn test_request_parser() {
+        let mut requests = RequestParser::new();
+        let input = b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\nPING\r\n*0\r\n";
+        let mut parsed = Vec::new();
+        for byte in input {
+            requests.feed(&[*byte]);
+            while let Ok(request) = requests.next() {
+                parsed.push(request);
+            }
+        }
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(text(&parsed[0].array[1]), "key");
+        assert_eq!(text(&parsed[1].array[0]), "PING");
+        assert!(parsed[2].array.is_empty());
+
+        // the arguments received so far are not parsed again
+        requests.feed(b"*2\r\n$3\r\nGET\r\n$5\r\nva");
+        assert_eq!(requests.next().unwrap_err(), ParseError::Incomplete);
+        assert_eq!(requests.args.len(), 1);
+        assert_eq!(requests.cursor, 13);
+        requests.feed(b"lue\r\n");
+        assert_eq!(requests.cursor, 0);
+        assert_eq!(text(&requests.next().unwrap().array[1]), "value");
+
+        // clients may only send bulk strings
+        requests.feed(b"*1\r\n*1\r\n");
+        assert_eq!(
+            requests.next().unwrap_err(),
+            ParseError::Protocol("expected '$', got '*'".to_string())
+        );
     }
 
     #[test]
-    fn test_null_stringify() {
-        let val = Value {
-            value: None,
-            value_type: ValueType::Null,
-            null: true,
-            array: Vec::new(),
-        };
+    fn test_inline_command() {
+        let val = parse(b"SET  key value\r\n");
+        assert_eq!(val.array.len(), 3);
+        assert_eq!(text(&val.array[2]), "value");
+        assert!(parse(b"\r\n").array.is_empty());
+    }
 
-        let s = stringify(&val);
-        dbg!(s);
+    #[test]
+    fn test_simple_string_stringify() {
+        assert_eq!(stringify(&Value::simple_string("Hello")), b"+Hello\r\n");
     }
 
     #[test]
-    fn test_simple_string_array_stringify() {
-        let val = Value {
-            value: None,
-            value_type: ValueType::Array,
-            null: false,
-            array: vec![
-                Value {
-                    value: Some("Hello".to_string()),
-                    value_type: ValueType::SimpleString,
-                    null: false,
-                    array: Vec::new(),
-                },
-                Value {
-                    value: Some("World".to_string()),
-                    value_type: ValueType::SimpleString,
-                    null: false,
-                    array: Vec::new(),
-                },
-                Value {
-                    value: Some("John".to_string()),
-                    value_type: ValueType::SimpleString,
-                    null: false,
-                    array: Vec::new(),
-                },
-            ],
-        };
+    fn test_null_stringify() {
+        assert_eq!(stringify(&Value::null()), b"$-1\r\n");
+        assert_eq!(stringify(&Value::null_array()), b"*-1\r\n");
+        assert_eq!(serialize(&Value::null_array(), Protocol::Resp3), b"_\r\n");
+    }
 
-        let s = stringify(&val);
-        dbg!(s);
+    #[test]
+    fn test_simple_string_array_stringify() {
+        let val = Value::array(vec![
+            Value::simple_string("Hello"),
+            Value::simple_string("World"),
+            Value::simple_string("John"),
+        ]);
+        assert_eq!(stringify(&val), b"*3\r\n+Hello\r\n+World\r\n+John\r\n");
     }
 
     #[test]
     fn test_bulk_string() {
-        let val = Value {
-            value: Some("Hello".to_string()),
-            value_type: ValueType::BulkString,
-            null: false,
-            array: Vec::new(),
-        };
-        let s = stringify(&val);
-        dbg!(s);
+        assert_eq!(stringify(&Value::bulk_string("Hello")), b"$5\r\nHello\r\n");
+    }
+
+    #[test]
+    fn test_resp3_serialize() {
+        let resp3 = |value: &Value| String::from_utf8(serialize(value, Protocol::Resp3)).unwrap();
+        let resp2 = |value: &Value| String::from_utf8(serialize(value, Protocol::Resp2)).unwrap();
+
+        let map = Value::map(vec![
+            (Value::bulk_string("a"), Value::double(1.5)),
+            (Value::bulk_string("b"), Value::boolean(true)),
+        ]);
+        assert_eq!(resp3(&map), "%2\r\n$1\r\na\r\n,1.5\r\n$1\r\nb\r\n#t\r\n");
+        assert_eq!(
+            resp2(&map),
+            "*4\r\n$1\r\na\r\n$3\r\n1.5\r\n$1\r\nb\r\n:1\r\n"
+        );
+
+        let set = Value::set(vec![Value::integer(1)]);
+        assert_eq!(resp3(&set), "~1\r\n:1\r\n");
+        assert_eq!(resp2(&set), "*1\r\n:1\r\n");
+        assert_eq!(resp3(&Value::null()), "_\r\n");
+        assert_eq!(resp2(&Value::null()), "$-1\r\n");
+        assert_eq!(resp3(&Value::double(f64::NEG_INFINITY)), ",-inf\r\n");
+
+        let verbatim = Value::verbatim_string("txt", "Some string");
+        assert_eq!(resp3(&verbatim), "=15\r\ntxt:Some string\r\n");
+        assert_eq!(resp2(&verbatim), "$11\r\nSome string\r\n");
+        let big = Value::big_number("3492890328409238509324850943850943825024385");
+        assert!(resp3(&big).starts_with("(3492"));
+        assert!(resp2(&big).starts_with("$43\r\n"));
+
+        let push = Value::push(vec![Value::bulk_string("message")]);
+        assert_eq!(resp3(&push), ">1\r\n$7\r\nmessage\r\n");
+        let attribute = Value::attribute(vec![(Value::simple_string("ttl"), Value::integer(3))]);
+        assert_eq!(resp3(&attribute), "|1\r\n+ttl\r\n:3\r\n");
+        assert_eq!(resp2(&attribute), "");
+    }
+
+    #[test]
+    fn test_resp3_parse() {
+        let input = b"%2\r\n+first\r\n,3.14\r\n+second\r\n~2\r\n#f\r\n_\r\n";
+        let val = parse(input);
+        assert_eq!(val.value_type, ValueType::Map);
+        assert_eq!(val.array.len(), 4);
+        assert_eq!(val.array[1].value_type, ValueType::Double);
+        assert_eq!(text(&val.array[1]), "3.14");
+        assert_eq!(val.array[3].value_type, ValueType::Set);
+        assert_eq!(text(&val.array[3].array[0]), "f");
+        assert!(val.array[3].array[1].null);
+
+        let input = b"=15\r\ntxt:Some string\r\n";
+        let val = parse(input);
+        assert_eq!(val.value_type, ValueType::VerbatimString);
+        assert_eq!(serialize(&val, Protocol::Resp3), input.to_vec());
+
+        let input = b">2\r\n(12345678901234567890\r\n|1\r\n+a\r\n:1\r\n";
+        assert_eq!(serialize(&parse(input), Protocol::Resp3), input.to_vec());
     }
 }
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+
+use crate::glob::glob_match;
+use crate::parser::{serialize, Protocol, Value};
+
+/// Where to deliver the messages of a subscribed client.
+#[derive(Clone)]
+pub struct Subscriber {
+    pub outbox: Sender<Vec<u8>>,
+    pub protocol: Protocol,
+}
+
+/// Channels and patterns clients are subscribed to, shared by the whole
+/// server. Published messages are encoded and handed to the outbox of every
+/// receiver, their connection writes them out.
+#[derive(Default)]
+pub struct PubSub {
+    /// channel -> subscribers by client id
+    channels: HashMap<String, HashMap<u64, Subscriber>>,
+    /// pattern -> subscribers by client id
+    patterns: HashMap<String, HashMap<u64, Subscriber>>,
+}
+
+/// `message` (or `pmessage` when `pattern` is given) push for a subscriber.
+fn message(protocol: Protocol, pattern: Option<&str>, channel: &str, payload: &[u8]) -> Vec<u8> {
+    let mut parts = Vec::new();
+    match pattern {
+        Some(pattern) => {
+            parts.push(Value::bulk_string("pmessage"));
+            parts.push(Value::bulk_string(pattern));
+        }
+        None => parts.push(Value::bulk_string("message")),
+    }
+    parts.push(Value::bulk_string(channel));
+    parts.push(Value::bulk_string(payload));
+    serialize(&Value::push(parts), protocol)
+}
+
+impl PubSub {
+    pub fn new() -> PubSub {
+        PubSub::default()
+    }
+
+    pub fn subscribe(&mut self, channel: &str, client: u64, subscriber: Subscriber) {
+        self.channels
+            .entry(channel.to_string())
+            .or_default()
+            .insert(client, subscriber);
+    }
+
+    pub fn unsubscribe(&mut self, channel: &str, client: u64) {
+        remove(&mut self.channels, channel, client);
+    }
+
+    pub fn psubscribe(&mut self, pattern: &str, client: u64, subscriber: Subscriber) {
+        self.patterns
+            .entry(pattern.to_string())
+            .or_default()
+            .insert(client, subscriber);
+    }
+
+    pub fn punsubscribe(&mut self, pattern: &str, client: u64) {
+        remove(&mut self.patterns, pattern, client);
+    }
+
+    /// Deliver `payload` to the subscribers of `channel` and of every
+    /// matching pattern. Returns how many clients received it.
+    pub fn publish(&self, channel: &str, payload: &[u8]) -> usize {
+        let mut receivers = 0;
+        for subscriber in self
+            .channels
+            .get(channel)
+            .into_iter()
+            .flat_map(|s| s.values())
+        {
+            let push = message(subscriber.protocol, None, channel, payload);
+            // a client which is gone unsubscribes when its thread notices
+            receivers += subscriber.outbox.send(push).is_ok() as usize;
+        }
+        for (pattern, subscribers) in &self.patterns {
+            if !glob_match(pattern.as_bytes(), channel.as_bytes()) {
+                continue;
+            }
+            for subscriber in subscribers.values() {
+                let push = message(subscriber.protocol, Some(pattern), channel, payload);
+                receivers += subscriber.outbox.send(push).is_ok() as usize;
+            }
+        }
+        receivers
+    }
+
+    /// Channels with at least one subscriber, optionally only the ones
+    /// matching `pattern`.
+    pub fn channels(&self, pattern: Option<&str>) -> Vec<String> {
+        self.channels
+            .keys()
+            .filter(|channel| {
+                pattern.is_none_or(|pattern| glob_match(pattern.as_bytes(), channel.as_bytes()))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Number of subscribers of `channel`, pattern subscriptions excluded.
+    pub fn numsub(&self, channel: &str) -> usize {
+        self.channels.get(channel).map_or(0, |s| s.len())
+    }
+
+    /// Number of patterns with at least one subscriber.
+    pub fn numpat(&self) -> usize {
+        self.patterns.len()
+    }
+}
+
+fn remove(subscriptions: &mut HashMap<String, HashMap<u64, Subscriber>>, name: &str, client: u64) {
+    if let Some(subscribers) = subscriptions.get_mut(name) {
+        subscribers.remove(&client);
+        if subscribers.is_empty() {
+            subscriptions.remove(name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc::{channel, Receiver};
+
+    fn subscriber(protocol: Protocol) -> (Subscriber, Receiver<Vec<u8>>) {
+        let (outbox, inbox) = channel();
+        (Subscriber { outbox, protocol }, inbox)
+    }
+
+    #[test]
+    fn test_publish_to_channels_and_patterns() {
+        let mut pubsub = PubSub::new();
+        let (first, first_inbox) = subscriber(Protocol::Resp2);
+        let (second, second_inbox) = subscriber(Protocol::Resp3);
+        pubsub.subscribe("news", 1, first.clone());
+        pubsub.psubscribe("n*", 2, second);
+
+        assert_eq!(pubsub.publish("news", b"hi"), 2);
+        assert_eq!(
+            first_inbox.try_recv().unwrap(),
+            b"*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$2\r\nhi\r\n"
+        );
+        assert_eq!(
+            second_inbox.try_recv().unwrap(),
+            b">4\r\n$8\r\npmessage\r\n$2\r\nn*\r\n$4\r\nnews\r\n$2\r\nhi\r\n"
+        );
+        assert_eq!(pubsub.publish("sports", b"hi"), 0);
+
+        assert_eq!(pubsub.channels(None), ["news"]);
+        assert!(pubsub.channels(Some("s*")).is_empty());
+        assert_eq!(pubsub.numsub("news"), 1);
+        assert_eq!(pubsub.numpat(), 1);
+
+        pubsub.unsubscribe("news", 1);
+        pubsub.punsubscribe("n*", 2);
+        assert_eq!(pubsub.publish("news", b"hi"), 0);
+        assert!(pubsub.channels(None).is_empty());
+        assert_eq!(pubsub.numpat(), 0);
+    }
+}
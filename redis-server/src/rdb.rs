@@ -0,0 +1,1354 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, Write},
+    path::Path,
+    sync::OnceLock,
+};
+
+use crate::dictionary_server::{now_ms, DictionaryServer, RedisValue};
+use crate::sorted_set::{parse_score, SortedSet};
+use crate::stream::{ConsumerGroup, PendingEntry, Stream, StreamId};
+
+/// RDB format version written by the server, readable by redis >= 5.0 and
+/// its tooling (`redis-check-rdb`, `rdb-tools`, ...).
+const RDB_VERSION: u32 = 9;
+
+const OPCODE_IDLE: u8 = 0xF8;
+const OPCODE_FREQ: u8 = 0xF9;
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZEDB: u8 = 0xFB;
+const OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const OPCODE_EXPIRETIME: u8 = 0xFD;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+/// sorted set with the scores stored as text, before RDB 8
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+/// sorted set with the scores stored as binary doubles
+const TYPE_ZSET_2: u8 = 5;
+// the compact encodings of small values, only read: the server writes every
+// value with the plain types above
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+/// stream with its entries in listpacks, the format of redis 5 and 6
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+/// list of listpacks, or of single elements too big for one
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+/// stream with its first and max deleted ids and the entries read by groups,
+/// the format of redis 7.0
+const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const TYPE_SET_LISTPACK: u8 = 20;
+/// stream with the active time of consumers as well, redis 7.2
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+/// quicklist node holding a single element instead of a listpack
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+
+/// entries of a stream stored in one listpack, like `stream-node-max-entries`
+const STREAM_NODE_MAX_ENTRIES: usize = 100;
+/// flags of an entry in a stream listpack
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2;
+
+// when the two most significant bits of a length are `11` the remaining six
+// bits tell how the following string is encoded
+const ENCODING_INT8: u8 = 0;
+const ENCODING_INT16: u8 = 1;
+const ENCODING_INT32: u8 = 2;
+const ENCODING_LZF: u8 = 3;
+
+fn corrupted(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// CRC-64/Jones as used by redis for the RDB checksum (reflected, poly
+/// `0xad93d23594c935a9`, no final xor).
+pub fn crc64(mut crc: u64, data: &[u8]) -> u64 {
+    static TABLE: OnceLock<[u64; 256]> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let mut table = [0u64; 256];
+        for (i, slot) in table.iter_mut().enumerate() {
+            let mut value = i as u64;
+            for _ in 0..8 {
+                value = if value & 1 == 1 {
+                    (value >> 1) ^ 0x95ac_9329_ac4b_c9b5
+                } else {
+                    value >> 1
+                };
+            }
+            *slot = value;
+        }
+        table
+    });
+
+    for byte in data {
+        crc = table[((crc ^ *byte as u64) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+/// Bytes taken by the length stored backwards after a listpack element of
+/// `len` bytes.
+fn listpack_backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
+
+/// Listpack as redis stores stream entries: a header with the total size and
+/// the number of elements, then every element followed by its own size
+/// written backwards, so the list can be walked from both ends.
+struct Listpack {
+    buf: Vec<u8>,
+    count: usize,
+}
+
+impl Listpack {
+    fn new() -> Listpack {
+        // the header is filled in by `finish`
+        Listpack {
+            buf: vec![0; 6],
+            count: 0,
+        }
+    }
+
+    fn push_element(&mut self, element: &[u8]) {
+        self.buf.extend_from_slice(element);
+        // the first byte read backwards (the last one) has the lowest bits,
+        // all but the last one read have the high bit set
+        let size = listpack_backlen_size(element.len());
+        for i in (0..size).rev() {
+            let byte = ((element.len() >> (7 * i)) & 127) as u8;
+            self.buf.push(if i == size - 1 { byte } else { byte | 128 });
+        }
+        self.count += 1;
+    }
+
+    fn push_int(&mut self, n: i64) {
+        let mut element = Vec::new();
+        match n {
+            0..=127 => element.push(n as u8),
+            -4096..=4095 => {
+                let n = n as u16 & 0x1FFF;
+                element.extend([0xC0 | (n >> 8) as u8, n as u8]);
+            }
+            _ if i16::try_from(n).is_ok() => {
+                element.push(0xF1);
+                element.extend_from_slice(&(n as i16).to_le_bytes());
+            }
+            -8_388_608..=8_388_607 => {
+                element.push(0xF2);
+                element.extend_from_slice(&(n as i32).to_le_bytes()[..3]);
+            }
+            _ if i32::try_from(n).is_ok() => {
+                element.push(0xF3);
+                element.extend_from_slice(&(n as i32).to_le_bytes());
+            }
+            _ => {
+                element.push(0xF4);
+                element.extend_from_slice(&n.to_le_bytes());
+            }
+        }
+        self.push_element(&element);
+    }
+
+    /// Strings which are plain integers get the integer encoding like redis
+    /// does.
+    fn push_string(&mut self, string: &[u8]) {
+        let integer = std::str::from_utf8(string)
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok().filter(|n| n.to_string() == s));
+        if let Some(n) = integer {
+            return self.push_int(n);
+        }
+        let len = string.len();
+        let mut element = match len {
+            0..=63 => vec![0x80 | len as u8],
+            64..=4095 => vec![0xE0 | (len >> 8) as u8, len as u8],
+            _ => {
+                let mut header = vec![0xF0];
+                header.extend_from_slice(&(len as u32).to_le_bytes());
+                header
+            }
+        };
+        element.extend_from_slice(string);
+        self.push_element(&element);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.buf.push(0xFF);
+        let total = self.buf.len() as u32;
+        self.buf[..4].copy_from_slice(&total.to_le_bytes());
+        // 65535 means too many elements to count in the header
+        let count = self.count.min(u16::MAX as usize) as u16;
+        self.buf[4..6].copy_from_slice(&count.to_le_bytes());
+        self.buf
+    }
+}
+
+/// Elements of a listpack, integers are turned back into their text.
+fn read_listpack(bytes: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let invalid = || corrupted("Invalid listpack in RDB file");
+    let slice = |start: usize, len: usize| {
+        let end = start.checked_add(len).ok_or_else(invalid)?;
+        bytes.get(start..end).ok_or_else(invalid)
+    };
+    let int = |n: i64| n.to_string().into_bytes();
+    let mut elements = Vec::new();
+    let mut pos = 6;
+    loop {
+        let first = *bytes.get(pos).ok_or_else(invalid)?;
+        let (element, len) = match first {
+            0xFF => break,
+            0x00..=0x7F => (int(first as i64), 1),
+            0x80..=0xBF => {
+                let len = (first & 0x3F) as usize;
+                (slice(pos + 1, len)?.to_vec(), 1 + len)
+            }
+            0xC0..=0xDF => {
+                let n = (((first & 0x1F) as u16) << 8) | slice(pos + 1, 1)?[0] as u16;
+                // sign extend the 13 bits
+                (int(((n << 3) as i16 >> 3) as i64), 2)
+            }
+            0xE0..=0xEF => {
+                let len = (((first & 0x0F) as usize) << 8) | slice(pos + 1, 1)?[0] as usize;
+                (slice(pos + 2, len)?.to_vec(), 2 + len)
+            }
+            0xF0 => {
+                let len = u32::from_le_bytes(slice(pos + 1, 4)?.try_into().unwrap()) as usize;
+                (slice(pos + 5, len)?.to_vec(), 5 + len)
+            }
+            0xF1 => {
+                let n = i16::from_le_bytes(slice(pos + 1, 2)?.try_into().unwrap());
+                (int(n as i64), 3)
+            }
+            0xF2 => {
+                let bytes = slice(pos + 1, 3)?;
+                let n = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8;
+                (int(n as i64), 4)
+            }
+            0xF3 => {
+                let n = i32::from_le_bytes(slice(pos + 1, 4)?.try_into().unwrap());
+                (int(n as i64), 5)
+            }
+            0xF4 => {
+                let n = i64::from_le_bytes(slice(pos + 1, 8)?.try_into().unwrap());
+                (int(n), 9)
+            }
+            _ => return Err(invalid()),
+        };
+        elements.push(element);
+        pos += len + listpack_backlen_size(len);
+    }
+    Ok(elements)
+}
+
+/// Elements of a ziplist, what redis used before listpacks: a header with
+/// the total size, the offset of the last element and their number, then
+/// every element after the size of the previous one.
+fn read_ziplist(bytes: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let invalid = || corrupted("Invalid ziplist in RDB file");
+    let slice = |start: usize, len: usize| {
+        let end = start.checked_add(len).ok_or_else(invalid)?;
+        bytes.get(start..end).ok_or_else(invalid)
+    };
+    let int = |n: i64| n.to_string().into_bytes();
+    let mut elements = Vec::new();
+    let mut pos = 10;
+    loop {
+        let prevlen = *bytes.get(pos).ok_or_else(invalid)?;
+        if prevlen == 0xFF {
+            break;
+        }
+        pos += if prevlen == 0xFE { 5 } else { 1 };
+        let first = *bytes.get(pos).ok_or_else(invalid)?;
+        let (element, len) = match first {
+            0x00..=0x3F => {
+                let len = (first & 0x3F) as usize;
+                (slice(pos + 1, len)?.to_vec(), 1 + len)
+            }
+            0x40..=0x7F => {
+                let len = (((first & 0x3F) as usize) << 8) | slice(pos + 1, 1)?[0] as usize;
+                (slice(pos + 2, len)?.to_vec(), 2 + len)
+            }
+            0x80..=0xBF => {
+                let len = u32::from_be_bytes(slice(pos + 1, 4)?.try_into().unwrap()) as usize;
+                (slice(pos + 5, len)?.to_vec(), 5 + len)
+            }
+            0xC0 => {
+                let n = i16::from_le_bytes(slice(pos + 1, 2)?.try_into().unwrap());
+                (int(n as i64), 3)
+            }
+            0xD0 => {
+                let n = i32::from_le_bytes(slice(pos + 1, 4)?.try_into().unwrap());
+                (int(n as i64), 5)
+            }
+            0xE0 => {
+                let n = i64::from_le_bytes(slice(pos + 1, 8)?.try_into().unwrap());
+                (int(n), 9)
+            }
+            0xF0 => {
+                let bytes = slice(pos + 1, 3)?;
+                let n = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8;
+                (int(n as i64), 4)
+            }
+            0xFE => (int(slice(pos + 1, 1)?[0] as i8 as i64), 2),
+            // 0 to 12 stored in the encoding byte itself, plus one
+            0xF1..=0xFD => (int((first & 0x0F) as i64 - 1), 1),
+            _ => return Err(invalid()),
+        };
+        elements.push(element);
+        pos += len;
+    }
+    Ok(elements)
+}
+
+/// Members of an intset, the encoding of small sets of integers: the width
+/// of the integers, their number, then the sorted integers.
+fn read_intset(bytes: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let invalid = || corrupted("Invalid intset in RDB file");
+    let header = bytes.get(..8).ok_or_else(invalid)?;
+    let width = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+    if !matches!(width, 2 | 4 | 8) || bytes.len() - 8 != width * len {
+        return Err(invalid());
+    }
+    let members = bytes[8..].chunks(width).map(|chunk| {
+        let n = match width {
+            2 => i16::from_le_bytes(chunk.try_into().unwrap()) as i64,
+            4 => i32::from_le_bytes(chunk.try_into().unwrap()) as i64,
+            _ => i64::from_le_bytes(chunk.try_into().unwrap()),
+        };
+        n.to_string().into_bytes()
+    });
+    Ok(members.collect())
+}
+
+/// Field value or member score pairs out of the elements of a ziplist or a
+/// listpack.
+fn pairs(elements: Vec<Vec<u8>>) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    if !elements.len().is_multiple_of(2) {
+        return Err(corrupted("Odd number of elements in RDB pairs"));
+    }
+    let mut elements = elements.into_iter();
+    Ok(std::iter::from_fn(|| Some((elements.next()?, elements.next()?))).collect())
+}
+
+fn sorted_set(pairs: Vec<(Vec<u8>, Vec<u8>)>) -> io::Result<SortedSet> {
+    let mut zset = SortedSet::new();
+    for (member, score) in pairs {
+        let score = std::str::from_utf8(&score)
+            .ok()
+            .and_then(parse_score)
+            .ok_or_else(|| corrupted("Invalid sorted set score in RDB file"))?;
+        zset.insert(&member, score);
+    }
+    Ok(zset)
+}
+
+/// Serialises values using the RDB primitives.
+struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    fn write_length(&mut self, len: u64) {
+        if len < 1 << 6 {
+            self.buf.push(len as u8);
+        } else if len < 1 << 14 {
+            self.buf.push(0x40 | (len >> 8) as u8);
+            self.buf.push(len as u8);
+        } else if len <= u32::MAX as u64 {
+            self.buf.push(0x80);
+            self.buf.extend_from_slice(&(len as u32).to_be_bytes());
+        } else {
+            self.buf.push(0x81);
+            self.buf.extend_from_slice(&len.to_be_bytes());
+        }
+    }
+
+    /// Strings which are plain integers are stored in their compact integer
+    /// encoding just like redis does.
+    fn write_string(&mut self, string: &[u8]) {
+        let integer = std::str::from_utf8(string)
+            .ok()
+            .and_then(|s| s.parse::<i32>().ok().filter(|n| n.to_string() == s));
+        match integer {
+            Some(n) if i8::try_from(n).is_ok() => {
+                self.buf.push(0xC0 | ENCODING_INT8);
+                self.buf.push(n as i8 as u8);
+            }
+            Some(n) if i16::try_from(n).is_ok() => {
+                self.buf.push(0xC0 | ENCODING_INT16);
+                self.buf.extend_from_slice(&(n as i16).to_le_bytes());
+            }
+            Some(n) => {
+                self.buf.push(0xC0 | ENCODING_INT32);
+                self.buf.extend_from_slice(&n.to_le_bytes());
+            }
+            None => {
+                self.write_length(string.len() as u64);
+                self.buf.extend_from_slice(string);
+            }
+        }
+    }
+
+    /// Type byte of the value followed by its encoding.
+    fn write_value(&mut self, key: &[u8], value: &RedisValue) {
+        match value {
+            RedisValue::String(string) => {
+                self.buf.push(TYPE_STRING);
+                self.write_string(key);
+                self.write_string(string);
+            }
+            RedisValue::List(list) => {
+                self.buf.push(TYPE_LIST);
+                self.write_string(key);
+                self.write_length(list.len() as u64);
+                for element in list {
+                    self.write_string(element);
+                }
+            }
+            RedisValue::Hash(hash) => {
+                self.buf.push(TYPE_HASH);
+                self.write_string(key);
+                self.write_length(hash.len() as u64);
+                for (field, value) in hash {
+                    self.write_string(field);
+                    self.write_string(value);
+                }
+            }
+            RedisValue::Set(set) => {
+                self.buf.push(TYPE_SET);
+                self.write_string(key);
+                self.write_length(set.len() as u64);
+                for member in set {
+                    self.write_string(member);
+                }
+            }
+            RedisValue::SortedSet(zset) => {
+                self.buf.push(TYPE_ZSET_2);
+                self.write_string(key);
+                self.write_length(zset.len() as u64);
+                for (member, score) in zset.iter() {
+                    self.write_string(&member);
+                    self.buf.extend_from_slice(&score.to_le_bytes());
+                }
+            }
+            RedisValue::Stream(stream) => {
+                self.buf.push(TYPE_STREAM_LISTPACKS);
+                self.write_string(key);
+                self.write_stream(stream);
+            }
+        }
+    }
+
+    /// The entries go into listpacks of up to `STREAM_NODE_MAX_ENTRIES`,
+    /// keyed by the id of their first entry which the ids in it are relative
+    /// to. The consumer groups follow with their pending entries.
+    fn write_stream(&mut self, stream: &Stream) {
+        let entries = stream.range(StreamId::MIN, StreamId::MAX, None, false);
+        let nodes = entries.chunks(STREAM_NODE_MAX_ENTRIES);
+        self.write_length(nodes.len() as u64);
+        for node in nodes {
+            let (master, master_fields) = node[0];
+            let mut listpack = Listpack::new();
+            listpack.push_int(node.len() as i64);
+            listpack.push_int(0);
+            listpack.push_int(master_fields.len() as i64);
+            for (field, _) in master_fields {
+                listpack.push_string(field);
+            }
+            listpack.push_int(0);
+            for (id, fields) in node {
+                listpack.push_int(0);
+                listpack.push_int(id.ms.wrapping_sub(master.ms) as i64);
+                listpack.push_int(id.seq.wrapping_sub(master.seq) as i64);
+                listpack.push_int(fields.len() as i64);
+                for (field, value) in fields.iter() {
+                    listpack.push_string(field);
+                    listpack.push_string(value);
+                }
+                // elements of the entry, to walk it backwards
+                listpack.push_int(2 * fields.len() as i64 + 4);
+            }
+            self.write_string(&master.to_bytes());
+            self.write_string(&listpack.finish());
+        }
+
+        self.write_length(stream.len() as u64);
+        self.write_length(stream.last_id.ms);
+        self.write_length(stream.last_id.seq);
+        self.write_length(stream.groups.len() as u64);
+        for (name, group) in &stream.groups {
+            self.write_string(name.as_bytes());
+            self.write_length(group.last_delivered.ms);
+            self.write_length(group.last_delivered.seq);
+            self.write_length(group.pending.len() as u64);
+            for (id, entry) in &group.pending {
+                self.buf.extend_from_slice(&id.to_bytes());
+                self.buf
+                    .extend_from_slice(&entry.delivered_at.to_le_bytes());
+                self.write_length(entry.delivery_count);
+            }
+            self.write_length(group.consumers.len() as u64);
+            for (name, consumer) in &group.consumers {
+                self.write_string(name.as_bytes());
+                self.buf.extend_from_slice(&consumer.seen_at.to_le_bytes());
+                let pending: Vec<&StreamId> = group
+                    .pending
+                    .iter()
+                    .filter(|(_, entry)| entry.consumer == *name)
+                    .map(|(id, _)| id)
+                    .collect();
+                self.write_length(pending.len() as u64);
+                for id in pending {
+                    self.buf.extend_from_slice(&id.to_bytes());
+                }
+            }
+        }
+    }
+
+    fn write_aux(&mut self, key: &str, value: &str) {
+        self.buf.push(OPCODE_AUX);
+        self.write_string(key.as_bytes());
+        self.write_string(value.as_bytes());
+    }
+}
+
+/// Reads RDB primitives out of a complete dump.
+struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    /// The next `len` bytes, lengths come from the file so they may point
+    /// anywhere.
+    fn read_bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let end = self.pos.checked_add(len);
+        let bytes = end
+            .and_then(|end| self.buf.get(self.pos..end))
+            .ok_or_else(|| corrupted("Unexpected end of RDB file"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> io::Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    /// Returns the decoded length and whether it is actually a special string
+    /// encoding (in which case the length holds the encoding type).
+    fn read_length_with_encoding(&mut self) -> io::Result<(u64, bool)> {
+        let first = self.read_u8()?;
+        match first >> 6 {
+            0 => Ok(((first & 0x3F) as u64, false)),
+            1 => {
+                let second = self.read_u8()?;
+                Ok(((((first & 0x3F) as u64) << 8) | second as u64, false))
+            }
+            2 => match first {
+                0x80 => {
+                    let bytes = self.read_bytes(4)?;
+                    Ok((u32::from_be_bytes(bytes.try_into().unwrap()) as u64, false))
+                }
+                0x81 => {
+                    let bytes = self.read_bytes(8)?;
+                    Ok((u64::from_be_bytes(bytes.try_into().unwrap()), false))
+                }
+                _ => Err(corrupted("Unknown length encoding in RDB file")),
+            },
+            _ => Ok(((first & 0x3F) as u64, true)),
+        }
+    }
+
+    fn read_length(&mut self) -> io::Result<u64> {
+        match self.read_length_with_encoding()? {
+            (len, false) => Ok(len),
+            (_, true) => Err(corrupted("Unexpected string encoding in RDB file")),
+        }
+    }
+
+    fn read_string(&mut self) -> io::Result<Vec<u8>> {
+        let (len, encoded) = self.read_length_with_encoding()?;
+        if !encoded {
+            return Ok(self.read_bytes(len as usize)?.to_vec());
+        }
+        match len as u8 {
+            ENCODING_INT8 => Ok((self.read_u8()? as i8).to_string().into_bytes()),
+            ENCODING_INT16 => {
+                let bytes = self.read_bytes(2)?;
+                Ok(i16::from_le_bytes(bytes.try_into().unwrap())
+                    .to_string()
+                    .into_bytes())
+            }
+            ENCODING_INT32 => {
+                let bytes = self.read_bytes(4)?;
+                Ok(i32::from_le_bytes(bytes.try_into().unwrap())
+                    .to_string()
+                    .into_bytes())
+            }
+            ENCODING_LZF => {
+                let compressed_len = self.read_length()? as usize;
+                let len = self.read_length()? as usize;
+                lzf_decompress(self.read_bytes(compressed_len)?, len)
+            }
+            _ => Err(corrupted("Unknown string encoding in RDB file")),
+        }
+    }
+
+    /// Score of a `TYPE_ZSET` member: its text after a one byte length,
+    /// the lengths 253 to 255 stand for nan, inf and -inf.
+    fn read_text_score(&mut self) -> io::Result<Vec<u8>> {
+        match self.read_u8()? {
+            253 => Ok(b"nan".to_vec()),
+            254 => Ok(b"inf".to_vec()),
+            255 => Ok(b"-inf".to_vec()),
+            len => Ok(self.read_bytes(len as usize)?.to_vec()),
+        }
+    }
+
+    fn read_utf8(&mut self) -> io::Result<String> {
+        Ok(String::from_utf8_lossy(&self.read_string()?).to_string())
+    }
+
+    /// Value encoded with the type byte `value_type`.
+    fn read_value(&mut self, value_type: u8) -> io::Result<RedisValue> {
+        match value_type {
+            TYPE_STRING => Ok(RedisValue::String(self.read_string()?)),
+            TYPE_LIST => {
+                let len = self.read_length()?;
+                let list = (0..len)
+                    .map(|_| self.read_string())
+                    .collect::<io::Result<_>>()?;
+                Ok(RedisValue::List(list))
+            }
+            TYPE_HASH => {
+                let len = self.read_length()?;
+                let hash = (0..len)
+                    .map(|_| Ok((self.read_string()?, self.read_string()?)))
+                    .collect::<io::Result<_>>()?;
+                Ok(RedisValue::Hash(hash))
+            }
+            TYPE_SET => {
+                let len = self.read_length()?;
+                let set = (0..len)
+                    .map(|_| self.read_string())
+                    .collect::<io::Result<_>>()?;
+                Ok(RedisValue::Set(set))
+            }
+            TYPE_ZSET_2 => {
+                let len = self.read_length()?;
+                let mut zset = SortedSet::new();
+                for _ in 0..len {
+                    let member = self.read_string()?;
+                    let score = self.read_bytes(8)?;
+                    let score = f64::from_le_bytes(score.try_into().unwrap());
+                    if score.is_nan() {
+                        return Err(corrupted("Invalid sorted set score in RDB file"));
+                    }
+                    zset.insert(&member, score);
+                }
+                Ok(RedisValue::SortedSet(zset))
+            }
+            TYPE_ZSET => {
+                let len = self.read_length()?;
+                let pairs = (0..len)
+                    .map(|_| Ok((self.read_string()?, self.read_text_score()?)))
+                    .collect::<io::Result<_>>()?;
+                Ok(RedisValue::SortedSet(sorted_set(pairs)?))
+            }
+            TYPE_LIST_ZIPLIST => Ok(RedisValue::List(read_ziplist(&self.read_string()?)?.into())),
+            TYPE_LIST_QUICKLIST | TYPE_LIST_QUICKLIST_2 => {
+                let mut list = VecDeque::new();
+                for _ in 0..self.read_length()? {
+                    if value_type == TYPE_LIST_QUICKLIST {
+                        list.extend(read_ziplist(&self.read_string()?)?);
+                    } else if self.read_length()? == QUICKLIST_NODE_PLAIN {
+                        list.push_back(self.read_string()?);
+                    } else {
+                        list.extend(read_listpack(&self.read_string()?)?);
+                    }
+                }
+                Ok(RedisValue::List(list))
+            }
+            TYPE_SET_INTSET => {
+                let members = read_intset(&self.read_string()?)?;
+                Ok(RedisValue::Set(members.into_iter().collect()))
+            }
+            TYPE_SET_LISTPACK => {
+                let members = read_listpack(&self.read_string()?)?;
+                Ok(RedisValue::Set(members.into_iter().collect()))
+            }
+            TYPE_HASH_ZIPLIST => Ok(RedisValue::Hash(
+                pairs(read_ziplist(&self.read_string()?)?)?
+                    .into_iter()
+                    .collect(),
+            )),
+            TYPE_HASH_LISTPACK => Ok(RedisValue::Hash(
+                pairs(read_listpack(&self.read_string()?)?)?
+                    .into_iter()
+                    .collect(),
+            )),
+            TYPE_ZSET_ZIPLIST => Ok(RedisValue::SortedSet(sorted_set(pairs(read_ziplist(
+                &self.read_string()?,
+            )?)?)?)),
+            TYPE_ZSET_LISTPACK => Ok(RedisValue::SortedSet(sorted_set(pairs(read_listpack(
+                &self.read_string()?,
+            )?)?)?)),
+            TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+                Ok(RedisValue::Stream(self.read_stream(value_type)?))
+            }
+            _ => Err(corrupted(&format!(
+                "Unsupported value type {} in RDB file",
+                value_type
+            ))),
+        }
+    }
+}
+
+impl Decoder<'_> {
+    fn read_stream_id(&mut self) -> io::Result<StreamId> {
+        Ok(StreamId::from_bytes(
+            self.read_bytes(16)?.try_into().unwrap(),
+        ))
+    }
+
+    fn read_millis(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+
+    /// Stream written by `Encoder::write_stream`, or by redis itself which
+    /// also leaves deleted entries in the listpacks and omits the fields of
+    /// entries having the same ones as the first entry of their listpack.
+    /// The ids and counters the later `value_type`s add are skipped.
+    fn read_stream(&mut self, value_type: u8) -> io::Result<Stream> {
+        let invalid = || corrupted("Invalid stream in RDB file");
+        let mut stream = Stream::new();
+        for _ in 0..self.read_length()? {
+            let master = self.read_string()?;
+            let master = StreamId::from_bytes(master.as_slice().try_into().map_err(|_| invalid())?);
+            let listpack = read_listpack(&self.read_string()?)?;
+            let mut elements = listpack.into_iter();
+            let mut next = || elements.next().ok_or_else(invalid);
+            let int = |element: Vec<u8>| -> io::Result<i64> {
+                String::from_utf8_lossy(&element)
+                    .parse()
+                    .map_err(|_| invalid())
+            };
+
+            let count = int(next()?)? + int(next()?)?;
+            let master_fields = (0..int(next()?)?)
+                .map(|_| next())
+                .collect::<io::Result<Vec<_>>>()?;
+            next()?;
+            for _ in 0..count {
+                let flags = int(next()?)?;
+                let ms = master.ms.wrapping_add(int(next()?)? as u64);
+                let seq = master.seq.wrapping_add(int(next()?)? as u64);
+                let fields = match flags & STREAM_ITEM_FLAG_SAMEFIELDS {
+                    0 => (0..int(next()?)?)
+                        .map(|_| Ok((next()?, next()?)))
+                        .collect::<io::Result<Vec<_>>>()?,
+                    _ => master_fields
+                        .iter()
+                        .map(|field| Ok((field.clone(), next()?)))
+                        .collect::<io::Result<Vec<_>>>()?,
+                };
+                next()?;
+                if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+                    stream.add(StreamId::new(ms, seq), fields);
+                }
+            }
+        }
+
+        self.read_length()?;
+        stream.last_id = StreamId::new(self.read_length()?, self.read_length()?);
+        if value_type >= TYPE_STREAM_LISTPACKS_2 {
+            // first id, max deleted id and number of entries ever added
+            for _ in 0..5 {
+                self.read_length()?;
+            }
+        }
+        for _ in 0..self.read_length()? {
+            let name = self.read_utf8()?;
+            let last_delivered = StreamId::new(self.read_length()?, self.read_length()?);
+            if value_type >= TYPE_STREAM_LISTPACKS_2 {
+                // entries read by the group
+                self.read_length()?;
+            }
+            let mut group = ConsumerGroup::new(last_delivered);
+            for _ in 0..self.read_length()? {
+                let id = self.read_stream_id()?;
+                let entry = PendingEntry {
+                    consumer: String::new(),
+                    delivered_at: self.read_millis()?,
+                    delivery_count: self.read_length()?,
+                };
+                group.pending.insert(id, entry);
+            }
+            for _ in 0..self.read_length()? {
+                let consumer = self.read_utf8()?;
+                let seen_at = self.read_millis()?;
+                if value_type == TYPE_STREAM_LISTPACKS_3 {
+                    // last time the consumer read an entry
+                    self.read_millis()?;
+                }
+                group.create_consumer(&consumer, seen_at);
+                for _ in 0..self.read_length()? {
+                    let id = self.read_stream_id()?;
+                    let entry = group.pending.get_mut(&id).ok_or_else(invalid)?;
+                    entry.consumer = consumer.clone();
+                }
+            }
+            stream.groups.insert(name, group);
+        }
+        Ok(stream)
+    }
+}
+
+/// Decompress an LZF block, redis compresses long strings with it when
+/// `rdbcompression` is enabled so dumps coming from a real server need this.
+fn lzf_decompress(input: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    let invalid = || corrupted("Invalid LZF compressed string");
+    // `len` comes from the file, the output can't be more than 88 times the
+    // input: a 3 bytes back reference copies at most 264 bytes
+    let mut out: Vec<u8> = Vec::with_capacity(len.min(input.len().saturating_mul(88)));
+    let mut i = 0;
+
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+        if ctrl < 32 {
+            // literal run of ctrl + 1 bytes
+            let run = input.get(i..i + ctrl + 1).ok_or_else(invalid)?;
+            out.extend_from_slice(run);
+            i += ctrl + 1;
+        } else {
+            // back reference into the already decompressed output
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += *input.get(i).ok_or_else(invalid)? as usize;
+                i += 1;
+            }
+            let offset = ((ctrl & 0x1F) << 8) + *input.get(i).ok_or_else(invalid)? as usize + 1;
+            i += 1;
+            let start = out.len().checked_sub(offset).ok_or_else(invalid)?;
+            for k in 0..run + 2 {
+                out.push(out[start + k]);
+            }
+        }
+    }
+
+    if out.len() != len {
+        return Err(invalid());
+    }
+    Ok(out)
+}
+
+/// Serialise the whole dictionary into an RDB file image.
+pub fn dump(map: &DictionaryServer) -> Vec<u8> {
+    dump_for_replica(map, None)
+}
+
+/// Serialise the dictionary for the full resync of a replica. When the
+/// replication stream which follows goes on in `stream_db` without a
+/// `SELECT`, like redis the file tells the replica in `repl-stream-db`.
+pub fn dump_for_replica(map: &DictionaryServer, stream_db: Option<usize>) -> Vec<u8> {
+    let mut encoder = Encoder { buf: Vec::new() };
+    encoder
+        .buf
+        .extend_from_slice(format!("REDIS{:04}", RDB_VERSION).as_bytes());
+    encoder.write_aux("redis-ver", env!("CARGO_PKG_VERSION"));
+    encoder.write_aux("redis-bits", &(usize::BITS).to_string());
+    encoder.write_aux("ctime", &(now_ms() / 1000).to_string());
+    if let Some(db) = stream_db {
+        encoder.write_aux("repl-stream-db", &db.to_string());
+    }
+
+    let now = now_ms();
+    for db in 0..map.databases() {
+        let live: Vec<_> = map
+            .entries(db)
+            .iter()
+            .filter(|(_, entry)| entry.expires_at.is_none_or(|when| when > now))
+            .collect();
+        if live.is_empty() {
+            continue;
+        }
+        let volatile = live
+            .iter()
+            .filter(|(_, entry)| entry.expires_at.is_some())
+            .count();
+        encoder.buf.push(OPCODE_SELECTDB);
+        encoder.write_length(db as u64);
+        encoder.buf.push(OPCODE_RESIZEDB);
+        encoder.write_length(live.len() as u64);
+        encoder.write_length(volatile as u64);
+
+        for (key, entry) in live {
+            if let Some(when) = entry.expires_at {
+                encoder.buf.push(OPCODE_EXPIRETIME_MS);
+                encoder.buf.extend_from_slice(&when.to_le_bytes());
+            }
+            encoder.write_value(key, &entry.value);
+        }
+    }
+
+    encoder.buf.push(OPCODE_EOF);
+    let checksum = crc64(0, &encoder.buf);
+    encoder.buf.extend_from_slice(&checksum.to_le_bytes());
+    encoder.buf
+}
+
+/// Rebuild a dictionary from an RDB file image. Keys which already expired
+/// are dropped, the file may use databases `0..databases`.
+pub fn restore(bytes: &[u8], databases: usize) -> io::Result<DictionaryServer> {
+    restore_for_replica(bytes, databases).map(|(map, _)| map)
+}
+
+/// Rebuild a dictionary from the snapshot of a full resync, along with the
+/// database the replication stream goes on in if the file tells.
+pub fn restore_for_replica(
+    bytes: &[u8],
+    databases: usize,
+) -> io::Result<(DictionaryServer, Option<usize>)> {
+    if bytes.len() < 9 || &bytes[..5] != b"REDIS" {
+        return Err(corrupted("Wrong signature trying to load DB from file"));
+    }
+    let version = std::str::from_utf8(&bytes[5..9])
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .ok_or_else(|| corrupted("Invalid RDB version"))?;
+    if version == 0 || version > 11 {
+        return Err(corrupted(&format!(
+            "Can't handle RDB format version {}",
+            version
+        )));
+    }
+
+    let mut map = DictionaryServer::new();
+    let mut decoder = Decoder { buf: bytes, pos: 9 };
+    let mut expires_at: Option<u64> = None;
+    let mut stream_db = None;
+    let now = now_ms();
+
+    loop {
+        let opcode = decoder.read_u8()?;
+        match opcode {
+            OPCODE_EOF => break,
+            OPCODE_AUX => {
+                let key = decoder.read_string()?;
+                let value = decoder.read_utf8()?;
+                if key == b"repl-stream-db" {
+                    stream_db = value.parse::<usize>().ok().filter(|db| *db < databases);
+                }
+            }
+            OPCODE_SELECTDB => match decoder.read_length()? {
+                db if db < databases as u64 => map.select(db as usize),
+                _ => {
+                    return Err(corrupted(&format!(
+                        "Data file was created with a server configured to handle more than {} databases",
+                        databases
+                    )))
+                }
+            },
+            OPCODE_RESIZEDB => {
+                decoder.read_length()?;
+                decoder.read_length()?;
+            }
+            OPCODE_EXPIRETIME_MS => {
+                let bytes = decoder.read_bytes(8)?;
+                expires_at = Some(u64::from_le_bytes(bytes.try_into().unwrap()));
+            }
+            OPCODE_EXPIRETIME => {
+                let bytes = decoder.read_bytes(4)?;
+                expires_at = Some(u32::from_le_bytes(bytes.try_into().unwrap()) as u64 * 1000);
+            }
+            OPCODE_IDLE => {
+                decoder.read_length()?;
+            }
+            OPCODE_FREQ => {
+                decoder.read_u8()?;
+            }
+            value_type => {
+                let key = decoder.read_string()?;
+                let value = decoder.read_value(value_type)?;
+                let expired = expires_at.is_some_and(|when| when <= now);
+                if !expired {
+                    map.insert(&key, value, expires_at);
+                }
+                expires_at = None;
+            }
+        }
+    }
+
+    // files since version 5 end with a checksum, zero means it was disabled
+    if version >= 5 {
+        let end = decoder.pos;
+        let bytes = decoder.read_bytes(8)?;
+        let expected = u64::from_le_bytes(bytes.try_into().unwrap());
+        if expected != 0 && expected != crc64(0, &decoder.buf[..end]) {
+            return Err(corrupted("Wrong RDB checksum"));
+        }
+    }
+
+    map.select(0);
+    map.dirty = 0;
+    Ok((map, stream_db))
+}
+
+/// Write a snapshot of `map` to `path`. The dump goes to a temporary file
+/// first and is renamed over the old one, so a crash never leaves a half
+/// written snapshot behind.
+pub fn save(map: &DictionaryServer, path: &Path) -> io::Result<()> {
+    let bytes = dump(map);
+    let temp = path.with_file_name(format!("temp-{}.rdb", std::process::id()));
+    let mut file = fs::File::create(&temp)?;
+    file.write_all(&bytes)?;
+    file.sync_all()?;
+    fs::rename(&temp, path)
+}
+
+/// Load the snapshot at `path`, `None` if there is no such file yet.
+pub fn load(path: &Path, databases: usize) -> io::Result<Option<DictionaryServer>> {
+    match fs::read(path) {
+        Ok(bytes) => restore(&bytes, databases).map(Some),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::run;
+
+    #[test]
+    fn test_crc64_check_value() {
+        assert_eq!(crc64(0, b"123456789"), 0xe9c6d914c4b8d9ca);
+    }
+
+    #[test]
+    fn test_dump_and_restore() {
+        let mut map = DictionaryServer::new();
+        let long = "x".repeat(20000);
+        map.set(b"name", b"redis", None);
+        map.set(b"counter", b"-12345", None);
+        map.set(b"big", b"4294967296", None);
+        map.set(b"long", long.as_bytes(), None);
+        map.set(b"blob", b"\x00\xff\r\n", None);
+        map.set(b"session", b"abc", Some(now_ms() + 60_000));
+        map.set(b"gone", b"abc", Some(now_ms() - 1));
+        let list = RedisValue::List(
+            ["a", "1", "c"]
+                .iter()
+                .map(|s| s.as_bytes().to_vec())
+                .collect(),
+        );
+        map.insert(b"list", list.clone(), None);
+        let hash = RedisValue::Hash(
+            [("name", "redis"), ("port", "6379")]
+                .iter()
+                .map(|(field, value)| (field.as_bytes().to_vec(), value.as_bytes().to_vec()))
+                .collect(),
+        );
+        map.insert(b"hash", hash, None);
+        let set = RedisValue::Set(["a", "b"].iter().map(|s| s.as_bytes().to_vec()).collect());
+        map.insert(b"set", set, None);
+        let mut zset = SortedSet::new();
+        zset.insert(b"one", 1.0);
+        zset.insert(b"half", 0.5);
+        zset.insert(b"low", f64::NEG_INFINITY);
+        map.insert(b"zset", RedisValue::SortedSet(zset), None);
+        // enough entries for more than one listpack, values of every encoding
+        run(
+            &mut map,
+            &["XGROUP", "CREATE", "stream", "g", "$", "MKSTREAM"],
+        );
+        for i in 0..250i64 {
+            let value = match i % 5 {
+                0 => (i * 1000).to_string(),
+                1 => (-i * 100_000).to_string(),
+                2 => (i << 40).to_string(),
+                3 => "y".repeat(i as usize * 20),
+                _ => format!("v{}", i),
+            };
+            let id = format!("{}-{}", 1000 + i / 3, i % 3);
+            run(
+                &mut map,
+                &["XADD", "stream", &id, "field", &value, "n", "1"],
+            );
+        }
+        run(
+            &mut map,
+            &[
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                "a",
+                "COUNT",
+                "3",
+                "STREAMS",
+                "stream",
+                ">",
+            ],
+        );
+        run(
+            &mut map,
+            &[
+                "XCLAIM",
+                "stream",
+                "g",
+                "b",
+                "0",
+                "1000-1",
+                "RETRYCOUNT",
+                "7",
+            ],
+        );
+        run(
+            &mut map,
+            &["XGROUP", "CREATECONSUMER", "stream", "g", "idle"],
+        );
+        run(&mut map, &["XSETID", "stream", "99999-0"]);
+
+        let restored = restore(&dump(&map), 16).unwrap();
+        assert_eq!(restored.server.len(), 11);
+        assert_eq!(restored.dirty, 0);
+        let keys = [
+            "name", "counter", "big", "long", "blob", "session", "list", "hash", "set", "zset",
+            "stream",
+        ];
+        for key in keys {
+            let key = key.as_bytes();
+            assert_eq!(restored.server[key].value, map.server[key].value);
+            assert_eq!(restored.server[key].expires_at, map.server[key].expires_at);
+        }
+    }
+
+    #[test]
+    fn test_restore_rejects_corrupted_file() {
+        let mut map = DictionaryServer::new();
+        map.set(b"name", b"redis", None);
+        let mut bytes = dump(&map);
+        let len = bytes.len();
+        bytes[len - 12] ^= 0xFF;
+        assert!(restore(&bytes, 16).is_err());
+        assert!(restore(b"NOTREDIS", 16).is_err());
+    }
+
+    #[test]
+    fn test_restore_compact_encodings() {
+        let strings = |elements: &[&str]| -> Vec<Vec<u8>> {
+            elements.iter().map(|e| e.as_bytes().to_vec()).collect()
+        };
+        let listpack = |elements: &[&str]| {
+            let mut listpack = Listpack::new();
+            for element in elements {
+                listpack.push_string(element.as_bytes());
+            }
+            listpack.finish()
+        };
+        // "x", 2 and -300 after the header nobody reads back
+        let mut ziplist = vec![0; 10];
+        ziplist.extend_from_slice(&[0x00, 0x01, b'x', 0x03, 0xF3, 0x02, 0xC0]);
+        ziplist.extend_from_slice(&(-300i16).to_le_bytes());
+        ziplist.push(0xFF);
+        let mut intset = Vec::new();
+        for n in [2u32, 3] {
+            intset.extend_from_slice(&n.to_le_bytes());
+        }
+        for n in [-1i16, 1, 256] {
+            intset.extend_from_slice(&n.to_le_bytes());
+        }
+
+        let mut encoder = Encoder {
+            buf: b"REDIS0011".to_vec(),
+        };
+        let mut object = |value_type: u8, key: &str, parts: &[&[u8]]| {
+            encoder.buf.push(value_type);
+            encoder.write_string(key.as_bytes());
+            for part in parts {
+                encoder.write_string(part);
+            }
+        };
+        object(TYPE_LIST_ZIPLIST, "ziplist", &[&ziplist]);
+        // the same without -300, x => 2
+        let ziphash = [&ziplist[..ziplist.len() - 5], &[0xFF]].concat();
+        object(TYPE_HASH_ZIPLIST, "ziphash", &[&ziphash]);
+        object(TYPE_SET_INTSET, "intset", &[&intset]);
+        object(TYPE_SET_LISTPACK, "set", &[&listpack(&["a", "7"])]);
+        object(TYPE_HASH_LISTPACK, "hash", &[&listpack(&["port", "6379"])]);
+        object(
+            TYPE_ZSET_LISTPACK,
+            "zset",
+            &[&listpack(&["one", "1", "half", "0.5"])],
+        );
+        encoder.buf.push(TYPE_LIST_QUICKLIST_2);
+        encoder.write_string(b"quicklist");
+        encoder.write_length(2);
+        encoder.write_length(2);
+        encoder.write_string(&listpack(&["a", "b"]));
+        encoder.write_length(QUICKLIST_NODE_PLAIN);
+        encoder.write_string(b"plain");
+        encoder.buf.push(TYPE_ZSET);
+        encoder.write_string(b"oldzset");
+        encoder.write_length(2);
+        encoder.write_string(b"m");
+        encoder.buf.extend_from_slice(b"\x031.5");
+        encoder.write_string(b"top");
+        encoder.buf.push(254);
+        encoder.buf.push(OPCODE_EOF);
+        encoder.buf.extend_from_slice(&[0; 8]);
+
+        let mut map = restore(&encoder.buf, 16).unwrap();
+        let mut value = |key: &str| map.lookup(key.as_bytes()).unwrap().value.as_ref().clone();
+        assert_eq!(
+            value("ziplist"),
+            RedisValue::List(strings(&["x", "2", "-300"]).into())
+        );
+        assert_eq!(
+            value("ziphash"),
+            RedisValue::Hash([(b"x".to_vec(), b"2".to_vec())].into_iter().collect())
+        );
+        assert_eq!(
+            value("intset"),
+            RedisValue::Set(strings(&["-1", "1", "256"]).into_iter().collect())
+        );
+        assert_eq!(
+            value("set"),
+            RedisValue::Set(strings(&["a", "7"]).into_iter().collect())
+        );
+        assert_eq!(
+            value("hash"),
+            RedisValue::Hash([(b"port".to_vec(), b"6379".to_vec())].into_iter().collect())
+        );
+        let mut zset = SortedSet::new();
+        zset.insert(b"one", 1.0);
+        zset.insert(b"half", 0.5);
+        assert_eq!(value("zset"), RedisValue::SortedSet(zset));
+        assert_eq!(
+            value("quicklist"),
+            RedisValue::List(strings(&["a", "b", "plain"]).into())
+        );
+        let mut zset = SortedSet::new();
+        zset.insert(b"m", 1.5);
+        zset.insert(b"top", f64::INFINITY);
+        assert_eq!(value("oldzset"), RedisValue::SortedSet(zset));
+    }
+
+    #[test]
+    fn test_restore_newer_streams() {
+        let mut map = DictionaryServer::new();
+        let mut stream = Stream::new();
+        stream.add(StreamId::new(1, 1), vec![(b"f".to_vec(), b"v".to_vec())]);
+        map.insert(b"s", RedisValue::Stream(stream), None);
+        let bytes = dump(&map);
+
+        // redis 7 adds the first id, the max deleted id and the number of
+        // entries added before the groups, there are none here
+        let mut encoder = Encoder { buf: Vec::new() };
+        encoder.write_value(b"s", &map.lookup(b"s").unwrap().value);
+        let old = encoder.buf;
+        let start = bytes.windows(old.len()).position(|w| w == old).unwrap();
+        let mut new = old.clone();
+        new[0] = TYPE_STREAM_LISTPACKS_3;
+        new.splice(old.len() - 1..old.len() - 1, [1, 1, 0, 0, 1]);
+        let mut bytes = bytes[..start].to_vec();
+        bytes.extend_from_slice(&new);
+        bytes.push(OPCODE_EOF);
+        bytes.extend_from_slice(&[0; 8]);
+
+        let mut restored = restore(&bytes, 16).unwrap();
+        match restored.lookup(b"s").unwrap().value.as_ref() {
+            RedisValue::Stream(stream) => {
+                assert_eq!(stream.len(), 1);
+                assert_eq!(stream.last_id, StreamId::new(1, 1));
+            }
+            _ => panic!("not a stream"),
+        }
+    }
+
+    #[test]
+    fn test_restore_rejects_truncated_file() {
+        let mut map = DictionaryServer::new();
+        map.set(b"name", b"redis", None);
+        map.insert(
+            b"list",
+            RedisValue::List(["a", "b"].iter().map(|e| e.as_bytes().to_vec()).collect()),
+            None,
+        );
+        let bytes = dump(&map);
+        for len in 9..bytes.len() {
+            assert!(restore(&bytes[..len], 16).is_err());
+        }
+    }
+
+    #[test]
+    fn test_restore_rejects_huge_lengths() {
+        // a string key whose 64 bits length runs past the end of the file
+        let mut bytes = b"REDIS0011".to_vec();
+        bytes.extend_from_slice(&[TYPE_STRING, 0x81]);
+        bytes.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert!(restore(&bytes, 16).is_err());
+
+        // an LZF string claiming to decompress into 2^62 bytes
+        let mut bytes = b"REDIS0011".to_vec();
+        bytes.extend_from_slice(&[TYPE_STRING, 0x01, b'k', 0xC0 | ENCODING_LZF, 0x02, 0x81]);
+        bytes.extend_from_slice(&(1u64 << 62).to_be_bytes());
+        bytes.extend_from_slice(&[0x00, b'a']);
+        assert!(restore(&bytes, 16).is_err());
+    }
+
+    #[test]
+    fn test_dump_and_restore_databases() {
+        let mut map = DictionaryServer::new();
+        map.set(b"a", b"0", None);
+        map.select(3);
+        map.set(b"a", b"3", Some(now_ms() + 60_000));
+        map.select(5);
+        let bytes = dump(&map);
+
+        let mut restored = restore(&bytes, 16).unwrap();
+        assert_eq!(restored.selected(), 0);
+        assert_eq!(restored.get(b"a"), Ok(Some(b"0".to_vec())));
+        restored.select(3);
+        assert_eq!(restored.get(b"a"), Ok(Some(b"3".to_vec())));
+        assert_eq!(restored.volatile_keys(3), 1);
+        // the snapshot needs as many databases as it has
+        assert!(restore(&bytes, 3).is_err());
+    }
+
+    #[test]
+    fn test_dump_and_restore_binary_keys() {
+        let mut map = DictionaryServer::new();
+        let key = b"k\xff\x00";
+        map.set(key, b"v", None);
+        let mut restored = restore(&dump(&map), 16).unwrap();
+        assert_eq!(restored.get(key), Ok(Some(b"v".to_vec())));
+    }
+
+    #[test]
+    fn test_lzf_decompress() {
+        // literal "a" followed by a back reference repeating it 9 more times
+        let compressed = [0x00, b'a', 0xE0, 0x00, 0x00];
+        assert_eq!(lzf_decompress(&compressed, 10).unwrap(), b"aaaaaaaaaa");
+        assert!(lzf_decompress(&compressed, 11).is_err());
+    }
+}
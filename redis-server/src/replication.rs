@@ -0,0 +1,552 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::aof;
+use crate::client::Client;
+use crate::commands::{arg, execute_command, is_write_command, serve_blocked};
+use crate::dictionary_server::now_ms;
+use crate::digest::{sha256, to_hex};
+use crate::parser::{ParseError, Parser, Value};
+use crate::rdb;
+use crate::server::{lock, Server};
+
+/// A master sends its replicas a `PING` this often, so they can tell a quiet
+/// master from a broken link.
+const PING_PERIOD_MS: u64 = 10_000;
+/// A link to the master without any traffic for this long is broken.
+const LINK_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often a replica acknowledges the offset it processed.
+const ACK_PERIOD: Duration = Duration::from_secs(1);
+/// Wait before connecting to the master again once the link broke.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A replica attached to this server. The replication stream is queued in
+/// the outbox of its connection like any reply.
+pub struct Replica {
+    pub outbox: Sender<Vec<u8>>,
+    /// where the replica listens for its own clients, as `ROLE` reports it
+    pub ip: String,
+    pub port: u16,
+    /// offset the replica acknowledged with `REPLCONF ACK`
+    pub ack_offset: u64,
+}
+
+/// Progress of a replica's link to its master.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinkState {
+    /// waiting to connect (again)
+    Connect,
+    /// connected, going through the handshake
+    Connecting,
+    /// waiting for `PSYNC` and the snapshot
+    Sync,
+    /// receiving the stream of write commands
+    Connected,
+}
+
+impl LinkState {
+    /// Name reported by `ROLE`.
+    pub fn name(self) -> &'static str {
+        match self {
+            LinkState::Connect => "connect",
+            LinkState::Connecting => "connecting",
+            LinkState::Sync => "sync",
+            LinkState::Connected => "connected",
+        }
+    }
+}
+
+/// The master this server replicates.
+pub struct MasterLink {
+    pub host: String,
+    pub port: u16,
+    pub state: LinkState,
+    /// socket to the master, shut down to stop the link right away
+    stream: Option<TcpStream>,
+}
+
+/// Replication state of the server. The stream of write commands is
+/// identified by the replication id and the offset of its last byte, the
+/// end of it is kept in the backlog so a replica which lost its link only
+/// for a moment can continue where it stopped (partial resync) instead of
+/// transferring the whole dataset again.
+pub struct Replication {
+    /// id of the history of the dataset, replicas take the one of their
+    /// master
+    pub replid: String,
+    /// previous id, after a replica is promoted the other replicas of its old
+    /// master can still continue up to `second_replid_offset`
+    pub replid2: String,
+    pub second_replid_offset: Option<u64>,
+    /// bytes of the stream so far
+    pub offset: u64,
+    backlog: VecDeque<u8>,
+    backlog_size: usize,
+    /// replicas by client id
+    pub replicas: BTreeMap<u64, Replica>,
+    /// `None` while the server is a master
+    pub master: Option<MasterLink>,
+    /// changed whenever the master changes, a link thread which was replaced
+    /// notices it by this
+    epoch: u64,
+    /// unix time (ms) of the last `PING` sent to the replicas
+    last_ping: u64,
+    /// database the write commands of the stream go to, `None` until a
+    /// `SELECT` is in it. A replica keeps the one of its master's stream so
+    /// a partial resync goes on in the right database.
+    pub selected_db: Option<usize>,
+}
+
+/// A new random replication id, 40 hex characters like the ones of redis.
+fn new_replid() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let seed = format!(
+        "{}:{}:{:?}:{}",
+        std::process::id(),
+        now_ms(),
+        Instant::now(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    to_hex(&sha256(seed.as_bytes()))[..40].to_string()
+}
+
+impl Replication {
+    pub fn new(backlog_size: usize) -> Replication {
+        Replication {
+            replid: new_replid(),
+            replid2: "0".repeat(40),
+            second_replid_offset: None,
+            offset: 0,
+            backlog: VecDeque::new(),
+            backlog_size,
+            replicas: BTreeMap::new(),
+            master: None,
+            epoch: 0,
+            last_ping: now_ms(),
+            selected_db: None,
+        }
+    }
+
+    pub fn set_backlog_size(&mut self, size: usize) {
+        self.backlog_size = size;
+        let excess = self.backlog.len().saturating_sub(size);
+        self.backlog.drain(..excess);
+    }
+
+    /// Append `bytes` to the stream: they're kept in the backlog and sent to
+    /// every replica. Replicas which disconnected are dropped.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.offset += bytes.len() as u64;
+        self.backlog.extend(bytes);
+        let excess = self.backlog.len().saturating_sub(self.backlog_size);
+        self.backlog.drain(..excess);
+        self.replicas
+            .retain(|_, replica| replica.outbox.send(bytes.to_vec()).is_ok());
+    }
+
+    /// Append a write command to the stream, preceded by a `SELECT` when it
+    /// goes to another database than the previous one.
+    pub fn feed_command(&mut self, db: usize, entry: &[u8]) {
+        if self.selected_db != Some(db) {
+            self.feed(&aof::encode_command(&["SELECT", &db.to_string()]));
+            self.selected_db = Some(db);
+        }
+        self.feed(entry);
+    }
+
+    /// The stream from `offset` on, if a replica which followed the history
+    /// `replid` can continue there.
+    pub fn backlog_from(&self, replid: &str, offset: u64) -> Option<Vec<u8>> {
+        let known = replid == self.replid
+            || (replid == self.replid2
+                && self.second_replid_offset.is_some_and(|last| offset <= last));
+        let first = self.offset + 1 - self.backlog.len() as u64;
+        if !known || offset < first || offset > self.offset + 1 {
+            return None;
+        }
+        Some(
+            self.backlog
+                .iter()
+                .skip((offset - first) as usize)
+                .copied()
+                .collect(),
+        )
+    }
+
+    /// Start a new history when a replica becomes a master. The old id is
+    /// kept so replicas of the old master can switch over without a full
+    /// resync.
+    fn shift_replid(&mut self, replid: String) {
+        self.replid2 = std::mem::replace(&mut self.replid, replid);
+        self.second_replid_offset = Some(self.offset + 1);
+    }
+
+    /// After a full resync the history is the one of the master, its stream
+    /// goes on in `selected_db`.
+    fn reset(&mut self, replid: String, offset: u64, selected_db: Option<usize>) {
+        self.replid = replid;
+        self.replid2 = "0".repeat(40);
+        self.second_replid_offset = None;
+        self.offset = offset;
+        self.backlog.clear();
+        self.selected_db = selected_db;
+    }
+
+    /// Keep the links of the replicas alive, a replica proxies the pings of
+    /// its own master instead.
+    pub fn ping_replicas(&mut self) {
+        let now = now_ms();
+        if self.master.is_none()
+            && !self.replicas.is_empty()
+            && now.saturating_sub(self.last_ping) >= PING_PERIOD_MS
+        {
+            self.last_ping = now;
+            self.feed(&aof::encode_command(&["PING"]));
+        }
+    }
+
+    /// Close the link to the current master, its thread stops once it sees
+    /// the epoch changed.
+    fn drop_master(&mut self) {
+        if let Some(stream) = self.master.take().and_then(|link| link.stream) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        self.epoch += 1;
+    }
+}
+
+/// `REPLICAOF host port`: replicate `host:port` from now on. The link is
+/// served by its own thread which reconnects until the master changes.
+pub fn follow(server: &Arc<Server>, host: &str, port: u16) -> io::Result<()> {
+    let mut replication = lock(&server.replication);
+    replication.drop_master();
+    replication.master = Some(MasterLink {
+        host: host.to_string(),
+        port,
+        state: LinkState::Connect,
+        stream: None,
+    });
+    let epoch = replication.epoch;
+    let s = server.clone();
+    let host = host.to_string();
+    let spawned = thread::Builder::new()
+        .name("redis-replication".to_string())
+        .spawn(move || run_link(s, host, port, epoch));
+    if let Err(e) = spawned {
+        replication.drop_master();
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// `REPLICAOF NO ONE`: stop replicating and accept writes again, the dataset
+/// is kept.
+pub fn promote(server: &Arc<Server>) {
+    let mut replication = lock(&server.replication);
+    if replication.master.is_some() {
+        replication.drop_master();
+        replication.shift_replid(new_replid());
+    }
+}
+
+/// Update the link to the master unless the link thread of `epoch` was
+/// replaced, in which case `false` is returned.
+fn update_link(server: &Server, epoch: u64, update: impl FnOnce(&mut MasterLink)) -> bool {
+    let mut replication = lock(&server.replication);
+    if replication.epoch != epoch {
+        return false;
+    }
+    match replication.master.as_mut() {
+        Some(link) => {
+            update(link);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Keep synchronizing with the master until it changes.
+fn run_link(server: Arc<Server>, host: String, port: u16, epoch: u64) {
+    while update_link(&server, epoch, |link| link.state = LinkState::Connect) {
+        match sync_with_master(&server, &host, port, epoch) {
+            Err(e) if update_link(&server, epoch, |_| {}) => {
+                eprintln!("Lost connection with master {}:{}: {}", host, port, e)
+            }
+            _ => {}
+        }
+        thread::sleep(RECONNECT_DELAY);
+    }
+}
+
+/// Socket to the master along with what was read but not processed yet.
+struct MasterConnection {
+    stream: TcpStream,
+    pending: Vec<u8>,
+    last_read: Instant,
+}
+
+impl MasterConnection {
+    /// Read more of the stream, `false` when nothing arrived for a while.
+    fn fill(&mut self) -> io::Result<bool> {
+        let mut chunk = [0u8; 16 * 1024];
+        match self.stream.read(&mut chunk) {
+            Ok(0) => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed by master",
+            )),
+            Ok(n) => {
+                self.pending.extend_from_slice(&chunk[..n]);
+                self.last_read = Instant::now();
+                Ok(true)
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                if self.last_read.elapsed() >= LINK_TIMEOUT {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "timeout"));
+                }
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        loop {
+            if let Some(end) = self.pending.windows(2).position(|w| w == b"\r\n") {
+                let line: Vec<u8> = self.pending.drain(..end + 2).take(end).collect();
+                return Ok(String::from_utf8_lossy(&line).to_string());
+            }
+            self.fill()?;
+        }
+    }
+
+    /// The snapshot of a full resync, sent as `$<length>\r\n` followed by the
+    /// RDB file without a trailing newline.
+    fn read_snapshot(&mut self) -> io::Result<Vec<u8>> {
+        let line = self.read_line()?;
+        let len = line
+            .strip_prefix('$')
+            .and_then(|len| len.parse::<usize>().ok())
+            .ok_or_else(|| io::Error::other(format!("bad snapshot header '{}'", line)))?;
+        while self.pending.len() < len {
+            self.fill()?;
+        }
+        Ok(self.pending.drain(..len).collect())
+    }
+
+    /// Send a handshake command and return the reply line, error replies fail
+    /// the handshake.
+    fn command(&mut self, args: &[&str]) -> io::Result<String> {
+        self.stream.write_all(&aof::encode_command(args))?;
+        let reply = self.read_line()?;
+        if reply.starts_with('-') {
+            return Err(io::Error::other(format!(
+                "master replied to {}: {}",
+                args[0], reply
+            )));
+        }
+        Ok(reply)
+    }
+
+    fn ack(&mut self, offset: u64) -> io::Result<()> {
+        self.stream.write_all(&aof::encode_command(&[
+            "REPLCONF",
+            "ACK",
+            &offset.to_string(),
+        ]))
+    }
+}
+
+/// Connect to the master, go through the handshake and then apply what it
+/// streams until the link breaks. `PSYNC` asks to continue our own history,
+/// the master either streams what we missed or replies with a full resync.
+fn sync_with_master(server: &Arc<Server>, host: &str, port: u16, epoch: u64) -> io::Result<()> {
+    update_link(server, epoch, |link| link.state = LinkState::Connecting);
+    let addr = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::other("can't resolve the master address"))?;
+    let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(ACK_PERIOD))?;
+    let handle = stream.try_clone()?;
+    if !update_link(server, epoch, |link| link.stream = Some(handle)) {
+        return Ok(());
+    }
+    let mut master = MasterConnection {
+        stream,
+        pending: Vec::new(),
+        last_read: Instant::now(),
+    };
+
+    let (masterauth, listening_port) = {
+        let config = server.config();
+        (config.masterauth.clone(), config.port)
+    };
+    // without AUTH the master may only be able to say it needs it
+    let pong = master.command(&["PING"]);
+    if masterauth.is_empty() {
+        pong?;
+    } else {
+        master.command(&["AUTH", &masterauth])?;
+    }
+    master.command(&["REPLCONF", "listening-port", &listening_port.to_string()])?;
+    master.command(&["REPLCONF", "capa", "psync2"])?;
+
+    update_link(server, epoch, |link| link.state = LinkState::Sync);
+    let (replid, offset) = {
+        let replication = lock(&server.replication);
+        (replication.replid.clone(), replication.offset + 1)
+    };
+    let reply = master.command(&["PSYNC", &replid, &offset.to_string()])?;
+    let words: Vec<&str> = reply.split_whitespace().collect();
+    match words.as_slice() {
+        ["+FULLRESYNC", replid, offset] => {
+            let offset = offset
+                .parse()
+                .map_err(|_| io::Error::other("invalid FULLRESYNC offset"))?;
+            let snapshot = master.read_snapshot()?;
+            load_snapshot(server, epoch, &snapshot, replid.to_string(), offset)?;
+        }
+        ["+CONTINUE", rest @ ..] => {
+            // the master may have been promoted meanwhile, its history goes on
+            // under a new id
+            if let Some(new_replid) = rest.first().filter(|id| **id != replid) {
+                lock(&server.replication).shift_replid(new_replid.to_string());
+            }
+        }
+        _ => {
+            return Err(io::Error::other(format!(
+                "unexpected PSYNC reply '{}'",
+                reply
+            )))
+        }
+    }
+
+    if update_link(server, epoch, |link| link.state = LinkState::Connected) {
+        stream_commands(server, &mut master, epoch)?;
+    }
+    Ok(())
+}
+
+/// Replace the dataset with the snapshot of a full resync.
+fn load_snapshot(
+    server: &Arc<Server>,
+    epoch: u64,
+    snapshot: &[u8],
+    replid: String,
+    offset: u64,
+) -> io::Result<()> {
+    let (restored, stream_db) = rdb::restore_for_replica(snapshot, server.config().databases)?;
+    let mut map = server.lock();
+    if !update_link(server, epoch, |_| {}) {
+        return Err(io::Error::other("master changed"));
+    }
+    map.replace(restored);
+    lock(&server.replication).reset(replid, offset, stream_db);
+    if server.config().appendonly {
+        server.start_aof(&map)?;
+    }
+    Ok(())
+}
+
+/// Apply the write commands of the master. They go to our append only file
+/// and to our own replicas exactly as they were received, so the offsets
+/// stay the ones of the master.
+fn stream_commands(
+    server: &Arc<Server>,
+    master: &mut MasterConnection,
+    epoch: u64,
+) -> io::Result<()> {
+    let mut client = Client::new(server.next_client_id());
+    client.is_master = true;
+    client.db = lock(&server.replication).selected_db.unwrap_or(0);
+    let mut last_ack = Instant::now();
+    loop {
+        loop {
+            let mut parser = Parser::new(&master.pending);
+            let value: Value = match parser.parse() {
+                Ok(value) => value,
+                Err(ParseError::Incomplete) => break,
+                Err(ParseError::Protocol(msg)) => return Err(io::Error::other(msg)),
+            };
+            let frame: Vec<u8> = master.pending.drain(..parser.position()).collect();
+
+            let mut map = server.lock();
+            if !update_link(server, epoch, |_| {}) {
+                return Ok(());
+            }
+            let name = arg(&value.array, 0).to_uppercase();
+            if name == "REPLCONF" && arg(&value.array, 1).eq_ignore_ascii_case("GETACK") {
+                master.ack(lock(&server.replication).offset)?;
+            } else if !value.array.is_empty() {
+                execute_command(&value, server, &mut client, &mut map);
+                serve_blocked(server, &mut map);
+                if is_write_command(&value) || name == "MULTI" || name == "EXEC" {
+                    server.feed_aof(client.db, &frame);
+                }
+            }
+            let mut replication = lock(&server.replication);
+            replication.feed(&frame);
+            replication.selected_db = Some(client.db);
+        }
+
+        master.fill()?;
+        if last_ack.elapsed() >= ACK_PERIOD {
+            master.ack(lock(&server.replication).offset)?;
+            last_ack = Instant::now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_backlog() {
+        let mut replication = Replication::new(10);
+        let replid = replication.replid.clone();
+        replication.feed(b"0123456");
+        assert_eq!(replication.offset, 7);
+        assert_eq!(replication.backlog_from(&replid, 1).unwrap(), b"0123456");
+        assert_eq!(replication.backlog_from(&replid, 5).unwrap(), b"456");
+        assert_eq!(replication.backlog_from(&replid, 8).unwrap(), b"");
+        assert!(replication.backlog_from(&replid, 9).is_none());
+        assert!(replication.backlog_from("other", 5).is_none());
+
+        // only the end of the stream is kept
+        replication.feed(b"789abc");
+        assert_eq!(replication.backlog_from(&replid, 4).unwrap(), b"3456789abc");
+        assert!(replication.backlog_from(&replid, 3).is_none());
+        replication.set_backlog_size(2);
+        assert_eq!(replication.backlog_from(&replid, 12).unwrap(), b"bc");
+    }
+
+    #[test]
+    fn test_promoted_replica_keeps_old_history() {
+        let mut replication = Replication::new(100);
+        let old = replication.replid.clone();
+        replication.feed(b"SET");
+        replication.shift_replid(new_replid());
+        assert_ne!(replication.replid, old);
+        assert_eq!(replication.second_replid_offset, Some(4));
+        replication.feed(b"DEL");
+
+        // replicas of the old master continue up to where it stopped
+        assert_eq!(replication.backlog_from(&old, 4).unwrap(), b"DEL");
+        assert!(replication.backlog_from(&old, 5).is_none());
+        let replid = replication.replid.clone();
+        assert_eq!(replication.backlog_from(&replid, 5).unwrap(), b"EL");
+    }
+}
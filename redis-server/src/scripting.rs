@@ -0,0 +1,539 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use mlua::{
+    Function, HookTriggers, Lua, LuaOptions, RegistryKey, StdLib, Table, Value as LuaValue,
+    Variadic,
+};
+
+use crate::digest::{sha1, to_hex};
+use crate::parser::{Value, ValueType};
+use crate::server::lock;
+
+pub const NOSCRIPT: &str = "NOSCRIPT No matching script. Please use EVAL.";
+pub const BUSY: &str =
+    "BUSY Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN NOSAVE.";
+const KILLED: &str = "Script killed by user with SCRIPT KILL...";
+/// Lua instructions between two checks whether a script ran for too long.
+const HOOK_INSTRUCTIONS: u32 = 100_000;
+
+/// Defined once per interpreter. `redis.pcall` is set for every run since it
+/// calls into the dictionary the script runs against, `redis.call` raises
+/// the `{err = message}` tables it returns for error replies. The globals
+/// are moved to a table of their own and `_G` is left empty and read only,
+/// so scripts can't leave anything behind for the next ones to find.
+const PRELUDE: &str = r#"
+redis = {}
+function redis.call(...)
+    local reply = redis.pcall(...)
+    if type(reply) == "table" and reply.err then
+        error(reply)
+    end
+    return reply
+end
+function redis.status_reply(status)
+    return {ok = status}
+end
+function redis.error_reply(message)
+    return {err = message}
+end
+dofile = nil
+loadfile = nil
+-- it would change the globals of the scripts which run next
+setfenv = nil
+local G, error, tostring, rawset, setmetatable = _G, error, tostring, rawset, setmetatable
+function _G.rawset(t, ...)
+    if t == G then
+        error("Attempt to modify a readonly table", 2)
+    end
+    return rawset(t, ...)
+end
+
+local globals = {}
+for name, value in pairs(G) do
+    globals[name] = value
+end
+for name in pairs(globals) do
+    G[name] = nil
+end
+setmetatable(G, {
+    __index = function(_, name)
+        local value = globals[name]
+        if value == nil then
+            error("Script attempted to access nonexistent global variable '" .. tostring(name) .. "'", 2)
+        end
+        return value
+    end,
+    __newindex = function()
+        error("Attempt to modify a readonly table", 2)
+    end,
+    __metatable = false,
+})
+return globals
+"#;
+
+/// The script holding the dictionary lock, as the other clients see it.
+/// Once it ran longer than `lua-time-limit` it is busy, they get a `BUSY`
+/// error instead of waiting and may kill it unless it wrote already.
+#[derive(Default)]
+pub struct RunningScript {
+    running: AtomicBool,
+    busy: AtomicBool,
+    written: AtomicBool,
+    /// held while the script runs a command, so it isn't killed half way
+    /// through a write
+    killed: Mutex<bool>,
+}
+
+impl RunningScript {
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.busy.load(Ordering::Relaxed)
+    }
+
+    /// The script changed the dataset, it can't be killed anymore.
+    pub fn wrote(&self) {
+        self.written.store(true, Ordering::Relaxed);
+    }
+
+    /// `SCRIPT KILL`, stopping a script which wrote would break its
+    /// atomicity, `SHUTDOWN NOSAVE` is the only way out then.
+    pub fn kill(&self) -> Value {
+        if !self.is_busy() {
+            return Value::error("NOTBUSY No scripts in execution right now.");
+        }
+        let mut killed = lock(&self.killed);
+        if self.written.load(Ordering::Relaxed) {
+            return Value::error("UNKILLABLE Sorry the script already executed write commands against the dataset. You can either wait the script termination or kill the server in a hard way using the SHUTDOWN NOSAVE command.");
+        }
+        *killed = true;
+        Value::ok()
+    }
+
+    fn start(&self) {
+        self.busy.store(false, Ordering::Relaxed);
+        self.written.store(false, Ordering::Relaxed);
+        *lock(&self.killed) = false;
+        self.running.store(true, Ordering::Relaxed);
+    }
+
+    fn stop(&self) {
+        self.running.store(false, Ordering::Relaxed);
+        self.busy.store(false, Ordering::Relaxed);
+    }
+}
+
+/// The Lua interpreter running `EVAL` and `EVALSHA`, scripts are compiled
+/// once and cached by the SHA1 of their body.
+pub struct Scripts {
+    lua: Lua,
+    functions: HashMap<String, RegistryKey>,
+}
+
+/// A sandboxed interpreter: no `io`, `os` or loading files, like redis.
+fn interpreter() -> mlua::Result<Lua> {
+    let lua = Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+        LuaOptions::new(),
+    )?;
+    prepare(&lua)?;
+    Ok(lua)
+}
+
+/// Define the `redis` library of the scripts.
+fn prepare(lua: &Lua) -> mlua::Result<()> {
+    let globals: Table = lua.load(PRELUDE).eval()?;
+    let redis: Table = globals.get("redis")?;
+    let sha1hex =
+        lua.create_function(|_, body: mlua::String| Ok(to_hex(&sha1(body.as_bytes()))))?;
+    redis.set("sha1hex", sha1hex)?;
+    // scripts may replace the global one
+    let pcall: Function = globals.get("pcall")?;
+    lua.set_named_registry_value("pcall", pcall)?;
+    lua.set_named_registry_value("globals", globals)
+}
+
+/// Message of a Lua error without the prefix mlua adds to it.
+fn error_message(error: &mlua::Error) -> String {
+    let message = match error {
+        mlua::Error::SyntaxError { message, .. } | mlua::Error::RuntimeError(message) => {
+            message.clone()
+        }
+        error => error.to_string(),
+    };
+    message.replace(['\r', '\n'], " ")
+}
+
+/// A reply of a command as the script sees it, the same conversion as redis
+/// does for RESP2: status and error replies become `{ok = ...}` and
+/// `{err = ...}` tables, nulls `false`.
+fn to_lua<'lua>(lua: &'lua Lua, reply: &Value) -> mlua::Result<LuaValue<'lua>> {
+    let bytes = reply.value.as_deref().unwrap_or_default();
+    let value = match reply.value_type {
+        _ if reply.null => LuaValue::Boolean(false),
+        ValueType::Null => LuaValue::Boolean(false),
+        ValueType::SimpleString | ValueType::Error => {
+            let table = lua.create_table()?;
+            let field = match reply.value_type {
+                ValueType::Error => "err",
+                _ => "ok",
+            };
+            table.set(field, lua.create_string(bytes)?)?;
+            LuaValue::Table(table)
+        }
+        ValueType::Integer => LuaValue::Integer(
+            std::str::from_utf8(bytes)
+                .ok()
+                .and_then(|number| number.parse().ok())
+                .unwrap_or_default(),
+        ),
+        ValueType::Boolean => LuaValue::Integer((bytes == b"t") as i64),
+        ValueType::Array | ValueType::Set | ValueType::Push | ValueType::Map => {
+            let values = reply
+                .array
+                .iter()
+                .map(|value| to_lua(lua, value))
+                .collect::<mlua::Result<Vec<_>>>()?;
+            LuaValue::Table(lua.create_sequence_from(values)?)
+        }
+        ValueType::Attribute => LuaValue::Nil,
+        _ => LuaValue::String(lua.create_string(bytes)?),
+    };
+    Ok(value)
+}
+
+/// What a script returned as the reply of `EVAL`. Numbers are truncated to
+/// integers and arrays end at their first `nil`, like redis.
+fn from_lua(value: LuaValue) -> Value {
+    match value {
+        LuaValue::Boolean(true) => Value::integer(1),
+        LuaValue::Integer(number) => Value::integer(number),
+        LuaValue::Number(number) => Value::integer(number as i64),
+        LuaValue::String(string) => Value::bulk_string(string.as_bytes()),
+        LuaValue::Table(table) => {
+            if let Ok(LuaValue::String(message)) = table.raw_get("err") {
+                return Value::error(&message.to_string_lossy());
+            }
+            if let Ok(LuaValue::String(status)) = table.raw_get("ok") {
+                return Value::simple_string(&status.to_string_lossy());
+            }
+            Value::array(
+                table
+                    .sequence_values::<LuaValue>()
+                    .map_while(Result::ok)
+                    .map(from_lua)
+                    .collect(),
+            )
+        }
+        _ => Value::null(),
+    }
+}
+
+/// Reply of a script which raised an error, error replies raised by
+/// `redis.call` are passed on as they were.
+fn script_error(sha: &str, error: LuaValue) -> Value {
+    let message = match error {
+        LuaValue::Table(table) => match table.raw_get("err") {
+            Ok(LuaValue::String(message)) => return Value::error(&message.to_string_lossy()),
+            _ => "unknown error".to_string(),
+        },
+        LuaValue::String(message) => message.to_string_lossy().replace(['\r', '\n'], " "),
+        LuaValue::Error(error) => error_message(&error),
+        _ => "unknown error".to_string(),
+    };
+    Value::error(&format!(
+        "ERR Error running script (call to f_{}): {}",
+        sha, message
+    ))
+}
+
+impl Scripts {
+    pub fn new() -> Scripts {
+        Scripts {
+            lua: interpreter().expect("the Lua interpreter starts"),
+            functions: HashMap::new(),
+        }
+    }
+
+    /// Compile `body` unless it is cached already, returns its SHA1.
+    pub fn load(&mut self, body: &[u8]) -> Result<String, Value> {
+        let sha = to_hex(&sha1(body));
+        if self.functions.contains_key(&sha) {
+            return Ok(sha);
+        }
+        let compiled = self
+            .lua
+            .load(body)
+            .set_name("@user_script")
+            .into_function()
+            .and_then(|function| self.lua.create_registry_value(function));
+        match compiled {
+            Ok(function) => {
+                self.functions.insert(sha.clone(), function);
+                Ok(sha)
+            }
+            Err(e) => Err(Value::error(&format!(
+                "ERR Error compiling script (new function): {}",
+                error_message(&e)
+            ))),
+        }
+    }
+
+    pub fn exists(&self, sha: &str) -> bool {
+        self.functions.contains_key(sha)
+    }
+
+    /// `SCRIPT FLUSH` forgets every script, including the globals they left
+    /// behind in the interpreter.
+    pub fn flush(&mut self) {
+        *self = Scripts::new();
+    }
+
+    /// Run the cached script `sha` with `KEYS` and `ARGV` set, the commands
+    /// it calls go through `dispatch`. Every client waits for the script, so
+    /// once it ran longer than `time_limit` `running` tells them it is busy
+    /// and lets them kill it.
+    pub fn run(
+        &self,
+        sha: &str,
+        keys: &[Vec<u8>],
+        args: &[Vec<u8>],
+        time_limit: Option<Duration>,
+        running: &Arc<RunningScript>,
+        mut dispatch: impl FnMut(Vec<Vec<u8>>) -> Value,
+    ) -> Value {
+        let function = match self.functions.get(sha) {
+            Some(function) => function,
+            None => return Value::error(NOSCRIPT),
+        };
+        let lua = &self.lua;
+        running.start();
+        let started = Instant::now();
+        let script = running.clone();
+        let triggers = HookTriggers::new().every_nth_instruction(HOOK_INSTRUCTIONS);
+        lua.set_hook(triggers, move |lua, _| {
+            if !*lock(&script.killed) {
+                if time_limit.is_some_and(|limit| started.elapsed() >= limit) {
+                    script.busy.store(true, Ordering::Relaxed);
+                }
+                return Ok(());
+            }
+            // from now on every instruction raises the error, a script
+            // catching it with `pcall` can't go on
+            lua.set_hook(HookTriggers::new().every_nth_instruction(1), |_, _| {
+                Err(mlua::Error::RuntimeError(KILLED.to_string()))
+            });
+            Err(mlua::Error::RuntimeError(KILLED.to_string()))
+        });
+        let result = lua.scope(|scope| {
+            let pcall = scope.create_function_mut(|lua, args: Variadic<LuaValue>| {
+                let mut command = Vec::new();
+                for arg in args {
+                    match lua.coerce_string(arg)? {
+                        Some(arg) => command.push(arg.as_bytes().to_vec()),
+                        None => {
+                            let error =
+                                "ERR Lua redis lib command arguments must be strings or integers";
+                            return to_lua(lua, &Value::error(error));
+                        }
+                    }
+                }
+                if command.is_empty() {
+                    let error = "ERR Please specify at least one argument for this redis lib call";
+                    return to_lua(lua, &Value::error(error));
+                }
+                let killed = lock(&running.killed);
+                if *killed {
+                    return Err(mlua::Error::RuntimeError(KILLED.to_string()));
+                }
+                let reply = dispatch(command);
+                drop(killed);
+                to_lua(lua, &reply)
+            })?;
+            let globals: Table = lua.named_registry_value("globals")?;
+            globals.get::<_, Table>("redis")?.set("pcall", pcall)?;
+            for (name, values) in [("KEYS", keys), ("ARGV", args)] {
+                let strings = values
+                    .iter()
+                    .map(|value| lua.create_string(value))
+                    .collect::<mlua::Result<Vec<_>>>()?;
+                globals.set(name, lua.create_sequence_from(strings)?)?;
+            }
+
+            let function: Function = lua.registry_value(function)?;
+            let protected: Function = lua.named_registry_value("pcall")?;
+            let (ok, result): (bool, LuaValue) = protected.call(function)?;
+            Ok(match ok {
+                true => from_lua(result),
+                false => script_error(sha, result),
+            })
+        });
+        lua.remove_hook();
+        let killed = *lock(&running.killed);
+        running.stop();
+        if killed {
+            return Value::error(&format!(
+                "ERR Error running script (call to f_{}): {}",
+                sha, KILLED
+            ));
+        }
+        result.unwrap_or_else(|e| script_error(sha, LuaValue::Error(e)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_conversions() {
+        let mut scripts = Scripts::new();
+        let mut run = |body: &str, reply: Value| {
+            let sha = scripts.load(body.as_bytes()).unwrap();
+            let (keys, args) = ([b"k".to_vec()], [b"a".to_vec()]);
+            scripts.run(&sha, &keys, &args, None, &Arc::default(), |command| {
+                assert_eq!(command[0], b"GET");
+                reply.clone()
+            })
+        };
+
+        let reply = run("return {1, 2.9, 'x', true, false, nil, 3}", Value::null());
+        assert_eq!(reply.array.len(), 5);
+        assert_eq!(reply.array[1].value, Some(b"2".to_vec()));
+        assert!(reply.array[4].null);
+        let reply = run(
+            "return redis.call('GET', KEYS[1]) .. ARGV[1]",
+            Value::bulk_string("v"),
+        );
+        assert_eq!(reply.value, Some(b"va".to_vec()));
+        let reply = run("return redis.call('GET', 'x') == false", Value::null());
+        assert_eq!(reply.value, Some(b"1".to_vec()));
+        let reply = run("return redis.pcall('GET', 'x')", Value::error("ERR oops"));
+        assert_eq!(reply.value_type, ValueType::Error);
+        let reply = run("return redis.status_reply('FINE')", Value::null());
+        assert_eq!(reply.value_type, ValueType::SimpleString);
+        let reply = run("return redis.sha1hex('')", Value::null());
+        assert_eq!(
+            reply.value,
+            Some(b"da39a3ee5e6b4b0d3255bfef95601890afd80709".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut scripts = Scripts::new();
+        let error = scripts.load(b"return +").unwrap_err();
+        let message = String::from_utf8(error.value.unwrap()).unwrap();
+        assert!(message.starts_with("ERR Error compiling script (new function): user_script:1:"));
+
+        let sha = scripts.load(b"return redis.call('GET', 'k')").unwrap();
+        let reply = scripts.run(&sha, &[], &[], None, &Arc::default(), |_| {
+            Value::error("WRONGTYPE nope")
+        });
+        assert_eq!(reply.value, Some(b"WRONGTYPE nope".to_vec()));
+
+        let sha = scripts.load(b"error('boom')").unwrap();
+        let reply = scripts.run(&sha, &[], &[], None, &Arc::default(), |_| Value::null());
+        let expected = format!(
+            "ERR Error running script (call to f_{}): user_script:1: boom",
+            sha
+        );
+        assert_eq!(reply.value, Some(expected.into_bytes()));
+
+        let sha = scripts.load(b"return os.time()").unwrap();
+        let reply = scripts.run(&sha, &[], &[], None, &Arc::default(), |_| Value::null());
+        assert_eq!(reply.value_type, ValueType::Error);
+
+        assert!(scripts.exists(&sha));
+        scripts.flush();
+        assert!(!scripts.exists(&sha));
+        let reply = scripts.run(&sha, &[], &[], None, &Arc::default(), |_| Value::null());
+        assert_eq!(reply.value, Some(NOSCRIPT.as_bytes().to_vec()));
+    }
+
+    #[test]
+    fn test_kill() {
+        let mut scripts = Scripts::new();
+        let running = Arc::new(RunningScript::default());
+        let limit = Some(Duration::from_millis(10));
+        let sha = scripts
+            .load(b"while true do pcall(function() while true do end end) end")
+            .unwrap();
+        assert!(running.kill().value.unwrap().starts_with(b"NOTBUSY"));
+        let reply = thread::scope(|scope| {
+            // the interpreter moves to the thread, it can't be shared
+            let (scripts, sha, running) = (&mut scripts, &sha, &running);
+            let script =
+                scope.spawn(move || scripts.run(sha, &[], &[], limit, running, |_| Value::null()));
+            while !running.is_busy() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            assert_eq!(running.kill().value, Some(b"OK".to_vec()));
+            script.join().unwrap()
+        });
+        let expected = format!("ERR Error running script (call to f_{}): {}", sha, KILLED);
+        assert_eq!(reply.value, Some(expected.into_bytes()));
+        assert!(!running.is_running());
+
+        // a script which wrote runs to its end
+        let sha = scripts
+            .load(b"redis.call('SET', 'k', 'v') while not redis.call('GET', 'k') do end return 1")
+            .unwrap();
+        let done = AtomicBool::new(false);
+        let reply = thread::scope(|scope| {
+            let (scripts, sha, running, done) = (&mut scripts, &sha, &running, &done);
+            let script = scope.spawn(move || {
+                scripts.run(sha, &[], &[], limit, running, |command| {
+                    if command[0] == b"SET" {
+                        running.wrote();
+                    }
+                    match done.load(Ordering::Relaxed) {
+                        true => Value::ok(),
+                        false => Value::null(),
+                    }
+                })
+            });
+            while !running.is_busy() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            assert!(running.kill().value.unwrap().starts_with(b"UNKILLABLE"));
+            done.store(true, Ordering::Relaxed);
+            script.join().unwrap()
+        });
+        assert_eq!(reply.value, Some(b"1".to_vec()));
+
+        // the hook doesn't outlive the script
+        let sha = scripts
+            .load(b"local n = 0 for i = 1, 2000000 do n = n + 1 end return n")
+            .unwrap();
+        let reply = scripts.run(&sha, &[], &[], None, &running, |_| Value::null());
+        assert_eq!(reply.value, Some(b"2000000".to_vec()));
+        assert!(!running.is_busy());
+    }
+
+    #[test]
+    fn test_globals_are_protected() {
+        let mut scripts = Scripts::new();
+        let mut run = |body: &str| {
+            let sha = scripts.load(body.as_bytes()).unwrap();
+            let reply = scripts.run(&sha, &[], &[], None, &Arc::default(), |_| Value::null());
+            String::from_utf8(reply.value.unwrap_or_default()).unwrap()
+        };
+
+        assert!(run("x = 1").ends_with("user_script:1: Attempt to modify a readonly table"));
+        assert!(run("return x").ends_with(
+            "user_script:1: Script attempted to access nonexistent global variable 'x'"
+        ));
+        assert!(run("rawset(_G, 'x', 1)").ends_with("Attempt to modify a readonly table"));
+        assert!(run("_G.redis = nil").ends_with("Attempt to modify a readonly table"));
+        assert!(run("return setmetatable(_G, nil)").contains("protected metatable"));
+        assert!(run("local x = 1 return x") == "1");
+        assert_eq!(run("return type(redis.call)"), "function");
+    }
+}
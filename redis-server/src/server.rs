@@ -0,0 +1,356 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, TryLockError,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::acl::Acl;
+use crate::aof::{self, Aof};
+use crate::client::Clients;
+use crate::config::{Config, FsyncPolicy};
+use crate::dictionary_server::{now_ms, DictionaryServer};
+use crate::pubsub::PubSub;
+use crate::rdb;
+use crate::replication::Replication;
+use crate::scripting::{RunningScript, Scripts};
+use crate::slowlog::SlowLog;
+
+const CRON_INTERVAL: Duration = Duration::from_millis(100);
+const ACTIVE_EXPIRE_KEYS_PER_CYCLE: usize = 200;
+/// seconds to wait before retrying an automatic snapshot which failed
+const BGSAVE_RETRY_DELAY: u64 = 5;
+/// How often a client waiting for a script checks whether it turned busy.
+const BUSY_CHECK: Duration = Duration::from_millis(1);
+
+/// Bookkeeping of RDB snapshots.
+#[derive(Debug)]
+pub struct SaveState {
+    /// unix time (seconds) of the last successful snapshot
+    pub last_save: u64,
+    /// unix time (seconds) of the last snapshot attempt
+    pub last_attempt: u64,
+    pub last_ok: bool,
+    pub in_progress: bool,
+}
+
+/// Counters reported by `INFO stats`.
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub connections_received: AtomicU64,
+    /// connections refused because of `maxclients`
+    pub rejected_connections: AtomicU64,
+    pub commands_processed: AtomicU64,
+    /// microseconds the last `BGSAVE` or `BGREWRITEAOF` held the dictionary
+    /// to copy it
+    pub latest_fork_usec: AtomicU64,
+}
+
+/// State shared by every client connection.
+pub struct Server {
+    config: Mutex<Config>,
+    db: Mutex<DictionaryServer>,
+    pub rdb: Mutex<SaveState>,
+    pub aof: Mutex<Aof>,
+    pub pubsub: Mutex<PubSub>,
+    pub acl: Mutex<Acl>,
+    pub replication: Mutex<Replication>,
+    pub clients: Mutex<Clients>,
+    pub slowlog: Mutex<SlowLog>,
+    pub scripts: Mutex<Scripts>,
+    /// the script running right now, if any, checked without locking
+    pub script: Arc<RunningScript>,
+    next_client_id: AtomicU64,
+    /// number of open connections, checked against `maxclients`
+    pub connected_clients: AtomicUsize,
+    pub stats: Stats,
+    /// unix time (ms) the server started at
+    pub started_at: u64,
+}
+
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Server {
+    pub fn new(config: Config, map: DictionaryServer) -> Server {
+        let now = now_ms() / 1000;
+        Server {
+            acl: Mutex::new(Acl::new(&config.requirepass)),
+            replication: Mutex::new(Replication::new(config.repl_backlog_size as usize)),
+            config: Mutex::new(config),
+            db: Mutex::new(map),
+            rdb: Mutex::new(SaveState {
+                last_save: now,
+                last_attempt: now,
+                last_ok: true,
+                in_progress: false,
+            }),
+            aof: Mutex::new(Aof::new()),
+            pubsub: Mutex::new(PubSub::new()),
+            clients: Mutex::new(Clients::new()),
+            slowlog: Mutex::new(SlowLog::new()),
+            scripts: Mutex::new(Scripts::new()),
+            script: Arc::default(),
+            next_client_id: AtomicU64::new(1),
+            connected_clients: AtomicUsize::new(0),
+            stats: Stats::default(),
+            started_at: now_ms(),
+        }
+    }
+
+    /// Id for a new connection.
+    pub fn next_client_id(&self) -> u64 {
+        self.next_client_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Current settings, `CONFIG SET` changes them while the server runs.
+    pub fn config(&self) -> MutexGuard<'_, Config> {
+        lock(&self.config)
+    }
+
+    /// Lock the dictionary, a panic in another client doesn't make the data
+    /// unusable for everyone else.
+    pub fn lock(&self) -> MutexGuard<'_, DictionaryServer> {
+        lock(&self.db)
+    }
+
+    /// Lock the dictionary unless a script holding it is busy, clients get
+    /// to kill it then instead of waiting for it. While a script runs the
+    /// lock is polled so they notice when it turns busy.
+    pub fn lock_unless_busy(&self) -> Option<MutexGuard<'_, DictionaryServer>> {
+        loop {
+            match self.db.try_lock() {
+                Ok(map) => return Some(map),
+                Err(TryLockError::Poisoned(poisoned)) => return Some(poisoned.into_inner()),
+                Err(TryLockError::WouldBlock) => {}
+            }
+            if self.script.is_busy() {
+                return None;
+            }
+            if !self.script.is_running() {
+                return Some(self.lock());
+            }
+            thread::sleep(BUSY_CHECK);
+        }
+    }
+
+    /// `SAVE`: write the snapshot right away while the caller keeps the
+    /// dictionary locked.
+    pub fn save(&self, map: &mut DictionaryServer) -> io::Result<()> {
+        let path = self.config().rdb_path();
+        let mut state = lock(&self.rdb);
+        if state.in_progress {
+            return Err(io::Error::other("Background save already in progress"));
+        }
+        let now = now_ms() / 1000;
+        state.last_attempt = now;
+        let result = rdb::save(map, &path);
+        state.last_ok = result.is_ok();
+        if result.is_ok() {
+            state.last_save = now;
+            map.dirty = 0;
+        }
+        result
+    }
+
+    /// Copy of the dataset for a background save or rewrite, taken while the
+    /// dictionary is locked. The values are shared with it until they
+    /// change, but the keys are still copied one by one so the copy takes
+    /// time in the number of keys, like the page tables a fork of redis
+    /// copies. `INFO stats` reports it as `latest_fork_usec`.
+    fn snapshot(&self, map: &DictionaryServer) -> DictionaryServer {
+        let start = Instant::now();
+        let snapshot = map.clone();
+        self.stats
+            .latest_fork_usec
+            .store(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+        snapshot
+    }
+
+    /// `BGSAVE`: copy the dataset and write it from another thread so clients
+    /// keep being served meanwhile, see `snapshot` for what the copy costs.
+    /// Changes made during the save stay dirty.
+    pub fn background_save(self: &Arc<Self>, map: &DictionaryServer) -> io::Result<()> {
+        let mut state = lock(&self.rdb);
+        if state.in_progress {
+            return Err(io::Error::other("Background save already in progress"));
+        }
+        state.in_progress = true;
+        state.last_attempt = now_ms() / 1000;
+
+        let snapshot = self.snapshot(map);
+        let server = self.clone();
+        let spawned = thread::Builder::new()
+            .name("redis-bgsave".to_string())
+            .spawn(move || {
+                let path = server.config().rdb_path();
+                let result = rdb::save(&snapshot, &path);
+                if let Err(e) = &result {
+                    eprintln!("Background saving error: {}", e);
+                }
+
+                let mut map = server.lock();
+                let mut state = lock(&server.rdb);
+                state.in_progress = false;
+                state.last_ok = result.is_ok();
+                if result.is_ok() {
+                    state.last_save = now_ms() / 1000;
+                    map.dirty = map.dirty.saturating_sub(snapshot.dirty);
+                }
+            });
+
+        if let Err(e) = spawned {
+            state.in_progress = false;
+            state.last_ok = false;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Append a write command of database `db` to the AOF (and to the
+    /// rewrite buffer when a rewrite is running).
+    pub fn feed_aof(&self, db: usize, entry: &[u8]) {
+        let policy = self.config().appendfsync;
+        let mut aof = lock(&self.aof);
+        if let Err(e) = aof.append(db, entry, policy) {
+            eprintln!("Error writing to the AOF file: {}", e);
+        }
+    }
+
+    /// Propagate a write command of database `db` to the AOF and to the
+    /// replicas.
+    pub fn propagate(&self, db: usize, entry: &[u8]) {
+        self.feed_aof(db, entry);
+        lock(&self.replication).feed_command(db, entry);
+    }
+
+    /// Propagate the `DEL`s of the keys which expired since the last call,
+    /// replaying the AOF or the replicas must not depend on their own clock.
+    pub fn propagate_expired(&self, map: &mut DictionaryServer) {
+        for (db, key) in std::mem::take(&mut map.expired) {
+            self.propagate(db, &aof::encode_command(&[b"DEL".as_slice(), &key]));
+        }
+    }
+
+    /// Whether the server replicates another one.
+    pub fn is_replica(&self) -> bool {
+        lock(&self.replication).master.is_some()
+    }
+
+    /// Turn on the append only file, it starts out with the commands
+    /// recreating the current dataset.
+    pub fn start_aof(&self, map: &DictionaryServer) -> io::Result<()> {
+        let path = self.config().aof_path();
+        fs::write(&path, aof::rewrite(map))?;
+        lock(&self.aof).open(&path)
+    }
+
+    /// `BGREWRITEAOF`: write the smallest set of commands recreating the
+    /// current dataset into a new file from another thread, out of a copy
+    /// taken by `snapshot`. Commands executed meanwhile are collected and
+    /// appended to it before it replaces the old log.
+    pub fn background_rewrite_aof(self: &Arc<Self>, map: &DictionaryServer) -> io::Result<()> {
+        let mut log = lock(&self.aof);
+        if log.is_rewriting() {
+            return Err(io::Error::other(
+                "Background append only file rewriting already in progress",
+            ));
+        }
+        log.start_rewrite();
+
+        let snapshot = self.snapshot(map);
+        let server = self.clone();
+        let spawned = thread::Builder::new()
+            .name("redis-aof-rewrite".to_string())
+            .spawn(move || {
+                if let Err(e) = server.rewrite_aof(&snapshot) {
+                    eprintln!("Background AOF rewrite error: {}", e);
+                    lock(&server.aof).finish_rewrite();
+                }
+            });
+
+        if let Err(e) = spawned {
+            log.finish_rewrite();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn rewrite_aof(&self, snapshot: &DictionaryServer) -> io::Result<()> {
+        let path = self.config().aof_path();
+        let temp = path.with_file_name(format!("temp-rewriteaof-bg-{}.aof", std::process::id()));
+        fs::write(&temp, aof::rewrite(snapshot))?;
+
+        // commands keep flowing into the buffer until the aof lock is taken,
+        // after that the new file is complete and takes over
+        let mut log = lock(&self.aof);
+        let mut file = OpenOptions::new().append(true).open(&temp)?;
+        file.write_all(&log.finish_rewrite())?;
+        file.sync_all()?;
+        fs::rename(&temp, &path)?;
+        if log.is_open() {
+            log.open(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Start a background save if one of the `save <seconds> <changes>` rules
+    /// is satisfied.
+    fn save_if_needed(self: &Arc<Self>, map: &DictionaryServer) {
+        let now = now_ms() / 1000;
+        let triggered = {
+            let rules = self.config().save.clone();
+            let state = lock(&self.rdb);
+            let retry_allowed =
+                state.last_ok || now.saturating_sub(state.last_attempt) > BGSAVE_RETRY_DELAY;
+            !state.in_progress
+                && retry_allowed
+                && rules.iter().any(|rule| {
+                    map.dirty >= rule.changes && now.saturating_sub(state.last_save) >= rule.seconds
+                })
+        };
+        if triggered {
+            let _ = self.background_save(map);
+        }
+    }
+
+    /// Periodic background job. Like redis it deletes keys whose deadline
+    /// passed even if nobody touches them again (active expiry) and takes
+    /// snapshots according to the save rules, pings the replicas and fsyncs
+    /// the append only file with `appendfsync everysec`. The lock is released
+    /// after every batch so clients aren't starved. Replicas leave expiry to
+    /// their master.
+    pub fn cron(self: Arc<Self>) {
+        loop {
+            thread::sleep(CRON_INTERVAL);
+            while !self.is_replica() {
+                let mut map = self.lock();
+                let removed = map.expire_cycle(now_ms(), ACTIVE_EXPIRE_KEYS_PER_CYCLE);
+                self.propagate_expired(&mut map);
+                if removed < ACTIVE_EXPIRE_KEYS_PER_CYCLE {
+                    break;
+                }
+            }
+            let map = self.lock();
+            self.save_if_needed(&map);
+            drop(map);
+            lock(&self.replication).ping_replicas();
+
+            if self.config().appendfsync == FsyncPolicy::EverySec {
+                let mut log = lock(&self.aof);
+                if now_ms().saturating_sub(log.last_fsync) >= 1000 {
+                    if let Err(e) = log.fsync() {
+                        eprintln!("Error syncing the AOF file: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
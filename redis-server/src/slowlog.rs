@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+
+/// Arguments of a logged command kept as they were, the rest is summarised.
+const MAX_ARGS: usize = 32;
+/// Bytes of an argument kept as they were, the rest is summarised.
+const MAX_ARG_LEN: usize = 128;
+
+/// A command which ran for longer than `slowlog-log-slower-than`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowLogEntry {
+    /// unique and increasing, `SLOWLOG RESET` doesn't start over
+    pub id: u64,
+    /// unix time (seconds) the command ran at
+    pub timestamp: u64,
+    /// microseconds it took
+    pub duration: u64,
+    pub args: Vec<Vec<u8>>,
+    /// address and name of the client which ran it
+    pub addr: String,
+    pub name: String,
+}
+
+/// The slowest commands, the newest first and at most `slowlog-max-len` of
+/// them.
+#[derive(Debug, Default)]
+pub struct SlowLog {
+    entries: VecDeque<SlowLogEntry>,
+    next_id: u64,
+}
+
+/// Like redis long commands and arguments are cut so the log can't grow too
+/// big, e.g. the value of a huge `SET`.
+fn summarise(args: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    let mut summary: Vec<Vec<u8>> = args
+        .iter()
+        .take(MAX_ARGS)
+        .map(|arg| match arg.len() {
+            len if len > MAX_ARG_LEN => {
+                let mut cut = arg[..MAX_ARG_LEN].to_vec();
+                cut.extend(format!("... ({} more bytes)", len - MAX_ARG_LEN).into_bytes());
+                cut
+            }
+            _ => arg.clone(),
+        })
+        .collect();
+    if args.len() > MAX_ARGS {
+        let more = format!("... ({} more arguments)", args.len() - MAX_ARGS + 1);
+        summary[MAX_ARGS - 1] = more.into_bytes();
+    }
+    summary
+}
+
+impl SlowLog {
+    pub fn new() -> SlowLog {
+        SlowLog::default()
+    }
+
+    /// Log a command, the oldest entries are dropped past `max_len`.
+    pub fn push(&mut self, mut entry: SlowLogEntry, max_len: usize) {
+        entry.id = self.next_id;
+        entry.args = summarise(&entry.args);
+        self.next_id += 1;
+        self.entries.push_front(entry);
+        self.entries.truncate(max_len);
+    }
+
+    /// Up to `count` entries, the newest first.
+    pub fn get(&self, count: usize) -> impl Iterator<Item = &SlowLogEntry> {
+        self.entries.iter().take(count)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn reset(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(args: Vec<Vec<u8>>) -> SlowLogEntry {
+        SlowLogEntry {
+            id: 0,
+            timestamp: 1,
+            duration: 20000,
+            args,
+            addr: "127.0.0.1:5000".to_string(),
+            name: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_slowlog_keeps_newest_entries() {
+        let mut slowlog = SlowLog::new();
+        for i in 0..5 {
+            slowlog.push(entry(vec![format!("cmd{}", i).into_bytes()]), 3);
+        }
+        assert_eq!(slowlog.len(), 3);
+        let ids: Vec<u64> = slowlog.get(2).map(|entry| entry.id).collect();
+        assert_eq!(ids, [4, 3]);
+        slowlog.reset();
+        assert_eq!(slowlog.len(), 0);
+        slowlog.push(entry(vec![b"PING".to_vec()]), 3);
+        assert_eq!(slowlog.get(10).next().unwrap().id, 5);
+    }
+
+    #[test]
+    fn test_long_commands_are_summarised() {
+        let mut slowlog = SlowLog::new();
+        let mut args = vec![b"SET".to_vec(), b"k".to_vec(), vec![b'x'; 200]];
+        slowlog.push(entry(args.clone()), 10);
+        let logged = &slowlog.get(1).next().unwrap().args;
+        let mut value = vec![b'x'; 128];
+        value.extend_from_slice(b"... (72 more bytes)");
+        assert_eq!(logged[2], value);
+
+        args = (0..40).map(|i| i.to_string().into_bytes()).collect();
+        slowlog.push(entry(args), 10);
+        let logged = &slowlog.get(1).next().unwrap().args;
+        assert_eq!(logged.len(), 32);
+        assert_eq!(logged[30], b"30");
+        assert_eq!(logged[31], b"... (9 more arguments)");
+    }
+}
@@ -0,0 +1,563 @@
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+};
+
+/// Same limits redis uses for its skiplist: enough levels for 2^64 elements
+/// with a 1/4 chance to promote a node to the next level.
+const MAX_LEVEL: usize = 32;
+const PROMOTE_ONE_IN: u64 = 4;
+
+/// Pointer to the next node of a level together with how many nodes of the
+/// lowest level it skips, summing spans along the search path gives the rank.
+#[derive(Debug, Clone, Copy)]
+struct Link {
+    next: Option<usize>,
+    span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    member: Vec<u8>,
+    score: f64,
+    levels: Vec<Link>,
+    backward: Option<usize>,
+}
+
+/// Skiplist ordered by `(score, member)` like the one backing redis sorted
+/// sets. Nodes live in an arena, index 0 is the header and freed slots are
+/// reused.
+#[derive(Debug, Clone)]
+struct SkipList {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    tail: Option<usize>,
+    level: usize,
+    len: usize,
+    seed: u64,
+}
+
+const HEAD: usize = 0;
+
+impl SkipList {
+    fn new() -> SkipList {
+        let head = Node {
+            member: Vec::new(),
+            score: 0.0,
+            levels: vec![
+                Link {
+                    next: None,
+                    span: 0
+                };
+                MAX_LEVEL
+            ],
+            backward: None,
+        };
+        SkipList {
+            nodes: vec![head],
+            free: Vec::new(),
+            tail: None,
+            level: 1,
+            len: 0,
+            seed: RandomState::new().build_hasher().finish() | 1,
+        }
+    }
+
+    /// Level of a new node, each extra level with probability 1/4.
+    fn random_level(&mut self) -> usize {
+        let mut level = 1;
+        loop {
+            // xorshift64
+            self.seed ^= self.seed << 13;
+            self.seed ^= self.seed >> 7;
+            self.seed ^= self.seed << 17;
+            if level >= MAX_LEVEL || !self.seed.is_multiple_of(PROMOTE_ONE_IN) {
+                return level;
+            }
+            level += 1;
+        }
+    }
+
+    fn link(&self, node: usize, level: usize) -> Link {
+        self.nodes[node].levels[level]
+    }
+
+    /// Whether `node` sorts strictly before `(score, member)`.
+    fn before(&self, node: usize, score: f64, member: &[u8]) -> bool {
+        let node = &self.nodes[node];
+        match node.score.partial_cmp(&score) {
+            Some(Ordering::Less) => true,
+            Some(Ordering::Equal) => node.member.as_slice() < member,
+            _ => false,
+        }
+    }
+
+    /// Last node of every level that sorts before `(score, member)` and the
+    /// rank of each of them.
+    fn find_path(&self, score: f64, member: &[u8]) -> ([usize; MAX_LEVEL], [usize; MAX_LEVEL]) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i == self.level - 1 { 0 } else { rank[i + 1] };
+            while let Some(next) = self.link(x, i).next {
+                if !self.before(next, score, member) {
+                    break;
+                }
+                rank[i] += self.link(x, i).span;
+                x = next;
+            }
+            update[i] = x;
+        }
+        (update, rank)
+    }
+
+    /// Insert a member which must not be in the list yet.
+    fn insert(&mut self, score: f64, member: Vec<u8>) {
+        let (mut update, mut rank) = self.find_path(score, &member);
+        let level = self.random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEAD;
+                self.nodes[HEAD].levels[i].span = self.len;
+            }
+            self.level = level;
+        }
+
+        let node = Node {
+            member,
+            score,
+            levels: vec![
+                Link {
+                    next: None,
+                    span: 0
+                };
+                level
+            ],
+            backward: if update[0] == HEAD {
+                None
+            } else {
+                Some(update[0])
+            },
+        };
+        let x = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = node;
+                slot
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        for i in 0..level {
+            let previous = self.link(update[i], i);
+            self.nodes[x].levels[i] = Link {
+                next: previous.next,
+                span: previous.span - (rank[0] - rank[i]),
+            };
+            self.nodes[update[i]].levels[i] = Link {
+                next: Some(x),
+                span: rank[0] - rank[i] + 1,
+            };
+        }
+        for (i, node) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[*node].levels[i].span += 1;
+        }
+
+        match self.link(x, 0).next {
+            Some(next) => self.nodes[next].backward = Some(x),
+            None => self.tail = Some(x),
+        }
+        self.len += 1;
+    }
+
+    /// Remove `(score, member)`, returns `false` if it isn't in the list.
+    fn delete(&mut self, score: f64, member: &[u8]) -> bool {
+        let (update, _) = self.find_path(score, member);
+        let x = match self.link(update[0], 0).next {
+            Some(x) if self.nodes[x].score == score && self.nodes[x].member == member => x,
+            _ => return false,
+        };
+
+        for (i, node) in update.iter().enumerate().take(self.level) {
+            let link = self.link(*node, i);
+            if link.next == Some(x) {
+                let removed = self.link(x, i);
+                self.nodes[*node].levels[i] = Link {
+                    next: removed.next,
+                    span: link.span + removed.span - 1,
+                };
+            } else {
+                self.nodes[*node].levels[i].span -= 1;
+            }
+        }
+        match self.link(x, 0).next {
+            Some(next) => self.nodes[next].backward = self.nodes[x].backward,
+            None => self.tail = self.nodes[x].backward,
+        }
+        while self.level > 1 && self.link(HEAD, self.level - 1).next.is_none() {
+            self.level -= 1;
+        }
+
+        self.nodes[x].member = Vec::new();
+        self.nodes[x].levels = Vec::new();
+        self.free.push(x);
+        self.len -= 1;
+        true
+    }
+
+    /// 0 based position of `(score, member)`.
+    fn rank(&self, score: f64, member: &[u8]) -> Option<usize> {
+        let mut rank = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.link(x, i).next {
+                let node = &self.nodes[next];
+                let not_after =
+                    node.score < score || (node.score == score && node.member.as_slice() <= member);
+                if !not_after {
+                    break;
+                }
+                rank += self.link(x, i).span;
+                x = next;
+            }
+            if x != HEAD && self.nodes[x].member == member {
+                return Some(rank - 1);
+            }
+        }
+        None
+    }
+
+    /// Node at the 0 based position `rank`.
+    fn by_rank(&self, rank: usize) -> Option<usize> {
+        let target = rank + 1;
+        let mut traversed = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.link(x, i).next {
+                if traversed + self.link(x, i).span > target {
+                    break;
+                }
+                traversed += self.link(x, i).span;
+                x = next;
+            }
+            if traversed == target {
+                return Some(x);
+            }
+        }
+        None
+    }
+
+    /// First node whose score is inside `min`.
+    fn first_from(&self, min: &ScoreBound) -> Option<usize> {
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.link(x, i).next {
+                if min.allows_min(self.nodes[next].score) {
+                    break;
+                }
+                x = next;
+            }
+        }
+        self.link(x, 0).next
+    }
+
+    /// Last node whose score is inside `max`.
+    fn last_until(&self, max: &ScoreBound) -> Option<usize> {
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.link(x, i).next {
+                if !max.allows_max(self.nodes[next].score) {
+                    break;
+                }
+                x = next;
+            }
+        }
+        if x == HEAD {
+            None
+        } else {
+            Some(x)
+        }
+    }
+
+    fn step(&self, node: usize, reverse: bool) -> Option<usize> {
+        if reverse {
+            self.nodes[node].backward
+        } else {
+            self.link(node, 0).next
+        }
+    }
+
+    fn entry(&self, node: usize) -> (Vec<u8>, f64) {
+        (self.nodes[node].member.clone(), self.nodes[node].score)
+    }
+}
+
+/// Boundary of a score range, `exclusive` for the `(1.5` syntax.
+#[derive(Debug, Clone, Copy)]
+pub struct ScoreBound {
+    pub score: f64,
+    pub exclusive: bool,
+}
+
+impl ScoreBound {
+    /// Parses `-inf`, `+inf`, `1.5` or `(1.5`.
+    pub fn parse(string: &str) -> Option<ScoreBound> {
+        let (exclusive, number) = match string.strip_prefix('(') {
+            Some(number) => (true, number),
+            None => (false, string),
+        };
+        let score = parse_score(number)?;
+        Some(ScoreBound { score, exclusive })
+    }
+
+    fn allows_min(&self, score: f64) -> bool {
+        if self.exclusive {
+            score > self.score
+        } else {
+            score >= self.score
+        }
+    }
+
+    fn allows_max(&self, score: f64) -> bool {
+        if self.exclusive {
+            score < self.score
+        } else {
+            score <= self.score
+        }
+    }
+}
+
+/// Parses a score the way redis does, accepting `inf` variants but no NaN.
+pub fn parse_score(string: &str) -> Option<f64> {
+    let score = match string.to_lowercase().as_str() {
+        "inf" | "+inf" => f64::INFINITY,
+        "-inf" => f64::NEG_INFINITY,
+        _ => string.parse::<f64>().ok()?,
+    };
+    if score.is_nan() {
+        None
+    } else {
+        Some(score)
+    }
+}
+
+/// Shortest representation of a score which parses back to the same value.
+pub fn format_score(score: f64) -> String {
+    if score.is_infinite() {
+        if score > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        score.to_string()
+    }
+}
+
+/// Sorted set: a dictionary from member to score for O(1) lookups and a
+/// skiplist for O(log n) rank and range queries.
+#[derive(Debug, Clone)]
+pub struct SortedSet {
+    scores: HashMap<Vec<u8>, f64>,
+    list: SkipList,
+}
+
+impl PartialEq for SortedSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.scores == other.scores
+    }
+}
+
+impl SortedSet {
+    pub fn new() -> SortedSet {
+        SortedSet {
+            scores: HashMap::new(),
+            list: SkipList::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Add `member` or update its score, returns `true` if it is new.
+    pub fn insert(&mut self, member: &[u8], score: f64) -> bool {
+        match self.scores.insert(member.to_vec(), score) {
+            Some(old) => {
+                if old != score {
+                    self.list.delete(old, member);
+                    self.list.insert(score, member.to_vec());
+                }
+                false
+            }
+            None => {
+                self.list.insert(score, member.to_vec());
+                true
+            }
+        }
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => self.list.delete(score, member),
+            None => false,
+        }
+    }
+
+    /// 0 based rank of `member`, with `reverse` counting from the highest
+    /// score.
+    pub fn rank(&self, member: &[u8], reverse: bool) -> Option<usize> {
+        let score = self.score(member)?;
+        let rank = self.list.rank(score, member)?;
+        Some(if reverse { self.len() - 1 - rank } else { rank })
+    }
+
+    /// Members between the inclusive ranks `start` and `stop`.
+    pub fn range_by_rank(&self, start: usize, stop: usize, reverse: bool) -> Vec<(Vec<u8>, f64)> {
+        let first = if reverse {
+            self.len().checked_sub(start + 1)
+        } else {
+            Some(start)
+        };
+        let mut node = first.and_then(|rank| self.list.by_rank(rank));
+        let mut range = Vec::new();
+        while let Some(x) = node {
+            if range.len() > stop - start {
+                break;
+            }
+            range.push(self.list.entry(x));
+            node = self.list.step(x, reverse);
+        }
+        range
+    }
+
+    /// Members with a score between `min` and `max`, skipping `offset` of
+    /// them and returning at most `count`.
+    pub fn range_by_score(
+        &self,
+        min: &ScoreBound,
+        max: &ScoreBound,
+        reverse: bool,
+        offset: usize,
+        count: Option<usize>,
+    ) -> Vec<(Vec<u8>, f64)> {
+        let mut node = if reverse {
+            self.list.last_until(max)
+        } else {
+            self.list.first_from(min)
+        };
+        let mut skipped = 0;
+        let mut range = Vec::new();
+        while let Some(x) = node {
+            let score = self.list.nodes[x].score;
+            if !min.allows_min(score) || !max.allows_max(score) {
+                break;
+            }
+            if count.is_some_and(|count| range.len() >= count) {
+                break;
+            }
+            if skipped < offset {
+                skipped += 1;
+            } else {
+                range.push(self.list.entry(x));
+            }
+            node = self.list.step(x, reverse);
+        }
+        range
+    }
+
+    /// Every member with its score in ascending order.
+    pub fn iter(&self) -> Vec<(Vec<u8>, f64)> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        self.range_by_rank(0, self.len() - 1, false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn members(range: Vec<(Vec<u8>, f64)>) -> Vec<String> {
+        range
+            .into_iter()
+            .map(|(member, _)| String::from_utf8(member).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_insert_rank_and_remove() {
+        let mut set = SortedSet::new();
+        for i in 0..1000 {
+            // insert out of order to exercise the search path
+            let n = (i * 7919) % 1000;
+            assert!(set.insert(format!("m{}", n).as_bytes(), n as f64));
+        }
+        assert_eq!(set.len(), 1000);
+        for n in 0..1000 {
+            assert_eq!(set.rank(format!("m{}", n).as_bytes(), false), Some(n));
+            assert_eq!(set.rank(format!("m{}", n).as_bytes(), true), Some(999 - n));
+        }
+
+        for n in (0..1000).step_by(2) {
+            assert!(set.remove(format!("m{}", n).as_bytes()));
+        }
+        assert!(!set.remove(b"m0"));
+        assert_eq!(set.len(), 500);
+        assert_eq!(set.rank(b"m999", false), Some(499));
+        assert_eq!(members(set.range_by_rank(0, 2, false)), ["m1", "m3", "m5"]);
+    }
+
+    #[test]
+    fn test_update_score_moves_member() {
+        let mut set = SortedSet::new();
+        set.insert(b"a", 1.0);
+        set.insert(b"b", 2.0);
+        set.insert(b"c", 3.0);
+        assert!(!set.insert(b"a", 10.0));
+        assert_eq!(members(set.iter()), ["b", "c", "a"]);
+        assert_eq!(set.rank(b"a", false), Some(2));
+    }
+
+    #[test]
+    fn test_equal_scores_sort_by_member() {
+        let mut set = SortedSet::new();
+        set.insert(b"c", 1.0);
+        set.insert(b"a", 1.0);
+        set.insert(b"b", 1.0);
+        assert_eq!(members(set.iter()), ["a", "b", "c"]);
+        assert_eq!(members(set.range_by_rank(0, 1, true)), ["c", "b"]);
+    }
+
+    #[test]
+    fn test_range_by_score() {
+        let mut set = SortedSet::new();
+        for n in 1..=10 {
+            set.insert(format!("m{}", n).as_bytes(), n as f64);
+        }
+        let min = ScoreBound::parse("(3").unwrap();
+        let max = ScoreBound::parse("6").unwrap();
+        assert_eq!(
+            members(set.range_by_score(&min, &max, false, 0, None)),
+            ["m4", "m5", "m6"]
+        );
+        assert_eq!(
+            members(set.range_by_score(&min, &max, true, 1, Some(1))),
+            ["m5"]
+        );
+        let all = ScoreBound::parse("-inf").unwrap();
+        let inf = ScoreBound::parse("+inf").unwrap();
+        assert_eq!(set.range_by_score(&all, &inf, false, 0, None).len(), 10);
+        assert!(ScoreBound::parse("nan").is_none());
+    }
+}
@@ -0,0 +1,436 @@
+use std::{collections::BTreeMap, fmt};
+
+/// Id of a stream entry: the unix time (ms) it was added at and a sequence
+/// number telling apart entries added in the same millisecond.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> StreamId {
+        StreamId { ms, seq }
+    }
+
+    /// Parse `ms-seq`, a bare `ms` gets `default_seq` as sequence number.
+    pub fn parse(string: &str, default_seq: u64) -> Option<StreamId> {
+        let (ms, seq) = match string.split_once('-') {
+            Some((ms, seq)) => (ms, seq.parse().ok()?),
+            None => (string, default_seq),
+        };
+        Some(StreamId::new(ms.parse().ok()?, seq))
+    }
+
+    /// The smallest id after this one.
+    pub fn next(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_add(1)?, 0)),
+        }
+    }
+
+    /// The greatest id before this one.
+    pub fn prev(self) -> Option<StreamId> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_sub(1)?, u64::MAX)),
+        }
+    }
+
+    /// Big endian encoding, sorts like the ids. Used as the key of the
+    /// listpacks in RDB files.
+    pub fn to_bytes(self) -> [u8; 16] {
+        let mut bytes = [0; 16];
+        bytes[..8].copy_from_slice(&self.ms.to_be_bytes());
+        bytes[8..].copy_from_slice(&self.seq.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; 16]) -> StreamId {
+        StreamId::new(
+            u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            u64::from_be_bytes(bytes[8..].try_into().unwrap()),
+        )
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// Field value pairs of an entry in the order they were given to `XADD`.
+pub type Fields = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// An entry delivered to a consumer of a group which wasn't acknowledged
+/// yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingEntry {
+    pub consumer: String,
+    /// unix time (ms) of the last delivery
+    pub delivered_at: u64,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Consumer {
+    /// unix time (ms) the consumer last read or claimed entries
+    pub seen_at: u64,
+}
+
+/// Consumer group, its consumers share the entries of the stream: each new
+/// entry is delivered to only one of them and stays pending until it is
+/// acknowledged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsumerGroup {
+    /// id of the last entry delivered to any consumer, `XREADGROUP ... >`
+    /// continues after it
+    pub last_delivered: StreamId,
+    /// pending entries list of the whole group, a consumer's own list is the
+    /// part of it it owns
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<String, Consumer>,
+}
+
+impl ConsumerGroup {
+    pub fn new(last_delivered: StreamId) -> ConsumerGroup {
+        ConsumerGroup {
+            last_delivered,
+            pending: BTreeMap::new(),
+            consumers: BTreeMap::new(),
+        }
+    }
+
+    /// Create the consumer if it doesn't exist, returns whether it did.
+    pub fn create_consumer(&mut self, name: &str, now: u64) -> bool {
+        if self.consumers.contains_key(name) {
+            return false;
+        }
+        self.consumers
+            .insert(name.to_string(), Consumer { seen_at: now });
+        true
+    }
+
+    /// Mark the consumer as active, it is created if needed.
+    pub fn touch_consumer(&mut self, name: &str, now: u64) {
+        self.create_consumer(name, now);
+        if let Some(consumer) = self.consumers.get_mut(name) {
+            consumer.seen_at = now;
+        }
+    }
+
+    /// Remove the consumer along with its pending entries, returns how many
+    /// entries it had pending.
+    pub fn delete_consumer(&mut self, name: &str) -> Option<usize> {
+        self.consumers.remove(name)?;
+        let before = self.pending.len();
+        self.pending.retain(|_, entry| entry.consumer != name);
+        Some(before - self.pending.len())
+    }
+
+    /// Ids pending for `consumer` after `after`, at most `count` of them.
+    pub fn pending_of(&self, consumer: &str, after: StreamId, count: usize) -> Vec<StreamId> {
+        self.pending
+            .range(after..)
+            .filter(|(id, entry)| **id != after && entry.consumer == consumer)
+            .map(|(id, _)| *id)
+            .take(count)
+            .collect()
+    }
+
+    /// Number of pending entries of every consumer which has any.
+    pub fn pending_per_consumer(&self) -> BTreeMap<&str, usize> {
+        let mut counts = BTreeMap::new();
+        for entry in self.pending.values() {
+            *counts.entry(entry.consumer.as_str()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Acknowledge an entry, returns whether it was pending.
+    pub fn ack(&mut self, id: StreamId) -> bool {
+        self.pending.remove(&id).is_some()
+    }
+}
+
+/// Append only log of entries ordered by id, the data type behind `XADD` and
+/// friends.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Fields>,
+    /// greatest id ever added, new ids must be greater even after the entry
+    /// was trimmed away
+    pub last_id: StreamId,
+    pub groups: BTreeMap<String, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn new() -> Stream {
+        Stream {
+            entries: BTreeMap::new(),
+            last_id: StreamId::MIN,
+            groups: BTreeMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, id: StreamId) -> Option<&Fields> {
+        self.entries.get(&id)
+    }
+
+    /// Id `XADD` gives the next entry. `ms` is the time part asked for, the
+    /// current time for `*`, and the sequence is picked so the id is greater
+    /// than every id so far. `None` when no such id exists.
+    pub fn next_id(&self, ms: u64, fixed_ms: bool) -> Option<StreamId> {
+        let last = self.last_id;
+        if ms > last.ms {
+            return Some(StreamId::new(ms, 0));
+        }
+        if fixed_ms && ms < last.ms {
+            return None;
+        }
+        match last.seq.checked_add(1) {
+            Some(seq) => Some(StreamId::new(last.ms, seq)),
+            // the time part can only move on when it wasn't given
+            None if !fixed_ms => Some(StreamId::new(last.ms.checked_add(1)?, 0)),
+            None => None,
+        }
+    }
+
+    /// Append an entry, `id` has to be greater than `last_id`.
+    pub fn add(&mut self, id: StreamId, fields: Fields) {
+        self.entries.insert(id, fields);
+        self.last_id = id;
+    }
+
+    /// Entries with an id between `start` and `end` included, at most
+    /// `count` of them, from the last one when `reverse` is set.
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+        reverse: bool,
+    ) -> Vec<(StreamId, &Fields)> {
+        if start > end {
+            return Vec::new();
+        }
+        let range = self.entries.range(start..=end);
+        let count = count.unwrap_or(usize::MAX);
+        let entries: Vec<_> = match reverse {
+            true => range.rev().take(count).collect(),
+            false => range.take(count).collect(),
+        };
+        entries
+            .into_iter()
+            .map(|(id, fields)| (*id, fields))
+            .collect()
+    }
+
+    /// Entries with an id greater than `after`.
+    pub fn after(&self, after: StreamId, count: Option<usize>) -> Vec<(StreamId, &Fields)> {
+        match after.next() {
+            Some(start) => self.range(start, StreamId::MAX, count, false),
+            None => Vec::new(),
+        }
+    }
+
+    /// Remove the oldest entries until at most `maxlen` are left, or at most
+    /// `limit` entries were removed. Returns how many were removed.
+    pub fn trim_maxlen(&mut self, maxlen: usize, limit: Option<usize>) -> usize {
+        let excess = self.len().saturating_sub(maxlen);
+        let excess = limit.map_or(excess, |limit| excess.min(limit));
+        for _ in 0..excess {
+            self.entries.pop_first();
+        }
+        excess
+    }
+
+    /// Remove the entries with an id smaller than `minid`, at most `limit`
+    /// of them. Returns how many were removed.
+    pub fn trim_minid(&mut self, minid: StreamId, limit: Option<usize>) -> usize {
+        let mut removed = 0;
+        while limit.is_none_or(|limit| removed < limit) {
+            match self.entries.first_key_value() {
+                Some((id, _)) if *id < minid => {
+                    self.entries.pop_first();
+                    removed += 1;
+                }
+                _ => break,
+            }
+        }
+        removed
+    }
+
+    /// `XREADGROUP ... >`: the entries after the last one delivered to the
+    /// group go to `consumer`, they stay pending until acknowledged unless
+    /// `noack` is set. `None` when there is no such group.
+    pub fn deliver(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        count: Option<usize>,
+        noack: bool,
+        now: u64,
+    ) -> Option<Vec<(StreamId, Fields)>> {
+        let group = self.groups.get_mut(group)?;
+        group.touch_consumer(consumer, now);
+        let start = match group.last_delivered.next() {
+            Some(start) => start,
+            None => return Some(Vec::new()),
+        };
+        let delivered: Vec<(StreamId, Fields)> = self
+            .entries
+            .range(start..)
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(id, fields)| (*id, fields.clone()))
+            .collect();
+        for (id, _) in &delivered {
+            group.last_delivered = *id;
+            if !noack {
+                group.pending.insert(
+                    *id,
+                    PendingEntry {
+                        consumer: consumer.to_string(),
+                        delivered_at: now,
+                        delivery_count: 1,
+                    },
+                );
+            }
+        }
+        Some(delivered)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fields(pairs: &[(&str, &str)]) -> Fields {
+        pairs
+            .iter()
+            .map(|(field, value)| (field.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn test_stream_ids() {
+        assert_eq!(StreamId::parse("5-3", 0), Some(StreamId::new(5, 3)));
+        assert_eq!(
+            StreamId::parse("5", u64::MAX),
+            Some(StreamId::new(5, u64::MAX))
+        );
+        assert_eq!(StreamId::parse("5-", 0), None);
+        assert_eq!(StreamId::parse("-1", 0), None);
+        assert_eq!(StreamId::new(5, 3).to_string(), "5-3");
+        assert_eq!(StreamId::new(5, u64::MAX).next(), Some(StreamId::new(6, 0)));
+        assert_eq!(StreamId::new(5, 0).prev(), Some(StreamId::new(4, u64::MAX)));
+        assert_eq!(StreamId::MAX.next(), None);
+        assert_eq!(StreamId::MIN.prev(), None);
+        let id = StreamId::new(1_700_000_000_000, 42);
+        assert_eq!(StreamId::from_bytes(&id.to_bytes()), id);
+    }
+
+    #[test]
+    fn test_next_id() {
+        let mut stream = Stream::new();
+        assert_eq!(stream.next_id(10, false), Some(StreamId::new(10, 0)));
+        stream.add(StreamId::new(10, 0), fields(&[("a", "1")]));
+        // the clock went backwards, the sequence keeps ids increasing
+        assert_eq!(stream.next_id(9, false), Some(StreamId::new(10, 1)));
+        assert_eq!(stream.next_id(10, true), Some(StreamId::new(10, 1)));
+        assert_eq!(stream.next_id(9, true), None);
+        stream.add(StreamId::new(10, u64::MAX), fields(&[("a", "1")]));
+        assert_eq!(stream.next_id(10, false), Some(StreamId::new(11, 0)));
+        assert_eq!(stream.next_id(10, true), None);
+    }
+
+    #[test]
+    fn test_range_and_trim() {
+        let mut stream = Stream::new();
+        for i in 1..=10 {
+            stream.add(StreamId::new(i, 0), fields(&[("n", &i.to_string())]));
+        }
+        let ids = |entries: Vec<(StreamId, &Fields)>| -> Vec<u64> {
+            entries.into_iter().map(|(id, _)| id.ms).collect()
+        };
+        assert_eq!(
+            ids(stream.range(StreamId::new(3, 0), StreamId::new(5, 0), None, false)),
+            [3, 4, 5]
+        );
+        assert_eq!(
+            ids(stream.range(StreamId::MIN, StreamId::MAX, Some(2), true)),
+            [10, 9]
+        );
+        assert_eq!(ids(stream.after(StreamId::new(8, 0), None)), [9, 10]);
+
+        assert_eq!(stream.trim_maxlen(8, Some(1)), 1);
+        assert_eq!(stream.trim_maxlen(8, None), 1);
+        assert_eq!(stream.trim_minid(StreamId::new(5, 0), None), 2);
+        assert_eq!(
+            stream.range(StreamId::MIN, StreamId::MAX, Some(1), false)[0].0,
+            StreamId::new(5, 0)
+        );
+        // trimming never lowers the last id
+        stream.trim_maxlen(0, None);
+        assert!(stream.is_empty());
+        assert_eq!(stream.last_id, StreamId::new(10, 0));
+    }
+
+    #[test]
+    fn test_consumer_groups() {
+        let mut stream = Stream::new();
+        for i in 1..=3 {
+            stream.add(StreamId::new(i, 0), fields(&[("n", &i.to_string())]));
+        }
+        stream
+            .groups
+            .insert("g".to_string(), ConsumerGroup::new(StreamId::MIN));
+        assert!(stream.deliver("nope", "alice", None, false, 100).is_none());
+
+        let delivered = stream.deliver("g", "alice", Some(2), false, 100).unwrap();
+        assert_eq!(delivered.len(), 2);
+        let delivered = stream.deliver("g", "bob", None, false, 200).unwrap();
+        assert_eq!(delivered[0].0, StreamId::new(3, 0));
+        assert!(stream
+            .deliver("g", "bob", None, false, 300)
+            .unwrap()
+            .is_empty());
+
+        let group = stream.groups.get_mut("g").unwrap();
+        assert_eq!(group.last_delivered, StreamId::new(3, 0));
+        assert_eq!(
+            group.pending_of("alice", StreamId::MIN, 10),
+            [StreamId::new(1, 0), StreamId::new(2, 0)]
+        );
+        assert_eq!(
+            group.pending_of("alice", StreamId::new(1, 0), 10),
+            [StreamId::new(2, 0)]
+        );
+        assert!(group.ack(StreamId::new(1, 0)));
+        assert!(!group.ack(StreamId::new(1, 0)));
+        assert_eq!(
+            group.pending_per_consumer(),
+            BTreeMap::from([("alice", 1), ("bob", 1)])
+        );
+        assert_eq!(group.consumers["bob"].seen_at, 300);
+        assert_eq!(group.delete_consumer("bob"), Some(1));
+        assert_eq!(group.delete_consumer("bob"), None);
+        assert_eq!(group.pending.len(), 1);
+    }
+}